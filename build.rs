@@ -0,0 +1,39 @@
+// Fingerprints the interpreter's source for the compile cache, see src/cache. Any change to the
+// source gives a new fingerprint, so a cache never hands a build bytecode written by a compiler
+// which differs from its own, even when the crate's version number hasn't changed.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+
+    let mut files = vec![];
+    collect(Path::new("src"), &mut files);
+    // sorted so the fingerprint doesn't depend on the order the file system lists files in
+    files.sort();
+
+    // 64 bit FNV-1a, over each file's path and then its contents
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for file in files {
+        let contents = fs::read(&file).unwrap();
+        for byte in file.to_string_lossy().bytes().chain(Some(0)).chain(contents) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    println!("cargo:rustc-env=MONKEY_COMPILER_FINGERPRINT={}-{:016x}", env::var("CARGO_PKG_VERSION").unwrap(), hash);
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "rs") {
+            files.push(path);
+        }
+    }
+}
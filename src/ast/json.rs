@@ -0,0 +1,331 @@
+// Programs as json, for tools such as block editors which build programs without writing
+// source. Each statement and expression is an object whose `type` names its kind, with the
+// rest of its fields named after those of the ast:
+//
+//     {"type": "let", "name": "x", "value": {"type": "integer", "value": 5}}
+//
+// Reading json back checks everything the parser would have, so a program read from json
+// compiles and runs like one parsed from source.
+
+use crate::lexer::{lex, Span, Token};
+use crate::parser::{Expr, Operator, Prefix, Statement};
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// what is wrong with a program read from json, the path is where in the json it is, such as
+///    `$[1].value.left`
+#[derive(Debug, PartialEq)]
+pub enum JsonAstError {
+    NotAnObject { path: String },
+    UnknownType { path: String, found: String },
+    MissingField { path: String, field: &'static str },
+    WrongFieldType { path: String, field: &'static str, expected: &'static str },
+    UnknownOperator { path: String, operator: String },
+    // a name which isn't a valid identifier, or is a keyword
+    InvalidName { path: String, name: String },
+}
+
+impl fmt::Display for JsonAstError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonAstError::NotAnObject { path } => write!(f, "json error at {}: expected an object", path),
+            JsonAstError::UnknownType { path, found } => write!(f, "json error at {}: unknown type `{}`", path, found),
+            JsonAstError::MissingField { path, field } => write!(f, "json error at {}: missing field `{}`", path, field),
+            JsonAstError::WrongFieldType { path, field, expected } =>
+                write!(f, "json error at {}: field `{}` must be {}", path, field, expected),
+            JsonAstError::UnknownOperator { path, operator } =>
+                write!(f, "json error at {}: unknown operator `{}`", path, operator),
+            JsonAstError::InvalidName { path, name } =>
+                write!(f, "json error at {}: `{}` is not a valid name", path, name),
+        }
+    }
+}
+
+impl std::error::Error for JsonAstError {}
+
+pub fn program_to_json(program: &[Statement]) -> Value {
+    Value::Array(program.iter().map(statement_to_json).collect())
+}
+
+fn statement_to_json(statement: &Statement) -> Value {
+    match statement {
+        Statement::Let { name, value, .. } => json!({ "type": "let", "name": name, "value": expr_to_json(value) }),
+        Statement::Declare { name, .. } => json!({ "type": "declare", "name": name }),
+        Statement::Assign { name, value, .. } => json!({ "type": "assign", "name": name, "value": expr_to_json(value) }),
+        Statement::Return { value, .. } => json!({ "type": "return", "value": expr_to_json(value) }),
+        Statement::Expression(expr, _) => json!({ "type": "expression", "expression": expr_to_json(expr) }),
+    }
+}
+
+fn expr_to_json(expr: &Expr) -> Value {
+    match expr {
+        Expr::Const(value) => json!({ "type": "integer", "value": value }),
+        Expr::Float(value) => json!({ "type": "float", "value": value }),
+        Expr::String(value) => json!({ "type": "string", "value": value }),
+        Expr::Boolean(value) => json!({ "type": "boolean", "value": value }),
+        Expr::Null => json!({ "type": "null" }),
+        Expr::Ident(name, _) => json!({ "type": "identifier", "name": name }),
+        Expr::Prefix { prefix, value } =>
+            json!({ "type": "prefix", "operator": prefix.to_string(), "operand": expr_to_json(value) }),
+        Expr::Infix { left, operator, right } => json!({
+            "type": "infix",
+            "operator": operator.to_string(),
+            "left": expr_to_json(left),
+            "right": expr_to_json(right),
+        }),
+        Expr::If { condition, consequence, alternative } => json!({
+            "type": "if",
+            "condition": expr_to_json(condition),
+            "consequence": program_to_json(consequence),
+            "alternative": program_to_json(alternative),
+        }),
+        Expr::Function { parameters, body } =>
+            json!({ "type": "function", "parameters": parameters, "body": program_to_json(body) }),
+        Expr::Call { function, arguments, .. } => json!({
+            "type": "call",
+            "function": expr_to_json(function),
+            "arguments": arguments.iter().map(expr_to_json).collect::<Vec<_>>(),
+        }),
+        Expr::Array(elements) => json!({ "type": "array", "elements": elements.iter().map(expr_to_json).collect::<Vec<_>>() }),
+        Expr::Hash(pairs) => json!({
+            "type": "hash",
+            "pairs": pairs.iter()
+                .map(|(key, value)| json!({ "key": expr_to_json(key), "value": expr_to_json(value) }))
+                .collect::<Vec<_>>(),
+        }),
+        Expr::Index { left, index } => json!({ "type": "index", "left": expr_to_json(left), "index": expr_to_json(index) }),
+        Expr::Interpolation(parts) =>
+            json!({ "type": "interpolation", "parts": parts.iter().map(expr_to_json).collect::<Vec<_>>() }),
+        Expr::Ternary { condition, consequence, alternative } => json!({
+            "type": "ternary",
+            "condition": expr_to_json(condition),
+            "consequence": expr_to_json(consequence),
+            "alternative": expr_to_json(alternative),
+        }),
+    }
+}
+
+/// reads a program written by program_to_json, or built by another tool in the same shape
+pub fn program_from_json(value: &Value) -> Result<Vec<Statement>, JsonAstError> {
+    statements_from_json(value, "$")
+}
+
+fn statements_from_json(value: &Value, path: &str) -> Result<Vec<Statement>, JsonAstError> {
+    match value {
+        Value::Array(statements) => statements.iter()
+            .enumerate()
+            .map(|(index, statement)| statement_from_json(statement, &format!("{}[{}]", path, index)))
+            .collect(),
+        _ => Err(JsonAstError::WrongFieldType { path: path.to_string(), field: "statements", expected: "an array" }),
+    }
+}
+
+// the fields of one statement or expression, each read with the path to it for errors
+struct Node<'a> {
+    fields: &'a Map<String, Value>,
+    path: &'a str,
+}
+
+impl<'a> Node<'a> {
+    fn field(&self, field: &'static str) -> Result<&'a Value, JsonAstError> {
+        self.fields.get(field).ok_or_else(|| JsonAstError::MissingField { path: self.path.to_string(), field })
+    }
+
+    fn wrong_type(&self, field: &'static str, expected: &'static str) -> JsonAstError {
+        JsonAstError::WrongFieldType { path: self.path.to_string(), field, expected }
+    }
+
+    fn str(&self, field: &'static str) -> Result<&'a str, JsonAstError> {
+        self.field(field)?.as_str().ok_or_else(|| self.wrong_type(field, "a string"))
+    }
+
+    fn name(&self, field: &'static str) -> Result<String, JsonAstError> {
+        name(self.str(field)?, self.path)
+    }
+
+    fn expr(&self, field: &'static str) -> Result<Expr, JsonAstError> {
+        expr_from_json(self.field(field)?, &format!("{}.{}", self.path, field))
+    }
+
+    fn exprs(&self, field: &'static str) -> Result<Vec<Expr>, JsonAstError> {
+        let elements = self.field(field)?.as_array().ok_or_else(|| self.wrong_type(field, "an array"))?;
+        elements.iter()
+            .enumerate()
+            .map(|(index, element)| expr_from_json(element, &format!("{}.{}[{}]", self.path, field, index)))
+            .collect()
+    }
+
+    // the pairs of a hash, each an object with a key and a value
+    fn pairs(&self, field: &'static str) -> Result<Vec<(Expr, Expr)>, JsonAstError> {
+        let pairs = self.field(field)?.as_array().ok_or_else(|| self.wrong_type(field, "an array"))?;
+        pairs.iter()
+            .enumerate()
+            .map(|(index, pair)| {
+                let path = format!("{}.{}[{}]", self.path, field, index);
+                let fields = pair.as_object().ok_or_else(|| JsonAstError::NotAnObject { path: path.clone() })?;
+                let pair = Node { fields, path: &path };
+                Ok((pair.expr("key")?, pair.expr("value")?))
+            })
+            .collect()
+    }
+
+    fn statements(&self, field: &'static str) -> Result<Vec<Statement>, JsonAstError> {
+        statements_from_json(self.field(field)?, &format!("{}.{}", self.path, field))
+    }
+}
+
+fn node<'a>(value: &'a Value, path: &'a str) -> Result<(Node<'a>, &'a str), JsonAstError> {
+    let fields = value.as_object().ok_or_else(|| JsonAstError::NotAnObject { path: path.to_string() })?;
+    let node = Node { fields, path };
+    let kind = node.str("type")?;
+
+    Ok((node, kind))
+}
+
+// names are checked by lexing them, so they are exactly those the parser accepts
+fn name(name: &str, path: &str) -> Result<String, JsonAstError> {
+    match lex(name).as_deref() {
+        Ok([token, _]) if token.token == Token::IDENT(name.to_string()) => Ok(name.to_string()),
+        _ => Err(JsonAstError::InvalidName { path: path.to_string(), name: name.to_string() }),
+    }
+}
+
+fn statement_from_json(value: &Value, path: &str) -> Result<Statement, JsonAstError> {
+    let (node, kind) = node(value, path)?;
+    let span = Span::default();
+
+    Ok(match kind {
+        "let" => Statement::Let { name: node.name("name")?, value: node.expr("value")?, span },
+        "declare" => Statement::Declare { name: node.name("name")?, span },
+        "assign" => Statement::Assign { name: node.name("name")?, value: node.expr("value")?, span },
+        "return" => Statement::Return { value: node.expr("value")?, span },
+        "expression" => Statement::Expression(node.expr("expression")?, span),
+        kind => return Err(JsonAstError::UnknownType { path: path.to_string(), found: kind.to_string() }),
+    })
+}
+
+fn expr_from_json(value: &Value, path: &str) -> Result<Expr, JsonAstError> {
+    let (node, kind) = node(value, path)?;
+
+    Ok(match kind {
+        "integer" => {
+            let value = node.field("value")?.as_i64().and_then(|value| i32::try_from(value).ok());
+            Expr::Const(value.ok_or_else(|| node.wrong_type("value", "an integer from -2147483648 to 2147483647"))?)
+        },
+        "float" => Expr::Float(node.field("value")?.as_f64().ok_or_else(|| node.wrong_type("value", "a number"))?),
+        "string" => Expr::String(node.str("value")?.to_string()),
+        "boolean" => Expr::Boolean(node.field("value")?.as_bool().ok_or_else(|| node.wrong_type("value", "a boolean"))?),
+        "null" => Expr::Null,
+        "identifier" => Expr::Ident(node.name("name")?, Span::default()),
+        "prefix" => {
+            let prefix = match node.str("operator")? {
+                "!" => Prefix::Bang,
+                "-" => Prefix::Minus,
+                operator => return Err(JsonAstError::UnknownOperator { path: path.to_string(), operator: operator.to_string() }),
+            };
+            Expr::Prefix { prefix, value: Box::new(node.expr("operand")?) }
+        },
+        "infix" => {
+            let operator = match node.str("operator")? {
+                "+" => Operator::Plus,
+                "-" => Operator::Minus,
+                "*" => Operator::Multiply,
+                "/" => Operator::Divide,
+                ">" => Operator::GreaterThan,
+                "<" => Operator::LessThan,
+                ">=" => Operator::GreaterThanOrEqual,
+                "<=" => Operator::LessThanOrEqual,
+                "==" => Operator::Equals,
+                "!=" => Operator::NotEquals,
+                "&&" => Operator::And,
+                "||" => Operator::Or,
+                operator => return Err(JsonAstError::UnknownOperator { path: path.to_string(), operator: operator.to_string() }),
+            };
+            Expr::Infix { left: Box::new(node.expr("left")?), operator, right: Box::new(node.expr("right")?) }
+        },
+        "if" => Expr::If {
+            condition: Box::new(node.expr("condition")?),
+            consequence: node.statements("consequence")?,
+            // an if without an else may leave the alternative out
+            alternative: match node.fields.get("alternative") {
+                Some(_) => node.statements("alternative")?,
+                None => vec![],
+            },
+        },
+        "function" => {
+            let parameters = node.field("parameters")?.as_array().ok_or_else(|| node.wrong_type("parameters", "an array"))?;
+            let parameters = parameters.iter()
+                .map(|parameter| name(parameter.as_str().ok_or_else(|| node.wrong_type("parameters", "an array of strings"))?, path))
+                .collect::<Result<_, _>>()?;
+            Expr::Function { parameters, body: node.statements("body")? }
+        },
+        "call" => Expr::Call {
+            function: Box::new(node.expr("function")?),
+            arguments: node.exprs("arguments")?,
+            span: Span::default(),
+        },
+        "array" => Expr::Array(node.exprs("elements")?),
+        "hash" => Expr::Hash(node.pairs("pairs")?),
+        "index" => Expr::Index { left: Box::new(node.expr("left")?), index: Box::new(node.expr("index")?) },
+        "interpolation" => Expr::Interpolation(node.exprs("parts")?),
+        "ternary" => Expr::Ternary {
+            condition: Box::new(node.expr("condition")?),
+            consequence: Box::new(node.expr("consequence")?),
+            alternative: Box::new(node.expr("alternative")?),
+        },
+        kind => return Err(JsonAstError::UnknownType { path: path.to_string(), found: kind.to_string() }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn json_round_trip() {
+        let input = r#"let f = fn(a, b) { if (!a || b >= 1.5) { return [a, "s"]; } else { null; }; }; let x; x = f(true, -2); f(1)(2); x[0][1]; "a ${x}"; x ? 1 : 2; {"k": x, 1: {}};"#;
+        let program = parse(&mut lex(input).unwrap()).unwrap();
+
+        assert_eq!(Ok(program.clone()), program_from_json(&program_to_json(&program)));
+        assert_eq!(
+            json!([{ "type": "let", "name": "x", "value": { "type": "integer", "value": 5 } }]),
+            program_to_json(&parse(&mut lex("let x = 5;").unwrap()).unwrap())
+        );
+    }
+
+    #[test]
+    fn json_validation() {
+        let read = |value: Value| program_from_json(&value).unwrap_err();
+
+        assert_eq!(
+            JsonAstError::UnknownOperator { path: String::from("$[0].expression"), operator: String::from("%") },
+            read(json!([{ "type": "expression", "expression": {
+                "type": "infix", "operator": "%", "left": { "type": "integer", "value": 1 }, "right": { "type": "integer", "value": 2 },
+            } }]))
+        );
+        assert_eq!(
+            JsonAstError::MissingField { path: String::from("$[0].value"), field: "right" },
+            read(json!([{ "type": "let", "name": "x", "value": {
+                "type": "infix", "operator": "+", "left": { "type": "integer", "value": 1 },
+            } }]))
+        );
+        assert_eq!(
+            JsonAstError::InvalidName { path: String::from("$[0]"), name: String::from("let") },
+            read(json!([{ "type": "declare", "name": "let" }]))
+        );
+        assert_eq!(
+            JsonAstError::InvalidName { path: String::from("$[0].expression"), name: String::from("a b") },
+            read(json!([{ "type": "expression", "expression": { "type": "function", "parameters": ["a b"], "body": [] } }]))
+        );
+        assert_eq!(
+            JsonAstError::UnknownType { path: String::from("$[1]"), found: String::from("while") },
+            read(json!([{ "type": "declare", "name": "x" }, { "type": "while" }]))
+        );
+        assert_eq!(
+            "json error at $[0].value: field `value` must be an integer from -2147483648 to 2147483647",
+            read(json!([{ "type": "return", "value": { "type": "integer", "value": 1e12 } }])).to_string()
+        );
+    }
+}
@@ -0,0 +1,167 @@
+// Shorthand constructors for building an AST by hand, so a program such as
+// `add(1, 2);` can be written as `ast::expr(ast::call("add", [ast::int(1), ast::int(2)]))`
+// rather than as nested enum literals. Statements, identifiers, and calls are given a default
+// span, which is ignored when comparing them.
+
+use crate::parser::{Statement, Expr, Prefix, Operator};
+use crate::lexer::Span;
+
+#[cfg(feature = "serde_json")]
+mod json;
+#[cfg(feature = "serde_json")]
+pub use self::json::{program_from_json, program_to_json, JsonAstError};
+
+pub fn int(value: i32) -> Expr {
+    Expr::Const(value)
+}
+
+pub fn float(value: f64) -> Expr {
+    Expr::Float(value)
+}
+
+pub fn string(value: &str) -> Expr {
+    Expr::String(String::from(value))
+}
+
+pub fn boolean(value: bool) -> Expr {
+    Expr::Boolean(value)
+}
+
+pub fn null() -> Expr {
+    Expr::Null
+}
+
+pub fn ident(name: &str) -> Expr {
+    Expr::Ident(String::from(name), Span::default())
+}
+
+pub fn prefix(prefix: Prefix, value: Expr) -> Expr {
+    Expr::Prefix { prefix, value: Box::new(value) }
+}
+
+pub fn infix(left: Expr, operator: Operator, right: Expr) -> Expr {
+    Expr::Infix { left: Box::new(left), operator, right: Box::new(right) }
+}
+
+pub fn if_(condition: Expr, consequence: impl IntoIterator<Item = Statement>) -> Expr {
+    if_else(condition, consequence, vec![])
+}
+
+pub fn if_else(
+    condition: Expr,
+    consequence: impl IntoIterator<Item = Statement>,
+    alternative: impl IntoIterator<Item = Statement>,
+) -> Expr {
+    Expr::If {
+        condition: Box::new(condition),
+        consequence: consequence.into_iter().collect(),
+        alternative: alternative.into_iter().collect(),
+    }
+}
+
+pub fn function<'a>(parameters: impl IntoIterator<Item = &'a str>, body: impl IntoIterator<Item = Statement>) -> Expr {
+    Expr::Function {
+        parameters: parameters.into_iter().map(String::from).collect(),
+        body: body.into_iter().collect(),
+    }
+}
+
+/// a call to a function by name, see call_expr to call the result of any other expression
+pub fn call(function: &str, arguments: impl IntoIterator<Item = Expr>) -> Expr {
+    call_expr(ident(function), arguments)
+}
+
+pub fn call_expr(function: Expr, arguments: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr::Call { function: Box::new(function), arguments: arguments.into_iter().collect(), span: Span::default() }
+}
+
+pub fn array(elements: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr::Array(elements.into_iter().collect())
+}
+
+pub fn hash(pairs: impl IntoIterator<Item = (Expr, Expr)>) -> Expr {
+    Expr::Hash(pairs.into_iter().collect())
+}
+
+pub fn interpolation(parts: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr::Interpolation(parts.into_iter().collect())
+}
+
+pub fn ternary(condition: Expr, consequence: Expr, alternative: Expr) -> Expr {
+    Expr::Ternary { condition: Box::new(condition), consequence: Box::new(consequence), alternative: Box::new(alternative) }
+}
+
+pub fn index(left: Expr, index: Expr) -> Expr {
+    Expr::Index { left: Box::new(left), index: Box::new(index) }
+}
+
+pub fn let_(name: &str, value: Expr) -> Statement {
+    Statement::Let { name: String::from(name), value, span: Span::default() }
+}
+
+pub fn declare(name: &str) -> Statement {
+    Statement::Declare { name: String::from(name), span: Span::default() }
+}
+
+pub fn assign(name: &str, value: Expr) -> Statement {
+    Statement::Assign { name: String::from(name), value, span: Span::default() }
+}
+
+pub fn return_(value: Expr) -> Statement {
+    Statement::Return { value, span: Span::default() }
+}
+
+pub fn expr(expr: Expr) -> Statement {
+    Statement::Expression(expr, Span::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_matches_enum_literals() {
+        assert_eq!(
+            Statement::Let {
+                name: String::from("max"),
+                value: Expr::Function {
+                    parameters: vec![String::from("a"), String::from("b")],
+                    body: vec![
+                        Statement::Expression(
+                            Expr::If {
+                                condition: Box::new(Expr::Infix {
+                                    left: Box::new(Expr::Ident(String::from("a"), Span::default())),
+                                    operator: Operator::GreaterThan,
+                                    right: Box::new(Expr::Ident(String::from("b"), Span::default())),
+                                }),
+                                consequence: vec![Statement::Return { value: Expr::Ident(String::from("a"), Span::default()), span: Span::default() }],
+                                alternative: vec![Statement::Return { value: Expr::Ident(String::from("b"), Span::default()), span: Span::default() }],
+                            },
+                            Span::default()
+                        ),
+                    ],
+                },
+                span: Span::default(),
+            },
+            let_("max", function(["a", "b"], [
+                expr(if_else(
+                    infix(ident("a"), Operator::GreaterThan, ident("b")),
+                    [return_(ident("a"))],
+                    [return_(ident("b"))],
+                )),
+            ]))
+        );
+    }
+
+    #[test]
+    fn build_calls() {
+        assert_eq!(
+            Expr::Call {
+                function: Box::new(Expr::Ident(String::from("add"), Span::default())),
+                arguments: vec![Expr::Const(1), Expr::Array(vec![Expr::Boolean(true)])],
+                span: Span::default(),
+            },
+            call("add", [int(1), array([boolean(true)])])
+        );
+    }
+}
@@ -0,0 +1,360 @@
+use crate::eval::{Object, RuntimeError};
+use crate::profile::heap::HeapProfile;
+use super::input::{Input, Terminal};
+use super::replay::{Event, Replay};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// what a program is allowed to do outside of computing values, an embedder can deny
+///    capabilities to run untrusted scripts
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Capabilities {
+    pub output: bool,
+    pub clock: bool,
+    pub random: bool,
+    // reading answers from the user, for prompt and the other interactive builtins
+    pub input: bool,
+    // reading the file system, for glob
+    pub fs: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities { output: true, clock: true, random: true, input: true, fs: true }
+    }
+}
+
+/// the largest values a program may create, an embedder can set limits to stop a script
+///    using up the host's memory
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Limits {
+    // in bytes, None for no limit
+    pub max_string_length: Option<usize>,
+    // in elements, None for no limit
+    pub max_array_length: Option<usize>,
+    // in pairs, None for no limit
+    pub max_hash_length: Option<usize>,
+}
+
+/// the seed used for deterministic runs, so random numbers are the same on every run and platform
+pub const DETERMINISTIC_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// the state builtins reach through a context, owned by the env or vm running the program
+pub struct Host {
+    output: Box<dyn Write>,
+    input: Box<dyn Input>,
+    capabilities: Capabilities,
+    limits: Limits,
+    // whether the output is a terminal which draws ansi escape codes, otherwise the builtins
+    //    which style text leave it plain
+    color: bool,
+    rng_state: u64,
+    // the objects the program has allocated, as recorded by the engine running it
+    heap: HeapProfile,
+    // when set, the clock, random numbers, and answers to prompts are recorded or replayed
+    replay: Option<Replay>,
+}
+
+impl Host {
+    pub fn new(output: Box<dyn Write>) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+
+        Host {
+            output,
+            input: Box::new(Terminal::default()),
+            capabilities: Capabilities::default(),
+            limits: Limits::default(),
+            color: false,
+            // xorshift gets stuck at zero, so make sure at least one bit is set
+            rng_state: seed | 1,
+            heap: HeapProfile::new(),
+            replay: None,
+        }
+    }
+
+    /// where answers to prompts are read from, in place of the terminal
+    pub fn with_input(mut self, input: Box<dyn Input>) -> Self {
+        self.input = input;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// seeds the random numbers, so a program reads the same sequence on every run
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        // xorshift gets stuck at zero, so make sure at least one bit is set
+        self.rng_state = seed | 1;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// lets color and the other builtins which style text use ansi escape codes
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn color(&self) -> bool {
+        self.color
+    }
+
+    /// records the clock, random numbers, and answers to prompts read by the program, see recorded
+    pub fn with_recording(mut self) -> Self {
+        self.replay = Some(Replay::Record(Vec::new()));
+        self
+    }
+
+    /// feeds the program the clock, random numbers, and answers to prompts from a recorded run,
+    ///    in place of reading them
+    pub fn with_replay(mut self, log: Vec<Event>) -> Self {
+        self.replay = Some(Replay::Replay(log.into()));
+        self
+    }
+
+    /// the values read by the program so far, when recording
+    pub fn recorded(&self) -> Option<&[Event]> {
+        match &self.replay {
+            Some(Replay::Record(log)) => Some(log),
+            _ => None,
+        }
+    }
+
+    fn observe(&mut self, kind: &'static str, read: impl FnOnce(&mut Host) -> Event) -> Result<Event, RuntimeError> {
+        match self.replay.take() {
+            Some(mut replay) => {
+                let event = replay.observe(kind, || read(self));
+                self.replay = Some(replay);
+                event
+            },
+            None => Ok(read(self)),
+        }
+    }
+
+    pub fn heap(&self) -> &HeapProfile {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut HeapProfile {
+        &mut self.heap
+    }
+
+    fn require(&self, allowed: bool, capability: &'static str) -> Result<(), RuntimeError> {
+        if allowed {
+            Ok(())
+        } else {
+            Err(RuntimeError::CapabilityDenied(capability))
+        }
+    }
+}
+
+/// the engine a builtin is called from, giving access to the host and a way to call
+///    back into functions of the running program
+pub trait Context {
+    fn host(&mut self) -> &mut Host;
+
+    fn call(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, RuntimeError>;
+
+    fn capabilities(&mut self) -> Capabilities {
+        self.host().capabilities
+    }
+
+    fn output(&mut self) -> Result<&mut dyn Write, RuntimeError> {
+        let host = self.host();
+        host.require(host.capabilities.output, "output")?;
+
+        Ok(&mut *host.output)
+    }
+
+    /// shows the prompt and reads the user's answer, None at the end of input
+    fn read_line(&mut self, prompt: &str) -> Result<Option<String>, RuntimeError> {
+        let host = self.host();
+        host.require(host.capabilities.input, "input")?;
+
+        // the prompt is only shown when the answer is read, not when it comes from the log
+        let event = host.observe("input", |host| Event::Input(host.input.read_line(prompt)))?;
+        match event {
+            Event::Input(answer) => Ok(answer),
+            _ => Err(RuntimeError::ReplayDiverged("input")),
+        }
+    }
+
+    /// the paths on the file system which match the pattern, see glob::glob
+    fn glob(&mut self, pattern: &str) -> Result<Vec<PathBuf>, RuntimeError> {
+        let host = self.host();
+        host.require(host.capabilities.fs, "fs")?;
+
+        Ok(super::glob::glob(pattern))
+    }
+
+    /// checks a newly created value against the host's limits
+    fn check_size(&mut self, object: &Object) -> Result<(), RuntimeError> {
+        let limits = self.host().limits;
+        match object {
+            Object::String(string) => match limits.max_string_length {
+                Some(limit) if string.len() > limit => Err(RuntimeError::ValueTooLarge { kind: "string", limit }),
+                _ => Ok(()),
+            },
+            Object::Array(elements) => match limits.max_array_length {
+                Some(limit) if elements.len() > limit => Err(RuntimeError::ValueTooLarge { kind: "array", limit }),
+                _ => Ok(()),
+            },
+            Object::Hash(pairs) => match limits.max_hash_length {
+                Some(limit) if pairs.len() > limit => Err(RuntimeError::ValueTooLarge { kind: "hash", limit }),
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn now(&mut self) -> Result<SystemTime, RuntimeError> {
+        let host = self.host();
+        host.require(host.capabilities.clock, "clock")?;
+
+        let event = host.observe("clock", |_| {
+            Event::Clock(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64))
+        })?;
+        match event {
+            Event::Clock(nanos) => Ok(UNIX_EPOCH + Duration::from_nanos(nanos)),
+            _ => Err(RuntimeError::ReplayDiverged("clock")),
+        }
+    }
+
+    /// a pseudo random number, not suitable for cryptography
+    fn random(&mut self) -> Result<u64, RuntimeError> {
+        let host = self.host();
+        host.require(host.capabilities.random, "random")?;
+
+        let event = host.observe("random", |host| {
+            // xorshift64
+            let mut x = host.rng_state;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            host.rng_state = x;
+
+            Event::Random(x)
+        })?;
+        match event {
+            Event::Random(x) => Ok(x),
+            _ => Err(RuntimeError::ReplayDiverged("random")),
+        }
+    }
+}
+
+// a host alone can run builtins which don't call back into the program, such as when
+//    the compiler evaluates calls to pure builtins
+impl Context for Host {
+    fn host(&mut self) -> &mut Host {
+        self
+    }
+
+    fn call(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+        match function {
+            Object::Builtin(index) => super::call(index, &arguments, self),
+            Object::Native(native) => native.call(&arguments),
+            function => Err(RuntimeError::NotCallable(function.type_name())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::HashKey;
+    use std::rc::Rc;
+
+    #[test]
+    fn host_capabilities() {
+        let mut host = Host::new(Box::new(std::io::sink()));
+        assert!(host.output().is_ok());
+        assert!(host.now().is_ok());
+        assert_ne!(host.random().unwrap(), host.random().unwrap());
+
+        let mut host = Host::new(Box::new(std::io::sink()))
+            .with_capabilities(Capabilities { output: false, clock: false, random: false, input: false, fs: false });
+        assert_eq!(Some(RuntimeError::CapabilityDenied("output")), host.output().err());
+        assert_eq!(Err(RuntimeError::CapabilityDenied("input")), host.read_line("> "));
+        assert_eq!(Err(RuntimeError::CapabilityDenied("clock")), host.now());
+        assert_eq!(Err(RuntimeError::CapabilityDenied("random")), host.random());
+        assert_eq!(Err(RuntimeError::CapabilityDenied("fs")), host.glob("*"));
+    }
+
+    #[test]
+    fn host_seed() {
+        let mut first = Host::new(Box::new(std::io::sink())).with_seed(DETERMINISTIC_SEED);
+        let mut second = Host::new(Box::new(std::io::sink())).with_seed(DETERMINISTIC_SEED);
+        for _ in 0..3 {
+            assert_eq!(first.random(), second.random());
+        }
+
+        let mut zero = Host::new(Box::new(std::io::sink())).with_seed(0);
+        assert_ne!(Ok(0), zero.random());
+    }
+
+    #[test]
+    fn host_limits() {
+        let mut host = Host::new(Box::new(std::io::sink()))
+            .with_limits(Limits { max_string_length: Some(3), max_array_length: Some(1), max_hash_length: Some(1) });
+
+        assert_eq!(Ok(()), host.check_size(&Object::String("abc".into())));
+        assert_eq!(
+            Err(RuntimeError::ValueTooLarge { kind: "string", limit: 3 }),
+            host.check_size(&Object::String("abcd".into()))
+        );
+        assert_eq!(
+            Err(RuntimeError::ValueTooLarge { kind: "array", limit: 1 }),
+            host.check_size(&Object::Array(Rc::new(vec![Object::Null, Object::Null])))
+        );
+        let pairs = [(HashKey::Integer(1), Object::Null), (HashKey::Integer(2), Object::Null)];
+        assert_eq!(
+            Err(RuntimeError::ValueTooLarge { kind: "hash", limit: 1 }),
+            host.check_size(&Object::Hash(Rc::new(pairs.iter().cloned().collect())))
+        );
+        assert_eq!(Ok(()), Host::new(Box::new(std::io::sink())).check_size(&Object::String("abcd".into())));
+    }
+
+    #[test]
+    fn host_replay() {
+        let mut host = Host::new(Box::new(std::io::sink())).with_recording();
+        let time = host.now().unwrap();
+        let random = host.random().unwrap();
+        let log = host.recorded().unwrap().to_vec();
+        assert_eq!(2, log.len());
+
+        let mut host = Host::new(Box::new(std::io::sink())).with_replay(log);
+        assert_eq!(Ok(time), host.now());
+        assert_eq!(Ok(random), host.random());
+        assert_eq!(Err(RuntimeError::ReplayDiverged("random")), host.random());
+    }
+
+    #[test]
+    fn host_replay_input() {
+        let answers = ["yes", "blue"];
+        let mut host = Host::new(Box::new(std::io::sink()))
+            .with_input(Box::new(answers.iter().copied().collect::<std::collections::VecDeque<_>>()))
+            .with_recording();
+        assert_eq!(Ok(Some(String::from("yes"))), host.read_line("continue? "));
+        assert_eq!(Ok(Some(String::from("blue"))), host.read_line("colour? "));
+        assert_eq!(Ok(None), host.read_line("more? "));
+        let log = host.recorded().unwrap().to_vec();
+        assert_eq!(Event::Input(None), log[2]);
+
+        // nothing is read from the input when replaying, the answers come from the log
+        let mut host = Host::new(Box::new(std::io::sink()))
+            .with_input(Box::new(std::collections::VecDeque::new()))
+            .with_replay(log);
+        assert_eq!(Ok(Some(String::from("yes"))), host.read_line("continue? "));
+        assert_eq!(Ok(Some(String::from("blue"))), host.read_line("colour? "));
+        assert_eq!(Ok(None), host.read_line("more? "));
+        assert_eq!(Err(RuntimeError::ReplayDiverged("input")), host.read_line("again? "));
+        assert_eq!(Err(RuntimeError::ReplayDiverged("clock")), host.now());
+    }
+}
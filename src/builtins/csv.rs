@@ -0,0 +1,148 @@
+// Reading and writing comma separated values for csv_parse and csv_stringify. Fields may be
+// quoted to hold commas, line breaks, or quotes, which are doubled inside a quoted field, and
+// rows end with either `\n` or `\r\n`.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CsvError {
+    // the line the problem was found on, from one
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+/// the rows of the text, each an array of its fields, text ending with a line break doesn't
+///    have an empty row after it
+pub fn parse(text: &str) -> Result<Vec<Vec<String>>, CsvError> {
+    let mut rows = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let mut row = vec![];
+        loop {
+            row.push(field(&mut chars, &mut line)?);
+            match chars.next() {
+                Some(',') => continue,
+                Some('\r') if chars.peek() == Some(&'\n') => { chars.next(); },
+                Some('\n') | None => {},
+                Some(_) => return Err(CsvError { line, reason: "text after a closing quote" }),
+            }
+            line += 1;
+            break;
+        }
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+// reads a field up to the comma or line break which ends it, leaving that for the caller
+fn field(chars: &mut Peekable<Chars>, line: &mut usize) -> Result<String, CsvError> {
+    let mut field = String::new();
+    if chars.peek() != Some(&'"') {
+        while let Some(&c) = chars.peek() {
+            if c == ',' || c == '\n' || (c == '\r' && field_ends_at_crlf(chars)) {
+                break;
+            }
+            field.push(c);
+            chars.next();
+        }
+        return Ok(field);
+    }
+
+    let start = *line;
+    chars.next();
+    loop {
+        match chars.next() {
+            Some('"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            Some('"') => return Ok(field),
+            Some(c) => {
+                if c == '\n' {
+                    *line += 1;
+                }
+                field.push(c);
+            },
+            None => return Err(CsvError { line: start, reason: "a quoted field which is never closed" }),
+        }
+    }
+}
+
+// a lone carriage return is kept as part of an unquoted field, only `\r\n` ends a row
+fn field_ends_at_crlf(chars: &Peekable<Chars>) -> bool {
+    let mut ahead = chars.clone();
+    ahead.next();
+    ahead.peek() == Some(&'\n')
+}
+
+/// the rows as csv text, quoting only the fields which need it and ending each row with `\n`
+pub fn stringify(rows: &[Vec<String>]) -> String {
+    let mut text = String::new();
+    for row in rows {
+        // a row of one empty field is quoted, as an empty line reads back as that row anyway
+        //    but is easily lost
+        if let [field] = row.as_slice() {
+            if field.is_empty() {
+                text.push_str("\"\"\n");
+                continue;
+            }
+        }
+        for (index, field) in row.iter().enumerate() {
+            if index > 0 {
+                text.push(',');
+            }
+            if field.contains([',', '"', '\n', '\r']) {
+                text.push('"');
+                text.push_str(&field.replace('"', "\"\""));
+                text.push('"');
+            } else {
+                text.push_str(field);
+            }
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|field| field.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn csv_parse() {
+        assert_eq!(Ok(rows(&[&["a", "b"], &["1", "2"]])), parse("a,b\n1,2\n"));
+        assert_eq!(Ok(rows(&[&["a", "b"], &["1", "2"]])), parse("a,b\r\n1,2"));
+        assert_eq!(Ok(rows(&[])), parse(""));
+        assert_eq!(Ok(rows(&[&["", "", ""], &[""]])), parse(",,\n\n"));
+        assert_eq!(Ok(rows(&[&["x\ry"]])), parse("x\ry"));
+        assert_eq!(
+            Ok(rows(&[&["a,b", "say \"hi\"", "two\nlines"], &["next"]])),
+            parse("\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\nnext")
+        );
+
+        assert_eq!(
+            Err(CsvError { line: 2, reason: "a quoted field which is never closed" }),
+            parse("a\n\"b\nc")
+        );
+        assert_eq!(Err(CsvError { line: 3, reason: "text after a closing quote" }), parse("a\n\"b\nc\"d,e"));
+    }
+
+    #[test]
+    fn csv_stringify() {
+        assert_eq!("a,b\n1,2\n", stringify(&rows(&[&["a", "b"], &["1", "2"]])));
+        assert_eq!("", stringify(&[]));
+        assert_eq!("\"a,b\",\"say \"\"hi\"\"\",\"x\ny\"\n", stringify(&rows(&[&["a,b", "say \"hi\"", "x\ny"]])));
+
+        for case in [rows(&[&[""], &["", ""]]), rows(&[&["\r\n", "\"", " spaced "]])] {
+            assert_eq!(Ok(case.clone()), parse(&stringify(&case)));
+        }
+    }
+}
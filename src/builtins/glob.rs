@@ -0,0 +1,156 @@
+// Finding the paths which match a pattern for glob. Within a component of the pattern `*`
+// matches any run of characters and `?` any one character, neither matching the `.` which
+// starts a hidden file, and a component which is only `**` matches any number of directories.
+// Directories which can't be read are skipped, as a shell does.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// the paths matching the pattern, sorted, a pattern without wildcards matches the path
+///    itself if it exists
+pub fn glob(pattern: &str) -> Vec<PathBuf> {
+    // the components before the first wildcard are a directory to start from, not a pattern
+    let mut base = PathBuf::new();
+    let mut parts = vec![];
+    for component in Path::new(pattern).components() {
+        match component {
+            Component::Normal(part) if !parts.is_empty() || part.to_str().is_some_and(has_wildcard) => match part.to_str() {
+                Some(part) => parts.push(part),
+                None => return vec![],
+            },
+            component => base.push(component),
+        }
+    }
+
+    let mut paths = vec![];
+    if parts.is_empty() {
+        if base.exists() {
+            paths.push(base);
+        }
+    } else {
+        walk(&base, &parts, &mut paths);
+    }
+    paths.sort();
+    // a path reached through more than one `**` is only listed once
+    paths.dedup();
+
+    paths
+}
+
+fn has_wildcard(part: &str) -> bool {
+    part.contains(['*', '?'])
+}
+
+fn walk(dir: &Path, parts: &[&str], paths: &mut Vec<PathBuf>) {
+    let (part, rest) = match parts.split_first() {
+        Some(split) => split,
+        None => return paths.push(dir.to_path_buf()),
+    };
+
+    if *part == "**" {
+        walk(dir, rest, paths);
+        for name in entries(dir) {
+            let path = dir.join(name);
+            if path.is_dir() {
+                walk(&path, parts, paths);
+            }
+        }
+    } else if !has_wildcard(part) {
+        let path = dir.join(part);
+        if path.exists() {
+            walk(&path, rest, paths);
+        }
+    } else {
+        for name in entries(dir).filter(|name| matches(part, name)) {
+            let path = dir.join(name);
+            if rest.is_empty() || path.is_dir() {
+                walk(&path, rest, paths);
+            }
+        }
+    }
+}
+
+// the names in the directory which are valid utf-8, `**` skips hidden directories as `*` does
+fn entries(dir: &Path) -> impl Iterator<Item = String> {
+    // an empty base is the current directory
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+}
+
+/// whether the name matches a single component of a pattern
+pub fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // the last `*` seen, and how much of the name it has matched up to, so a mismatch after it
+    //    can be retried with the `*` taking one more character
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*", "main.rs"));
+        assert!(matches("m??n.*", "main.rs"));
+        assert!(matches("*a*b", "aXaXb"));
+        assert!(!matches("*.rs", "main.rs.bak"));
+        assert!(!matches("?", ""));
+        assert!(!matches("*", ".hidden"));
+        assert!(matches(".*", ".hidden"));
+    }
+
+    #[test]
+    fn glob_paths() {
+        let dir = std::env::temp_dir().join(format!("monkey-glob-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src/nested")).unwrap();
+        for file in &["a.txt", "b.txt", "c.md", ".hidden.txt", "src/d.txt", "src/nested/e.txt"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let glob = |pattern: &str| glob(dir.join(pattern).to_str().unwrap())
+            .into_iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["a.txt", "b.txt"], glob("*.txt"));
+        assert_eq!(vec!["src/d.txt"], glob("*/*.txt"));
+        assert_eq!(vec!["a.txt", "b.txt", "src/d.txt", "src/nested/e.txt"], glob("**/*.txt"));
+        assert_eq!(vec!["c.md"], glob("c.md"));
+        assert!(glob("missing/*").is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
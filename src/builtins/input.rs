@@ -0,0 +1,33 @@
+// Where the interactive builtins, such as prompt, read the user's answers from. By default
+// answers are read from the terminal with rustyline, so they can be edited as they are typed,
+// but an embedder can supply them from anywhere else.
+
+use rustyline::Editor;
+
+pub trait Input {
+    /// shows the prompt and reads a line without its line ending, None at the end of input or
+    ///    when the input can't be read
+    fn read_line(&mut self, prompt: &str) -> Option<String>;
+}
+
+/// reads from the terminal, the editor is only created when the first line is read so hosts
+///    which never prompt leave the terminal alone
+#[derive(Default)]
+pub struct Terminal(Option<Editor<()>>);
+
+impl Input for Terminal {
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let editor = self.0.get_or_insert_with(Editor::new);
+        // an interrupted prompt is answered with nothing, like the end of input, and when
+        //    stdin isn't a terminal rustyline leaves the line ending in place
+        editor.readline(prompt).ok().map(|line| line.trim_end_matches(&['\n', '\r'][..]).to_string())
+    }
+}
+
+/// answers given up front, for testing the interactive builtins
+#[cfg(test)]
+impl Input for std::collections::VecDeque<&'static str> {
+    fn read_line(&mut self, _prompt: &str) -> Option<String> {
+        self.pop_front().map(String::from)
+    }
+}
@@ -0,0 +1,812 @@
+use crate::eval::{Object, RuntimeError};
+use crate::term::{self, Color};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+mod context;
+mod csv;
+mod glob;
+pub mod input;
+pub use self::context::{Capabilities, Context, Host, Limits, DETERMINISTIC_SEED};
+pub mod replay;
+
+/// builtins reach the output and anything else outside the program through the context,
+///    they are only called with a number of arguments within their arity so they can index
+///    the arguments directly
+pub type BuiltinFunction = fn(&[Object], &mut dyn Context) -> Result<Object, BuiltinError>;
+
+#[derive(Debug, PartialEq)]
+pub enum BuiltinError {
+    // the builtin's name is added when the error is reported
+    ArgumentType { expected: &'static str, found: &'static str },
+    Runtime(RuntimeError),
+}
+
+impl From<RuntimeError> for BuiltinError {
+    fn from(err: RuntimeError) -> Self {
+        BuiltinError::Runtime(err)
+    }
+}
+
+fn wrong_type(expected: &'static str, argument: &Object) -> BuiltinError {
+    BuiltinError::ArgumentType { expected, found: argument.type_name() }
+}
+
+fn expect_array(argument: &Object) -> Result<&[Object], BuiltinError> {
+    match argument {
+        Object::Array(elements) => Ok(elements),
+        argument => Err(wrong_type("array", argument)),
+    }
+}
+
+fn expect_string(argument: &Object) -> Result<&str, BuiltinError> {
+    match argument {
+        Object::String(string) => Ok(string),
+        argument => Err(wrong_type("string", argument)),
+    }
+}
+
+pub struct BuiltinDef {
+    pub name: &'static str,
+    pub min_arity: usize,
+    // None for builtins which accept any number of arguments
+    pub max_arity: Option<usize>,
+    pub doc: &'static str,
+    // pure builtins always return the same output for the same input and have no side effects,
+    //    which allows the compiler to evaluate calls to them at compile time
+    pub pure: bool,
+    pub handler: BuiltinFunction,
+}
+
+impl BuiltinDef {
+    pub fn accepts(&self, argument_count: usize) -> bool {
+        argument_count >= self.min_arity && self.max_arity.is_none_or(|max| argument_count <= max)
+    }
+}
+
+impl fmt::Display for BuiltinDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.min_arity, self.max_arity) {
+            (1, Some(1)) => write!(f, "{}, takes 1 argument", self.name)?,
+            (min, Some(max)) if min == max => write!(f, "{}, takes {} arguments", self.name, min)?,
+            (min, Some(max)) => write!(f, "{}, takes {} to {} arguments", self.name, min, max)?,
+            (min, None) => write!(f, "{}, takes {} or more arguments", self.name, min)?,
+        }
+        write!(f, "\n{}", self.doc)
+    }
+}
+
+/// native functions available to every program, the vm refers to them by their index in this table
+pub const BUILTINS: &[BuiltinDef] = &[
+    BuiltinDef {
+        name: "len",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the number of characters in a string or elements in an array",
+        pure: true,
+        handler: len,
+    },
+    BuiltinDef {
+        name: "first",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the first element of an array, or null if it is empty",
+        pure: true,
+        handler: first,
+    },
+    BuiltinDef {
+        name: "last",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the last element of an array, or null if it is empty",
+        pure: true,
+        handler: last,
+    },
+    BuiltinDef {
+        name: "rest",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns a new array of every element but the first, or null if it is empty",
+        pure: true,
+        handler: rest,
+    },
+    BuiltinDef {
+        name: "push",
+        min_arity: 2,
+        max_arity: Some(2),
+        doc: "returns a new array with the element added to the end",
+        pure: true,
+        handler: push,
+    },
+    BuiltinDef {
+        name: "puts",
+        min_arity: 0,
+        max_arity: None,
+        doc: "prints each argument on its own line and returns null",
+        pure: false,
+        handler: puts,
+    },
+    BuiltinDef {
+        name: "str",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "converts an integer, boolean, or string to a string",
+        pure: true,
+        handler: str,
+    },
+    BuiltinDef {
+        name: "memory_stats",
+        min_arity: 0,
+        max_arity: Some(0),
+        doc: "returns an array of [kind, count, bytes] for each kind of object the program has allocated",
+        pure: false,
+        handler: memory_stats,
+    },
+    BuiltinDef {
+        name: "csv_parse",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the rows of csv text as an array of arrays of strings",
+        pure: true,
+        handler: csv_parse,
+    },
+    BuiltinDef {
+        name: "csv_stringify",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns an array of rows as csv text, each row an array of strings, numbers, or booleans",
+        pure: true,
+        handler: csv_stringify,
+    },
+    BuiltinDef {
+        name: "path_join",
+        min_arity: 1,
+        max_arity: None,
+        doc: "joins paths with the platform's separator, an absolute path replaces those before it",
+        pure: true,
+        handler: path_join,
+    },
+    BuiltinDef {
+        name: "basename",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the last component of a path, or null if it has none such as for `/`",
+        pure: true,
+        handler: basename,
+    },
+    BuiltinDef {
+        name: "dirname",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns a path without its last component, or null if it has none such as for `/`",
+        pure: true,
+        handler: dirname,
+    },
+    BuiltinDef {
+        name: "extension",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the extension of a path without the dot, or null if it has none",
+        pure: true,
+        handler: extension,
+    },
+    BuiltinDef {
+        name: "glob",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the sorted paths matching a pattern, where `*` and `?` match within a component and `**` matches any number of directories",
+        pure: false,
+        handler: glob,
+    },
+    BuiltinDef {
+        name: "prompt",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "shows the message and returns the line the user answers with, or null at the end of input",
+        pure: false,
+        handler: prompt,
+    },
+    BuiltinDef {
+        name: "confirm",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "asks a yes or no question, returning true for yes and false for no or at the end of input",
+        pure: false,
+        handler: confirm,
+    },
+    BuiltinDef {
+        name: "select",
+        min_arity: 2,
+        max_arity: Some(2),
+        doc: "lists the options under the message and returns the one the user picks by number, or null at the end of input",
+        pure: false,
+        handler: select,
+    },
+    BuiltinDef {
+        name: "color",
+        min_arity: 2,
+        max_arity: Some(2),
+        doc: "returns the value as a string drawn in the color when printed, such as \"red\", left plain when the output isn't a terminal",
+        pure: false,
+        handler: color,
+    },
+    BuiltinDef {
+        name: "bold",
+        min_arity: 1,
+        max_arity: Some(1),
+        doc: "returns the value as a string drawn in bold when printed, left plain when the output isn't a terminal",
+        pure: false,
+        handler: bold,
+    },
+    BuiltinDef {
+        name: "clear_screen",
+        min_arity: 0,
+        max_arity: Some(0),
+        doc: "clears the terminal and returns null, doing nothing when the output isn't a terminal",
+        pure: false,
+        handler: clear_screen,
+    },
+    BuiltinDef {
+        name: "clock",
+        min_arity: 0,
+        max_arity: Some(0),
+        doc: "returns the seconds since the unix epoch as a float",
+        pure: false,
+        handler: clock,
+    },
+    BuiltinDef {
+        name: "random",
+        min_arity: 0,
+        max_arity: Some(1),
+        doc: "returns a float from 0 up to but not including 1, or given n an integer from 0 up to but not including n",
+        pure: false,
+        handler: random,
+    },
+];
+
+pub fn lookup(name: &str) -> Option<usize> {
+    BUILTINS.iter().position(|builtin| builtin.name == name)
+}
+
+pub fn name(index: usize) -> &'static str {
+    BUILTINS[index].name
+}
+
+pub fn call(index: usize, arguments: &[Object], context: &mut dyn Context) -> Result<Object, RuntimeError> {
+    let builtin = &BUILTINS[index];
+    if !builtin.accepts(arguments.len()) {
+        return Err(RuntimeError::BuiltinArgumentCount {
+            name: builtin.name,
+            min: builtin.min_arity,
+            max: builtin.max_arity,
+            found: arguments.len(),
+        });
+    }
+
+    let result = (builtin.handler)(arguments, context).map_err(|err| match err {
+        BuiltinError::ArgumentType { expected, found } =>
+            RuntimeError::BuiltinArgumentType { name: builtin.name, expected, found },
+        BuiltinError::Runtime(err) => err,
+    })?;
+    // such as push growing an array past the limit
+    context.check_size(&result)?;
+
+    Ok(result)
+}
+
+pub fn is_pure(index: usize) -> bool {
+    BUILTINS[index].pure
+}
+
+fn len(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    match &arguments[0] {
+        Object::String(string) => Ok(Object::Integer(string.len() as i32)),
+        Object::Array(elements) => Ok(Object::Integer(elements.len() as i32)),
+        argument => Err(wrong_type("string or array", argument)),
+    }
+}
+
+fn first(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    Ok(expect_array(&arguments[0])?.first().cloned().unwrap_or(Object::Null))
+}
+
+fn last(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    Ok(expect_array(&arguments[0])?.last().cloned().unwrap_or(Object::Null))
+}
+
+fn rest(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    match expect_array(&arguments[0])? {
+        [] => Ok(Object::Null),
+        [_, rest @ ..] => Ok(Object::Array(Rc::new(rest.to_vec()))),
+    }
+}
+
+// arrays are immutable, so push returns a new array leaving the original untouched
+fn push(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let mut elements = expect_array(&arguments[0])?.to_vec();
+    elements.push(arguments[1].clone());
+
+    Ok(Object::Array(Rc::new(elements)))
+}
+
+fn puts(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let output = context.output()?;
+    // like print, a failure to write the output is not an error in the program
+    for argument in arguments {
+        let _ = writeln!(output, "{}", argument);
+    }
+
+    Ok(Object::Null)
+}
+
+fn str(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    match &arguments[0] {
+        Object::Integer(num) => Ok(Object::String(num.to_string().into())),
+        Object::Boolean(val) => Ok(Object::String(val.to_string().into())),
+        Object::String(string) => Ok(Object::String(string.clone())),
+        argument => Err(wrong_type("integer, boolean, or string", argument)),
+    }
+}
+
+fn memory_stats(_arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let kinds = context.host().heap().by_kind().into_iter()
+        .map(|(kind, allocations)| Object::Array(Rc::new(vec![
+            Object::String(kind.into()),
+            Object::Integer(allocations.count as i32),
+            Object::Integer(allocations.bytes as i32),
+        ])))
+        .collect();
+
+    Ok(Object::Array(Rc::new(kinds)))
+}
+
+fn csv_parse(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let rows = csv::parse(expect_string(&arguments[0])?)
+        .map_err(|err| RuntimeError::InvalidCsv { line: err.line, reason: err.reason })?
+        .into_iter()
+        .map(|row| Object::Array(Rc::new(row.into_iter().map(|field| Object::String(field.into())).collect())))
+        .collect();
+
+    Ok(Object::Array(Rc::new(rows)))
+}
+
+fn csv_stringify(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let rows = expect_array(&arguments[0])?.iter()
+        .map(|row| expect_array(row)?.iter()
+            .map(|field| match field {
+                Object::String(string) => Ok(string.to_string()),
+                Object::Integer(_) | Object::Float(_) | Object::Boolean(_) => Ok(field.to_string()),
+                Object::Null => Ok(String::new()),
+                field => Err(wrong_type("string, number, boolean, or null", field)),
+            })
+            .collect::<Result<Vec<_>, _>>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Object::String(csv::stringify(&rows).into()))
+}
+
+// paths are only taken apart and put together as strings, the file system is never read
+fn path_join(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let path = arguments.iter()
+        .map(expect_string)
+        .collect::<Result<PathBuf, _>>()?;
+
+    Ok(path_object(&path))
+}
+
+fn basename(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    Ok(Path::new(expect_string(&arguments[0])?).file_name().map_or(Object::Null, |name| path_object(name.as_ref())))
+}
+
+fn dirname(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    Ok(Path::new(expect_string(&arguments[0])?).parent().map_or(Object::Null, path_object))
+}
+
+fn extension(arguments: &[Object], _context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    Ok(Path::new(expect_string(&arguments[0])?).extension().map_or(Object::Null, |extension| path_object(extension.as_ref())))
+}
+
+fn glob(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let paths = context.glob(expect_string(&arguments[0])?)?;
+
+    Ok(Object::Array(Rc::new(paths.iter().map(|path| path_object(path)).collect())))
+}
+
+// paths built from strings are always valid utf-8
+fn path_object(path: &Path) -> Object {
+    Object::String(path.to_string_lossy().into())
+}
+
+fn prompt(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let answer = context.read_line(expect_string(&arguments[0])?)?;
+
+    Ok(answer.map_or(Object::Null, |answer| Object::String(answer.into())))
+}
+
+// the question is asked again until it is answered with yes or no
+fn confirm(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let prompt = format!("{} [y/n] ", expect_string(&arguments[0])?);
+    while let Some(answer) = context.read_line(&prompt)? {
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(Object::Boolean(true)),
+            "n" | "no" => return Ok(Object::Boolean(false)),
+            _ => {},
+        }
+    }
+
+    Ok(Object::Boolean(false))
+}
+
+// the user is asked again until they answer with the number of one of the options
+fn select(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let message = expect_string(&arguments[0])?;
+    let options = expect_array(&arguments[1])?;
+    if options.is_empty() {
+        return Ok(Object::Null);
+    }
+
+    let output = context.output()?;
+    // like print, a failure to write the output is not an error in the program
+    let _ = writeln!(output, "{}", message);
+    for (index, option) in options.iter().enumerate() {
+        let _ = writeln!(output, "  {}) {}", index + 1, option);
+    }
+
+    let prompt = format!("choose 1-{}: ", options.len());
+    while let Some(answer) = context.read_line(&prompt)? {
+        let choice = answer.trim().parse::<usize>().ok()
+            .and_then(|choice| choice.checked_sub(1))
+            .and_then(|index| options.get(index));
+        if let Some(choice) = choice {
+            return Ok(choice.clone());
+        }
+    }
+
+    Ok(Object::Null)
+}
+
+// styled text is only worth checking for whether the output is a terminal after the arguments,
+//    so a script gets the same errors wherever its output goes
+fn color(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let name = expect_string(&arguments[1])?;
+    let color = Color::parse(name).ok_or_else(|| RuntimeError::UnknownColor(name.to_string()))?;
+    let text = arguments[0].to_string();
+
+    Ok(Object::String(if context.host().color() { color.paint(&text) } else { text }.into()))
+}
+
+fn clock(_arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let since_epoch = context.now()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    Ok(Object::Float(since_epoch.as_secs_f64()))
+}
+
+fn random(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let bound = match arguments.first() {
+        Some(Object::Integer(bound)) if *bound > 0 => Some(*bound as u64),
+        Some(argument) => return Err(wrong_type("positive integer", argument)),
+        None => None,
+    };
+    let random = context.random()?;
+
+    Ok(match bound {
+        Some(bound) => Object::Integer((random % bound) as i32),
+        // the top 53 bits, as many as a float holds exactly
+        None => Object::Float((random >> 11) as f64 / (1u64 << 53) as f64),
+    })
+}
+
+fn bold(arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let text = arguments[0].to_string();
+
+    Ok(Object::String(if context.host().color() { term::bold(&text) } else { text }.into()))
+}
+
+fn clear_screen(_arguments: &[Object], context: &mut dyn Context) -> Result<Object, BuiltinError> {
+    let color = context.host().color();
+    let output = context.output()?;
+    if color {
+        // clears the screen and moves the cursor to the top left, a failure to write is not
+        //    an error in the program like print
+        let _ = write!(output, "\x1b[2J\x1b[H");
+        let _ = output.flush();
+    }
+
+    Ok(Object::Null)
+}
+
+/// an output sink which can still be read after it has been handed to the env or vm,
+///    for capturing the output of a program in tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct CapturedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl CapturedOutput {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::Write::write(&mut *self.0.borrow_mut(), buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::context::Capabilities;
+    use std::collections::VecDeque;
+    use std::fs;
+
+    fn call_builtin(name: &str, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+        call(lookup(name).unwrap(), &arguments, &mut Host::new(Box::new(std::io::sink())))
+    }
+
+    fn array(elements: &[i32]) -> Object {
+        Object::Array(Rc::new(elements.iter().map(|num| Object::Integer(*num)).collect()))
+    }
+
+    #[test]
+    fn builtin_lookup() {
+        assert_eq!(Some(0), lookup("len"));
+        assert_eq!("puts", name(lookup("puts").unwrap()));
+        assert_eq!(None, lookup("nope"));
+    }
+
+    #[test]
+    fn builtin_arrays() {
+        assert_eq!(Ok(Object::Integer(3)), call_builtin("len", vec![array(&[1, 2, 3])]));
+        assert_eq!(Ok(Object::Integer(1)), call_builtin("first", vec![array(&[1, 2, 3])]));
+        assert_eq!(Ok(Object::Null), call_builtin("first", vec![array(&[])]));
+        assert_eq!(Ok(Object::Integer(3)), call_builtin("last", vec![array(&[1, 2, 3])]));
+        assert_eq!(Ok(array(&[2, 3])), call_builtin("rest", vec![array(&[1, 2, 3])]));
+        assert_eq!(Ok(Object::Null), call_builtin("rest", vec![array(&[])]));
+        assert_eq!(Ok(array(&[1, 2])), call_builtin("push", vec![array(&[1]), Object::Integer(2)]));
+    }
+
+    #[test]
+    fn builtin_puts() {
+        let output = CapturedOutput::default();
+        let mut host = Host::new(Box::new(output.clone()));
+        let arguments = vec![Object::String("hello".into()), array(&[1, 2])];
+
+        assert_eq!(Ok(Object::Null), call(lookup("puts").unwrap(), &arguments, &mut host));
+        assert_eq!("hello\n[1, 2]\n", output.contents());
+
+        let mut host = Host::new(Box::new(output.clone()))
+            .with_capabilities(Capabilities { output: false, ..Capabilities::default() });
+        assert_eq!(Err(RuntimeError::CapabilityDenied("output")), call(lookup("puts").unwrap(), &arguments, &mut host));
+        assert_eq!("hello\n[1, 2]\n", output.contents());
+    }
+
+    #[test]
+    fn builtin_csv() {
+        let string = |string: &str| Object::String(string.into());
+        let rows = Object::Array(Rc::new(vec![
+            Object::Array(Rc::new(vec![string("name"), string("note")])),
+            Object::Array(Rc::new(vec![string("a"), string("x, y")])),
+        ]));
+        assert_eq!(Ok(rows.clone()), call_builtin("csv_parse", vec![string("name,note\na,\"x, y\"\n")]));
+        assert_eq!(Ok(string("name,note\na,\"x, y\"\n")), call_builtin("csv_stringify", vec![rows]));
+
+        let mixed = Object::Array(Rc::new(vec![
+            Object::Array(Rc::new(vec![Object::Integer(1), Object::Float(2.5), Object::Boolean(true), Object::Null])),
+        ]));
+        assert_eq!(Ok(string("1,2.5,true,\n")), call_builtin("csv_stringify", vec![mixed]));
+
+        assert_eq!(
+            "runtime error: invalid csv on line 1, a quoted field which is never closed",
+            call_builtin("csv_parse", vec![string("\"a")]).unwrap_err().to_string()
+        );
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentType { name: "csv_stringify", expected: "array", found: "string" }),
+            call_builtin("csv_stringify", vec![Object::Array(Rc::new(vec![string("a,b")]))])
+        );
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentType {
+                name: "csv_stringify",
+                expected: "string, number, boolean, or null",
+                found: "array",
+            }),
+            call_builtin("csv_stringify", vec![Object::Array(Rc::new(vec![array(&[]), Object::Array(Rc::new(vec![array(&[1])]))]))])
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn builtin_paths() {
+        let string = |string: &str| Object::String(string.into());
+        let path = |name: &str, argument: &str| call_builtin(name, vec![string(argument)]);
+
+        assert_eq!(Ok(string("a/b/c.txt")), call_builtin("path_join", vec![string("a"), string("b/"), string("c.txt")]));
+        assert_eq!(Ok(string("/etc/hosts")), call_builtin("path_join", vec![string("a"), string("/etc"), string("hosts")]));
+        assert_eq!(Ok(string("a")), call_builtin("path_join", vec![string("a")]));
+
+        assert_eq!(Ok(string("c.txt")), path("basename", "a/b/c.txt"));
+        assert_eq!(Ok(string("b")), path("basename", "a/b/"));
+        assert_eq!(Ok(Object::Null), path("basename", "/"));
+
+        assert_eq!(Ok(string("a/b")), path("dirname", "a/b/c.txt"));
+        assert_eq!(Ok(string("")), path("dirname", "c.txt"));
+        assert_eq!(Ok(Object::Null), path("dirname", "/"));
+
+        assert_eq!(Ok(string("gz")), path("extension", "archive.tar.gz"));
+        assert_eq!(Ok(Object::Null), path("extension", "Makefile"));
+        assert_eq!(Ok(Object::Null), path("extension", ".bashrc"));
+
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentType { name: "path_join", expected: "string", found: "integer" }),
+            call_builtin("path_join", vec![string("a"), Object::Integer(1)])
+        );
+    }
+
+    #[test]
+    fn builtin_glob() {
+        let dir = std::env::temp_dir().join(format!("monkey-builtin-glob-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "").unwrap();
+        let pattern = Object::String(dir.join("*.txt").to_string_lossy().into());
+
+        assert_eq!(
+            Ok(Object::Array(Rc::new(vec![Object::String(dir.join("a.txt").to_string_lossy().into())]))),
+            call_builtin("glob", vec![pattern.clone()])
+        );
+
+        let mut host = Host::new(Box::new(std::io::sink()))
+            .with_capabilities(Capabilities { fs: false, ..Capabilities::default() });
+        assert_eq!(Err(RuntimeError::CapabilityDenied("fs")), call(lookup("glob").unwrap(), &[pattern], &mut host));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn builtin_prompts() {
+        let output = CapturedOutput::default();
+        let answers = ["monkey", "maybe", "YES", "", "n", "0", "two", "2"];
+        let mut host = Host::new(Box::new(output.clone())).with_input(Box::new(answers.iter().copied().collect::<VecDeque<_>>()));
+        let mut call = |name: &str, arguments: Vec<Object>| call(lookup(name).unwrap(), &arguments, &mut host);
+        let string = |string: &str| Object::String(string.into());
+
+        assert_eq!(Ok(string("monkey")), call("prompt", vec![string("name: ")]));
+        // questions are asked again until they are answered
+        assert_eq!(Ok(Object::Boolean(true)), call("confirm", vec![string("continue?")]));
+        assert_eq!(Ok(Object::Boolean(false)), call("confirm", vec![string("continue?")]));
+        let options = Object::Array(Rc::new(vec![string("red"), string("green")]));
+        assert_eq!(Ok(string("green")), call("select", vec![string("colour?"), options.clone()]));
+        assert_eq!("colour?\n  1) red\n  2) green\n", output.contents());
+
+        // the answers have run out
+        assert_eq!(Ok(Object::Null), call("prompt", vec![string("name: ")]));
+        assert_eq!(Ok(Object::Boolean(false)), call("confirm", vec![string("continue?")]));
+        assert_eq!(Ok(Object::Null), call("select", vec![string("colour?"), options]));
+        assert_eq!(Ok(Object::Null), call("select", vec![string("colour?"), array(&[])]));
+
+        let mut host = Host::new(Box::new(std::io::sink()))
+            .with_input(Box::new(VecDeque::from(vec!["y"])))
+            .with_capabilities(Capabilities { input: false, ..Capabilities::default() });
+        assert_eq!(
+            Err(RuntimeError::CapabilityDenied("input")),
+            super::call(lookup("confirm").unwrap(), &[string("continue?")], &mut host)
+        );
+    }
+
+    #[test]
+    fn builtin_terminal_styles() {
+        let string = |string: &str| Object::String(string.into());
+        let output = CapturedOutput::default();
+        let mut plain = Host::new(Box::new(output.clone()));
+        let mut styled = Host::new(Box::new(output.clone())).with_color(true);
+        let call = |host: &mut Host, name: &str, arguments: Vec<Object>| call(lookup(name).unwrap(), &arguments, host);
+
+        assert_eq!(Ok(string("hi")), call(&mut plain, "color", vec![string("hi"), string("red")]));
+        assert_eq!(Ok(string("\x1b[31mhi\x1b[0m")), call(&mut styled, "color", vec![string("hi"), string("red")]));
+        assert_eq!(Ok(string("\x1b[36m[1, 2]\x1b[0m")), call(&mut styled, "color", vec![array(&[1, 2]), string("cyan")]));
+        assert_eq!(Ok(string("5")), call(&mut plain, "bold", vec![Object::Integer(5)]));
+        assert_eq!(Ok(string("\x1b[1m5\x1b[0m")), call(&mut styled, "bold", vec![Object::Integer(5)]));
+
+        assert_eq!(Ok(Object::Null), call(&mut plain, "clear_screen", vec![]));
+        assert_eq!("", output.contents());
+        assert_eq!(Ok(Object::Null), call(&mut styled, "clear_screen", vec![]));
+        assert_eq!("\x1b[2J\x1b[H", output.contents());
+
+        // an unknown color is an error whether or not the output is a terminal
+        assert_eq!(
+            "runtime error: unknown color `pink`, expected red, green, yellow, blue, magenta, or cyan",
+            call(&mut plain, "color", vec![string("hi"), string("pink")]).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn builtin_clock_and_random() {
+        let mut host = Host::new(Box::new(std::io::sink())).with_seed(DETERMINISTIC_SEED).with_recording();
+        let mut call = |name: &str, arguments: Vec<Object>| call(lookup(name).unwrap(), &arguments, &mut host);
+
+        let time = call("clock", vec![]).unwrap();
+        assert!(matches!(time, Object::Float(seconds) if seconds > 1_600_000_000.0));
+        let fraction = call("random", vec![]).unwrap();
+        assert!(matches!(fraction, Object::Float(fraction) if (0.0..1.0).contains(&fraction)));
+        for _ in 0..20 {
+            assert!(matches!(call("random", vec![Object::Integer(3)]), Ok(Object::Integer(0..=2))));
+        }
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentType { name: "random", expected: "positive integer", found: "integer" }),
+            call("random", vec![Object::Integer(0)])
+        );
+
+        // both are read through the host, so a recorded run gives the same values when replayed
+        let log = host.recorded().unwrap()[..2].to_vec();
+        let mut host = Host::new(Box::new(std::io::sink())).with_replay(log);
+        let mut call = |name: &str| super::call(lookup(name).unwrap(), &[], &mut host);
+        assert_eq!(Ok(time), call("clock"));
+        assert_eq!(Ok(fraction), call("random"));
+        assert_eq!(Err(RuntimeError::ReplayDiverged("random")), call("random"));
+    }
+
+    #[test]
+    fn builtin_defs() {
+        for builtin in BUILTINS {
+            // every builtin is callable with its minimum number of arguments, and the handler
+            //    never sees a call with too few or too many
+            assert!(builtin.accepts(builtin.min_arity), "{}", builtin.name);
+            assert!(!builtin.doc.is_empty(), "{}", builtin.name);
+        }
+        assert!(!BUILTINS[lookup("push").unwrap()].accepts(3));
+        assert!(BUILTINS[lookup("puts").unwrap()].accepts(100));
+        assert_eq!(vec!["puts", "memory_stats", "glob", "prompt", "confirm", "select", "color", "bold", "clear_screen", "clock", "random"], BUILTINS.iter().filter(|builtin| !builtin.pure).map(|builtin| builtin.name).collect::<Vec<_>>());
+
+        assert_eq!(
+            "push, takes 2 arguments\nreturns a new array with the element added to the end",
+            BUILTINS[lookup("push").unwrap()].to_string()
+        );
+        assert_eq!(
+            "len, takes 1 argument",
+            BUILTINS[lookup("len").unwrap()].to_string().lines().next().unwrap()
+        );
+        assert_eq!(
+            "puts, takes 0 or more arguments\nprints each argument on its own line and returns null",
+            BUILTINS[lookup("puts").unwrap()].to_string()
+        );
+    }
+
+    #[test]
+    fn builtin_invalid_arguments() {
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentType { name: "len", expected: "string or array", found: "integer" }),
+            call_builtin("len", vec![Object::Integer(1)])
+        );
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentType { name: "push", expected: "array", found: "boolean" }),
+            call_builtin("push", vec![Object::Boolean(true), array(&[1])])
+        );
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentCount { name: "first", min: 1, max: Some(1), found: 2 }),
+            call_builtin("first", vec![array(&[1]), array(&[1])])
+        );
+        assert_eq!(
+            Err(RuntimeError::BuiltinArgumentCount { name: "str", min: 1, max: Some(1), found: 0 }),
+            call_builtin("str", vec![])
+        );
+    }
+
+    #[test]
+    fn builtin_error_messages() {
+        assert_eq!(
+            "runtime error: wrong number of arguments to `len`: got 2, want 1",
+            call_builtin("len", vec![array(&[]), array(&[])]).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "runtime error: argument to `first` must be array, got integer",
+            call_builtin("first", vec![Object::Integer(1)]).unwrap_err().to_string()
+        );
+    }
+}
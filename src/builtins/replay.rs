@@ -0,0 +1,177 @@
+// Records the values a program reads from outside itself, such as the clock, so a run can be
+// reproduced exactly by feeding the same values back. The log is a line per value, such as
+// `clock 1700000000000000000`, `random 42`, or `input "yes"`, in the order the program read them.
+
+use crate::eval::RuntimeError;
+use std::collections::VecDeque;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    // nanoseconds since the unix epoch
+    Clock(u64),
+    Random(u64),
+    // a line the user answered a prompt with, None at the end of input
+    Input(Option<String>),
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Clock(_) => "clock",
+            Event::Random(_) => "random",
+            Event::Input(_) => "input",
+        }
+    }
+}
+
+pub enum Replay {
+    // values are read as normal, and added to the log
+    Record(Vec<Event>),
+    // values are taken from the log rather than read
+    Replay(VecDeque<Event>),
+}
+
+impl Replay {
+    /// returns the value which was read, or is taken from the log in place of reading it
+    pub fn observe(&mut self, kind: &'static str, read: impl FnOnce() -> Event) -> Result<Event, RuntimeError> {
+        match self {
+            Replay::Record(log) => {
+                let event = read();
+                log.push(event.clone());
+                Ok(event)
+            },
+            // the program has read something other than it did when the log was recorded,
+            //    so it can no longer be reproduced
+            Replay::Replay(log) => match log.pop_front() {
+                Some(event) if event.kind() == kind => Ok(event),
+                _ => Err(RuntimeError::ReplayDiverged(kind)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct InvalidLogLine {
+    pub line: usize,
+}
+
+impl fmt::Display for InvalidLogLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid replay log entry on line {}", self.line)
+    }
+}
+
+pub fn format_log(log: &[Event]) -> String {
+    log.iter()
+        .map(|event| match event {
+            Event::Clock(value) | Event::Random(value) => format!("{} {}\n", event.kind(), value),
+            Event::Input(Some(answer)) => format!("input {}\n", quote(answer)),
+            Event::Input(None) => String::from("input end\n"),
+        })
+        .collect()
+}
+
+// answers are quoted with the line endings escaped, so each stays on its own line of the log
+fn quote(answer: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in answer.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+fn unquote(quoted: &str) -> Option<String> {
+    let mut chars = quoted.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut answer = String::new();
+    while let Some(c) = chars.next() {
+        answer.push(match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            },
+            '"' => return None,
+            c => c,
+        });
+    }
+
+    Some(answer)
+}
+
+pub fn parse_log(input: &str) -> Result<Vec<Event>, InvalidLogLine> {
+    input.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let invalid = InvalidLogLine { line: index + 1 };
+            if let Some(answer) = line.trim().strip_prefix("input ") {
+                return match answer.trim() {
+                    "end" => Ok(Event::Input(None)),
+                    answer => unquote(answer).map(|answer| Event::Input(Some(answer))).ok_or(invalid),
+                };
+            }
+            let mut parts = line.split_whitespace();
+            let kind = parts.next().ok_or(invalid)?;
+            let value = parts.next().and_then(|value| value.parse().ok()).ok_or(invalid)?;
+            match (kind, parts.next()) {
+                ("clock", None) => Ok(Event::Clock(value)),
+                ("random", None) => Ok(Event::Random(value)),
+                _ => Err(invalid),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_round_trip() {
+        let log = vec![Event::Clock(1_700_000_000_000_000_000), Event::Random(42)];
+
+        assert_eq!("clock 1700000000000000000\nrandom 42\n", format_log(&log));
+        assert_eq!(Ok(log.clone()), parse_log(&format_log(&log)));
+        assert_eq!(Err(InvalidLogLine { line: 2 }), parse_log("random 1\nrandom\n"));
+        assert_eq!(Err(InvalidLogLine { line: 1 }), parse_log("dice 4\n"));
+    }
+
+    #[test]
+    fn log_input() {
+        let log = vec![
+            Event::Input(Some(String::from("yes"))),
+            Event::Input(Some(String::from("say \"hi\"\\n\tback\\slash\r\n"))),
+            Event::Input(Some(String::new())),
+            Event::Input(None),
+        ];
+
+        assert_eq!(
+            "input \"yes\"\ninput \"say \\\"hi\\\"\\\\n\\tback\\\\slash\\r\\n\"\ninput \"\"\ninput end\n",
+            format_log(&log)
+        );
+        assert_eq!(Ok(log.clone()), parse_log(&format_log(&log)));
+        assert_eq!(Err(InvalidLogLine { line: 1 }), parse_log("input yes\n"));
+        assert_eq!(Err(InvalidLogLine { line: 1 }), parse_log("input \"a\"b\"\n"));
+        assert_eq!(Err(InvalidLogLine { line: 1 }), parse_log("input \"\\q\"\n"));
+    }
+
+    #[test]
+    fn replay_diverged() {
+        let mut replay = Replay::Replay(vec![Event::Random(7)].into());
+
+        assert_eq!(Err(RuntimeError::ReplayDiverged("clock")), replay.observe("clock", || Event::Clock(0)));
+        assert_eq!(Err(RuntimeError::ReplayDiverged("random")), replay.observe("random", || Event::Random(0)));
+    }
+}
@@ -0,0 +1,302 @@
+// Caches compiled programs on disk, so running the same script again skips parsing and
+// compiling it. Entries are keyed by a hash of the source and a fingerprint of the compiler which
+// wrote them, so a changed script or a new compiler never reads a stale entry. An entry which
+// can't be read back is treated as missing rather than as an error.
+
+use crate::compiler::{compile_from_source_optimized, ByteCode, CompileError};
+use crate::eval::{HashKey, Object};
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const MAGIC: &[u8; 4] = b"MKYC";
+// bumped whenever the layout of an entry changes
+const FORMAT_VERSION: u8 = 3;
+// a hash of the interpreter's source made by build.rs, which changes with any change to the
+//    compiler, the opcodes, or the builtins, not only with a new release
+const COMPILER_FINGERPRINT: &str = env!("MONKEY_COMPILER_FINGERPRINT");
+
+const TAG_NULL: u8 = 0;
+const TAG_INTEGER: u8 = 1;
+const TAG_FLOAT: u8 = 2;
+const TAG_BOOLEAN: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_ARRAY: u8 = 5;
+const TAG_COMPILED_FUNCTION: u8 = 6;
+const TAG_UNINITIALIZED: u8 = 7;
+const TAG_HASH: u8 = 8;
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// a cache storing its entries in the directory, which is created when the first entry is
+    ///    written
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cache { dir: dir.into() }
+    }
+
+    /// the compiled source, read from the cache when it holds an entry for it, otherwise
+    ///    compiled with the optimizer and written to the cache for next time
+    pub fn compile(&self, source: &str) -> Result<ByteCode, CompileError> {
+        if let Some(byte_code) = self.get(source) {
+            return Ok(byte_code);
+        }
+
+        let byte_code = compile_from_source_optimized(source)?;
+        // failing to write the cache only costs the next run a compile
+        let _ = self.put(source, &byte_code);
+
+        Ok(byte_code)
+    }
+
+    pub fn get(&self, source: &str) -> Option<ByteCode> {
+        decode(&fs::read(self.path(source)).ok()?)
+    }
+
+    /// writes an entry for the source, skipping programs holding constants which can't be
+    ///    written out
+    pub fn put(&self, source: &str, byte_code: &ByteCode) -> io::Result<()> {
+        let bytes = match encode(byte_code) {
+            Some(bytes) => bytes,
+            None => return Ok(()),
+        };
+        fs::create_dir_all(&self.dir)?;
+        // written alongside and then renamed, so a run reading the entry never sees it half written
+        let path = self.path(source);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(partial, path)
+    }
+
+    fn path(&self, source: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.mkc", key(source)))
+    }
+}
+
+// a 64 bit FNV-1a hash of the compiler fingerprint and the source, which unlike the standard
+//    library's hasher is the same across builds
+fn key(source: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in COMPILER_FINGERPRINT.bytes().chain(Some(0)).chain(source.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+fn encode(byte_code: &ByteCode) -> Option<Vec<u8>> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(FORMAT_VERSION);
+    write_bytes(&mut bytes, &byte_code.instructions);
+    write_number(&mut bytes, byte_code.constants.len());
+    for constant in &byte_code.constants {
+        write_object(&mut bytes, constant)?;
+    }
+    write_number(&mut bytes, byte_code.globals.len());
+    for name in &byte_code.globals {
+        write_bytes(&mut bytes, name.as_bytes());
+    }
+
+    Some(bytes)
+}
+
+fn write_number(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_number(bytes, data.len());
+    bytes.extend_from_slice(data);
+}
+
+// None for objects which only exist while a program runs, such as natives
+fn write_object(bytes: &mut Vec<u8>, object: &Object) -> Option<()> {
+    match object {
+        Object::Null => bytes.push(TAG_NULL),
+        Object::Integer(value) => {
+            bytes.push(TAG_INTEGER);
+            bytes.extend_from_slice(&value.to_le_bytes());
+        },
+        Object::Float(value) => {
+            bytes.push(TAG_FLOAT);
+            bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+        },
+        Object::Boolean(value) => {
+            bytes.push(TAG_BOOLEAN);
+            bytes.push(*value as u8);
+        },
+        Object::String(value) => {
+            bytes.push(TAG_STRING);
+            write_bytes(bytes, value.as_bytes());
+        },
+        Object::Array(elements) => {
+            bytes.push(TAG_ARRAY);
+            write_number(bytes, elements.len());
+            for element in elements.iter() {
+                write_object(bytes, element)?;
+            }
+        },
+        Object::Hash(pairs) => {
+            bytes.push(TAG_HASH);
+            write_number(bytes, pairs.len());
+            for (key, value) in pairs.iter() {
+                write_object(bytes, &key.to_object())?;
+                write_object(bytes, value)?;
+            }
+        },
+        Object::CompiledFunction { instructions, num_locals, num_parameters } => {
+            bytes.push(TAG_COMPILED_FUNCTION);
+            write_bytes(bytes, instructions);
+            write_number(bytes, *num_locals);
+            write_number(bytes, *num_parameters);
+        },
+        Object::Uninitialized(name) => {
+            bytes.push(TAG_UNINITIALIZED);
+            write_bytes(bytes, name.as_bytes());
+        },
+        _ => return None,
+    }
+
+    Some(())
+}
+
+// reads an entry, None if it is truncated, corrupt, or written by another format version
+fn decode(bytes: &[u8]) -> Option<ByteCode> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC || reader.take(1)? != [FORMAT_VERSION] {
+        return None;
+    }
+    let instructions = reader.bytes()?.to_vec();
+    let constants = (0..reader.len()?)
+        .map(|_| reader.object())
+        .collect::<Option<Vec<_>>>()?;
+    let globals = (0..reader.len()?)
+        .map(|_| Some(std::str::from_utf8(reader.bytes()?).ok()?.to_string()))
+        .collect::<Option<Vec<_>>>()?;
+    if !reader.bytes.is_empty() {
+        return None;
+    }
+
+    Some(ByteCode { instructions, constants, globals })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Option<&'a [u8]> {
+        if count > self.bytes.len() {
+            return None;
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+
+        Some(taken)
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        u64::from_le_bytes(self.take(8)?.try_into().ok()?).try_into().ok()
+    }
+
+    // the length of what follows, in bytes or in objects which each take at least a byte
+    fn len(&mut self) -> Option<usize> {
+        let len = self.number()?;
+        // a length longer than the rest of the entry can only come from corruption, and is
+        //    rejected before anything is allocated for it
+        if len > self.bytes.len() {
+            return None;
+        }
+
+        Some(len)
+    }
+
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn object(&mut self) -> Option<Object> {
+        Some(match self.take(1)?[0] {
+            TAG_NULL => Object::Null,
+            TAG_INTEGER => Object::Integer(i32::from_le_bytes(self.take(4)?.try_into().ok()?)),
+            TAG_FLOAT => Object::Float(f64::from_bits(u64::from_le_bytes(self.take(8)?.try_into().ok()?))),
+            TAG_BOOLEAN => Object::Boolean(self.take(1)?[0] != 0),
+            TAG_STRING => Object::String(std::str::from_utf8(self.bytes()?).ok()?.into()),
+            TAG_ARRAY => {
+                let elements = (0..self.len()?)
+                    .map(|_| self.object())
+                    .collect::<Option<Vec<_>>>()?;
+                Object::Array(Rc::new(elements))
+            },
+            TAG_HASH => {
+                let pairs = (0..self.len()?)
+                    .map(|_| Some((HashKey::from_object(&self.object()?).ok()?, self.object()?)))
+                    .collect::<Option<_>>()?;
+                Object::Hash(Rc::new(pairs))
+            },
+            TAG_COMPILED_FUNCTION => Object::CompiledFunction {
+                instructions: Rc::new(self.bytes()?.to_vec()),
+                num_locals: self.number()?,
+                num_parameters: self.number()?,
+            },
+            TAG_UNINITIALIZED => Object::Uninitialized(std::str::from_utf8(self.bytes()?).ok()?.into()),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let byte_code = ByteCode {
+            instructions: vec![0, 0, 1, 2],
+            constants: vec![
+                Object::Null,
+                Object::Integer(-7),
+                Object::Float(2.5),
+                Object::Boolean(true),
+                Object::String("monkey".into()),
+                Object::Array(Rc::new(vec![Object::Integer(1), Object::String("a".into())])),
+                Object::CompiledFunction { instructions: Rc::new(vec![3, 4]), num_locals: 2, num_parameters: 1 },
+                Object::Uninitialized("x".into()),
+                Object::Hash(Rc::new(vec![
+                    (HashKey::Integer(1), Object::Null),
+                    (HashKey::String("a".into()), Object::Array(Rc::new(vec![Object::Boolean(false)]))),
+                ].into_iter().collect())),
+            ],
+            globals: vec![String::from("add"), String::from("main")],
+        };
+        let bytes = encode(&byte_code).unwrap();
+
+        assert_eq!(Some(byte_code), decode(&bytes));
+        // a truncated or corrupt entry reads as missing
+        assert_eq!(None, decode(&bytes[..bytes.len() - 1]));
+        assert_eq!(None, decode(b"MKYC\x03\xff\xff\xff\xff\xff\xff\xff\xff"));
+        assert_eq!(None, encode(&ByteCode { instructions: vec![], constants: vec![Object::Builtin(0)], globals: vec![] }));
+    }
+
+    #[test]
+    fn cache_hit() {
+        let dir = std::env::temp_dir().join(format!("monkey-cache-test-{}", std::process::id()));
+        let cache = Cache::new(&dir);
+        let source = "let add = fn(a, b) { a + b; }; add(1, 2);";
+
+        assert_eq!(None, cache.get(source));
+        let compiled = cache.compile(source).unwrap();
+        assert_eq!(compile_from_source_optimized(source).unwrap(), compiled);
+        assert_eq!(Some(compiled), cache.get(source));
+        assert_eq!(None, cache.get("1;"));
+        assert_ne!(key(source), key("1;"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
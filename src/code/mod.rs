@@ -1,3 +1,8 @@
+use crate::eval::Object;
+use crate::builtins;
+
+#[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::enum_variant_names)]
 pub enum OpCode {
     OpConstant(u16), // args: pointer to constant table
     OpPop,
@@ -16,6 +21,20 @@ pub enum OpCode {
     OpJump(u16), // args: byte address to jump to
     OpSetGlobal(u16), // args: id of global
     OpGetGlobal(u16), // args: id of global
+    OpGetBuiltin(u8), // args: index into the builtins table
+    OpCall(u8), // args: number of arguments, which sit on the stack above the function
+    OpReturnValue, // returns the value on top of the stack from the current function
+    OpReturn, // returns null from the current function
+    OpGetLocal(u8), // args: id of local
+    OpSetLocal(u8), // args: id of local
+    OpNull,
+    OpArray(u16), // args: number of elements, which sit on the stack in order
+    OpIndex, // indexes the value below the top of the stack by the value on top
+    OpInterpolate(u16), // args: number of pieces of an interpolated string, which sit on the stack in order
+    OpGreaterEqual,
+    OpAndOperand, // checks the value on top of the stack is a boolean, as `&&` requires
+    OpOrOperand, // checks the value on top of the stack is a boolean, as `||` requires
+    OpHash(u16), // args: number of keys and values, which sit on the stack in pairs in order
 }
 
 fn convert_u16_to_two_u8s_be(integer: u16) -> [u8; 2] {
@@ -26,6 +45,10 @@ pub fn convert_two_u8s_be_to_usize(int1: u8, int2: u8) -> usize {
     ((int1 as usize) << 8) | int2 as usize
 }
 
+fn make_two_byte_op(code: u8, data: u8) -> Vec<u8> {
+    vec![code, data]
+}
+
 fn make_three_byte_op(code: u8, data: u16) -> Vec<u8> {
     let mut output = vec![code];
     output.extend(&convert_u16_to_two_u8s_be(data));
@@ -52,7 +75,114 @@ pub fn make_op(op: OpCode) -> Vec<u8> {
         OpCode::OpJump(address) => make_three_byte_op(0x0F, address),
         OpCode::OpSetGlobal(global_id) => make_three_byte_op(0x10, global_id),
         OpCode::OpGetGlobal(global_id) => make_three_byte_op(0x11, global_id),
+        OpCode::OpGetBuiltin(builtin_index) => make_two_byte_op(0x12, builtin_index),
+        OpCode::OpCall(argument_count) => make_two_byte_op(0x13, argument_count),
+        OpCode::OpReturnValue => vec![0x14],
+        OpCode::OpReturn => vec![0x15],
+        OpCode::OpGetLocal(local_id) => make_two_byte_op(0x16, local_id),
+        OpCode::OpSetLocal(local_id) => make_two_byte_op(0x17, local_id),
+        OpCode::OpNull => vec![0x18],
+        OpCode::OpArray(element_count) => make_three_byte_op(0x19, element_count),
+        OpCode::OpIndex => vec![0x1A],
+        OpCode::OpInterpolate(part_count) => make_three_byte_op(0x1B, part_count),
+        OpCode::OpGreaterEqual => vec![0x1C],
+        OpCode::OpAndOperand => vec![0x1D],
+        OpCode::OpOrOperand => vec![0x1E],
+        OpCode::OpHash(value_count) => make_three_byte_op(0x1F, value_count),
+    }
+}
+
+/// decodes the instruction at the given address, returning it along with its width in bytes
+pub fn read_op(instructions: &[u8], address: usize) -> (OpCode, usize) {
+    let u8_arg = || instructions[address + 1];
+    let u16_arg = || convert_two_u8s_be_to_usize(instructions[address + 1], instructions[address + 2]) as u16;
+
+    let op = match instructions[address] {
+        0x01 => OpCode::OpConstant(u16_arg()),
+        0x02 => OpCode::OpPop,
+        0x03 => OpCode::OpAdd,
+        0x04 => OpCode::OpSub,
+        0x05 => OpCode::OpMul,
+        0x06 => OpCode::OpDiv,
+        0x07 => OpCode::OpTrue,
+        0x08 => OpCode::OpFalse,
+        0x09 => OpCode::OpEquals,
+        0x0A => OpCode::OpNotEquals,
+        0x0B => OpCode::OpGreaterThan,
+        0x0C => OpCode::OpMinus,
+        0x0D => OpCode::OpBang,
+        0x0E => OpCode::OpJumpNotTrue(u16_arg()),
+        0x0F => OpCode::OpJump(u16_arg()),
+        0x10 => OpCode::OpSetGlobal(u16_arg()),
+        0x11 => OpCode::OpGetGlobal(u16_arg()),
+        0x12 => OpCode::OpGetBuiltin(u8_arg()),
+        0x13 => OpCode::OpCall(u8_arg()),
+        0x14 => OpCode::OpReturnValue,
+        0x15 => OpCode::OpReturn,
+        0x16 => OpCode::OpGetLocal(u8_arg()),
+        0x17 => OpCode::OpSetLocal(u8_arg()),
+        0x18 => OpCode::OpNull,
+        0x19 => OpCode::OpArray(u16_arg()),
+        0x1A => OpCode::OpIndex,
+        0x1B => OpCode::OpInterpolate(u16_arg()),
+        0x1C => OpCode::OpGreaterEqual,
+        0x1D => OpCode::OpAndOperand,
+        0x1E => OpCode::OpOrOperand,
+        0x1F => OpCode::OpHash(u16_arg()),
+        code => panic!("unknown opcode {:#04x} at {}", code, address),
+    };
+    let width = make_op(op.clone()).len();
+
+    (op, width)
+}
+
+fn operand(op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::OpConstant(arg)
+            | OpCode::OpJumpNotTrue(arg)
+            | OpCode::OpJump(arg)
+            | OpCode::OpSetGlobal(arg)
+            | OpCode::OpGetGlobal(arg)
+            | OpCode::OpArray(arg)
+            | OpCode::OpHash(arg)
+            | OpCode::OpInterpolate(arg) => Some(*arg as usize),
+        OpCode::OpGetBuiltin(arg)
+            | OpCode::OpCall(arg)
+            | OpCode::OpGetLocal(arg)
+            | OpCode::OpSetLocal(arg) => Some(*arg as usize),
+        _ => None,
+    }
+}
+
+/// formats each instruction on its own line with its byte address and operand, along with
+///    the constant or builtin the operand refers to, such as `0004 OpConstant 0 (10)`
+pub fn disassemble(instructions: &[u8], constants: &[Object]) -> String {
+    let mut output = String::new();
+    let mut address = 0;
+
+    while address < instructions.len() {
+        let (op, width) = read_op(instructions, address);
+
+        let debug = format!("{:?}", op);
+        let name = debug.split('(').next().unwrap_or(&debug);
+        output.push_str(&format!("{:04} {}", address, name));
+        if let Some(operand) = operand(&op) {
+            output.push_str(&format!(" {}", operand));
+        }
+        match op {
+            OpCode::OpConstant(index) => match &constants[index as usize] {
+                Object::String(string) => output.push_str(&format!(" ({:?})", string)),
+                constant => output.push_str(&format!(" ({})", constant)),
+            },
+            OpCode::OpGetBuiltin(index) => output.push_str(&format!(" ({})", builtins::name(index as usize))),
+            _ => {},
+        }
+        output.push('\n');
+
+        address += width;
     }
+
+    output
 }
 
 #[cfg(test)]
@@ -82,4 +212,67 @@ mod tests {
             make_op(OpCode::OpAdd)
         );
     }
+
+    #[test]
+    fn make_op_call() {
+        assert_eq!(
+            vec![0x13, 2],
+            make_op(OpCode::OpCall(2))
+        );
+    }
+
+    #[test]
+    fn read_op_round_trips() {
+        let ops = vec![
+            OpCode::OpConstant(65534),
+            OpCode::OpJumpNotTrue(3),
+            OpCode::OpGetBuiltin(5),
+            OpCode::OpSetLocal(1),
+            OpCode::OpNull,
+            OpCode::OpArray(300),
+            OpCode::OpIndex,
+            OpCode::OpInterpolate(2),
+            OpCode::OpGreaterEqual,
+            OpCode::OpAndOperand,
+            OpCode::OpOrOperand,
+            OpCode::OpHash(4),
+        ];
+        let instructions = ops.iter().cloned().flat_map(make_op).collect::<Vec<u8>>();
+
+        let mut address = 0;
+        for op in ops {
+            let (decoded, width) = read_op(&instructions, address);
+            assert_eq!(op, decoded);
+            address += width;
+        }
+        assert_eq!(instructions.len(), address);
+    }
+
+    #[test]
+    fn disassemble_instructions() {
+        let instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpConstant(1),
+            OpCode::OpAdd,
+            OpCode::OpJumpNotTrue(13),
+            OpCode::OpGetBuiltin(0),
+            OpCode::OpCall(0),
+            OpCode::OpPop,
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect::<Vec<u8>>();
+        let constants = vec![Object::Integer(10), Object::String("a".into())];
+
+        assert_eq!(
+            "0000 OpConstant 0 (10)\n\
+             0003 OpConstant 1 (\"a\")\n\
+             0006 OpAdd\n\
+             0007 OpJumpNotTrue 13\n\
+             0010 OpGetBuiltin 0 (len)\n\
+             0012 OpCall 0\n\
+             0014 OpPop\n",
+            disassemble(&instructions, &constants)
+        );
+    }
 }
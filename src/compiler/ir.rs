@@ -0,0 +1,518 @@
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+use crate::code::{make_op, read_op, OpCode};
+use crate::eval::Object;
+
+// The compiler emits a flat list of `OpCode`s which is only assembled into bytes once
+//   compilation is complete. While in this form, jump operands hold the index of the target
+//   instruction in the list rather than a byte address, so the optimization passes below can
+//   rewrite and remove instructions without tracking the width of each one.
+
+pub fn assemble(instructions: &[OpCode]) -> Vec<u8> {
+    let mut addresses = Vec::with_capacity(instructions.len() + 1);
+    let mut address = 0;
+    for op in instructions {
+        addresses.push(address);
+        address += make_op(op.clone()).len();
+    }
+    // jumps to the end of the program target the position just past the last instruction
+    addresses.push(address);
+
+    instructions.iter()
+        .map(|op| match jump_target(op) {
+            Some(target) => with_jump_target(op, addresses[target]),
+            None => op.clone(),
+        })
+        .flat_map(make_op)
+        .collect()
+}
+
+pub fn optimize(mut instructions: Vec<OpCode>, constants: &mut Vec<Object>) -> Vec<OpCode> {
+    // each pass can expose more work for the others, so run them until nothing changes
+    loop {
+        let before = instructions.clone();
+
+        instructions = fold_constants(instructions, constants);
+        instructions = thread_jumps(instructions);
+        instructions = eliminate_dead_code(instructions);
+
+        if instructions == before {
+            return instructions;
+        }
+    }
+}
+
+fn jump_target(op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::OpJumpNotTrue(target) | OpCode::OpJump(target) => Some(*target as usize),
+        _ => None,
+    }
+}
+
+pub(super) fn with_jump_target(op: &OpCode, target: usize) -> OpCode {
+    match op {
+        OpCode::OpJumpNotTrue(_) => OpCode::OpJumpNotTrue(target as u16),
+        OpCode::OpJump(_) => OpCode::OpJump(target as u16),
+        _ => panic!("attempted to set the jump target of a non-jump instruction"),
+    }
+}
+
+fn jump_targets(instructions: &[OpCode]) -> HashSet<usize> {
+    instructions.iter().filter_map(jump_target).collect()
+}
+
+/// removes the instructions set to `None`, re-pointing any jump to a removed instruction at
+/// the next remaining instruction
+fn compact(instructions: Vec<Option<OpCode>>) -> Vec<OpCode> {
+    let mut new_positions = Vec::with_capacity(instructions.len() + 1);
+    let mut next_position = 0;
+    for op in &instructions {
+        new_positions.push(next_position);
+        if op.is_some() {
+            next_position += 1;
+        }
+    }
+    new_positions.push(next_position);
+
+    instructions.into_iter()
+        .flatten()
+        .map(|op| match jump_target(&op) {
+            Some(target) => with_jump_target(&op, new_positions[target]),
+            None => op,
+        })
+        .collect()
+}
+
+fn constant_value(op: &OpCode, constants: &[Object]) -> Option<Object> {
+    match op {
+        OpCode::OpConstant(index) => Some(constants[*index as usize].clone()),
+        OpCode::OpTrue => Some(Object::Boolean(true)),
+        OpCode::OpFalse => Some(Object::Boolean(false)),
+        OpCode::OpNull => Some(Object::Null),
+        _ => None,
+    }
+}
+
+fn load_constant(obj: Object, constants: &mut Vec<Object>) -> OpCode {
+    match obj {
+        Object::Boolean(true) => OpCode::OpTrue,
+        Object::Boolean(false) => OpCode::OpFalse,
+        Object::Null => OpCode::OpNull,
+        obj => {
+            constants.push(obj);
+            OpCode::OpConstant((constants.len() - 1) as u16)
+        },
+    }
+}
+
+fn fold_prefix(op: &OpCode, value: Object) -> Option<Object> {
+    match (op, value) {
+        (OpCode::OpMinus, Object::Integer(num)) => num.checked_neg().map(Object::Integer),
+        (OpCode::OpBang, Object::Boolean(val)) => Some(Object::Boolean(!val)),
+        _ => None,
+    }
+}
+
+fn fold_infix(op: &OpCode, left: Object, right: Object) -> Option<Object> {
+    // operations which would fail at runtime are left for the vm to report
+    match (op, left, right) {
+        (OpCode::OpAdd, Object::Integer(left), Object::Integer(right)) => left.checked_add(right).map(Object::Integer),
+        (OpCode::OpAdd, Object::String(left), Object::String(right)) => Some(Object::String((left.to_string() + &right).into())),
+        (OpCode::OpSub, Object::Integer(left), Object::Integer(right)) => left.checked_sub(right).map(Object::Integer),
+        (OpCode::OpMul, Object::Integer(left), Object::Integer(right)) => left.checked_mul(right).map(Object::Integer),
+        (OpCode::OpDiv, Object::Integer(left), Object::Integer(right)) => left.checked_div(right).map(Object::Integer),
+        (OpCode::OpGreaterThan, Object::Integer(left), Object::Integer(right)) => Some(Object::Boolean(left > right)),
+        (OpCode::OpGreaterEqual, Object::Integer(left), Object::Integer(right)) => Some(Object::Boolean(left >= right)),
+        (OpCode::OpEquals, Object::Integer(left), Object::Integer(right)) => Some(Object::Boolean(left == right)),
+        (OpCode::OpEquals, Object::Boolean(left), Object::Boolean(right)) => Some(Object::Boolean(left == right)),
+        (OpCode::OpNotEquals, Object::Integer(left), Object::Integer(right)) => Some(Object::Boolean(left != right)),
+        (OpCode::OpNotEquals, Object::Boolean(left), Object::Boolean(right)) => Some(Object::Boolean(left != right)),
+        (OpCode::OpEquals, Object::Null, other) | (OpCode::OpEquals, other, Object::Null) => Some(Object::Boolean(other == Object::Null)),
+        (OpCode::OpNotEquals, Object::Null, other) | (OpCode::OpNotEquals, other, Object::Null) => Some(Object::Boolean(other != Object::Null)),
+        _ => None,
+    }
+}
+
+/// replaces operators whose operands are all constants with the constant result
+fn fold_constants(instructions: Vec<OpCode>, constants: &mut Vec<Object>) -> Vec<OpCode> {
+    // an instruction which is the target of a jump can't be folded into the instruction
+    //    before it, because the value pushed before the jump is not known
+    let targets = jump_targets(&instructions);
+    let mut instructions : Vec<Option<OpCode>> = instructions.into_iter().map(Some).collect();
+
+    let mut i = 0;
+    while i + 1 < instructions.len() {
+        let operand = instructions[i].as_ref().and_then(|op| constant_value(op, constants));
+        let operand = match operand {
+            Some(operand) => operand,
+            None => {
+                i += 1;
+                continue;
+            },
+        };
+
+        if !targets.contains(&(i + 1)) {
+            let folded = instructions[i + 1].as_ref().and_then(|op| fold_prefix(op, operand.clone()));
+            if let Some(obj) = folded {
+                instructions[i] = Some(load_constant(obj, constants));
+                instructions[i + 1] = None;
+                i += 2;
+                continue;
+            }
+        }
+
+        if i + 2 < instructions.len() && !targets.contains(&(i + 1)) && !targets.contains(&(i + 2)) {
+            let right = instructions[i + 1].as_ref().and_then(|op| constant_value(op, constants));
+            let folded = match (right, &instructions[i + 2]) {
+                (Some(right), Some(op)) => fold_infix(op, operand, right),
+                _ => None,
+            };
+            if let Some(obj) = folded {
+                instructions[i] = Some(load_constant(obj, constants));
+                instructions[i + 1] = None;
+                instructions[i + 2] = None;
+                i += 3;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    compact(instructions)
+}
+
+/// points jumps which land on an unconditional jump directly at its target, and removes
+///    unconditional jumps to the very next instruction
+fn thread_jumps(mut instructions: Vec<OpCode>) -> Vec<OpCode> {
+    for i in 0..instructions.len() {
+        let mut target = match jump_target(&instructions[i]) {
+            Some(target) => target,
+            None => continue,
+        };
+
+        // bound the number of hops so a jump cycle can't loop forever
+        let mut hops = 0;
+        while let Some(OpCode::OpJump(next_target)) = instructions.get(target) {
+            if hops == instructions.len() {
+                break;
+            }
+            target = *next_target as usize;
+            hops += 1;
+        }
+
+        instructions[i] = with_jump_target(&instructions[i], target);
+    }
+
+    let instructions = instructions.into_iter()
+        .enumerate()
+        .map(|(i, op)| match op {
+            OpCode::OpJump(target) if target as usize == i + 1 => None,
+            op => Some(op),
+        })
+        .collect();
+
+    compact(instructions)
+}
+
+/// resolves conditional jumps on constant conditions and removes unreachable instructions
+fn eliminate_dead_code(instructions: Vec<OpCode>) -> Vec<OpCode> {
+    let targets = jump_targets(&instructions);
+    let mut instructions : Vec<Option<OpCode>> = instructions.into_iter().map(Some).collect();
+
+    for i in 0..instructions.len().saturating_sub(1) {
+        if targets.contains(&(i + 1)) {
+            continue;
+        }
+        match (&instructions[i], &instructions[i + 1]) {
+            (Some(OpCode::OpTrue), Some(OpCode::OpJumpNotTrue(_))) => {
+                instructions[i] = None;
+                instructions[i + 1] = None;
+            },
+            (Some(OpCode::OpFalse), Some(OpCode::OpJumpNotTrue(target))) => {
+                instructions[i + 1] = Some(OpCode::OpJump(*target));
+                instructions[i] = None;
+            },
+            // a boolean constant always passes the check on an operand of `&&` or `||`
+            (Some(OpCode::OpTrue | OpCode::OpFalse), Some(OpCode::OpAndOperand | OpCode::OpOrOperand)) => {
+                instructions[i + 1] = None;
+            },
+            _ => {},
+        }
+    }
+
+    let mut reachable = vec![false; instructions.len()];
+    let mut to_visit = vec![0];
+    while let Some(position) = to_visit.pop() {
+        if position >= instructions.len() || reachable[position] {
+            continue;
+        }
+        reachable[position] = true;
+
+        match &instructions[position] {
+            Some(OpCode::OpJump(target)) => to_visit.push(*target as usize),
+            // nothing after a return is reached by falling through
+            Some(OpCode::OpReturnValue) | Some(OpCode::OpReturn) => {},
+            Some(OpCode::OpJumpNotTrue(target)) => {
+                to_visit.push(*target as usize);
+                to_visit.push(position + 1);
+            },
+            _ => to_visit.push(position + 1),
+        }
+    }
+
+    let instructions = instructions.into_iter()
+        .zip(reachable)
+        .map(|(op, reachable)| if reachable { op } else { None })
+        .collect();
+
+    compact(instructions)
+}
+
+/// drops the constants which nothing loads, such as the operands of folded expressions, and
+///    renumbers the loads of those which are kept, both in the instructions and in the bodies of
+///    the compiled functions among the constants
+pub fn remove_unused_constants(instructions: &mut [OpCode], constants: &mut Vec<Object>) {
+    // a constant is used when it is loaded by the instructions, or by a function which is used
+    let mut used = vec![false; constants.len()];
+    let mut to_visit = instructions.iter().filter_map(constant_index).collect::<Vec<_>>();
+    while let Some(index) = to_visit.pop() {
+        if mem::replace(&mut used[index], true) {
+            continue;
+        }
+        if let Object::CompiledFunction { instructions, .. } = &constants[index] {
+            to_visit.extend(assembled_ops(instructions).filter_map(|op| constant_index(&op)));
+        }
+    }
+
+    let mut new_indices = Vec::with_capacity(constants.len());
+    let mut next_index = 0;
+    for used in &used {
+        new_indices.push(next_index);
+        if *used {
+            next_index += 1;
+        }
+    }
+    let renumber = |op: OpCode| match op {
+        OpCode::OpConstant(index) => OpCode::OpConstant(new_indices[index as usize]),
+        op => op,
+    };
+
+    for op in instructions.iter_mut() {
+        *op = renumber(op.clone());
+    }
+    *constants = mem::take(constants).into_iter()
+        .zip(used)
+        .filter(|(_, used)| *used)
+        .map(|(constant, _)| match constant {
+            // every load is as wide as before, so the jumps in the body still land where they did
+            Object::CompiledFunction { instructions, num_locals, num_parameters } => Object::CompiledFunction {
+                instructions: Rc::new(assembled_ops(&instructions).map(renumber).flat_map(make_op).collect()),
+                num_locals,
+                num_parameters,
+            },
+            constant => constant,
+        })
+        .collect();
+}
+
+fn constant_index(op: &OpCode) -> Option<usize> {
+    match op {
+        OpCode::OpConstant(index) => Some(*index as usize),
+        _ => None,
+    }
+}
+
+// the instructions of a body which has already been assembled
+fn assembled_ops(instructions: &[u8]) -> impl Iterator<Item = OpCode> + '_ {
+    let mut address = 0;
+    std::iter::from_fn(move || {
+        if address >= instructions.len() {
+            return None;
+        }
+        let (op, width) = read_op(instructions, address);
+        address += width;
+        Some(op)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_converts_jump_targets_to_addresses() {
+        let instructions = vec![
+            OpCode::OpTrue,
+            OpCode::OpJumpNotTrue(4),
+            OpCode::OpConstant(0),
+            OpCode::OpJump(5),
+            OpCode::OpConstant(1),
+            OpCode::OpPop,
+        ];
+
+        assert_eq!(
+            vec![
+                OpCode::OpTrue, // 0000
+                OpCode::OpJumpNotTrue(10), // 0001
+                OpCode::OpConstant(0), // 0004
+                OpCode::OpJump(13), // 0007
+                OpCode::OpConstant(1), // 0010
+                OpCode::OpPop, // 0013
+            ]
+                .into_iter()
+                .flat_map(make_op)
+                .collect::<Vec<u8>>(),
+            assemble(&instructions)
+        );
+    }
+
+    #[test]
+    fn optimize_folds_prefix_and_infix() {
+        let mut constants = vec![Object::Integer(2), Object::Integer(3)];
+        let instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpMinus,
+            OpCode::OpConstant(1),
+            OpCode::OpMul,
+            OpCode::OpPop,
+            OpCode::OpTrue,
+            OpCode::OpBang,
+            OpCode::OpPop,
+        ];
+
+        assert_eq!(
+            vec![
+                OpCode::OpConstant(3),
+                OpCode::OpPop,
+                OpCode::OpFalse,
+                OpCode::OpPop,
+            ],
+            optimize(instructions, &mut constants)
+        );
+        assert_eq!(Object::Integer(-6), constants[3]);
+    }
+
+    #[test]
+    fn optimize_does_not_fold_division_by_zero() {
+        let mut constants = vec![Object::Integer(1), Object::Integer(0)];
+        let instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpConstant(1),
+            OpCode::OpDiv,
+            OpCode::OpPop,
+        ];
+
+        assert_eq!(instructions.clone(), optimize(instructions, &mut constants));
+    }
+
+    #[test]
+    fn optimize_does_not_fold_across_jump_target() {
+        let mut constants = vec![Object::Integer(1)];
+        // the OpMinus may be reached with a different value on the stack
+        let instructions = vec![
+            OpCode::OpJumpNotTrue(2),
+            OpCode::OpConstant(0),
+            OpCode::OpMinus,
+            OpCode::OpPop,
+        ];
+
+        assert_eq!(instructions.clone(), optimize(instructions, &mut constants));
+    }
+
+    #[test]
+    fn optimize_threads_jumps() {
+        let mut constants = vec![Object::Integer(1)];
+        let instructions = vec![
+            OpCode::OpJumpNotTrue(3),
+            OpCode::OpConstant(0),
+            OpCode::OpPop,
+            OpCode::OpJump(5),
+            OpCode::OpConstant(0),
+            OpCode::OpPop,
+        ];
+
+        assert_eq!(
+            vec![
+                OpCode::OpJumpNotTrue(3),
+                OpCode::OpConstant(0),
+                OpCode::OpPop,
+                OpCode::OpPop,
+            ],
+            optimize(instructions, &mut constants)
+        );
+    }
+
+    #[test]
+    fn optimize_removes_constant_condition() {
+        let mut constants = vec![Object::Integer(10), Object::Integer(20)];
+        let instructions = vec![
+            OpCode::OpTrue,
+            OpCode::OpJumpNotTrue(4),
+            OpCode::OpConstant(0),
+            OpCode::OpJump(5),
+            OpCode::OpConstant(1),
+            OpCode::OpPop,
+        ];
+
+        assert_eq!(
+            vec![
+                OpCode::OpConstant(0),
+                OpCode::OpPop,
+            ],
+            optimize(instructions, &mut constants)
+        );
+    }
+
+    #[test]
+    fn optimize_removes_constant_logical_operand() {
+        let mut constants = vec![];
+        // `false || true;`
+        let instructions = vec![
+            OpCode::OpFalse,
+            OpCode::OpOrOperand,
+            OpCode::OpJumpNotTrue(5),
+            OpCode::OpTrue,
+            OpCode::OpJump(7),
+            OpCode::OpTrue,
+            OpCode::OpOrOperand,
+            OpCode::OpPop,
+        ];
+
+        assert_eq!(
+            vec![
+                OpCode::OpTrue,
+                OpCode::OpPop,
+            ],
+            optimize(instructions, &mut constants)
+        );
+    }
+
+    #[test]
+    fn remove_unused_constants_renumbers_loads() {
+        let function = Object::CompiledFunction {
+            instructions: Rc::new([OpCode::OpConstant(3), OpCode::OpReturnValue].iter().cloned().flat_map(make_op).collect()),
+            num_locals: 0,
+            num_parameters: 0,
+        };
+        let mut constants = vec![Object::Integer(1), Object::Integer(2), function, Object::Integer(4), Object::Integer(5)];
+        let mut instructions = vec![OpCode::OpConstant(4), OpCode::OpPop, OpCode::OpConstant(2), OpCode::OpPop];
+        remove_unused_constants(&mut instructions, &mut constants);
+
+        assert_eq!(vec![OpCode::OpConstant(2), OpCode::OpPop, OpCode::OpConstant(0), OpCode::OpPop], instructions);
+        // the constant only loaded by the function is kept, and the function loads it by its new index
+        assert_eq!(
+            vec![
+                Object::CompiledFunction {
+                    instructions: Rc::new([OpCode::OpConstant(1), OpCode::OpReturnValue].iter().cloned().flat_map(make_op).collect()),
+                    num_locals: 0,
+                    num_parameters: 0,
+                },
+                Object::Integer(4),
+                Object::Integer(5),
+            ],
+            constants
+        );
+    }
+}
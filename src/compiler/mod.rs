@@ -1,82 +1,217 @@
-use crate::eval::Object;
-use crate::parser::{Statement, Expr, parse};
-use crate::code::{make_op, OpCode};
-use crate::lexer::lex;
+use crate::eval::{self, Object};
+use crate::builtins::{self, Host};
+use crate::parser::{Statement, Expr, ParseError, parse};
+use crate::desugar::{desugar, desugar_expr};
+use crate::code::OpCode;
+use crate::lexer::{lex, LexError};
 use crate::parser::Operator;
 use crate::parser::Prefix;
-use crate::compiler::symbol_table::SymbolTable;
+use std::fmt;
+use std::rc::Rc;
 
 mod symbol_table;
+pub use self::symbol_table::{Symbol, SymbolScope, SymbolTable};
+mod ir;
 
 #[derive(Debug, PartialEq)]
 pub struct ByteCode {
     pub instructions: Vec<u8>,
-    pub constants: Vec<Object>
+    pub constants: Vec<Object>,
+    // the names of the globals by index, so the vm can name a global which is read before it
+    //    is set and find the `main` function a script is run through, see VM::run_main
+    pub globals: Vec<String>,
 }
 
-impl ByteCode {
-    fn new() -> Self {
-        ByteCode {
-            instructions: Vec::new(),
-            constants: Vec::new(),
+#[derive(Debug, PartialEq, Clone)]
+pub enum CompileError {
+    Lex(LexError),
+    Parse(ParseError),
+    UndefinedVariable(String),
+    UndefinedAssignment(String),
+    // a language feature which the compiler does not support yet
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Lex(err) => write!(f, "{}", err),
+            CompileError::Parse(err) => write!(f, "{}", err),
+            CompileError::UndefinedVariable(name) => write!(f, "compile error: `{}` is not defined", name),
+            CompileError::UndefinedAssignment(name) => write!(f, "compile error: cannot assign to `{}` which is not defined", name),
+            CompileError::Unsupported(feature) => write!(f, "compile error: {} are not supported by the compiler yet", feature),
         }
     }
 }
 
-struct Compiler {
-    byte_code: ByteCode,
+impl From<LexError> for CompileError {
+    fn from(err: LexError) -> Self {
+        CompileError::Lex(err)
+    }
+}
+
+impl From<ParseError> for CompileError {
+    fn from(err: ParseError) -> Self {
+        CompileError::Parse(err)
+    }
+}
+
+// the position of a jump emitted before its target was known, which must be patched
+#[must_use]
+struct PendingJump(usize);
+
+pub struct Compiler {
+    // instructions are kept in their IR form until compilation is complete, see the ir module
+    instructions: Vec<OpCode>,
+    constants: Vec<Object>,
     symbol_table: SymbolTable,
+    optimize: bool,
 }
 
 impl Compiler {
-    fn compile_from_source(input: &str) -> ByteCode {
-        let mut compiler = Compiler {
-            byte_code: ByteCode::new(),
+    fn new(optimize: bool) -> Self {
+        Compiler {
+            instructions: Vec::new(),
+            constants: Vec::new(),
             symbol_table: SymbolTable::new(),
-        };
+            optimize,
+        }
+    }
+
+    /// a compiler which continues from the symbol table and constants of an earlier one, so
+    ///    each line of the repl can use the globals defined by the lines before it
+    pub fn new_with_state(symbol_table: SymbolTable, constants: Vec<Object>) -> Self {
+        Compiler {
+            symbol_table,
+            constants,
+            ..Compiler::new(false)
+        }
+    }
+
+    /// the state to continue from when compiling the next line
+    pub fn into_state(self) -> (SymbolTable, Vec<Object>) {
+        (self.symbol_table, self.constants)
+    }
+
+    /// compiles a line of the repl, which can refer to the globals defined by earlier lines,
+    ///    a line which fails to compile leaves the compiler as it was before the line
+    pub fn compile_line(&mut self, input: &str) -> Result<ByteCode, CompileError> {
+        let symbol_table = self.symbol_table.clone();
+        let constant_count = self.constants.len();
+        self.instructions.clear();
+
+        if let Err(err) = self.compile_source(input) {
+            self.symbol_table = symbol_table;
+            self.constants.truncate(constant_count);
+            return Err(err);
+        }
+
+        Ok(ByteCode {
+            instructions: ir::assemble(&self.instructions),
+            constants: self.constants.clone(),
+            globals: self.global_names(),
+        })
+    }
+
+    fn compile_from_source(input: &str, optimize: bool) -> Result<ByteCode, CompileError> {
+        let mut compiler = Compiler::new(optimize);
+        compiler.compile_source(input)?;
 
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
-        compiler.compile_statements(ast);
+        Ok(compiler.into_byte_code())
+    }
+
+    fn compile_source(&mut self, input: &str) -> Result<(), CompileError> {
+        let mut tokens = lex(input)?;
+        let ast = desugar(parse(&mut tokens)?);
+        self.compile_statements(ast)
+    }
+
+    fn into_byte_code(mut self) -> ByteCode {
+        if self.optimize {
+            self.instructions = ir::optimize(self.instructions, &mut self.constants);
+            ir::remove_unused_constants(&mut self.instructions, &mut self.constants);
+        }
+
+        ByteCode {
+            instructions: ir::assemble(&self.instructions),
+            globals: self.global_names(),
+            constants: self.constants,
+        }
+    }
 
-        compiler.byte_code
+    fn global_names(&self) -> Vec<String> {
+        self.symbol_table.globals().into_iter().map(|(name, _)| name.to_string()).collect()
     }
 
     fn add_constant(&mut self, obj: Object) -> u16 {
-        self.byte_code.constants.push(obj);
-        (self.byte_code.constants.len() - 1) as u16 // cast to u16 because that is the size of our constant pool index
+        self.constants.push(obj);
+        (self.constants.len() - 1) as u16 // cast to u16 because that is the size of our constant pool index
     }
 
     fn add_instruction(&mut self, op_code: OpCode) -> u16 {
-        let position_of_new_instruction = self.byte_code.instructions.len() as u16;
-        self.byte_code.instructions.extend(make_op(op_code));
+        let position_of_new_instruction = self.instructions.len() as u16;
+        self.instructions.push(op_code);
 
         position_of_new_instruction
     }
 
-    fn change_op(&mut self, position: usize, op_code: OpCode) {
-        let op_bytes = make_op(op_code);
+    /// emits a jump whose target isn't known yet, to be set by patch_jump once it is
+    fn emit_jump(&mut self, jump: fn(u16) -> OpCode) -> PendingJump {
+        PendingJump(self.add_instruction(jump(u16::MAX)) as usize)
+    }
 
-        self.byte_code.instructions.splice(position..position+op_bytes.len(), op_bytes);
+    /// points a pending jump at the next instruction to be emitted
+    fn patch_jump(&mut self, pending: PendingJump) {
+        let PendingJump(position) = pending;
+        let target = self.instructions.len();
+        self.instructions[position] = ir::with_jump_target(&self.instructions[position], target);
     }
 
-    fn compile_expression(&mut self, expr: Expr) {
+    fn compile_expression(&mut self, expr: Expr) -> Result<(), CompileError> {
         match expr {
             Expr::Const(num) => {
                 let const_index = self.add_constant(Object::Integer(num));
                 self.add_instruction(OpCode::OpConstant(const_index));
             },
+            Expr::Float(num) => {
+                let const_index = self.add_constant(Object::Float(num));
+                self.add_instruction(OpCode::OpConstant(const_index));
+            },
+            Expr::Infix { left, operator: Operator::And, right } => {
+                // `if (left) { right } else { false }`, with both operands checked to be booleans
+                self.compile_expression(*left)?;
+                self.add_instruction(OpCode::OpAndOperand);
+                let jump_to_false = self.emit_jump(OpCode::OpJumpNotTrue);
+                self.compile_expression(*right)?;
+                self.add_instruction(OpCode::OpAndOperand);
+                let jump_to_end = self.emit_jump(OpCode::OpJump);
+                self.patch_jump(jump_to_false);
+                self.add_instruction(OpCode::OpFalse);
+                self.patch_jump(jump_to_end);
+            },
+            Expr::Infix { left, operator: Operator::Or, right } => {
+                // `if (left) { true } else { right }`
+                self.compile_expression(*left)?;
+                self.add_instruction(OpCode::OpOrOperand);
+                let jump_to_right = self.emit_jump(OpCode::OpJumpNotTrue);
+                self.add_instruction(OpCode::OpTrue);
+                let jump_to_end = self.emit_jump(OpCode::OpJump);
+                self.patch_jump(jump_to_right);
+                self.compile_expression(*right)?;
+                self.add_instruction(OpCode::OpOrOperand);
+                self.patch_jump(jump_to_end);
+            },
             Expr::Infix { left, operator, right } => {
                 match &operator {
-                    Operator::LessThan => {
+                    Operator::LessThan | Operator::LessThanOrEqual => {
                         // flip left/right order so that less than statements can be re-written as greater than statements
-                        // this allows the vm to only support a greater than instruction
-                        self.compile_expression(*right);
-                        self.compile_expression(*left);
+                        // this allows the vm to only support greater than instructions
+                        self.compile_expression(*right)?;
+                        self.compile_expression(*left)?;
                     },
                     _ => {
-                        self.compile_expression(*left);
-                        self.compile_expression(*right);
+                        self.compile_expression(*left)?;
+                        self.compile_expression(*right)?;
                     }
                 }
                 match operator {
@@ -91,98 +226,311 @@ impl Compiler {
                         //    order of the operands are flipped when they are pushed on to the stack
                         self.add_instruction(OpCode::OpGreaterThan)
                     },
+                    // not `!(a > b)`, which is true rather than false when either side is NaN
+                    Operator::GreaterThanOrEqual | Operator::LessThanOrEqual => self.add_instruction(OpCode::OpGreaterEqual),
+                    Operator::And | Operator::Or => unreachable!("logical operators are compiled with jumps"),
                 };
             },
             Expr::Prefix {prefix: Prefix::Minus, value} => {
-                self.compile_expression(*value);
+                self.compile_expression(*value)?;
                 self.add_instruction(OpCode::OpMinus);
             },
             Expr::Prefix {prefix: Prefix::Bang, value} => {
-                self.compile_expression(*value);
+                self.compile_expression(*value)?;
                 self.add_instruction(OpCode::OpBang);
             },
             Expr::Boolean(true) => { self.add_instruction(OpCode::OpTrue); },
             Expr::Boolean(false) => { self.add_instruction(OpCode::OpFalse); },
+            Expr::Null => { self.add_instruction(OpCode::OpNull); },
             Expr::If {condition, consequence, alternative} => {
-                self.compile_expression(*condition);
-                let op_jump_position = self.byte_code.instructions.len();
-                self.add_instruction(OpCode::OpJumpNotTrue(9999));
-                self.compile_statements(consequence);
-                if self.last_instruction_is_pop() {
-                    self.remove_last_pop();
-                }
-                if alternative.is_empty() {
-                    self.change_op(
-                        op_jump_position,
-                        OpCode::OpJumpNotTrue(self.byte_code.instructions.len() as u16)
-                    );
-                } else {
-                    self.change_op(
-                        op_jump_position,
-                        OpCode::OpJumpNotTrue(self.byte_code.instructions.len() as u16 + 3) // plus three to account for extra jump at end of if block
-                    );
-
-                    let op_jump_position = self.byte_code.instructions.len();
-                    self.add_instruction(OpCode::OpJump(9999));
-                    self.compile_statements(alternative);
-                    if self.last_instruction_is_pop() {
-                        self.remove_last_pop();
-                    }
-                    self.change_op(
-                        op_jump_position,
-                        OpCode::OpJump(self.byte_code.instructions.len() as u16)
-                    );
+                self.compile_expression(*condition)?;
+                let jump_to_alternative = self.emit_jump(OpCode::OpJumpNotTrue);
+                self.compile_block(consequence)?;
+                let jump_to_end = self.emit_jump(OpCode::OpJump);
+
+                // an if without an else still has an alternative branch, which evaluates to null
+                self.patch_jump(jump_to_alternative);
+                self.compile_block(alternative)?;
+                self.patch_jump(jump_to_end);
+            },
+            Expr::Ident(name, _) => {
+                match (self.symbol_table.resolve(&name), builtins::lookup(&name)) {
+                    (Some(symbol), _) => {
+                        self.add_instruction(get_symbol(symbol));
+                    },
+                    (None, Some(builtin_index)) => {
+                        self.add_instruction(OpCode::OpGetBuiltin(builtin_index as u8));
+                    },
+                    (None, None) => return Err(CompileError::UndefinedVariable(name)),
                 }
             },
-            Expr::Ident(name) => {
-                match self.symbol_table.resolve(&name) {
-                    None => panic!("attempted to use undefined variable"),
-                    Some(index) => {
-                        self.add_instruction(OpCode::OpGetGlobal(index));
+            Expr::Call { function, arguments, .. } => {
+                match self.fold_builtin_call(&function, &arguments) {
+                    Some(obj) => {
+                        let const_index = self.add_constant(obj);
+                        self.add_instruction(OpCode::OpConstant(const_index));
+                    },
+                    None => {
+                        let argument_count = arguments.len() as u8;
+                        self.compile_expression(*function)?;
+                        for argument in arguments {
+                            self.compile_expression(argument)?;
+                        }
+                        self.add_instruction(OpCode::OpCall(argument_count));
                     },
                 }
             },
-            _ => panic!("unsupported expression"),
+            Expr::String(string) => {
+                let const_index = self.add_constant(Object::String(string.into()));
+                self.add_instruction(OpCode::OpConstant(const_index));
+            },
+            Expr::Function { parameters, body } => self.compile_function(parameters, body)?,
+            // a literal made only of constants is built once here rather than every time it runs
+            expr @ Expr::Array(_) | expr @ Expr::Hash(_) if constant_object(&expr).is_some() => {
+                let const_index = self.add_constant(constant_object(&expr).unwrap());
+                self.add_instruction(OpCode::OpConstant(const_index));
+            },
+            Expr::Array(elements) => {
+                let element_count = elements.len() as u16;
+                for element in elements {
+                    self.compile_expression(element)?;
+                }
+                self.add_instruction(OpCode::OpArray(element_count));
+            },
+            Expr::Hash(pairs) => {
+                let value_count = (pairs.len() * 2) as u16;
+                for (key, value) in pairs {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                self.add_instruction(OpCode::OpHash(value_count));
+            },
+            Expr::Index { left, index } => {
+                self.compile_expression(*left)?;
+                self.compile_expression(*index)?;
+                self.add_instruction(OpCode::OpIndex);
+            },
+            Expr::Interpolation(parts) => {
+                let part_count = parts.len() as u16;
+                for part in parts {
+                    self.compile_expression(part)?;
+                }
+                self.add_instruction(OpCode::OpInterpolate(part_count));
+            },
+            // only reached when the program wasn't desugared before it was compiled
+            expr @ Expr::Ternary { .. } => self.compile_expression(desugar_expr(expr))?,
+        };
+
+        Ok(())
+    }
+
+    fn compile_function(&mut self, parameters: Vec<String>, body: Vec<Statement>) -> Result<(), CompileError> {
+        // the body is compiled on its own, since the vm runs each function from its own instructions
+        let outer_instructions = std::mem::take(&mut self.instructions);
+        self.symbol_table.push_scope();
+
+        let num_parameters = parameters.len();
+        for parameter in parameters {
+            self.symbol_table.define(parameter);
+        }
+        let result = self.compile_statements(body);
+
+        // the value of the last expression statement is returned, otherwise the function returns null
+        if self.last_instruction_is_pop() {
+            self.remove_last_pop();
+            self.add_instruction(OpCode::OpReturnValue);
+        } else if !self.last_instruction_is_return() {
+            self.add_instruction(OpCode::OpReturn);
+        }
+
+        let num_locals = self.symbol_table.pop_scope() as usize;
+        let mut body_instructions = std::mem::replace(&mut self.instructions, outer_instructions);
+        result?;
+
+        if self.optimize {
+            body_instructions = ir::optimize(body_instructions, &mut self.constants);
+        }
+        let const_index = self.add_constant(Object::CompiledFunction {
+            instructions: Rc::new(ir::assemble(&body_instructions)),
+            num_locals,
+            num_parameters,
+        });
+        self.add_instruction(OpCode::OpConstant(const_index));
+
+        Ok(())
+    }
+
+    /// evaluates a call to a pure builtin at compile time, if the optimizer is enabled
+    /// and every argument is a literal
+    fn fold_builtin_call(&self, function: &Expr, arguments: &[Expr]) -> Option<Object> {
+        if !self.optimize {
+            return None;
+        }
+
+        let builtin_index = match function {
+            // a user defined binding shadows the builtin of the same name
+            Expr::Ident(name, _) if self.symbol_table.resolve(name).is_none() => builtins::lookup(name)?,
+            _ => return None,
         };
+        if !builtins::is_pure(builtin_index) {
+            return None;
+        }
+
+        let arguments = arguments.iter().map(constant_object).collect::<Option<Vec<Object>>>()?;
+
+        // pure builtins never write any output, and invalid calls are left for the vm to report
+        builtins::call(builtin_index, &arguments, &mut Host::new(Box::new(std::io::sink()))).ok()
     }
 
     fn last_instruction_is_pop(&self) -> bool {
-        self.byte_code.instructions.last() == Some(&make_op(OpCode::OpPop)[0])
+        self.instructions.last() == Some(&OpCode::OpPop)
     }
 
     fn remove_last_pop(&mut self) {
-        self.byte_code.instructions.pop();
+        self.instructions.pop();
+    }
+
+    fn last_instruction_is_return(&self) -> bool {
+        self.instructions.last() == Some(&OpCode::OpReturnValue)
+    }
+
+    /// compiles the block of an if expression, leaving its value on the stack
+    fn compile_block(&mut self, statements: Vec<Statement>) -> Result<(), CompileError> {
+        let start = self.instructions.len();
+        self.compile_statements(statements)?;
+
+        if self.instructions.len() > start && self.last_instruction_is_pop() {
+            self.remove_last_pop();
+        } else if self.instructions.len() == start || !self.last_instruction_is_return() {
+            // blocks which end without a value evaluate to null, so that both branches
+            //    of the if expression leave one value on the stack for the statement to pop
+            self.add_instruction(OpCode::OpNull);
+        }
+
+        Ok(())
     }
 
-    fn compile_statements(&mut self, ast: Vec<Statement>) {
+    fn compile_statements(&mut self, ast: Vec<Statement>) -> Result<(), CompileError> {
         for statement in ast {
             match statement {
-                Statement::Let { name, value } => {
-                    self.compile_expression(value);
-                    let symbol_index = self.symbol_table.define(name);
-                    self.add_instruction(OpCode::OpSetGlobal(symbol_index));
+                Statement::Let { name, value: value @ Expr::Function { .. }, .. } => {
+                    // the name is defined before compiling the function so that its body can
+                    //    refer to the function itself, allowing recursion
+                    let symbol = self.symbol_table.define(name);
+                    self.compile_expression(value)?;
+                    self.add_instruction(set_symbol(symbol));
+                },
+                Statement::Let { name, value, .. } => {
+                    self.compile_expression(value)?;
+                    let symbol = self.symbol_table.define(name);
+                    self.add_instruction(set_symbol(symbol));
+                },
+                Statement::Declare { name, .. } => {
+                    // reserve the slot for the variable, holding a marker which the vm reports
+                    //    as an error if it is read before being assigned
+                    let const_index = self.add_constant(Object::Uninitialized(name.as_str().into()));
+                    self.add_instruction(OpCode::OpConstant(const_index));
+                    let symbol = self.symbol_table.define(name);
+                    self.add_instruction(set_symbol(symbol));
                 },
-                Statement::Return { .. } => unimplemented!(),
-                Statement::Expression(expr) => {
-                    self.compile_expression(expr);
+                Statement::Assign { name, value, .. } => {
+                    let symbol = match self.symbol_table.resolve(&name) {
+                        Some(symbol) => symbol,
+                        None => return Err(CompileError::UndefinedAssignment(name)),
+                    };
+                    self.compile_expression(value)?;
+                    self.add_instruction(set_symbol(symbol));
+                },
+                Statement::Return { value, .. } => {
+                    self.compile_expression(value)?;
+                    self.add_instruction(OpCode::OpReturnValue);
+                },
+                Statement::Expression(expr, _) => {
+                    self.compile_expression(expr)?;
 
                     // pop one element from the stack after each expression statement to clean up
                     self.add_instruction(OpCode::OpPop);
                 },
             }
         }
+
+        Ok(())
+    }
+
+}
+
+fn get_symbol(symbol: Symbol) -> OpCode {
+    match symbol.scope {
+        SymbolScope::Global => OpCode::OpGetGlobal(symbol.index),
+        SymbolScope::Local => OpCode::OpGetLocal(symbol.index as u8),
+    }
+}
+
+fn set_symbol(symbol: Symbol) -> OpCode {
+    match symbol.scope {
+        SymbolScope::Global => OpCode::OpSetGlobal(symbol.index),
+        SymbolScope::Local => OpCode::OpSetLocal(symbol.index as u8),
+    }
+}
+
+// helpers for inspecting the compiler state in tests, before the instructions are optimized and assembled
+#[cfg(test)]
+impl Compiler {
+    pub fn compile_for_test(input: &str) -> Self {
+        let mut compiler = Compiler::new(false);
+        compiler.compile_source(input).unwrap();
+
+        compiler
+    }
+
+    pub fn instructions(&self) -> &[OpCode] {
+        &self.instructions
+    }
+
+    pub fn constants(&self) -> &[Object] {
+        &self.constants
     }
 
+    pub fn symbol_table(&self) -> &SymbolTable {
+        &self.symbol_table
+    }
 }
 
-pub fn compile_from_source(input: &str) -> ByteCode {
+pub fn compile_from_source(input: &str) -> Result<ByteCode, CompileError> {
     // wrap compiler method to hide compiler struct from outside this module
-    Compiler::compile_from_source(input)
+    Compiler::compile_from_source(input, false)
+}
+
+pub fn compile_from_source_optimized(input: &str) -> Result<ByteCode, CompileError> {
+    Compiler::compile_from_source(input, true)
+}
+
+/// the value of a literal, or of an array or hash literal made only of literals, which the
+///    compiler can put in the constant pool
+fn constant_object(expr: &Expr) -> Option<Object> {
+    match expr {
+        Expr::Const(num) => Some(Object::Integer(*num)),
+        Expr::Float(num) => Some(Object::Float(*num)),
+        Expr::String(string) => Some(Object::String(string.as_str().into())),
+        Expr::Boolean(val) => Some(Object::Boolean(*val)),
+        Expr::Null => Some(Object::Null),
+        Expr::Array(elements) => Some(Object::Array(Rc::new(
+            elements.iter().map(constant_object).collect::<Option<_>>()?
+        ))),
+        // a hash with a key which can't be used is left for the vm to report
+        Expr::Hash(pairs) => eval::build_hash(
+            pairs.iter()
+                .map(|(key, value)| Some((constant_object(key)?, constant_object(value)?)))
+                .collect::<Option<_>>()?
+        ).ok(),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::code::{make_op, disassemble};
 
     #[test]
     fn compile_infix() {
@@ -194,7 +542,7 @@ mod tests {
 
     fn compile_infix_template(infix_str: &str, op_code: OpCode) {
         let input = format!("1 {} 2;", infix_str);
-        let byte_code = compile_from_source(&input);
+        let byte_code = compile_from_source(&input).unwrap();
 
         let expected_instructions = vec![
             OpCode::OpConstant(0),
@@ -209,24 +557,140 @@ mod tests {
         assert_eq!(
             ByteCode {
                 instructions: expected_instructions,
-                constants: vec![Object::Integer(1), Object::Integer(2)]
+                constants: vec![Object::Integer(1), Object::Integer(2)],
+                globals: vec![],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_float() {
+        let byte_code = compile_from_source("1.5 + 2;").unwrap();
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpConstant(1),
+            OpCode::OpAdd,
+            OpCode::OpPop
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Float(1.5), Object::Integer(2)],
+                globals: vec![],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_less_greater_equal() {
+        let byte_code = compile_from_source("1 <= 2;").unwrap();
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpConstant(1),
+            OpCode::OpGreaterEqual,
+            OpCode::OpPop
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Integer(2), Object::Integer(1)],
+                globals: vec![],
+            },
+            byte_code
+        );
+
+        let byte_code = compile_from_source("1 >= 2;").unwrap();
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpConstant(1),
+            OpCode::OpGreaterEqual,
+            OpCode::OpPop
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Integer(1), Object::Integer(2)],
+                globals: vec![],
             },
             byte_code
         );
     }
 
+    #[test]
+    fn compile_string() {
+        let input = r#""mon" + "key";"#;
+        let byte_code = compile_from_source(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpConstant(1),
+            OpCode::OpAdd,
+            OpCode::OpPop,
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::String("mon".into()), Object::String("key".into())],
+                globals: vec![],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_disassembly() {
+        let byte_code = compile_from_source("let x = 10; if (x > 5) { len(\"ab\"); } else { x; };").unwrap();
+
+        assert_eq!(
+            "0000 OpConstant 0 (10)\n\
+             0003 OpSetGlobal 0\n\
+             0006 OpGetGlobal 0\n\
+             0009 OpConstant 1 (5)\n\
+             0012 OpGreaterThan\n\
+             0013 OpJumpNotTrue 26\n\
+             0016 OpGetBuiltin 0 (len)\n\
+             0018 OpConstant 2 (\"ab\")\n\
+             0021 OpCall 1\n\
+             0023 OpJump 29\n\
+             0026 OpGetGlobal 0\n\
+             0029 OpPop\n",
+            disassemble(&byte_code.instructions, &byte_code.constants)
+        );
+    }
+
     #[test]
     fn compile_if() {
         let input = "if (true) { 10; }; 3333;";
-        let byte_code = compile_from_source(input);
+        let byte_code = compile_from_source(input).unwrap();
 
         let expected_instructions = vec![
             OpCode::OpTrue, // 0000
-            OpCode::OpJumpNotTrue(7), // 0001
+            OpCode::OpJumpNotTrue(10), // 0001
             OpCode::OpConstant(0), // 0004
-            OpCode::OpPop, // 0007
-            OpCode::OpConstant(1), // 0008
+            OpCode::OpJump(11), // 0007
+            // without an else the if evaluates to null when the condition is false
+            OpCode::OpNull, // 0010
             OpCode::OpPop, // 0011
+            OpCode::OpConstant(1), // 0012
+            OpCode::OpPop, // 0015
         ]
             .into_iter()
             .flat_map(make_op)
@@ -235,7 +699,8 @@ mod tests {
         assert_eq!(
             ByteCode {
                 instructions: expected_instructions,
-                constants: vec![Object::Integer(10), Object::Integer(3333)]
+                constants: vec![Object::Integer(10), Object::Integer(3333)],
+                globals: vec![],
             },
             byte_code
         );
@@ -244,7 +709,7 @@ mod tests {
     #[test]
     fn compile_if_else() {
         let input = "if (true) { 10; } else { 20; };";
-        let byte_code = compile_from_source(input);
+        let byte_code = compile_from_source(input).unwrap();
 
         let expected_instructions = vec![
             OpCode::OpTrue, // 0000
@@ -261,7 +726,8 @@ mod tests {
         assert_eq!(
             ByteCode {
                 instructions: expected_instructions,
-                constants: vec![Object::Integer(10), Object::Integer(20)]
+                constants: vec![Object::Integer(10), Object::Integer(20)],
+                globals: vec![],
             },
             byte_code
         );
@@ -270,7 +736,7 @@ mod tests {
     #[test]
     fn compile_if_else_extra_statement() {
         let input = "if (true) { 10; } else { 20; }; 3333;";
-        let byte_code = compile_from_source(input);
+        let byte_code = compile_from_source(input).unwrap();
 
         let expected_instructions = vec![
             OpCode::OpTrue, // 0000
@@ -289,7 +755,75 @@ mod tests {
         assert_eq!(
             ByteCode {
                 instructions: expected_instructions,
-                constants: vec![Object::Integer(10), Object::Integer(20), Object::Integer(3333)]
+                constants: vec![Object::Integer(10), Object::Integer(20), Object::Integer(3333)],
+                globals: vec![],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_and() {
+        let input = "true && false;";
+        let byte_code = compile_from_source(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpTrue, // 0000
+            OpCode::OpAndOperand, // 0001
+            OpCode::OpJumpNotTrue(10), // 0002
+            OpCode::OpFalse, // 0005
+            OpCode::OpAndOperand, // 0006
+            OpCode::OpJump(11), // 0007
+            OpCode::OpFalse, // 0010
+            OpCode::OpPop, // 0011
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![],
+                globals: vec![],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_without_desugar() {
+        let ast = parse(&mut lex("true ? 1 : 2;").unwrap()).unwrap();
+        let mut compiler = Compiler::new(false);
+        compiler.compile_statements(ast).unwrap();
+
+        assert_eq!(compile_from_source("true ? 1 : 2;").unwrap(), compiler.into_byte_code());
+    }
+
+    #[test]
+    fn compile_or() {
+        let input = "false || true;";
+        let byte_code = compile_from_source(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpFalse, // 0000
+            OpCode::OpOrOperand, // 0001
+            OpCode::OpJumpNotTrue(9), // 0002
+            OpCode::OpTrue, // 0005
+            OpCode::OpJump(11), // 0006
+            OpCode::OpTrue, // 0009
+            OpCode::OpOrOperand, // 0010
+            OpCode::OpPop, // 0011
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![],
+                globals: vec![],
             },
             byte_code
         );
@@ -298,7 +832,7 @@ mod tests {
     #[test]
     fn compile_let_single_var() {
         let input = "let one = 1;";
-        let byte_code = compile_from_source(input);
+        let byte_code = compile_from_source(input).unwrap();
 
         let expected_instructions = vec![
             OpCode::OpConstant(0),
@@ -311,7 +845,8 @@ mod tests {
         assert_eq!(
             ByteCode {
                 instructions: expected_instructions,
-                constants: vec![Object::Integer(1),]
+                constants: vec![Object::Integer(1),],
+                globals: vec![String::from("one")],
             },
             byte_code
         );
@@ -320,7 +855,7 @@ mod tests {
     #[test]
     fn compile_let_multiple_var() {
         let input = "let one = 1; let two = 2;";
-        let byte_code = compile_from_source(input);
+        let byte_code = compile_from_source(input).unwrap();
 
         let expected_instructions = vec![
             OpCode::OpConstant(0),
@@ -335,16 +870,431 @@ mod tests {
         assert_eq!(
             ByteCode {
                 instructions: expected_instructions,
-                constants: vec![Object::Integer(1), Object::Integer(2),]
+                constants: vec![Object::Integer(1), Object::Integer(2),],
+                globals: vec![String::from("one"), String::from("two")],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_fold_pure_builtin() {
+        let input = r#"len("hello"); str(5);"#;
+        let byte_code = compile_from_source_optimized(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpPop,
+            OpCode::OpConstant(1),
+            OpCode::OpPop,
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Integer(5), Object::String("5".into())],
+                globals: vec![],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_builtin_call() {
+        // without the optimizer, calls to pure builtins are left for the vm
+        let input = "str(5); let len = 1; len;";
+        let byte_code = compile_from_source(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpGetBuiltin(6),
+            OpCode::OpConstant(0),
+            OpCode::OpCall(1),
+            OpCode::OpPop,
+            OpCode::OpConstant(1),
+            OpCode::OpSetGlobal(0),
+            OpCode::OpGetGlobal(0),
+            OpCode::OpPop,
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Integer(5), Object::Integer(1)],
+                globals: vec![String::from("len")],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_function() {
+        let compiler = Compiler::compile_for_test("let fact = fn(n) { let m = n - 1; fact(m); }; fact(3);");
+
+        let body = vec![
+            OpCode::OpGetLocal(0),
+            OpCode::OpConstant(0),
+            OpCode::OpSub,
+            OpCode::OpSetLocal(1),
+            // the function refers to itself through the global defined before its body was compiled
+            OpCode::OpGetGlobal(0),
+            OpCode::OpGetLocal(1),
+            OpCode::OpCall(1),
+            OpCode::OpReturnValue,
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            &[
+                Object::Integer(1),
+                Object::CompiledFunction { instructions: Rc::new(body), num_locals: 2, num_parameters: 1 },
+                Object::Integer(3),
+            ],
+            compiler.constants()
+        );
+        assert_eq!(
+            &[
+                OpCode::OpConstant(1),
+                OpCode::OpSetGlobal(0),
+                OpCode::OpGetGlobal(0),
+                OpCode::OpConstant(2),
+                OpCode::OpCall(1),
+                OpCode::OpPop,
+            ],
+            compiler.instructions()
+        );
+        assert_eq!(None, compiler.symbol_table().resolve(&String::from("n")));
+    }
+
+    #[test]
+    fn compile_function_without_value_returns_null() {
+        let compiler = Compiler::compile_for_test("fn() { let x = 1; };");
+
+        assert_eq!(
+            Object::CompiledFunction {
+                instructions: Rc::new(
+                    vec![OpCode::OpConstant(0), OpCode::OpSetLocal(0), OpCode::OpReturn]
+                        .into_iter()
+                        .flat_map(make_op)
+                        .collect()
+                ),
+                num_locals: 1,
+                num_parameters: 0,
+            },
+            compiler.constants()[1]
+        );
+    }
+
+    #[test]
+    fn compile_return() {
+        let compiler = Compiler::compile_for_test("fn(a) { if (a) { return 1; }; 2; };");
+
+        let body = vec![
+            OpCode::OpGetLocal(0),
+            OpCode::OpJumpNotTrue(5),
+            OpCode::OpConstant(0),
+            OpCode::OpReturnValue,
+            OpCode::OpJump(6),
+            OpCode::OpNull,
+            OpCode::OpPop,
+            OpCode::OpConstant(1),
+            OpCode::OpReturnValue,
+        ];
+
+        assert_eq!(
+            Object::CompiledFunction { instructions: Rc::new(ir::assemble(&body)), num_locals: 1, num_parameters: 1 },
+            compiler.constants()[2]
+        );
+    }
+
+    #[test]
+    fn compile_optimized_folds_constants() {
+        let input = "1 + 2 * 3;";
+        let byte_code = compile_from_source_optimized(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpPop,
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        // the operands which were folded away are dropped from the pool
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Integer(7)],
+                globals: vec![],
             },
             byte_code
         );
     }
 
+    #[test]
+    fn compile_optimized_removes_dead_branch() {
+        let input = "if (1 > 2) { 10; } else { 20; };";
+        let byte_code = compile_from_source_optimized(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpPop,
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Integer(20)],
+                globals: vec![],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_for_test_exposes_compiler_state() {
+        let compiler = Compiler::compile_for_test("let one = 1; if (one > 0) { one; } else { 2; };");
+
+        assert_eq!(
+            Some(Symbol { scope: SymbolScope::Global, index: 0 }),
+            compiler.symbol_table().resolve(&String::from("one"))
+        );
+        assert_eq!(None, compiler.symbol_table().resolve(&String::from("two")));
+        assert_eq!(&[Object::Integer(1), Object::Integer(0), Object::Integer(2)], compiler.constants());
+        // jump targets are instruction indexes until the program is assembled
+        assert_eq!(
+            &[
+                OpCode::OpConstant(0),
+                OpCode::OpSetGlobal(0),
+                OpCode::OpGetGlobal(0),
+                OpCode::OpConstant(1),
+                OpCode::OpGreaterThan,
+                OpCode::OpJumpNotTrue(8),
+                OpCode::OpGetGlobal(0),
+                OpCode::OpJump(9),
+                OpCode::OpConstant(2),
+                OpCode::OpPop,
+            ],
+            compiler.instructions()
+        );
+    }
+
+    #[test]
+    fn compile_nested_if_patches_each_jump() {
+        let compiler = Compiler::compile_for_test("if (true) { if (false) { 1; } else { 2; }; } else { 3; };");
+
+        assert_eq!(
+            &[
+                OpCode::OpTrue, // 0
+                OpCode::OpJumpNotTrue(8), // 1
+                OpCode::OpFalse, // 2
+                OpCode::OpJumpNotTrue(6), // 3
+                OpCode::OpConstant(0), // 4
+                OpCode::OpJump(7), // 5
+                OpCode::OpConstant(1), // 6
+                OpCode::OpJump(9), // 7
+                OpCode::OpConstant(2), // 8
+                OpCode::OpPop, // 9
+            ],
+            compiler.instructions()
+        );
+    }
+
+    #[test]
+    fn compile_block_ending_in_operand_like_pop() {
+        // the last instruction of the block has an operand with the same byte as OpPop, which
+        //    must not be mistaken for a pop since the compiler inspects instructions, not bytes
+        let compiler = Compiler::compile_for_test("let a = 1; let b = 2; if (true) { let c = 3; };");
+
+        assert_eq!(
+            &[OpCode::OpConstant(2), OpCode::OpSetGlobal(2), OpCode::OpNull],
+            &compiler.instructions()[6..9]
+        );
+    }
+
+    #[test]
+    fn compile_array() {
+        let compiler = Compiler::compile_for_test("[1, 2 + 3, [], [true]];");
+
+        assert_eq!(
+            &[
+                OpCode::OpConstant(0),
+                OpCode::OpConstant(1),
+                OpCode::OpConstant(2),
+                OpCode::OpAdd,
+                OpCode::OpConstant(3),
+                OpCode::OpConstant(4),
+                OpCode::OpArray(4),
+                OpCode::OpPop,
+            ],
+            compiler.instructions()
+        );
+        // the arrays made only of constants are built once, into the constant pool
+        assert_eq!(
+            &[Object::Array(Rc::new(vec![])), Object::Array(Rc::new(vec![Object::Boolean(true)]))],
+            &compiler.constants()[3..]
+        );
+    }
+
+    #[test]
+    fn compile_hash() {
+        let compiler = Compiler::compile_for_test(r#"{"a": 1, 2: 3 + 4}; {true: [1]};"#);
+
+        assert_eq!(
+            &[
+                OpCode::OpConstant(0),
+                OpCode::OpConstant(1),
+                OpCode::OpConstant(2),
+                OpCode::OpConstant(3),
+                OpCode::OpConstant(4),
+                OpCode::OpAdd,
+                OpCode::OpHash(4),
+                OpCode::OpPop,
+                OpCode::OpConstant(5),
+                OpCode::OpPop,
+            ],
+            compiler.instructions()
+        );
+        assert_eq!(
+            Ok(&compiler.constants()[5]),
+            crate::eval::build_hash(vec![(Object::Boolean(true), Object::Array(Rc::new(vec![Object::Integer(1)])))]).as_ref()
+        );
+    }
+
+    #[test]
+    fn compile_index() {
+        let compiler = Compiler::compile_for_test("[1, 2][1 + 1];");
+
+        assert_eq!(
+            &[
+                OpCode::OpConstant(0),
+                OpCode::OpConstant(1),
+                OpCode::OpConstant(2),
+                OpCode::OpAdd,
+                OpCode::OpIndex,
+                OpCode::OpPop,
+            ],
+            compiler.instructions()
+        );
+    }
+
+    #[test]
+    fn compile_interpolation() {
+        let compiler = Compiler::compile_for_test(r#""a ${1 + 2}";"#);
+
+        assert_eq!(
+            &[
+                OpCode::OpConstant(0),
+                OpCode::OpConstant(1),
+                OpCode::OpConstant(2),
+                OpCode::OpAdd,
+                OpCode::OpInterpolate(2),
+                OpCode::OpPop,
+            ],
+            compiler.instructions()
+        );
+    }
+
+    #[test]
+    fn compile_assign() {
+        let input = "let one = 1; one = 2;";
+        let byte_code = compile_from_source(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpSetGlobal(0),
+            OpCode::OpConstant(1),
+            OpCode::OpSetGlobal(0),
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Integer(1), Object::Integer(2)],
+                globals: vec![String::from("one")],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_declare() {
+        let input = "let one; one = 1;";
+        let byte_code = compile_from_source(input).unwrap();
+
+        let expected_instructions = vec![
+            OpCode::OpConstant(0),
+            OpCode::OpSetGlobal(0),
+            OpCode::OpConstant(1),
+            OpCode::OpSetGlobal(0),
+        ]
+            .into_iter()
+            .flat_map(make_op)
+            .collect();
+
+        assert_eq!(
+            ByteCode {
+                instructions: expected_instructions,
+                constants: vec![Object::Uninitialized("one".into()), Object::Integer(1)],
+                globals: vec![String::from("one")],
+            },
+            byte_code
+        );
+    }
+
+    #[test]
+    fn compile_assign_undefined() {
+        assert_eq!(
+            Err(CompileError::UndefinedAssignment(String::from("one"))),
+            compile_from_source("one = 2;")
+        );
+    }
+
+    #[test]
+    fn compile_errors() {
+        assert_eq!(Err(CompileError::UndefinedVariable(String::from("x"))), compile_from_source("1 + x;"));
+        assert!(matches!(compile_from_source("1 +;"), Err(CompileError::Parse(_))));
+    }
+
+    #[test]
+    fn compile_lines() {
+        let mut compiler = Compiler::new_with_state(SymbolTable::new(), Vec::new());
+        compiler.compile_line("let one = 1;").unwrap();
+        let (symbol_table, constants) = compiler.into_state();
+
+        let mut compiler = Compiler::new_with_state(symbol_table, constants);
+        // a line which fails to compile defines nothing, even the bindings before the error
+        assert!(compiler.compile_line("let two = 2; x;").is_err());
+        assert_eq!(
+            Err(CompileError::UndefinedVariable(String::from("two"))),
+            compiler.compile_line("two;")
+        );
+
+        let byte_code = compiler.compile_line("one;").unwrap();
+        assert_eq!(
+            vec![OpCode::OpGetGlobal(0), OpCode::OpPop].into_iter().flat_map(make_op).collect::<Vec<u8>>(),
+            byte_code.instructions
+        );
+        assert_eq!(vec![Object::Integer(1)], byte_code.constants);
+    }
+
     #[test]
     fn compile_let_get() {
         let input = "let one = 1; one;";
-        let byte_code = compile_from_source(input);
+        let byte_code = compile_from_source(input).unwrap();
 
         let expected_instructions = vec![
             OpCode::OpConstant(0),
@@ -359,7 +1309,8 @@ mod tests {
         assert_eq!(
             ByteCode {
                 instructions: expected_instructions,
-                constants: vec![Object::Integer(1),]
+                constants: vec![Object::Integer(1),],
+                globals: vec![String::from("one")],
             },
             byte_code
         );
@@ -3,21 +3,33 @@ use std::collections::HashMap;
 type SymbolName = String;
 type SymbolIndex = u16;
 
-pub struct SymbolTable {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SymbolScope {
+    Global,
+    Local,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Symbol {
+    pub scope: SymbolScope,
+    pub index: SymbolIndex,
+}
+
+#[derive(Clone)]
+struct Scope {
     store: HashMap<SymbolName, SymbolIndex>,
     next_index: SymbolIndex,
 }
 
-impl SymbolTable {
-
-    pub fn new() -> Self {
-        SymbolTable {
+impl Scope {
+    fn new() -> Self {
+        Scope {
             store: HashMap::new(),
             next_index: 0,
         }
     }
 
-    pub fn define(&mut self, name: SymbolName) -> SymbolIndex {
+    fn define(&mut self, name: SymbolName) -> SymbolIndex {
         let index = self.next_index;
         self.store.insert(name, index);
 
@@ -25,9 +37,92 @@ impl SymbolTable {
 
         index
     }
+}
+
+#[derive(Clone)]
+pub struct SymbolTable {
+    globals: Scope,
+    // one scope per function being compiled, as in the evaluator only the innermost is
+    //    visible so a function sees its own bindings and the globals
+    locals: Vec<Scope>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        SymbolTable::new()
+    }
+}
+
+impl SymbolTable {
+
+    pub fn new() -> Self {
+        SymbolTable {
+            globals: Scope::new(),
+            locals: Vec::new(),
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.locals.push(Scope::new());
+    }
 
-    pub fn resolve(&self, name: &SymbolName) -> Option<SymbolIndex> {
-        self.store.get(name).map(|&index| index)
+    /// returns the number of locals defined in the scope
+    pub fn pop_scope(&mut self) -> SymbolIndex {
+        self.locals.pop().map_or(0, |scope| scope.next_index)
     }
 
+    pub fn define(&mut self, name: SymbolName) -> Symbol {
+        match self.locals.last_mut() {
+            Some(scope) => Symbol { scope: SymbolScope::Local, index: scope.define(name) },
+            None => Symbol { scope: SymbolScope::Global, index: self.globals.define(name) },
+        }
+    }
+
+    /// the names of the globals along with their indexes, in the order they were defined
+    pub fn globals(&self) -> Vec<(&str, SymbolIndex)> {
+        let mut globals = self.globals.store.iter().map(|(name, index)| (name.as_str(), *index)).collect::<Vec<_>>();
+        globals.sort_by_key(|(_, index)| *index);
+
+        globals
+    }
+
+    pub fn resolve(&self, name: &SymbolName) -> Option<Symbol> {
+        let local = self.locals.last()
+            .and_then(|scope| scope.store.get(name))
+            .map(|index| Symbol { scope: SymbolScope::Local, index: *index });
+
+        local.or_else(|| {
+            self.globals.store.get(name).map(|index| Symbol { scope: SymbolScope::Global, index: *index })
+        })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_scopes() {
+        let mut symbol_table = SymbolTable::new();
+        symbol_table.define(String::from("a"));
+        symbol_table.define(String::from("b"));
+
+        symbol_table.push_scope();
+        symbol_table.define(String::from("b"));
+        symbol_table.define(String::from("c"));
+
+        assert_eq!(Some(Symbol { scope: SymbolScope::Global, index: 0 }), symbol_table.resolve(&String::from("a")));
+        assert_eq!(Some(Symbol { scope: SymbolScope::Local, index: 0 }), symbol_table.resolve(&String::from("b")));
+        assert_eq!(Some(Symbol { scope: SymbolScope::Local, index: 1 }), symbol_table.resolve(&String::from("c")));
+
+        // the locals of an enclosing function are not visible
+        symbol_table.push_scope();
+        assert_eq!(None, symbol_table.resolve(&String::from("c")));
+        assert_eq!(0, symbol_table.pop_scope());
+
+        assert_eq!(2, symbol_table.pop_scope());
+        assert_eq!(Some(Symbol { scope: SymbolScope::Global, index: 1 }), symbol_table.resolve(&String::from("b")));
+        assert_eq!(None, symbol_table.resolve(&String::from("c")));
+    }
 }
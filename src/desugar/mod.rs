@@ -0,0 +1,147 @@
+use crate::parser::{Statement, Expr};
+use crate::lexer::Span;
+use crate::visit::{Fold, map_statement, map_expr};
+
+/// Rewrites syntactic sugar into the smaller core AST understood by both the
+/// tree-walking evaluator and the bytecode compiler. This runs after parsing
+/// and before either backend sees the program.
+pub fn desugar(program: Vec<Statement>) -> Vec<Statement> {
+    Desugar { span: Span::default() }.fold_block(program)
+}
+
+/// desugars a single expression, for a backend given an AST which wasn't passed through desugar
+pub fn desugar_expr(expr: Expr) -> Expr {
+    Desugar { span: Span::default() }.fold_expr(expr)
+}
+
+struct Desugar {
+    // the span of the statement being rewritten, statements generated from sugar inside
+    //    it are given this span so diagnostics still point at the user's source
+    span: Span,
+}
+
+impl Fold for Desugar {
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        let outer_span = std::mem::replace(&mut self.span, statement.span());
+        let statement = map_statement(self, statement);
+        self.span = outer_span;
+
+        statement
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        match expr {
+            // `condition ? consequence : alternative` becomes
+            //    `if (condition) { consequence; } else { alternative; }`
+            Expr::Ternary { condition, consequence, alternative } => Expr::If {
+                condition: Box::new(self.fold_expr(*condition)),
+                consequence: vec![Statement::Expression(self.fold_expr(*consequence), self.span)],
+                alternative: vec![Statement::Expression(self.fold_expr(*alternative), self.span)],
+            },
+            expr => map_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::{parse, Operator};
+
+    fn desugar_source(input: &str) -> Vec<Statement> {
+        let mut tokens = lex(input).unwrap();
+        desugar(parse(&mut tokens).unwrap())
+    }
+
+    #[test]
+    fn desugar_keeps_logical_operators() {
+        // each backend checks the operands of `&&` and `||` are booleans, which `if` doesn't
+        assert_eq!(
+            vec![
+                Statement::Expression(Expr::Infix {
+                    left: Box::new(Expr::Ident(String::from("a"), Span::default())),
+                    operator: Operator::And,
+                    right: Box::new(Expr::If {
+                        condition: Box::new(Expr::Ident(String::from("b"), Span::default())),
+                        consequence: vec![Statement::Expression(Expr::Boolean(true), Span::default())],
+                        alternative: vec![Statement::Expression(Expr::Ident(String::from("c"), Span::default()), Span::default())],
+                    }),
+                }, Span::default()),
+            ],
+            desugar_source("a && (b ? true : c);")
+        );
+    }
+
+    #[test]
+    fn desugar_ternary() {
+        assert_eq!(
+            vec![
+                Statement::Expression(Expr::If {
+                    condition: Box::new(Expr::Ident(String::from("a"), Span::default())),
+                    consequence: vec![Statement::Expression(Expr::Ident(String::from("b"), Span::default()), Span::default())],
+                    alternative: vec![Statement::Expression(Expr::If {
+                        condition: Box::new(Expr::Ident(String::from("c"), Span::default())),
+                        consequence: vec![Statement::Expression(Expr::Const(1), Span::default())],
+                        alternative: vec![Statement::Expression(Expr::Const(2), Span::default())],
+                    }, Span::default())],
+                }, Span::default()),
+            ],
+            desugar_source("a ? b : c ? 1 : 2;")
+        );
+    }
+
+    #[test]
+    fn desugar_nested_in_function_body() {
+        assert_eq!(
+            vec![
+                Statement::Let {
+                    name: String::from("f"),
+                    value: Expr::Function {
+                        parameters: vec![String::from("a")],
+                        body: vec![
+                            Statement::Return {
+                                value: Expr::If {
+                                    condition: Box::new(Expr::Ident(String::from("a"), Span::default())),
+                                    consequence: vec![Statement::Expression(Expr::Boolean(true), Span::default())],
+                                    alternative: vec![Statement::Expression(Expr::Boolean(false), Span::default())],
+                                },
+                                span: Span::default(),
+                            },
+                        ],
+                    },
+                    span: Span::default(),
+                },
+            ],
+            desugar_source("let f = fn(a) { return a ? true : false; };")
+        );
+    }
+
+    #[test]
+    fn desugar_preserves_spans() {
+        let program = desugar_source("let x = 1;\nlet f = fn() {\n    return a ? b : c;\n};");
+
+        let return_span = Span { start: 30, end: 47, line: 3, column: 5 };
+        let body = match &program[1] {
+            Statement::Let { value: Expr::Function { body, .. }, .. } => body,
+            statement => panic!("unexpected statement {:?}", statement),
+        };
+        assert_eq!(return_span, body[0].span());
+        match &body[0] {
+            Statement::Return { value: Expr::If { consequence, alternative, .. }, .. } => {
+                assert_eq!(return_span, consequence[0].span());
+                assert_eq!(return_span, alternative[0].span());
+            },
+            statement => panic!("unexpected statement {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn desugar_leaves_core_ast_unchanged() {
+        let input = "let x = fn(a, b) { if (a > b) { a; } else { -b; }; }; x(1, 2);";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(ast.clone(), desugar(ast));
+    }
+}
@@ -0,0 +1,116 @@
+// Compares two versions of a program statement by statement, for tools which show what changed
+// between runs. Statements are compared by structure, so moving code around or reformatting it
+// isn't a change. The statements common to both programs are found as their longest common
+// subsequence, and between those a statement removed from the old program and one added in the
+// new program at the same place are reported together as a modification.
+
+use crate::parser::Statement;
+
+#[derive(Debug, PartialEq)]
+pub enum Change<'a> {
+    // the index is that of the statement in the new program
+    Added { index: usize, statement: &'a Statement },
+    // the index is that of the statement in the old program
+    Removed { index: usize, statement: &'a Statement },
+    Modified { old_index: usize, new_index: usize, old: &'a Statement, new: &'a Statement },
+}
+
+/// the changes which turn the old program into the new one, in program order
+pub fn ast_diff<'a>(old: &'a [Statement], new: &'a [Statement]) -> Vec<Change<'a>> {
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = vec![];
+    let (mut removed, mut added) = (vec![], vec![]);
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            pair_up(&mut changes, old, new, &mut removed, &mut added);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            added.push(j);
+            j += 1;
+        } else {
+            removed.push(i);
+            i += 1;
+        }
+    }
+    pair_up(&mut changes, old, new, &mut removed, &mut added);
+
+    changes
+}
+
+// reports the statements which differ between two common statements, pairing them in order as
+//    modifications and leaving any left over as added or removed
+fn pair_up<'a>(
+    changes: &mut Vec<Change<'a>>,
+    old: &'a [Statement],
+    new: &'a [Statement],
+    removed: &mut Vec<usize>,
+    added: &mut Vec<usize>,
+) {
+    for (&old_index, &new_index) in removed.iter().zip(added.iter()) {
+        changes.push(Change::Modified { old_index, new_index, old: &old[old_index], new: &new[new_index] });
+    }
+    let paired = removed.len().min(added.len());
+    for &index in &removed[paired..] {
+        changes.push(Change::Removed { index, statement: &old[index] });
+    }
+    for &index in &added[paired..] {
+        changes.push(Change::Added { index, statement: &new[index] });
+    }
+    removed.clear();
+    added.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+
+    fn program(input: &str) -> Vec<Statement> {
+        parse(&mut lex(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn diff_unchanged() {
+        let old = program("let a = 1;\nlet b = 2;");
+        // formatting alone isn't a change
+        let new = program("let a=1; let b =  2;");
+
+        assert!(ast_diff(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn diff_changes() {
+        let old = program("let a = 1; let b = 2; puts(a); let c = 3;");
+        let new = program("let a = 1; let b = 20; puts(a); let c = 3; puts(c);");
+
+        assert_eq!(
+            vec![
+                Change::Modified { old_index: 1, new_index: 1, old: &old[1], new: &new[1] },
+                Change::Added { index: 4, statement: &new[4] },
+            ],
+            ast_diff(&old, &new)
+        );
+
+        assert_eq!(
+            vec![
+                Change::Removed { index: 0, statement: &old[0] },
+                Change::Removed { index: 2, statement: &old[2] },
+            ],
+            ast_diff(&old, &program("let b = 2; let c = 3;"))
+        );
+    }
+}
@@ -0,0 +1,214 @@
+// Conversions between rust values and objects, for embedders passing data into and out of
+// the evaluator and the vm.
+
+use super::Object;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+/// an object which isn't of the type it was being converted to
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl From<i32> for Object {
+    fn from(num: i32) -> Self {
+        Object::Integer(num)
+    }
+}
+
+impl From<f64> for Object {
+    fn from(num: f64) -> Self {
+        Object::Float(num)
+    }
+}
+
+impl From<bool> for Object {
+    fn from(val: bool) -> Self {
+        Object::Boolean(val)
+    }
+}
+
+impl From<String> for Object {
+    fn from(string: String) -> Self {
+        Object::String(string.into())
+    }
+}
+
+impl From<&str> for Object {
+    fn from(string: &str) -> Self {
+        Object::String(string.into())
+    }
+}
+
+impl From<Vec<Object>> for Object {
+    fn from(elements: Vec<Object>) -> Self {
+        Object::Array(Rc::new(elements))
+    }
+}
+
+// a value returned from a function is converted as the value itself
+fn unwrap_return(object: Object) -> Object {
+    match object {
+        Object::Return(value) => unwrap_return(*value),
+        object => object,
+    }
+}
+
+// the conversion takes the value out of the variant, copying it only if it is shared
+macro_rules! try_from_object {
+    ($target:ty, $variant:ident, $expected:expr, $convert:expr) => {
+        impl TryFrom<Object> for $target {
+            type Error = ConversionError;
+
+            fn try_from(object: Object) -> Result<Self, Self::Error> {
+                match unwrap_return(object) {
+                    Object::$variant(value) => Ok($convert(value)),
+                    object => Err(ConversionError { expected: $expected, found: object.type_name() }),
+                }
+            }
+        }
+    };
+}
+
+try_from_object!(i32, Integer, "integer", |num| num);
+try_from_object!(f64, Float, "float", |num| num);
+try_from_object!(bool, Boolean, "boolean", |val| val);
+try_from_object!(String, String, "string", |string: Rc<str>| string.to_string());
+try_from_object!(Vec<Object>, Array, "array", |elements: Rc<Vec<Object>>| Rc::try_unwrap(elements)
+    .unwrap_or_else(|elements| elements.as_ref().clone()));
+
+// json has no functions, so they don't convert, and json object keys are strings, so only hashes
+//    keyed by strings do
+#[cfg(feature = "serde_json")]
+impl Object {
+    pub fn to_json_value(&self) -> Result<serde_json::Value, ConversionError> {
+        use super::HashKey;
+        use serde_json::Value;
+
+        let unsupported = || ConversionError { expected: "null, boolean, number, string, array, or hash", found: self.type_name() };
+        Ok(match self {
+            Object::Null => Value::Null,
+            Object::Boolean(val) => Value::Bool(*val),
+            Object::Integer(num) => Value::from(*num),
+            // json has no infinity or nan
+            Object::Float(num) => serde_json::Number::from_f64(*num).map(Value::Number).ok_or_else(unsupported)?,
+            Object::String(string) => Value::String(string.to_string()),
+            Object::Array(elements) => Value::Array(
+                elements.iter().map(Object::to_json_value).collect::<Result<_, _>>()?
+            ),
+            Object::Hash(pairs) => Value::Object(
+                pairs.iter()
+                    .map(|(key, value)| match key {
+                        HashKey::String(key) => Ok((key.to_string(), value.to_json_value()?)),
+                        key => Err(ConversionError { expected: "string", found: key.to_object().type_name() }),
+                    })
+                    .collect::<Result<_, _>>()?
+            ),
+            Object::Return(value) => value.to_json_value()?,
+            Object::Function { .. } | Object::CompiledFunction { .. } | Object::Builtin(_) | Object::Native(_)
+            | Object::Uninitialized(_) =>
+                return Err(unsupported()),
+        })
+    }
+
+    /// numbers become integers when they fit in one, and floats otherwise, and objects become
+    ///    hashes keyed by strings
+    pub fn from_json_value(value: &serde_json::Value) -> Result<Object, ConversionError> {
+        use super::HashKey;
+        use serde_json::Value;
+
+        Ok(match value {
+            Value::Null => Object::Null,
+            Value::Bool(val) => Object::Boolean(*val),
+            Value::Number(num) => match num.as_i64().and_then(|num| i32::try_from(num).ok()) {
+                Some(num) => Object::Integer(num),
+                None => Object::Float(num.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(string) => Object::String(string.as_str().into()),
+            Value::Array(elements) => Object::Array(Rc::new(
+                elements.iter().map(Object::from_json_value).collect::<Result<_, _>>()?
+            )),
+            Value::Object(pairs) => Object::Hash(Rc::new(
+                pairs.iter()
+                    .map(|(key, value)| Ok((HashKey::String(key.as_str().into()), Object::from_json_value(value)?)))
+                    .collect::<Result<_, _>>()?
+            )),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn into_object() {
+        assert_eq!(Object::Integer(5), Object::from(5));
+        assert_eq!(Object::Float(1.5), Object::from(1.5));
+        assert_eq!(Object::Boolean(true), Object::from(true));
+        assert_eq!(Object::String("a".into()), Object::from("a"));
+        assert_eq!(Object::String("a".into()), Object::from("a".to_string()));
+        assert_eq!(
+            Object::Array(Rc::new(vec![Object::Integer(1), Object::Boolean(false)])),
+            Object::from(vec![1.into(), false.into()])
+        );
+    }
+
+    #[test]
+    fn from_object() {
+        assert_eq!(Ok(5), i32::try_from(Object::Integer(5)));
+        assert_eq!(Ok(1.5), f64::try_from(Object::Float(1.5)));
+        assert_eq!(Ok(true), bool::try_from(Object::Boolean(true)));
+        assert_eq!(Ok("a".to_string()), String::try_from(Object::String("a".into())));
+        assert_eq!(Ok(vec![Object::Null]), Vec::<Object>::try_from(Object::Array(Rc::new(vec![Object::Null]))));
+        assert_eq!(Ok(5), i32::try_from(Object::Return(Box::new(Object::Integer(5)))));
+    }
+
+    #[test]
+    fn from_object_wrong_type() {
+        let error = i32::try_from(Object::String("5".into())).unwrap_err();
+        assert_eq!(ConversionError { expected: "integer", found: "string" }, error);
+        assert_eq!("expected integer, got string", error.to_string());
+        assert!(f64::try_from(Object::Integer(1)).is_err());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn json_values() {
+        use crate::eval::HashKey;
+        use serde_json::json;
+
+        let object = Object::from(vec![1.into(), 1.5.into(), "a".into(), true.into(), Object::Null]);
+        assert_eq!(Ok(json!([1, 1.5, "a", true, null])), object.to_json_value());
+        assert_eq!(Ok(object), Object::from_json_value(&json!([1, 1.5, "a", true, null])));
+
+        // integers too large for monkey are kept as floats
+        assert_eq!(Ok(Object::Float(1e10)), Object::from_json_value(&json!(10_000_000_000i64)));
+
+        assert!(Object::Float(f64::NAN).to_json_value().is_err());
+        assert!(Object::Builtin(0).to_json_value().is_err());
+
+        let hash = Object::Hash(Rc::new(vec![
+            (HashKey::String("a".into()), Object::Integer(1)),
+            (HashKey::String("b".into()), Object::from(vec![Object::Boolean(true)])),
+        ].into_iter().collect()));
+        assert_eq!(Ok(json!({ "a": 1, "b": [true] })), hash.to_json_value());
+        assert_eq!(Ok(hash), Object::from_json_value(&json!({ "a": 1, "b": [true] })));
+
+        // json object keys are strings, so a hash keyed by anything else doesn't convert
+        let hash = Object::Hash(Rc::new(vec![(HashKey::Integer(1), Object::Null)].into_iter().collect()));
+        assert_eq!(Err(ConversionError { expected: "string", found: "integer" }), hash.to_json_value());
+    }
+}
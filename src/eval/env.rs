@@ -1,22 +1,192 @@
 use std::collections::HashMap;
-use crate::eval::Object;
+use std::io::{self, Write};
+use crate::eval::{Object, NativeFunction, RuntimeError, apply_function};
+use crate::builtins::{Context, Host};
+use crate::profile::Profiler;
+use crate::profile::heap::{self, HeapProfile};
+use crate::trace::{TraceEvent, Tracer};
+
+// bindings which have been declared with `let x;` but not yet assigned hold None
+type Scope = HashMap<String, Option<Object>>;
 
 pub struct Env {
-    env: HashMap<String, Object>,
+    globals: Scope,
+    // one scope per active function call, only the innermost of which is visible
+    //    since functions can see their own bindings and the globals but not those of their caller
+    locals: Vec<Scope>,
+    // where builtins such as puts write their output, along with anything else they can reach
+    host: Host,
+    // only present when profiling was enabled
+    profiler: Option<Profiler>,
+    // the line of the statement being evaluated, which allocations are attributed to
+    line: usize,
+    // receives an event for each step the evaluator takes, when set
+    tracer: Option<Box<dyn Tracer>>,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Env::new()
+    }
 }
 
 impl Env {
     pub fn new() -> Self {
+        Env::with_output(Box::new(io::stdout()))
+    }
+
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        Env::with_host(Host::new(output))
+    }
+
+    pub fn with_host(host: Host) -> Self {
         Env {
-            env: HashMap::new(),
+            globals: HashMap::new(),
+            locals: Vec::new(),
+            host,
+            profiler: None,
+            line: 0,
+            tracer: None,
         }
     }
 
+    /// starts counting the work done while evaluating, by the stack of functions active at the time
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer>) {
+        self.tracer = Some(tracer);
+    }
+
+    pub(crate) fn trace(&mut self, event: TraceEvent) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.event(event);
+        }
+    }
+
+    /// the line of the statement being evaluated
+    pub(crate) fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// the objects allocated by the program so far
+    pub fn heap(&self) -> &HeapProfile {
+        self.host.heap()
+    }
+
+    /// sets the line allocations are attributed to, returning the previous line
+    pub(crate) fn set_line(&mut self, line: usize) -> usize {
+        std::mem::replace(&mut self.line, line)
+    }
+
+    /// records the object in the heap profile if it lives on the heap
+    pub(crate) fn record_allocation(&mut self, object: &Object) {
+        let line = self.line;
+        self.host.heap_mut().record(object, line);
+        if let Some(bytes) = heap::allocation_size(object) {
+            self.trace(TraceEvent::Allocation { kind: object.type_name(), bytes, line });
+        }
+    }
+
+    pub(crate) fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    pub fn push_scope(&mut self) {
+        self.locals.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.locals.pop();
+    }
+
+    /// discards the scopes of any calls which were interrupted part way through, leaving only the globals
+    pub fn reset_scopes(&mut self) {
+        self.locals.clear();
+    }
+
+    fn innermost_scope(&mut self) -> &mut Scope {
+        match self.locals.last_mut() {
+            Some(scope) => scope,
+            None => &mut self.globals,
+        }
+    }
+
+    /// defines a new binding in the innermost scope
     pub fn set(&mut self, key: String, value: Object) {
-        self.env.insert(key, value);
+        self.innermost_scope().insert(key, Some(value));
+    }
+
+    /// defines a global which calls the rust function, so an embedder can expose its own
+    ///    functions to scripts
+    pub fn register_native(&mut self, name: &str, function: impl Fn(&[Object]) -> Result<Object, RuntimeError> + 'static) {
+        self.set_global(name, Object::Native(NativeFunction::new(name, function)));
+    }
+
+    /// defines or replaces a global, so an embedder can pass values into a script
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.globals.insert(name.to_string(), Some(value));
+    }
+
+    /// the value of a global, so an embedder can read values out of a script after it runs
+    pub fn get_global(&self, name: &str) -> Option<&Object> {
+        self.globals.get(name).and_then(Option::as_ref)
+    }
+
+    /// defines a new binding in the innermost scope without a value
+    pub fn declare(&mut self, key: String) {
+        self.innermost_scope().insert(key, None);
+    }
+
+    /// updates an existing binding, walking out from the innermost scope to the globals,
+    /// returning false if the binding does not exist
+    pub fn assign(&mut self, key: &str, value: Object) -> bool {
+        let scopes = self.locals.last_mut().into_iter().chain(std::iter::once(&mut self.globals));
+        for scope in scopes {
+            if let Some(binding) = scope.get_mut(key) {
+                *binding = Some(value);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn lookup(&self, key: &str) -> Option<&Option<Object>> {
+        self.locals.last()
+            .and_then(|scope| scope.get(key))
+            .or_else(|| self.globals.get(key))
     }
 
+    /// returns the value of a binding, or None if it is undefined or uninitialized
     pub fn get(&self, key: &str) -> Option<Object> {
-        self.env.get(key).map(|val| val.clone())
+        self.lookup(key).cloned().flatten()
+    }
+
+    /// the global bindings sorted by name, with None for those declared but not yet assigned
+    pub fn globals(&self) -> Vec<(&str, Option<&Object>)> {
+        let mut globals = self.globals.iter().map(|(name, value)| (name.as_str(), value.as_ref())).collect::<Vec<_>>();
+        globals.sort_by_key(|(name, _)| *name);
+
+        globals
+    }
+
+    pub fn is_declared(&self, key: &str) -> bool {
+        self.lookup(key).is_some()
+    }
+}
+
+impl Context for Env {
+    fn host(&mut self) -> &mut Host {
+        &mut self.host
+    }
+
+    fn call(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+        apply_function(function, arguments, self)
     }
 }
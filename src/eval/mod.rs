@@ -2,169 +2,686 @@ use crate::parser::Statement;
 use crate::parser::Expr;
 use crate::parser::Prefix;
 use crate::parser::Operator;
+use crate::builtins::{self, Context};
+use crate::desugar::desugar_expr;
+use crate::lexer::{format_float, Span};
+use crate::profile;
+use crate::trace::TraceEvent;
 
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+mod convert;
+#[cfg(feature = "msgpack")]
+mod msgpack;
 mod env;
+pub use self::convert::ConversionError;
 pub use self::env::Env;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Object {
     Null,
     Integer(i32),
-    String(String),
+    Float(f64),
+    String(Rc<str>),
     Boolean(bool),
     Return(Box<Object>),
-    Function{parameters: Vec<String>, body: Vec<Statement>},
+    // shared rather than copied when the function is passed around, since the body can be large
+    Function{parameters: Rc<Vec<String>>, body: Rc<Vec<Statement>>},
+    Builtin(usize), // index into the builtins table
+    // a function compiled to bytecode, only produced by the compiler for the vm
+    CompiledFunction { instructions: Rc<Vec<u8>>, num_locals: usize, num_parameters: usize },
+    Array(Rc<Vec<Object>>),
+    // ordered by key, so a hash is displayed the same way however it was built
+    Hash(Rc<BTreeMap<HashKey, Object>>),
+    // a function provided by the program embedding the interpreter
+    Native(NativeFunction),
+    // the value the vm gives a variable declared with `let x;` until it is assigned, holding
+    //    the name so reading it can be reported like the evaluator does
+    Uninitialized(Rc<str>),
+}
+
+/// a value which can be used as the key of a hash
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum HashKey {
+    Integer(i32),
+    Boolean(bool),
+    String(Rc<str>),
+}
+
+impl HashKey {
+    pub fn from_object(object: &Object) -> Result<HashKey, RuntimeError> {
+        match object {
+            Object::Integer(value) => Ok(HashKey::Integer(*value)),
+            Object::Boolean(value) => Ok(HashKey::Boolean(*value)),
+            Object::String(value) => Ok(HashKey::String(value.clone())),
+            object => Err(RuntimeError::UnusableHashKey(object.type_name())),
+        }
+    }
+
+    pub fn to_object(&self) -> Object {
+        match self {
+            HashKey::Integer(value) => Object::Integer(*value),
+            HashKey::Boolean(value) => Object::Boolean(*value),
+            HashKey::String(value) => Object::String(value.clone()),
+        }
+    }
+}
+
+/// the signature of a rust function exposed to scripts by an embedder
+pub type NativeFn = dyn Fn(&[Object]) -> Result<Object, RuntimeError>;
+
+/// a rust function exposed to scripts, see Env::register_native and vm::register_native
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, function: impl Fn(&[Object]) -> Result<Object, RuntimeError> + 'static) -> Self {
+        NativeFunction { name: name.to_string(), function: Rc::new(function) }
+    }
+
+    pub fn call(&self, arguments: &[Object]) -> Result<Object, RuntimeError> {
+        (self.function)(arguments)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+// closures can't be compared, so natives are only equal to themselves
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl Object {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Null => "null",
+            Object::Integer(_) => "integer",
+            Object::Float(_) => "float",
+            Object::String(_) => "string",
+            Object::Boolean(_) => "boolean",
+            Object::Return(value) => value.type_name(),
+            Object::Function { .. } | Object::CompiledFunction { .. } => "function",
+            Object::Builtin(_) => "builtin",
+            Object::Native(_) => "builtin",
+            Object::Array(_) => "array",
+            Object::Hash(_) => "hash",
+            Object::Uninitialized(_) => "uninitialized",
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Object::Integer(num) => write!(f, "{}", num),
+            Object::Float(num) => write!(f, "{}", format_float(*num)),
+            Object::String(string) => write!(f, "{}", string),
+            Object::Boolean(val) => write!(f, "{}", val),
+            Object::Function { .. } | Object::CompiledFunction { .. } => write!(f, "function"),
+            Object::Builtin(index) => write!(f, "builtin {}", builtins::name(*index)),
+            Object::Native(native) => write!(f, "builtin {}", native.name),
+            Object::Null => write!(f, "null"),
+            Object::Uninitialized(_) => write!(f, "unassigned"),
+            Object::Return(obj) => write!(f, "{}", obj),
+            Object::Array(elements) => {
+                let elements = elements.iter()
+                    .map(|element| element.to_string())
+                    .collect::<Vec<String>>();
+                write!(f, "[{}]", elements.join(", "))
+            },
+            Object::Hash(pairs) => {
+                let pairs = pairs.iter()
+                    .map(|(key, value)| format!("{}: {}", key.to_object(), value))
+                    .collect::<Vec<String>>();
+                write!(f, "{{{}}}", pairs.join(", "))
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum RuntimeError {
+    InvalidPrefixOperand { prefix: Prefix, operand: &'static str },
+    InvalidInfixOperands { operator: Operator, left: &'static str, right: &'static str },
+    // an operand of `&&` or `||` which isn't a boolean
+    InvalidLogicalOperand { operator: Operator, operand: &'static str },
+    UnknownIdentifier(String),
+    UninitializedVariable(String),
+    UndefinedAssignment(String),
+    NotCallable(&'static str),
+    WrongArgumentCount { expected: usize, found: usize },
+    BuiltinArgumentCount { name: &'static str, min: usize, max: Option<usize>, found: usize },
+    BuiltinArgumentType { name: &'static str, expected: &'static str, found: &'static str },
+    CapabilityDenied(&'static str),
+    // a value larger than the host allows, in bytes for strings, elements for arrays, and pairs
+    //    for hashes
+    ValueTooLarge { kind: &'static str, limit: usize },
+    // a replayed program read a value which wasn't the next one in the log
+    ReplayDiverged(&'static str),
+    // the vm ran out of room on its stack, usually from recursion which never ends
+    StackOverflow,
+    // the vm popped from an empty stack, which only malformed bytecode does
+    StackUnderflow,
+    // the operation, such as `2147483647 + 1`, whose result doesn't fit in an integer
+    IntegerOverflow(String),
+    DivisionByZero,
+    InvalidIndex { left: &'static str, index: &'static str },
+    // a value such as an array used as the key of a hash, which only integers, booleans, and
+    //    strings can be
+    UnusableHashKey(&'static str),
+    // text given to csv_parse which isn't valid csv, and the line the problem is on
+    InvalidCsv { line: usize, reason: &'static str },
+    // a color name given to the color builtin which it doesn't know
+    UnknownColor(String),
+    // an error along with where in the source it happened, added by the evaluator
+    At { error: Box<RuntimeError>, span: Span },
+}
+
+impl RuntimeError {
+    pub fn invalid_infix_operands(operator: Operator, left: &Object, right: &Object) -> RuntimeError {
+        RuntimeError::InvalidInfixOperands { operator, left: left.type_name(), right: right.type_name() }
+    }
+
+    /// the error located at the span, unless it was already located somewhere more specific,
+    ///    or the span is the default given to an ast built by hand rather than parsed
+    pub fn at(self, span: Span) -> RuntimeError {
+        match self {
+            RuntimeError::At { .. } => self,
+            _ if span == Span::default() => self,
+            error => RuntimeError::At { error: Box::new(error), span },
+        }
+    }
+
+    /// where in the source the error happened, if it is known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RuntimeError::At { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    pub fn without_location(self) -> RuntimeError {
+        match self {
+            RuntimeError::At { error, .. } => *error,
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::At { error, span } => {
+                write!(f, "runtime error at {}:{}: ", span.line, span.column)?;
+                error.fmt_message(f)
+            },
+            error => {
+                write!(f, "runtime error: ")?;
+                error.fmt_message(f)
+            },
+        }
+    }
+}
+
+impl RuntimeError {
+    fn fmt_message(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::InvalidPrefixOperand { prefix, operand } =>
+                write!(f, "`{}` cannot be applied to {}", prefix, operand),
+            RuntimeError::InvalidInfixOperands { operator, left, right } =>
+                write!(f, "`{}` cannot be applied to {} and {}", operator, left, right),
+            RuntimeError::InvalidLogicalOperand { operator, operand } =>
+                write!(f, "`{}` cannot be applied to {}", operator, operand),
+            RuntimeError::UnknownIdentifier(name) => write!(f, "`{}` is not defined", name),
+            RuntimeError::UninitializedVariable(name) => write!(f, "`{}` was read before it was assigned a value", name),
+            RuntimeError::UndefinedAssignment(name) => write!(f, "cannot assign to `{}` which is not defined", name),
+            RuntimeError::NotCallable(type_name) => write!(f, "{} is not a function", type_name),
+            RuntimeError::WrongArgumentCount { expected, found } =>
+                write!(f, "function expects {} arguments but was called with {}", expected, found),
+            RuntimeError::BuiltinArgumentCount { name, min, max, found } => {
+                write!(f, "wrong number of arguments to `{}`: got {}, want ", name, found)?;
+                match max {
+                    Some(max) if max == min => write!(f, "{}", min),
+                    Some(max) => write!(f, "{} to {}", min, max),
+                    None => write!(f, "at least {}", min),
+                }
+            },
+            RuntimeError::BuiltinArgumentType { name, expected, found } =>
+                write!(f, "argument to `{}` must be {}, got {}", name, expected, found),
+            RuntimeError::CapabilityDenied(capability) => write!(f, "{} is not permitted", capability),
+            RuntimeError::ValueTooLarge { kind: "string", limit } =>
+                write!(f, "value too large, strings are limited to {} bytes", limit),
+            RuntimeError::ValueTooLarge { kind: "hash", limit } =>
+                write!(f, "value too large, hashes are limited to {} pairs", limit),
+            RuntimeError::ValueTooLarge { kind, limit } =>
+                write!(f, "value too large, {}s are limited to {} elements", kind, limit),
+            RuntimeError::ReplayDiverged(kind) =>
+                write!(f, "the program read the {} where the replay log doesn't have it", kind),
+            RuntimeError::StackOverflow => write!(f, "stack overflow, function calls are nested too deeply"),
+            RuntimeError::StackUnderflow => write!(f, "stack underflow"),
+            RuntimeError::IntegerOverflow(operation) =>
+                write!(f, "integer overflow in `{}`, integers range from {} to {}", operation, i32::MIN, i32::MAX),
+            RuntimeError::DivisionByZero => write!(f, "division by zero"),
+            RuntimeError::InvalidIndex { left, index } => write!(f, "{} cannot be indexed by {}", left, index),
+            RuntimeError::UnusableHashKey(type_name) => write!(f, "{} cannot be used as a hash key", type_name),
+            RuntimeError::InvalidCsv { line, reason } => write!(f, "invalid csv on line {}, {}", line, reason),
+            RuntimeError::UnknownColor(name) =>
+                write!(f, "unknown color `{}`, expected red, green, yellow, blue, magenta, or cyan", name),
+            RuntimeError::At { error, .. } => error.fmt_message(f),
+        }
+    }
+}
+
+/// applies an arithmetic operator to two integers, an error rather than wrapping around when
+///    the result doesn't fit, so both engines agree whichever way they were built
+pub fn integer_arithmetic(operator: Operator, left: i32, right: i32) -> Result<i32, RuntimeError> {
+    let result = match operator {
+        Operator::Divide if right == 0 => return Err(RuntimeError::DivisionByZero),
+        Operator::Plus => left.checked_add(right),
+        Operator::Minus => left.checked_sub(right),
+        Operator::Multiply => left.checked_mul(right),
+        // the only division which overflows is the smallest integer by -1
+        Operator::Divide if right == -1 => left.checked_neg(),
+        Operator::Divide => Some(left / right),
+        _ => unreachable!("{} is not an arithmetic operator", operator),
+    };
+
+    result.ok_or_else(|| RuntimeError::IntegerOverflow(format!("{} {} {}", left, operator, right)))
+}
+
+/// the value of an operand of `&&` or `||`, which must be a boolean
+pub fn boolean_operand(operator: Operator, operand: &Object) -> Result<bool, RuntimeError> {
+    match operand {
+        Object::Boolean(value) => Ok(*value),
+        operand => Err(RuntimeError::InvalidLogicalOperand { operator, operand: operand.type_name() }),
+    }
+}
+
+/// a hash of the pairs, a later pair replacing an earlier one with the same key
+pub fn build_hash(pairs: Vec<(Object, Object)>) -> Result<Object, RuntimeError> {
+    let pairs = pairs.into_iter()
+        .map(|(key, value)| Ok((HashKey::from_object(&key)?, value)))
+        .collect::<Result<BTreeMap<_, _>, RuntimeError>>()?;
+
+    Ok(Object::Hash(Rc::new(pairs)))
+}
+
+/// the element at the index of an array or the value of a key in a hash, null when the index
+///    is out of range or the key isn't in the hash
+pub fn index_object(left: &Object, index: &Object) -> Result<Object, RuntimeError> {
+    match (left, index) {
+        (Object::Array(elements), Object::Integer(index)) => Ok(
+            usize::try_from(*index).ok()
+                .and_then(|index| elements.get(index))
+                .cloned()
+                .unwrap_or(Object::Null)
+        ),
+        (Object::Hash(pairs), key) => Ok(pairs.get(&HashKey::from_object(key)?).cloned().unwrap_or(Object::Null)),
+        _ => Err(RuntimeError::InvalidIndex { left: left.type_name(), index: index.type_name() }),
+    }
+}
+
+/// the string an interpolated string literal evaluates to, the values of its pieces joined as
+///    they are displayed, so strings are included without quotes
+pub fn interpolate(parts: &[Object]) -> Object {
+    Object::String(parts.iter().map(|part| part.to_string()).collect::<String>().into())
+}
+
+/// negates an integer, an error for the smallest integer which has no positive counterpart
+pub fn integer_negate(value: i32) -> Result<i32, RuntimeError> {
+    value.checked_neg().ok_or_else(|| RuntimeError::IntegerOverflow(format!("-({})", value)))
 }
 
-fn eval_expr(expression: Expr, env: &mut Env) -> Object {
-    match expression {
-        Expr::String(string) => Object::String(string),
-        Expr::Const(num) => Object::Integer(num),
-        Expr::Boolean(val) => Object::Boolean(val),
+/// converts both operands to floats when at least one of them is a float and the other is a number,
+/// following the rule that mixed integer and float arithmetic produces a float
+pub fn float_operands(left: &Object, right: &Object) -> Option<(f64, f64)> {
+    match (left, right) {
+        (Object::Float(left), Object::Float(right)) => Some((*left, *right)),
+        (Object::Integer(left), Object::Float(right)) => Some((*left as f64, *right)),
+        (Object::Float(left), Object::Integer(right)) => Some((*left, *right as f64)),
+        _ => None,
+    }
+}
+
+fn eval_expr(expression: &Expr, env: &mut Env) -> Result<Object, RuntimeError> {
+    if let Some(profiler) = env.profiler_mut() {
+        profiler.count();
+    }
+    // the expressions which create new objects, rather than passing on existing ones
+    let allocates = matches!(
+        expression,
+        Expr::String(_) | Expr::Array(_) | Expr::Hash(_) | Expr::Function { .. } | Expr::Infix { operator: Operator::Plus, .. }
+            | Expr::Interpolation(_)
+    );
+
+    let result = match expression {
+        Expr::String(string) => Object::String(string.as_str().into()),
+        Expr::Const(num) => Object::Integer(*num),
+        Expr::Float(num) => Object::Float(*num),
+        Expr::Boolean(val) => Object::Boolean(*val),
+        Expr::Null => Object::Null,
         Expr::Prefix { prefix: Prefix::Bang, value: expr } => {
-            match eval_expr(*expr, env) {
+            match eval_expr(expr, env)? {
                 Object::Boolean(val) => Object::Boolean(!val),
-                _ => panic!("! operator only valid for boolean type"),
+                operand => return Err(RuntimeError::InvalidPrefixOperand { prefix: Prefix::Bang, operand: operand.type_name() }),
             }
         },
         Expr::Prefix { prefix: Prefix::Minus, value: expr } => {
-            match eval_expr(*expr, env) {
-                Object::Integer(val) => Object::Integer(-val),
-                _ => panic!("minus operator only valid for integer type"),
+            match eval_expr(expr, env)? {
+                Object::Integer(val) => Object::Integer(integer_negate(val)?),
+                Object::Float(val) => Object::Float(-val),
+                operand => return Err(RuntimeError::InvalidPrefixOperand { prefix: Prefix::Minus, operand: operand.type_name() }),
             }
         },
         Expr::Infix { left, operator: Operator::Plus, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
-                (Object::Integer(left), Object::Integer(right)) => Object::Integer(left + right),
-                (Object::String(left), Object::String(right)) => Object::String(left + &right),
-                _ => panic!("plus operator used on invalid types")
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
+                (Object::Integer(left), Object::Integer(right)) => Object::Integer(integer_arithmetic(Operator::Plus, left, right)?),
+                (Object::String(left), Object::String(right)) => Object::String((left.to_string() + &right).into()),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Float(left + right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::Plus, &left, &right)),
+                }
             }
         },
         Expr::Infix { left, operator: Operator::Minus, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
-                (Object::Integer(left), Object::Integer(right)) => Object::Integer(left - right),
-                _ => panic!("minus operator only valid on integer types")
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
+                (Object::Integer(left), Object::Integer(right)) => Object::Integer(integer_arithmetic(Operator::Minus, left, right)?),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Float(left - right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::Minus, &left, &right)),
+                }
             }
         },
         Expr::Infix { left, operator: Operator::Multiply, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
-                (Object::Integer(left), Object::Integer(right)) => Object::Integer(left * right),
-                _ => panic!("multiply operator only valid on integer types")
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
+                (Object::Integer(left), Object::Integer(right)) => Object::Integer(integer_arithmetic(Operator::Multiply, left, right)?),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Float(left * right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::Multiply, &left, &right)),
+                }
             }
         },
         Expr::Infix { left, operator: Operator::Divide, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
-                (Object::Integer(left), Object::Integer(right)) => Object::Integer(left / right),
-                _ => panic!("divide operator only valid on integer types")
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
+                (Object::Integer(left), Object::Integer(right)) => Object::Integer(integer_arithmetic(Operator::Divide, left, right)?),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Float(left / right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::Divide, &left, &right)),
+                }
             }
         },
         Expr::Infix { left, operator: Operator::LessThan, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
                 (Object::Integer(left), Object::Integer(right)) => Object::Boolean(left < right),
-                _ => panic!("less than operator only valid on integer types")
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Boolean(left < right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::LessThan, &left, &right)),
+                }
             }
         },
         Expr::Infix { left, operator: Operator::GreaterThan, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
                 (Object::Integer(left), Object::Integer(right)) => Object::Boolean(left > right),
-                _ => panic!("greater than operator only valid on integer types")
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Boolean(left > right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::GreaterThan, &left, &right)),
+                }
+            }
+        },
+        Expr::Infix { left, operator: Operator::LessThanOrEqual, right } => {
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
+                (Object::Integer(left), Object::Integer(right)) => Object::Boolean(left <= right),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Boolean(left <= right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::LessThanOrEqual, &left, &right)),
+                }
+            }
+        },
+        Expr::Infix { left, operator: Operator::GreaterThanOrEqual, right } => {
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
+                (Object::Integer(left), Object::Integer(right)) => Object::Boolean(left >= right),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Boolean(left >= right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::GreaterThanOrEqual, &left, &right)),
+                }
             }
         },
         Expr::Infix { left, operator: Operator::Equals, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
                 (Object::Integer(left), Object::Integer(right)) => Object::Boolean(left == right),
                 (Object::Boolean(left), Object::Boolean(right)) => Object::Boolean(left == right),
-                _ => panic!("equals operator used on invalid types")
+                // any value can be compared with null, which is only equal to itself
+                (Object::Null, right) => Object::Boolean(right == Object::Null),
+                (left, Object::Null) => Object::Boolean(left == Object::Null),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Boolean(left == right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::Equals, &left, &right)),
+                }
             }
         },
         Expr::Infix { left, operator: Operator::NotEquals, right } => {
-            match (eval_expr(*left, env), eval_expr(*right, env)) {
+            match (eval_expr(left, env)?, eval_expr(right, env)?) {
                 (Object::Integer(left), Object::Integer(right)) => Object::Boolean(left != right),
                 (Object::Boolean(left), Object::Boolean(right)) => Object::Boolean(left != right),
-                _ => panic!("not equals operator used on invalid types")
+                (Object::Null, right) => Object::Boolean(right != Object::Null),
+                (left, Object::Null) => Object::Boolean(left != Object::Null),
+                (left, right) => match float_operands(&left, &right) {
+                    Some((left, right)) => Object::Boolean(left != right),
+                    None => return Err(RuntimeError::invalid_infix_operands(Operator::NotEquals, &left, &right)),
+                }
             }
         },
+        Expr::Infix { left, operator: Operator::And, right } => {
+            // the right operand is only evaluated if the left operand is true
+            let value = boolean_operand(Operator::And, &eval_expr(left, env)?)?
+                && boolean_operand(Operator::And, &eval_expr(right, env)?)?;
+            Object::Boolean(value)
+        },
+        Expr::Infix { left, operator: Operator::Or, right } => {
+            // the right operand is only evaluated if the left operand is false
+            let value = boolean_operand(Operator::Or, &eval_expr(left, env)?)?
+                || boolean_operand(Operator::Or, &eval_expr(right, env)?)?;
+            Object::Boolean(value)
+        },
+        // only reached when the program wasn't desugared before it was evaluated
+        Expr::Ternary { .. } => eval_expr(&desugar_expr(expression.clone()), env)?,
         Expr::If { condition, consequence, alternative } => {
-            if eval_expr(*condition, env) == Object::Boolean(true) {
-                eval_statements(consequence, env)
+            if eval_expr(condition, env)? == Object::Boolean(true) {
+                eval_statements(consequence, env)?
             } else {
-                eval_statements(alternative, env)
+                eval_statements(alternative, env)?
             }
         },
-        Expr::Ident(name) => env.get(&name).expect("attempted access to invalid binding"),
-        Expr::Function{parameters, body} => Object::Function {parameters, body},
-        Expr::Call{function, arguments} => {
-            let (parameters, body) = match *function {
-                Expr::Ident(func_name) => {
-                    match env.get(&func_name) {
-                        Some(Object::Function {parameters, body}) => (parameters, body),
-                        None => {
-                            let arguments = arguments.into_iter().map(|expr| eval_expr(expr, env)).collect();
-                            return eval_builtin(&func_name, arguments).expect("error calling function");
-                        },
-                        _ => panic!("attempted to call non-function"),
-                    }
-                }
-                Expr::Function {parameters, body} => (parameters, body),
-                _ => panic!("attempted to call non-function"),
+        Expr::Ident(name, span) => match env.get(name) {
+            Some(obj) => obj,
+            None if env.is_declared(name) => return Err(RuntimeError::UninitializedVariable(name.clone()).at(*span)),
+            None => match builtins::lookup(name) {
+                // builtins are only visible when no binding shadows them
+                Some(index) => Object::Builtin(index),
+                None => return Err(RuntimeError::UnknownIdentifier(name.clone()).at(*span)),
+            },
+        },
+        Expr::Function{parameters, body} => Object::Function {
+            parameters: Rc::new(parameters.clone()),
+            body: Rc::new(body.clone()),
+        },
+        Expr::Call{function, arguments, span} => {
+            // functions are profiled under the name they were called by
+            let name = match function.as_ref() {
+                Expr::Ident(name, _) => name.clone(),
+                _ => String::from(profile::ANONYMOUS),
             };
+            let function = eval_expr(function, env)?;
+            let arguments = arguments.iter()
+                .map(|arg_value| eval_expr(arg_value, env))
+                .collect::<Result<Vec<Object>, RuntimeError>>()?;
 
-            // run user defined function
-            assert_eq!(parameters.len(), arguments.len(), "called function with wrong number of parameters");
-
-            let mut env_func = Env::new();
-            for (parameter, arg_value) in parameters.into_iter().zip(arguments.into_iter()) {
-                env_func.set(parameter, eval_expr(arg_value, env));
+            if let Some(profiler) = env.profiler_mut() {
+                profiler.enter(&name);
+            }
+            let line = env.line();
+            env.trace(TraceEvent::Call { function: &name, line });
+            // errors inside the function body are already located at the statement which failed,
+            //    the call is where calling the function itself failed
+            let result = apply_function(function, arguments, env).map_err(|err| err.at(*span));
+            env.trace(TraceEvent::Return { function: &name, line });
+            if let Some(profiler) = env.profiler_mut() {
+                profiler.exit();
             }
 
-            eval_return_scope(body, &mut env_func)
+            result?
         },
+        Expr::Array(elements) => Object::Array(Rc::new(
+            elements.iter()
+                .map(|element| eval_expr(element, env))
+                .collect::<Result<Vec<Object>, RuntimeError>>()?
+        )),
+        Expr::Hash(pairs) => build_hash(
+            pairs.iter()
+                .map(|(key, value)| Ok((eval_expr(key, env)?, eval_expr(value, env)?)))
+                .collect::<Result<Vec<(Object, Object)>, RuntimeError>>()?
+        )?,
+        Expr::Index { left, index } => {
+            let left = eval_expr(left, env)?;
+            index_object(&left, &eval_expr(index, env)?)?
+        },
+        Expr::Interpolation(parts) => interpolate(
+            &parts.iter()
+                .map(|part| eval_expr(part, env))
+                .collect::<Result<Vec<Object>, RuntimeError>>()?
+        ),
+    };
+
+    if allocates {
+        env.check_size(&result)?;
+        env.record_allocation(&result);
     }
+
+    Ok(result)
 }
 
-fn eval_builtin(func_name: &str, arguments: Vec<Object>) -> Option<Object> {
-    match (func_name, arguments.as_slice()) {
-        ("len", [Object::String(string)]) => Some(Object::Integer(string.len() as i32)),
-        _ => None,
+pub fn apply_function(function: Object, arguments: Vec<Object>, env: &mut Env) -> Result<Object, RuntimeError> {
+    let (parameters, body) = match function {
+        Object::Function {parameters, body} => (parameters, body),
+        Object::Builtin(index) => {
+            let result = builtins::call(index, &arguments, env)?;
+            env.record_allocation(&result);
+            return Ok(result);
+        },
+        Object::Native(native) => {
+            let result = native.call(&arguments)?;
+            env.check_size(&result)?;
+            env.record_allocation(&result);
+            return Ok(result);
+        },
+        obj => return Err(RuntimeError::NotCallable(obj.type_name())),
+    };
+
+    // run user defined function
+    if parameters.len() != arguments.len() {
+        return Err(RuntimeError::WrongArgumentCount { expected: parameters.len(), found: arguments.len() });
+    }
+
+    env.push_scope();
+    for (parameter, arg_value) in parameters.iter().zip(arguments) {
+        env.set(parameter.clone(), arg_value);
     }
+    // the scope is popped before checking for errors, so the env is still usable afterwards
+    let result = eval_return_scope(&body, env);
+    env.pop_scope();
+
+    result
+}
+
+fn eval_statement(statement: &Statement, env: &mut Env) -> Result<Object, RuntimeError> {
+    // the caller's line is restored afterwards, so allocations after a call returns are
+    //    attributed to the caller rather than the last line of the function
+    let statement_line = statement.span().line;
+    let line = env.set_line(statement_line);
+    env.trace(TraceEvent::StatementEnter { line: statement_line });
+    let result = eval_statement_at_line(statement, env).map_err(|err| err.at(statement.span()));
+    env.trace(TraceEvent::StatementExit { line: statement_line });
+    env.set_line(line);
+
+    result
 }
 
-fn eval_statement(statement: Statement, env: &mut Env) -> Object {
-    match statement {
-        Statement::Expression(expr) => eval_expr(expr, env),
-        Statement::Let{name, value} => {
-            let value = eval_expr(value, env);
-            env.set(name, value.clone());
+fn eval_statement_at_line(statement: &Statement, env: &mut Env) -> Result<Object, RuntimeError> {
+    Ok(match statement {
+        Statement::Expression(expr, _) => eval_expr(expr, env)?,
+        Statement::Let{name, value, ..} => {
+            let value = eval_expr(value, env)?;
+            env.set(name.clone(), value.clone());
             value
         },
-        Statement::Return{value: expr} => Object::Return(Box::new(eval_expr(expr, env))),
-    }
+        Statement::Declare{name, ..} => {
+            env.declare(name.clone());
+            Object::Null
+        },
+        Statement::Assign{name, value, ..} => {
+            let value = eval_expr(value, env)?;
+            if !env.assign(name, value.clone()) {
+                return Err(RuntimeError::UndefinedAssignment(name.clone()));
+            }
+            value
+        },
+        Statement::Return{value: expr, ..} => Object::Return(Box::new(eval_expr(expr, env)?)),
+    })
 }
 
 /// similar to eval_return_scope but doesn't unwrap Return types
 /// useful for if-else blocks where the return should return from the parent scope as well
-fn eval_statements(statements: Vec<Statement>, env: &mut Env) -> Object {
+fn eval_statements(statements: &[Statement], env: &mut Env) -> Result<Object, RuntimeError> {
     let mut result = Object::Null;
 
     for statement in statements {
-        result = eval_statement(statement, env);
+        result = eval_statement(statement, env)?;
 
         if let &Object::Return(_) = &result {
-            return result;
+            return Ok(result);
         }
     }
 
-    result
+    Ok(result)
 }
 
-pub fn eval_return_scope(statements: Vec<Statement>, env: &mut Env) -> Object {
-    let result = eval_statements(statements, env);
+pub fn eval_return_scope(statements: &[Statement], env: &mut Env) -> Result<Object, RuntimeError> {
+    let result = eval_statements(statements, env)?;
 
-    match result {
+    Ok(match result {
         // unwrap Return type
         Object::Return(res) => *res,
         _ => result,
-    }
+    })
+}
+
+/// runs a script, then calls its `main` function if it defines one, returning the value of
+///    `main` or of the script when there is none. the arguments are passed to `main` as an
+///    array of strings, unless it takes no parameters
+pub fn run_main(statements: &[Statement], args: &[String], env: &mut Env) -> Result<Object, RuntimeError> {
+    let result = eval_return_scope(statements, env)?;
+    let main = match env.get_global("main") {
+        Some(main) => main.clone(),
+        None => return Ok(result),
+    };
+
+    let arguments = match &main {
+        Object::Function { parameters, .. } if parameters.is_empty() => vec![],
+        _ => vec![Object::Array(Rc::new(args.iter().map(|arg| Object::from(arg.as_str())).collect()))],
+    };
+    apply_function(main, arguments, env)
 }
 
 #[cfg(test)]
@@ -172,6 +689,9 @@ mod tests {
     use super::*;
     use crate::lexer::lex;
     use crate::parser::parse;
+    use crate::desugar::desugar;
+    use crate::lexer::Span;
+    use crate::builtins::CapturedOutput;
 
     #[test]
     fn eval_int_literal() {
@@ -180,7 +700,10 @@ mod tests {
 
     #[test]
     fn eval_string_literal() {
-        test_eval(r#""foo bar";"#, Object::String(String::from("foo bar")));
+        test_eval(r#""foo bar";"#, Object::String("foo bar".into()));
+        // escapes are resolved by the lexer, so the value holds the characters they stand for
+        test_eval(r#""say \"hi\"\n\tback\\slash";"#, Object::String("say \"hi\"\n\tback\\slash".into()));
+        test_eval(r#"len("a\nb");"#, Object::Integer(3));
     }
 
     #[test]
@@ -189,6 +712,16 @@ mod tests {
         test_eval("false;", Object::Boolean(false));
     }
 
+    #[test]
+    fn eval_null() {
+        test_eval("null;", Object::Null);
+        test_eval("null == null;", Object::Boolean(true));
+        test_eval("let a; a = null; a != null;", Object::Boolean(false));
+        test_eval("1 == null;", Object::Boolean(false));
+        test_eval("if (false) { 1; } == null;", Object::Boolean(true));
+        test_eval_error("null + 1;", RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "null", right: "integer" });
+    }
+
     #[test]
     fn eval_bang() {
         test_eval("!true;", Object::Boolean(false));
@@ -210,6 +743,10 @@ mod tests {
         test_eval("5 / 5;", Object::Integer(1));
         test_eval("5 > 1;", Object::Boolean(true));
         test_eval("5 < 1;", Object::Boolean(false));
+        test_eval("5 >= 5;", Object::Boolean(true));
+        test_eval("5 >= 6;", Object::Boolean(false));
+        test_eval("5 <= 5;", Object::Boolean(true));
+        test_eval("6 <= 5;", Object::Boolean(false));
         test_eval("5 == 1;", Object::Boolean(false));
         test_eval("5 != 1;", Object::Boolean(true));
         test_eval("true == true;", Object::Boolean(true));
@@ -217,9 +754,61 @@ mod tests {
         test_eval("(1 > 2) == false;", Object::Boolean(true));
     }
 
+    #[test]
+    fn eval_and_or() {
+        test_eval("true && true;", Object::Boolean(true));
+        test_eval("true && false;", Object::Boolean(false));
+        test_eval("false || true;", Object::Boolean(true));
+        test_eval("false || false;", Object::Boolean(false));
+        test_eval("1 < 2 && 2 < 3;", Object::Boolean(true));
+    }
+
+    #[test]
+    fn eval_and_or_short_circuit() {
+        // the right operand would panic on an undefined binding if it were evaluated
+        test_eval("false && undefined;", Object::Boolean(false));
+        test_eval("true || undefined;", Object::Boolean(true));
+    }
+
+    #[test]
+    fn eval_without_desugar() {
+        let ast = parse(&mut lex("let x = 3; x > 2 ? x * 2 : 0;").unwrap()).unwrap();
+
+        assert_eq!(Ok(Object::Integer(6)), eval_return_scope(&ast, &mut Env::new()));
+    }
+
+    #[test]
+    fn eval_and_or_require_booleans() {
+        let error = |operator, operand| RuntimeError::InvalidLogicalOperand { operator, operand };
+        test_eval_error("5 && true;", error(Operator::And, "integer"));
+        test_eval_error("true && 5;", error(Operator::And, "integer"));
+        test_eval_error("null || false;", error(Operator::Or, "null"));
+        test_eval_error(r#"false || "a";"#, error(Operator::Or, "string"));
+        // the right operand isn't checked when it isn't evaluated
+        test_eval("false && 5;", Object::Boolean(false));
+        test_eval("true || 5;", Object::Boolean(true));
+    }
+
+    #[test]
+    fn eval_float() {
+        test_eval("1.5;", Object::Float(1.5));
+        test_eval("-1.5;", Object::Float(-1.5));
+        test_eval("1.5 + 1.5;", Object::Float(3.0));
+        test_eval("1 + 0.5;", Object::Float(1.5));
+        test_eval("0.5 * 4;", Object::Float(2.0));
+        test_eval("3 / 2.0;", Object::Float(1.5));
+        test_eval("3 / 2;", Object::Integer(1));
+        test_eval("2.5 - 3;", Object::Float(-0.5));
+        test_eval("1.5 > 1;", Object::Boolean(true));
+        test_eval("1 < 0.5;", Object::Boolean(false));
+        test_eval("2.0 >= 2;", Object::Boolean(true));
+        test_eval("2.0 == 2;", Object::Boolean(true));
+        test_eval("2.5 != 2.5;", Object::Boolean(false));
+    }
+
     #[test]
     fn eval_infix_string() {
-        test_eval(r#""hello " + "world";"#, Object::String(String::from("hello world")));
+        test_eval(r#""hello " + "world";"#, Object::String("hello world".into()));
     }
 
     #[test]
@@ -243,6 +832,15 @@ mod tests {
         test_eval("if (1 < 2) { 10; } else { 11; };", Object::Integer(10));
     }
 
+    #[test]
+    fn eval_ternary() {
+        test_eval("1 < 2 ? 10 : 11;", Object::Integer(10));
+        test_eval("let x = 3; x > 5 ? \"big\" : x > 1 ? \"medium\" : \"small\";", Object::String("medium".into()));
+        test_eval("let f = fn(n) { n == 0 ? 1 : n * f(n - 1); }; f(5);", Object::Integer(120));
+        // only the chosen branch is evaluated
+        test_eval("false ? y : 2;", Object::Integer(2));
+    }
+
     #[test]
     fn eval_return() {
         test_eval("return 10;", Object::Integer(10));
@@ -271,8 +869,8 @@ mod tests {
     #[test]
     fn eval_function() {
         test_eval("fn(x) { x; };", Object::Function {
-            parameters: vec![String::from("x")],
-            body: vec![Statement::Expression(Expr::Ident(String::from("x")))]
+            parameters: Rc::new(vec![String::from("x")]),
+            body: Rc::new(vec![Statement::Expression(Expr::Ident(String::from("x"), Span::default()), Span::default())])
         });
         test_eval("let identity = fn(x) { x; }; identity(5);", Object::Integer(5));
         test_eval("let identity = fn(x) { return x; }; identity(5);", Object::Integer(5));
@@ -280,22 +878,388 @@ mod tests {
         test_eval("let add = fn(x, y) { x + y; }; add(5, 5);", Object::Integer(10));
         test_eval("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", Object::Integer(20));
         test_eval("let add = fn(x, y) { return x + y; }; let three = add(1, 2); 5;", Object::Integer(5)); // return value inside the function should not cause the entire program to return
+        test_eval("fn(x) { x * 2; }(5);", Object::Integer(10));
+        test_eval("let double = fn(x) { x * 2; }; let get = fn() { double; }; get()(4);", Object::Integer(8));
+        test_eval("let my_var2 = 3; let add_one = fn(_n) { _n + 1; }; add_one(my_var2);", Object::Integer(4));
+    }
+
+    #[test]
+    fn eval_assign() {
+        test_eval("let a = 1; a = a + 1; a;", Object::Integer(2));
+        test_eval("let a = 1; a = 5;", Object::Integer(5));
+        test_eval("let a = 1; let f = fn() { a = 10; }; f(); a;", Object::Integer(10));
+        // assigning to a parameter doesn't affect a global of the same name
+        test_eval("let a = 1; let f = fn(a) { a = 10; }; f(2); a;", Object::Integer(1));
+    }
+
+    #[test]
+    fn eval_assign_undefined() {
+        test_eval_error("a = 1;", RuntimeError::UndefinedAssignment(String::from("a")));
+    }
+
+    #[test]
+    fn eval_declare() {
+        test_eval("let a;", Object::Null);
+        test_eval("let a; a = 5; a;", Object::Integer(5));
+        test_eval("let a; let f = fn() { a = 1; }; f(); a;", Object::Integer(1));
+    }
+
+    #[test]
+    fn eval_declare_read_before_write() {
+        test_eval_error("let a; a + 1;", RuntimeError::UninitializedVariable(String::from("a")));
+    }
+
+    #[test]
+    fn eval_function_scope() {
+        // functions can see globals, including themselves
+        test_eval("let fact = fn(n) { if (n == 0) { 1; } else { n * fact(n - 1); }; }; fact(5);", Object::Integer(120));
+        // but not the bindings of their caller
+        test_eval("let x = 1; let f = fn() { x; }; let g = fn(x) { f(); }; g(2);", Object::Integer(1));
     }
 
     fn test_eval(input: &str, expected: Object) {
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = desugar(parse(&mut tokens).unwrap());
         let mut env = Env::new();
-        let obj = eval_return_scope(ast, &mut env);
+        let obj = eval_return_scope(&ast, &mut env);
 
         assert_eq!(
-            expected,
+            Ok(expected),
             obj
         );
     }
 
+    fn test_eval_error(input: &str, expected: RuntimeError) {
+        let mut tokens = lex(input).unwrap();
+        let ast = desugar(parse(&mut tokens).unwrap());
+        let mut env = Env::new();
+
+        // locations are checked by eval_error_locations
+        assert_eq!(
+            Err(expected),
+            eval_return_scope(&ast, &mut env).map_err(RuntimeError::without_location)
+        );
+    }
+
+    #[test]
+    fn eval_error_locations() {
+        let error = |input: &str| {
+            let ast = desugar(parse(&mut lex(input).unwrap()).unwrap());
+            eval_return_scope(&ast, &mut Env::new()).unwrap_err().to_string()
+        };
+
+        assert_eq!("runtime error at 2:5: `y` is not defined", error("let x = 1;
+x + y;"));
+        assert_eq!("runtime error at 1:12: `x` was read before it was assigned a value", error("let x; 1 + x;"));
+        assert_eq!("runtime error at 1:1: argument to `len` must be string or array, got integer", error("len(1);"));
+        assert_eq!(
+            "runtime error at 1:26: function expects 2 arguments but was called with 1",
+            error("let f = fn(a, b) { a; }; f(1);")
+        );
+        // an error inside a function is located in its body rather than at the call
+        assert_eq!("runtime error at 2:3: division by zero", error("let f = fn(x) {
+  x / 0;
+};
+f(1);"));
+        assert_eq!("runtime error at 1:1: integer overflow in `2147483647 + 1`, integers range from -2147483648 to 2147483647", error("2147483647 + 1;"));
+        assert_eq!("runtime error at 1:5: `nope` is not defined", error(r#""a${nope}";"#));
+
+        // an ast built by hand has no locations to report
+        let ast = vec![crate::ast::expr(crate::ast::ident("x"))];
+        assert_eq!(
+            Err(RuntimeError::UnknownIdentifier(String::from("x"))),
+            eval_return_scope(&ast, &mut Env::new())
+        );
+        assert_eq!(
+            Some(Span { start: 3, end: 4, line: 1, column: 4 }),
+            eval_return_scope(&parse(&mut lex("1; x;").unwrap()).unwrap(), &mut Env::new()).unwrap_err().span()
+        );
+    }
+
+    #[test]
+    fn eval_runtime_errors() {
+        test_eval_error("!5;", RuntimeError::InvalidPrefixOperand { prefix: Prefix::Bang, operand: "integer" });
+        test_eval_error("-true;", RuntimeError::InvalidPrefixOperand { prefix: Prefix::Minus, operand: "boolean" });
+        test_eval_error(r#"1 + "a";"#, RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "integer", right: "string" });
+        test_eval_error("true < false;", RuntimeError::InvalidInfixOperands { operator: Operator::LessThan, left: "boolean", right: "boolean" });
+        test_eval_error("x;", RuntimeError::UnknownIdentifier(String::from("x")));
+        test_eval_error("f(1);", RuntimeError::UnknownIdentifier(String::from("f")));
+        test_eval_error("let x = 5; x();", RuntimeError::NotCallable("integer"));
+        test_eval_error("let f = fn(a, b) { a; }; f(1);", RuntimeError::WrongArgumentCount { expected: 2, found: 1 });
+        test_eval_error("len(1, true);", RuntimeError::BuiltinArgumentCount { name: "len", min: 1, max: Some(1), found: 2 });
+        test_eval_error("len(1);", RuntimeError::BuiltinArgumentType { name: "len", expected: "string or array", found: "integer" });
+        // errors inside a function body propagate out of the call
+        test_eval_error("let f = fn() { y; }; f();", RuntimeError::UnknownIdentifier(String::from("y")));
+    }
+
+    #[test]
+    fn eval_division_by_zero() {
+        test_eval_error("5 / 0;", RuntimeError::DivisionByZero);
+        test_eval_error("let zero = 0; let f = fn(x) { x / zero; }; f(1);", RuntimeError::DivisionByZero);
+        // floats follow IEEE 754, dividing by zero gives infinity
+        test_eval("1.0 / 0;", Object::Float(f64::INFINITY));
+        assert_eq!("runtime error: division by zero", RuntimeError::DivisionByZero.to_string());
+    }
+
+    #[test]
+    fn eval_integer_overflow() {
+        test_eval_error("2147483647 + 1;", RuntimeError::IntegerOverflow(String::from("2147483647 + 1")));
+        test_eval_error("-2147483647 - 2;", RuntimeError::IntegerOverflow(String::from("-2147483647 - 2")));
+        test_eval_error("65536 * 65536;", RuntimeError::IntegerOverflow(String::from("65536 * 65536")));
+        test_eval_error("let min = -2147483647 - 1; min / -1;", RuntimeError::IntegerOverflow(String::from("-2147483648 / -1")));
+        test_eval_error("let min = -2147483647 - 1; -min;", RuntimeError::IntegerOverflow(String::from("-(-2147483648)")));
+        test_eval("-2147483647 - 1;", Object::Integer(i32::MIN));
+        assert_eq!(
+            "runtime error: integer overflow in `2147483647 + 1`, integers range from -2147483648 to 2147483647",
+            RuntimeError::IntegerOverflow(String::from("2147483647 + 1")).to_string()
+        );
+    }
+
+    #[test]
+    fn eval_env_usable_after_error() {
+        let mut env = Env::new();
+        let mut tokens = lex("let a = 1; let f = fn(a) { a + true; };").unwrap();
+        eval_return_scope(&parse(&mut tokens).unwrap(), &mut env).unwrap();
+
+        let mut tokens = lex("f(2);").unwrap();
+        assert!(eval_return_scope(&parse(&mut tokens).unwrap(), &mut env).is_err());
+
+        // the scope of the failed call has been popped, so globals are visible again
+        let mut tokens = lex("a;").unwrap();
+        assert_eq!(Ok(Object::Integer(1)), eval_return_scope(&parse(&mut tokens).unwrap(), &mut env));
+    }
+
+    #[test]
+    fn eval_runtime_error_display() {
+        assert_eq!(
+            "runtime error: `+` cannot be applied to integer and string",
+            RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "integer", right: "string" }.to_string()
+        );
+    }
+
     #[test]
     fn eval_builtin_len() {
         test_eval(r#"len("hello");"#, Object::Integer(5));
     }
+
+    #[test]
+    fn eval_array() {
+        test_eval("[1, 2 * 2, true];", Object::Array(Rc::new(vec![Object::Integer(1), Object::Integer(4), Object::Boolean(true)])));
+        test_eval("[];", Object::Array(Rc::new(vec![])));
+    }
+
+    #[test]
+    fn eval_hash() {
+        let hash = |pairs: Vec<(HashKey, Object)>| Object::Hash(Rc::new(pairs.into_iter().collect()));
+
+        test_eval(
+            r#"let two = 2; {"one": 1, two: two * 2, true: [], "one": 3};"#,
+            hash(vec![
+                (HashKey::String("one".into()), Object::Integer(3)),
+                (HashKey::Integer(2), Object::Integer(4)),
+                (HashKey::Boolean(true), Object::Array(Rc::new(vec![]))),
+            ])
+        );
+        test_eval("{};", hash(vec![]));
+        test_eval_error("{[1]: 2};", RuntimeError::UnusableHashKey("array"));
+        test_eval_error("{fn() { 1; }: 2};", RuntimeError::UnusableHashKey("function"));
+        // displayed in the order of the keys, integers before booleans before strings
+        assert_eq!(
+            "{1: a, true: [2], b: {}}",
+            eval_return_scope(&parse(&mut lex(r#"{"b": {}, true: [2], 1: "a"};"#).unwrap()).unwrap(), &mut Env::new())
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn eval_index() {
+        test_eval("[1, 2, 3][0];", Object::Integer(1));
+        test_eval("let xs = [1, 2, 3]; xs[1 + 1];", Object::Integer(3));
+        test_eval("let i = 0; [[1, 2], [3]][i + 1][i];", Object::Integer(3));
+        test_eval("let f = fn() { [fn(x) { x * 2; }]; }; f()[0](4);", Object::Integer(8));
+        // an index out of range is null rather than an error
+        test_eval("[1, 2, 3][3];", Object::Null);
+        test_eval("[1, 2, 3][-1];", Object::Null);
+        test_eval("[][0];", Object::Null);
+
+        test_eval_error("1[0];", RuntimeError::InvalidIndex { left: "integer", index: "integer" });
+        test_eval_error("[1][true];", RuntimeError::InvalidIndex { left: "array", index: "boolean" });
+
+        test_eval(r#"{"a": 1, 2: true}["a"];"#, Object::Integer(1));
+        test_eval(r#"let key = 1; {"a": 1, 2: true}[key + 1];"#, Object::Boolean(true));
+        test_eval(r#"{"a": {"b": [3]}}["a"]["b"][0];"#, Object::Integer(3));
+        test_eval(r#"{"a": 1}["b"];"#, Object::Null);
+        test_eval("{}[false];", Object::Null);
+        test_eval_error("{}[[]];", RuntimeError::UnusableHashKey("array"));
+        assert_eq!(
+            "runtime error: array cannot be indexed by string",
+            RuntimeError::InvalidIndex { left: "array", index: "string" }.to_string()
+        );
+    }
+
+    #[test]
+    fn eval_interpolation() {
+        test_eval(r#"let x = 2; "total: ${x + 1}";"#, Object::String("total: 3".into()));
+        test_eval(r#""${1.5} ${true} ${null} ${[1, "a"]} ${"s"}";"#, Object::String("1.5 true null [1, a] s".into()));
+        test_eval(r#"let f = fn(name) { "hi ${name}"; }; "${f("${1}")}!";"#, Object::String("hi 1!".into()));
+        test_eval_error(r#""${1 + true}";"#, RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "integer", right: "boolean" });
+    }
+
+    #[test]
+    fn eval_builtin_arrays() {
+        test_eval("len([1, 2, 3]);", Object::Integer(3));
+        test_eval("first([1, 2, 3]);", Object::Integer(1));
+        test_eval("last([1, 2, 3]);", Object::Integer(3));
+        test_eval("rest([1, 2, 3]);", Object::Array(Rc::new(vec![Object::Integer(2), Object::Integer(3)])));
+        test_eval("let a = [1]; let b = push(a, 2); len(a) + len(b);", Object::Integer(3));
+        test_eval("puts(1, [2]);", Object::Null);
+    }
+
+    #[test]
+    fn eval_builtin_as_value() {
+        test_eval("let f = len; f([1]);", Object::Integer(1));
+        test_eval("len;", Object::Builtin(0));
+        // bindings shadow builtins
+        test_eval("let len = fn(x) { 42; }; len([1]);", Object::Integer(42));
+    }
+
+    #[test]
+    fn eval_puts_output() {
+        let output = CapturedOutput::default();
+        let mut env = Env::with_output(Box::new(output.clone()));
+        let mut tokens = lex(r#"let greet = fn(name) { puts("hello " + name); }; greet("monkey"); puts(1, [true]);"#).unwrap();
+        eval_return_scope(&parse(&mut tokens).unwrap(), &mut env).unwrap();
+
+        assert_eq!("hello monkey\n1\n[true]\n", output.contents());
+    }
+
+    #[test]
+    fn eval_context_calls_back_into_program() {
+        use crate::builtins::Context;
+
+        let mut env = Env::new();
+        let mut tokens = lex("let double = fn(x) { x * 2; };").unwrap();
+        eval_return_scope(&parse(&mut tokens).unwrap(), &mut env).unwrap();
+
+        let double = env.get("double").unwrap();
+        assert_eq!(Ok(Object::Integer(6)), env.call(double, vec![Object::Integer(3)]));
+        assert_eq!(Ok(Object::Integer(2)), env.call(Object::Builtin(0), vec![Object::String("ab".into())]));
+        assert_eq!(Err(RuntimeError::NotCallable("integer")), env.call(Object::Integer(1), vec![]));
+    }
+
+    #[test]
+    fn eval_builtin_str() {
+        test_eval("str(5);", Object::String("5".into()));
+        test_eval("str(true);", Object::String("true".into()));
+        test_eval(r#"str("five");"#, Object::String("five".into()));
+    }
+
+    #[test]
+    fn eval_profile() {
+        let input = "let double = fn(x) { x * 2; }; let quad = fn(x) { double(double(x)); }; quad(1);";
+        let mut env = Env::new();
+        env.enable_profiling();
+        eval_return_scope(&desugar(parse(&mut lex(input).unwrap()).unwrap()), &mut env).unwrap();
+
+        assert_eq!(
+            "main 5\nmain;quad 5\nmain;quad;double 6\n",
+            env.profiler().unwrap().folded()
+        );
+    }
+
+    #[test]
+    fn eval_heap_profile() {
+        let input = "let s = \"a\";\nlet t = s + s + s;\nlet a = push([], t);";
+        let mut env = Env::new();
+        eval_return_scope(&desugar(parse(&mut lex(input).unwrap()).unwrap()), &mut env).unwrap();
+
+        let heap = env.heap();
+        assert_eq!(
+            vec![("array", 2), ("string", 3)],
+            heap.by_kind().into_iter().map(|(kind, allocations)| (kind, allocations.count)).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(3, 2), (2, 2), (1, 1)],
+            heap.by_line().into_iter().map(|(line, allocations)| (line, allocations.count)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn eval_trace() {
+        // the tracer is owned by the env, so events are shared with the test through an rc
+        struct Events(Rc<std::cell::RefCell<Vec<String>>>);
+        impl crate::trace::Tracer for Events {
+            fn event(&mut self, event: TraceEvent) {
+                self.0.borrow_mut().push(format!("{:?}", event));
+            }
+        }
+
+        let events = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut env = Env::new();
+        env.set_tracer(Box::new(Events(events.clone())));
+        let input = "let f = fn() { \"a\"; };\nf();";
+        eval_return_scope(&desugar(parse(&mut lex(input).unwrap()).unwrap()), &mut env).unwrap();
+
+        // functions are counted as the size of the object, which depends on the platform
+        let function_allocation = format!(
+            "Allocation {{ kind: \"function\", bytes: {}, line: 1 }}", std::mem::size_of::<Object>()
+        );
+        assert_eq!(
+            vec![
+                "StatementEnter { line: 1 }",
+                function_allocation.as_str(),
+                "StatementExit { line: 1 }",
+                "StatementEnter { line: 2 }",
+                "Call { function: \"f\", line: 2 }",
+                "StatementEnter { line: 1 }",
+                "Allocation { kind: \"string\", bytes: 1, line: 1 }",
+                "StatementExit { line: 1 }",
+                "Return { function: \"f\", line: 2 }",
+                "StatementExit { line: 2 }",
+            ],
+            *events.borrow()
+        );
+    }
+
+    #[test]
+    fn eval_native() {
+        let mut env = Env::new();
+        env.register_native("double", |arguments| match arguments {
+            [Object::Integer(num)] => Ok(Object::Integer(num * 2)),
+            _ => Err(RuntimeError::WrongArgumentCount { expected: 1, found: arguments.len() }),
+        });
+        let mut run = |input: &str| eval_return_scope(&desugar(parse(&mut lex(input).unwrap()).unwrap()), &mut env);
+
+        assert_eq!(Ok(Object::Integer(42)), run("double(21);"));
+        // natives are values like any other function
+        assert_eq!(Ok(Object::Integer(4)), run("let f = double; f(2);"));
+        assert_eq!(Err(RuntimeError::WrongArgumentCount { expected: 1, found: 0 }), run("double();").map_err(RuntimeError::without_location));
+    }
+
+    #[test]
+    fn eval_globals_from_host() {
+        let mut env = Env::new();
+        env.set_global("limit", Object::Integer(10));
+        let input = "let doubled = limit * 2;";
+        eval_return_scope(&desugar(parse(&mut lex(input).unwrap()).unwrap()), &mut env).unwrap();
+
+        assert_eq!(Some(&Object::Integer(20)), env.get_global("doubled"));
+        assert_eq!(None, env.get_global("missing"));
+    }
+
+    #[test]
+    fn eval_run_main() {
+        let run = |input: &str| {
+            let ast = desugar(parse(&mut lex(input).unwrap()).unwrap());
+            run_main(&ast, &[String::from("a"), String::from("b")], &mut Env::new())
+        };
+
+        assert_eq!(Ok(Object::Integer(2)), run("let main = fn(args) { len(args); };"));
+        assert_eq!(Ok(Object::String("b".into())), run("let main = fn(args) { last(args); };"));
+        assert_eq!(Ok(Object::Integer(7)), run("let main = fn() { 7; };"));
+        // without a main function the value of the script is returned
+        assert_eq!(Ok(Object::Integer(3)), run("1 + 2;"));
+        assert_eq!(Err(RuntimeError::NotCallable("integer")), run("let main = 5;"));
+    }
 }
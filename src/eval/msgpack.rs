@@ -0,0 +1,360 @@
+// Conversions between objects and MessagePack, a binary format with the same data model as
+// json, for embedders passing large values to and from scripts without the cost of writing and
+// parsing text. Only the parts of the format monkey values need are written, but any value of
+// those kinds is read, such as 64 bit integers and 32 bit floats.
+
+use super::{ConversionError, HashKey, Object};
+
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+const NIL: u8 = 0xc0;
+const FALSE: u8 = 0xc2;
+const TRUE: u8 = 0xc3;
+const FLOAT32: u8 = 0xca;
+const FLOAT64: u8 = 0xcb;
+const UINT8: u8 = 0xcc;
+const UINT16: u8 = 0xcd;
+const UINT32: u8 = 0xce;
+const UINT64: u8 = 0xcf;
+const INT8: u8 = 0xd0;
+const INT16: u8 = 0xd1;
+const INT32: u8 = 0xd2;
+const INT64: u8 = 0xd3;
+const STR8: u8 = 0xd9;
+const STR16: u8 = 0xda;
+const STR32: u8 = 0xdb;
+const ARRAY16: u8 = 0xdc;
+const ARRAY32: u8 = 0xdd;
+const MAP16: u8 = 0xde;
+const MAP32: u8 = 0xdf;
+
+// deeper arrays and maps than this are rejected rather than risking the stack while reading them
+const MAX_DEPTH: usize = 128;
+
+const SUPPORTED: &str = "null, boolean, number, string, array, or map";
+
+impl Object {
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, ConversionError> {
+        let mut bytes = vec![];
+        write_object(&mut bytes, self)?;
+
+        Ok(bytes)
+    }
+
+    /// reads a single value taking up all of the bytes, numbers become integers when they fit
+    ///    in one, and floats otherwise
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Object, ConversionError> {
+        let mut reader = Reader { bytes };
+        let object = reader.object(0)?;
+        if !reader.bytes.is_empty() {
+            return Err(malformed("trailing bytes"));
+        }
+
+        Ok(object)
+    }
+}
+
+fn malformed(found: &'static str) -> ConversionError {
+    ConversionError { expected: "a messagepack value", found }
+}
+
+fn write_object(bytes: &mut Vec<u8>, object: &Object) -> Result<(), ConversionError> {
+    match object {
+        Object::Null => bytes.push(NIL),
+        Object::Boolean(false) => bytes.push(FALSE),
+        Object::Boolean(true) => bytes.push(TRUE),
+        Object::Integer(num) => write_integer(bytes, *num),
+        Object::Float(num) => {
+            bytes.push(FLOAT64);
+            bytes.extend_from_slice(&num.to_be_bytes());
+        },
+        Object::String(string) => {
+            write_length(bytes, string.len(), (0xa0, 32), Some(STR8), STR16, STR32);
+            bytes.extend_from_slice(string.as_bytes());
+        },
+        Object::Array(elements) => {
+            // there is no array8, so the fixed size is followed by array16
+            write_length(bytes, elements.len(), (0x90, 16), None, ARRAY16, ARRAY32);
+            for element in elements.iter() {
+                write_object(bytes, element)?;
+            }
+        },
+        Object::Hash(pairs) => {
+            // like arrays, maps have no 8 bit length
+            write_length(bytes, pairs.len(), (0x80, 16), None, MAP16, MAP32);
+            for (key, value) in pairs.iter() {
+                write_object(bytes, &key.to_object())?;
+                write_object(bytes, value)?;
+            }
+        },
+        Object::Return(value) => write_object(bytes, value)?,
+        Object::Function { .. } | Object::CompiledFunction { .. } | Object::Builtin(_) | Object::Native(_)
+            | Object::Uninitialized(_) =>
+            return Err(ConversionError { expected: SUPPORTED, found: object.type_name() }),
+    }
+
+    Ok(())
+}
+
+// in the fewest bytes which hold the value
+fn write_integer(bytes: &mut Vec<u8>, num: i32) {
+    match num {
+        // positive and negative fixints are the value's own byte
+        -32..=127 => bytes.push(num as u8),
+        _ if i8::try_from(num).is_ok() => bytes.extend_from_slice(&[INT8, num as u8]),
+        _ if i16::try_from(num).is_ok() => {
+            bytes.push(INT16);
+            bytes.extend_from_slice(&(num as i16).to_be_bytes());
+        },
+        _ => {
+            bytes.push(INT32);
+            bytes.extend_from_slice(&num.to_be_bytes());
+        },
+    }
+}
+
+// the length of a string, array or map, in the fixed form holding it in the marker when it is short
+//    enough, otherwise with the marker for the smallest length which fits
+fn write_length(bytes: &mut Vec<u8>, len: usize, (fixed, fixed_limit): (u8, usize), marker8: Option<u8>, marker16: u8, marker32: u8) {
+    match marker8 {
+        _ if len < fixed_limit => bytes.push(fixed | len as u8),
+        Some(marker8) if len <= u8::MAX as usize => bytes.extend_from_slice(&[marker8, len as u8]),
+        _ if len <= u16::MAX as usize => {
+            bytes.push(marker16);
+            bytes.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        _ => {
+            bytes.push(marker32);
+            bytes.extend_from_slice(&(len as u32).to_be_bytes());
+        },
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ConversionError> {
+        if count > self.bytes.len() {
+            return Err(malformed("the end of input"));
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ConversionError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+
+        Ok(array)
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Object, ConversionError> {
+        let marker = self.take(1)?[0];
+        Ok(match marker {
+            0x00..=0x7f => Object::Integer(i32::from(marker)),
+            0xe0..=0xff => Object::Integer(i32::from(marker as i8)),
+            NIL => Object::Null,
+            FALSE => Object::Boolean(false),
+            TRUE => Object::Boolean(true),
+            UINT8 => Object::Integer(i32::from(self.take(1)?[0])),
+            UINT16 => Object::Integer(i32::from(u16::from_be_bytes(self.array()?))),
+            UINT32 => integer(i64::from(u32::from_be_bytes(self.array()?))),
+            UINT64 => {
+                let num = u64::from_be_bytes(self.array()?);
+                i64::try_from(num).map_or(Object::Float(num as f64), integer)
+            },
+            INT8 => Object::Integer(i32::from(self.take(1)?[0] as i8)),
+            INT16 => Object::Integer(i32::from(i16::from_be_bytes(self.array()?))),
+            INT32 => Object::Integer(i32::from_be_bytes(self.array()?)),
+            INT64 => integer(i64::from_be_bytes(self.array()?)),
+            FLOAT32 => Object::Float(f64::from(f32::from_be_bytes(self.array()?))),
+            FLOAT64 => Object::Float(f64::from_be_bytes(self.array()?)),
+            0xa0..=0xbf => self.string(usize::from(marker & 0x1f))?,
+            STR8 => {
+                let len = self.take(1)?[0];
+                self.string(usize::from(len))?
+            },
+            STR16 => {
+                let len = u16::from_be_bytes(self.array()?);
+                self.string(usize::from(len))?
+            },
+            STR32 => {
+                let len = u32::from_be_bytes(self.array()?);
+                self.string(len as usize)?
+            },
+            0x90..=0x9f => self.elements(usize::from(marker & 0x0f), depth)?,
+            ARRAY16 => {
+                let len = u16::from_be_bytes(self.array()?);
+                self.elements(usize::from(len), depth)?
+            },
+            ARRAY32 => {
+                let len = u32::from_be_bytes(self.array()?);
+                self.elements(len as usize, depth)?
+            },
+            0x80..=0x8f => self.pairs(usize::from(marker & 0x0f), depth)?,
+            MAP16 => {
+                let len = u16::from_be_bytes(self.array()?);
+                self.pairs(usize::from(len), depth)?
+            },
+            MAP32 => {
+                let len = u32::from_be_bytes(self.array()?);
+                self.pairs(len as usize, depth)?
+            },
+            0xc4..=0xc6 => return Err(ConversionError { expected: SUPPORTED, found: "binary" }),
+            0xc7..=0xc9 | 0xd4..=0xd8 => return Err(ConversionError { expected: SUPPORTED, found: "extension" }),
+            _ => return Err(malformed("an unused marker")),
+        })
+    }
+
+    fn string(&mut self, len: usize) -> Result<Object, ConversionError> {
+        let bytes = self.take(len)?;
+        let string = std::str::from_utf8(bytes).map_err(|_| malformed("a string which isn't utf-8"))?;
+
+        Ok(Object::String(string.into()))
+    }
+
+    fn elements(&mut self, len: usize, depth: usize) -> Result<Object, ConversionError> {
+        if depth == MAX_DEPTH {
+            return Err(malformed("values nested too deeply"));
+        }
+        // every element takes at least a byte, so a longer length can only come from corrupt
+        //    input and is rejected before anything is allocated for it
+        if len > self.bytes.len() {
+            return Err(malformed("the end of input"));
+        }
+
+        let elements = (0..len)
+            .map(|_| self.object(depth + 1))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Object::Array(Rc::new(elements)))
+    }
+
+    // a key repeated in the map takes the last value given for it
+    fn pairs(&mut self, len: usize, depth: usize) -> Result<Object, ConversionError> {
+        if depth == MAX_DEPTH {
+            return Err(malformed("values nested too deeply"));
+        }
+        // every pair takes at least two bytes
+        if len > self.bytes.len() / 2 {
+            return Err(malformed("the end of input"));
+        }
+
+        let pairs = (0..len)
+            .map(|_| {
+                let key = self.object(depth + 1)?;
+                let key = HashKey::from_object(&key)
+                    .map_err(|_| ConversionError { expected: "an integer, boolean, or string key", found: key.type_name() })?;
+                Ok((key, self.object(depth + 1)?))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Object::Hash(Rc::new(pairs)))
+    }
+}
+
+// integers outside the range of monkey's become floats, as they do read from json
+fn integer(num: i64) -> Object {
+    match i32::try_from(num) {
+        Ok(num) => Object::Integer(num),
+        Err(_) => Object::Float(num as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msgpack_values() {
+        let object = Object::from(vec![1.into(), (-1).into(), 1.5.into(), "a".into(), true.into(), Object::Null]);
+        let bytes = vec![0x96, 0x01, 0xff, 0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, 0xa1, b'a', 0xc3, 0xc0];
+
+        assert_eq!(Ok(bytes.clone()), object.to_msgpack());
+        assert_eq!(Ok(object), Object::from_msgpack(&bytes));
+
+        let hash = Object::Hash(Rc::new(vec![
+            (HashKey::Integer(1), Object::from("a")),
+            (HashKey::Boolean(true), Object::Null),
+            (HashKey::String("b".into()), Object::from(vec![Object::Integer(2)])),
+        ].into_iter().collect()));
+        let bytes = vec![0x83, 0x01, 0xa1, b'a', 0xc3, 0xc0, 0xa1, b'b', 0x91, 0x02];
+
+        assert_eq!(Ok(bytes.clone()), hash.to_msgpack());
+        assert_eq!(Ok(hash), Object::from_msgpack(&bytes));
+    }
+
+    #[test]
+    fn msgpack_sizes() {
+        let round_trip = |object: Object| Object::from_msgpack(&object.to_msgpack().unwrap());
+        let encoded_len = |object: Object| object.to_msgpack().unwrap().len();
+
+        for num in [0, 127, 128, -32, -33, -128, -129, 300, 40_000, -40_000, i32::MAX, i32::MIN] {
+            assert_eq!(Ok(Object::Integer(num)), round_trip(Object::Integer(num)));
+        }
+        assert_eq!(1, encoded_len(Object::Integer(-32)));
+        assert_eq!(2, encoded_len(Object::Integer(-33)));
+        assert_eq!(3, encoded_len(Object::Integer(300)));
+        assert_eq!(5, encoded_len(Object::Integer(i32::MIN)));
+
+        for len in [31, 32, 255, 256, 70_000] {
+            let string = Object::from("x".repeat(len));
+            assert_eq!(Ok(string.clone()), round_trip(string));
+        }
+        assert_eq!(1 + 31, encoded_len(Object::from("x".repeat(31))));
+        assert_eq!(2 + 32, encoded_len(Object::from("x".repeat(32))));
+
+        for len in [15, 16, 70_000] {
+            let array = Object::from(vec![Object::Null; len]);
+            assert_eq!(Ok(array.clone()), round_trip(array));
+        }
+        assert_eq!(3 + 16, encoded_len(Object::from(vec![Object::Null; 16])));
+
+        let hash = |len: i32| Object::Hash(Rc::new((0..len).map(|key| (HashKey::Integer(key), Object::Null)).collect()));
+        for len in [15, 16, 70_000] {
+            assert_eq!(Ok(hash(len)), round_trip(hash(len)));
+        }
+        assert_eq!(1 + 15 * 2, encoded_len(hash(15)));
+        assert_eq!(3 + 16 * 2, encoded_len(hash(16)));
+    }
+
+    #[test]
+    fn msgpack_from_other_encoders() {
+        // wider encodings than monkey writes, and integers beyond its range which become floats
+        assert_eq!(Ok(Object::Integer(5)), Object::from_msgpack(&[0xd3, 0, 0, 0, 0, 0, 0, 0, 5]));
+        assert_eq!(Ok(Object::Integer(200)), Object::from_msgpack(&[0xcc, 200]));
+        assert_eq!(Ok(Object::Float(1e10)), Object::from_msgpack(&[0xcf, 0, 0, 0, 0x02, 0x54, 0x0b, 0xe4, 0]));
+        assert_eq!(Ok(Object::Float(1.5)), Object::from_msgpack(&[0xca, 0x3f, 0xc0, 0, 0]));
+        assert_eq!(Ok(Object::from("ab")), Object::from_msgpack(&[0xd9, 2, b'a', b'b']));
+        // a repeated key keeps its last value
+        assert_eq!(
+            Ok(Object::Hash(Rc::new(vec![(HashKey::Integer(1), Object::Integer(3))].into_iter().collect()))),
+            Object::from_msgpack(&[0x82, 0x01, 0x02, 0x01, 0x03])
+        );
+    }
+
+    #[test]
+    fn msgpack_errors() {
+        assert_eq!(
+            Err(ConversionError { expected: SUPPORTED, found: "builtin" }),
+            Object::Builtin(0).to_msgpack()
+        );
+        assert_eq!(
+            Err(ConversionError { expected: "an integer, boolean, or string key", found: "null" }),
+            Object::from_msgpack(&[0x81, 0xc0, 0x01])
+        );
+        assert_eq!(Err(ConversionError { expected: SUPPORTED, found: "binary" }), Object::from_msgpack(&[0xc4, 0]));
+        assert_eq!(Err(malformed("the end of input")), Object::from_msgpack(&[0xdf, 0xff, 0xff, 0xff, 0xff]));
+        assert_eq!(Err(malformed("the end of input")), Object::from_msgpack(&[0x92, 0x01]));
+        assert_eq!(Err(malformed("the end of input")), Object::from_msgpack(&[0xdd, 0xff, 0xff, 0xff, 0xff]));
+        assert_eq!(Err(malformed("trailing bytes")), Object::from_msgpack(&[0x01, 0x02]));
+        assert_eq!(Err(malformed("a string which isn't utf-8")), Object::from_msgpack(&[0xa1, 0xff]));
+        assert_eq!(Err(malformed("an unused marker")), Object::from_msgpack(&[0xc1]));
+        assert_eq!(Err(malformed("values nested too deeply")), Object::from_msgpack(&[0x91; MAX_DEPTH + 1]));
+    }
+}
@@ -0,0 +1,162 @@
+// Picks short names for the parameters and `let` bindings of each function, for minify. A
+// function sees only its own bindings and the globals, not the bindings of a function it is
+// written inside, so each function's bindings are named on their own starting again from `a`.
+// Globals are kept as written, since the host and the repl read them by name.
+
+use crate::builtins::BUILTINS;
+use crate::lexer::{SpannedToken, StringPart, Token, KEYWORDS};
+use crate::parser::RESERVED_WORDS;
+use std::collections::{BTreeMap, HashSet};
+
+/// a renamed identifier, keyed by where its token starts in the source
+pub struct Rename {
+    // where the token ends in the source
+    pub end: usize,
+    pub name: String,
+}
+
+#[derive(Default)]
+struct Function<'a> {
+    // the parameters and `let` bindings, in the order they first appear
+    bindings: Vec<&'a str>,
+    // every identifier in the function, outside of the functions written inside it
+    identifiers: Vec<(&'a SpannedToken, &'a str)>,
+    // the depth of braces inside the body, None until the body starts
+    body_depth: Option<usize>,
+}
+
+/// the short name for each identifier which refers to a binding local to a function
+pub fn short_names(tokens: &[SpannedToken]) -> BTreeMap<usize, Rename> {
+    let mut flat = Vec::new();
+    flatten(tokens, &mut flat);
+
+    let mut functions = Vec::new();
+    // the functions the current token is inside, innermost last
+    let mut stack: Vec<Function> = Vec::new();
+    let mut globals = HashSet::new();
+    let mut depth = 0;
+    let mut in_parameters = false;
+    let mut previous = None;
+    for token in &flat {
+        match &token.token {
+            Token::FUNCTION => stack.push(Function::default()),
+            Token::LPAREN if matches!(stack.last(), Some(Function { body_depth: None, .. })) => in_parameters = true,
+            Token::RPAREN if in_parameters => in_parameters = false,
+            Token::LBRACE => {
+                depth += 1;
+                if let Some(function @ Function { body_depth: None, .. }) = stack.last_mut() {
+                    function.body_depth = Some(depth);
+                }
+            },
+            Token::RBRACE => {
+                if stack.last().is_some_and(|function| function.body_depth == Some(depth)) {
+                    functions.extend(stack.pop());
+                }
+                depth -= 1;
+            },
+            Token::IDENT(name) => {
+                let binds = in_parameters || previous == Some(&Token::LET);
+                match stack.last_mut() {
+                    Some(function) => {
+                        if binds && !function.bindings.contains(&name.as_str()) {
+                            function.bindings.push(name);
+                        }
+                        function.identifiers.push((token, name));
+                    },
+                    None if binds => {
+                        globals.insert(name.as_str());
+                    },
+                    None => {},
+                }
+            },
+            _ => {},
+        }
+        previous = Some(&token.token);
+    }
+
+    // a new name must not be mistaken for a keyword, or clash with any name in the program
+    let taken = flat.iter()
+        .filter_map(|token| match &token.token {
+            Token::IDENT(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .chain(KEYWORDS.iter().copied())
+        .chain(RESERVED_WORDS.iter().copied())
+        .chain(BUILTINS.iter().map(|builtin| builtin.name))
+        .collect::<HashSet<_>>();
+
+    let mut renames = BTreeMap::new();
+    for function in functions {
+        let mut names = (0..).map(short_name).filter(|name| !taken.contains(name.as_str()));
+        // a binding which shares its name with a global or builtin is kept, since reading it
+        //    before it is bound reads the global instead
+        let renamed = function.bindings.iter()
+            .filter(|binding| !globals.contains(*binding) && !BUILTINS.iter().any(|builtin| builtin.name == **binding))
+            .map(|binding| (*binding, names.next().unwrap()))
+            .collect::<Vec<_>>();
+        for (token, name) in function.identifiers {
+            if let Some((_, new_name)) = renamed.iter().find(|(binding, _)| *binding == name) {
+                renames.insert(token.span.start, Rename { end: token.span.end, name: new_name.clone() });
+            }
+        }
+    }
+
+    renames
+}
+
+// the tokens in source order, including those of the code interpolated into strings
+fn flatten<'a>(tokens: &'a [SpannedToken], flat: &mut Vec<&'a SpannedToken>) {
+    for token in tokens.iter().filter(|token| token.token != Token::EOF) {
+        flat.push(token);
+        if let Token::INTERPOLATED(parts) = &token.token {
+            for part in parts {
+                if let StringPart::Code(code) = part {
+                    flatten(code, flat);
+                }
+            }
+        }
+    }
+}
+
+// a, b, ..., z, aa, ab, ...
+fn short_name(index: usize) -> String {
+    let mut name = String::new();
+    let mut index = index + 1;
+    while index > 0 {
+        index -= 1;
+        name.insert(0, (b'a' + (index % 26) as u8) as char);
+        index /= 26;
+    }
+
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+
+    #[test]
+    fn short_names_in_order() {
+        assert_eq!(
+            vec!["a", "b", "z", "aa", "az", "ba", "zz", "aaa"],
+            [0, 1, 25, 26, 51, 52, 701, 702].iter().map(|index| short_name(*index)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn short_names_skip_taken_names() {
+        let input = "let a = 1; let f = fn(x, y) { let len = 2; a + x + y + len; };";
+        let tokens = lex(input).unwrap();
+        let renames = short_names(&tokens)
+            .into_iter()
+            .map(|(start, rename)| (&input[start..rename.end], rename.name))
+            .collect::<Vec<_>>();
+
+        // `a` is a global and len a builtin, so neither is used as a new name or renamed
+        assert_eq!(
+            vec![("x", String::from("b")), ("y", String::from("c")), ("x", String::from("b")), ("y", String::from("c"))],
+            renames
+        );
+    }
+}
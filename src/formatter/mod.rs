@@ -0,0 +1,469 @@
+use crate::lexer::{format_float, trivia, SpannedToken, Token, Trivia, TriviaKind};
+use crate::parser::{Statement, Expr, Operator};
+
+mod locals;
+
+const INDENT: &str = "    ";
+
+/// Formats a program back into Monkey source code, with one statement per line and
+/// blocks indented by four spaces.
+pub fn format_program(program: &[Statement]) -> String {
+    format_statements(program, 0, &mut Comments::none(), None)
+}
+
+/// formats a program like format_program, keeping the comments from the source it was parsed
+///    from, which needs the tokens lexed from the source before parsing consumed them
+pub fn format_with_comments(source: &str, tokens: &[SpannedToken], program: &[Statement]) -> String {
+    let mut comments = Comments::new(source, tokens);
+    format_statements(program, 0, &mut comments, Some(source.len()))
+}
+
+// the comments not yet written out, in source order. A comment goes on the line before the
+//    statement which follows it, or at the end of the line of the statement it follows on the
+//    same line. Comments within an expression have nowhere to go in the formatted expression,
+//    so are moved to before its statement rather than dropped.
+struct Comments<'a> {
+    source: &'a str,
+    tokens: &'a [SpannedToken],
+    pending: Vec<Trivia>,
+    next: usize,
+}
+
+impl<'a> Comments<'a> {
+    fn new(source: &'a str, tokens: &'a [SpannedToken]) -> Self {
+        let pending = trivia(source, tokens).into_iter()
+            .flatten()
+            .filter(Trivia::is_comment)
+            .collect();
+
+        Comments { source, tokens, pending, next: 0 }
+    }
+
+    fn none() -> Comments<'static> {
+        Comments { source: "", tokens: &[], pending: vec![], next: 0 }
+    }
+
+    /// takes the comments which start before the offset
+    fn before(&mut self, offset: usize) -> Vec<&'a str> {
+        let mut taken = vec![];
+        while let Some(comment) = self.pending.get(self.next).filter(|comment| comment.start < offset) {
+            taken.push(comment.text(self.source));
+            self.next += 1;
+        }
+
+        taken
+    }
+
+    /// takes the comment following the offset on the same line, if there is one before the
+    ///    next token
+    fn trailing(&mut self, offset: usize) -> Option<&'a str> {
+        let comment = self.pending.get(self.next)?;
+        let next_token = self.tokens.iter().find(|token| token.span.start >= offset)?;
+        let same_line = comment.start >= offset
+            && comment.start < next_token.span.start
+            && !self.source[offset..comment.start].contains('\n');
+        // a block comment running onto later lines reads as leading the next statement
+        let multiline = comment.kind == TriviaKind::BlockComment && comment.text(self.source).contains('\n');
+        if !same_line || multiline {
+            return None;
+        }
+        self.next += 1;
+
+        Some(comment.text(self.source))
+    }
+
+    /// the start of the brace closing the block whose last statement ends at the offset
+    fn block_end(&self, offset: usize) -> Option<usize> {
+        self.tokens.iter()
+            .find(|token| token.span.start >= offset && token.token == Token::RBRACE)
+            .map(|token| token.span.start)
+    }
+}
+
+/// writes the lexed source back out with comments and all whitespace between tokens dropped,
+///    keeping a space only where two tokens would otherwise run together, and with the
+///    parameters and bindings of functions given short names
+pub fn minify(source: &str, tokens: &[SpannedToken]) -> String {
+    let renames = locals::short_names(tokens);
+    let mut output = String::with_capacity(source.len());
+    let mut previous = String::new();
+    for token in tokens.iter().filter(|token| token.token != Token::EOF) {
+        // an interpolated string can hold renamed identifiers as well as being one token
+        let mut text = String::new();
+        let mut position = token.span.start;
+        for (start, rename) in renames.range(token.span.start..token.span.end) {
+            text.push_str(&source[position..*start]);
+            text.push_str(&rename.name);
+            position = rename.end;
+        }
+        text.push_str(&source[position..token.span.end]);
+
+        if needs_space(&previous, &text) {
+            output.push(' ');
+        }
+        output.push_str(&text);
+        previous = text;
+    }
+
+    output
+}
+
+// whether the text of two adjacent tokens would lex differently written without a space, as
+//    two names do, or an operator followed by one which would join it into a longer operator
+fn needs_space(previous: &str, next: &str) -> bool {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    match (previous.chars().last(), next.chars().next()) {
+        (Some(last), Some(first)) if is_word(last) && is_word(first) => true,
+        (Some(last), Some(first)) => matches!((last, first), ('/', '/') | ('/', '*') | ('=', '=') | ('!', '=')
+            | ('<', '=') | ('>', '=') | ('&', '&') | ('|', '|')),
+        _ => false,
+    }
+}
+
+// each statement on its own line, followed by the comments before the end of the enclosing
+//    block or program when it is known
+fn format_statements(statements: &[Statement], depth: usize, comments: &mut Comments, end: Option<usize>) -> String {
+    let indent = INDENT.repeat(depth);
+    let mut output = String::new();
+    for statement in statements {
+        let span = statement.span();
+        let mut leading = comments.before(span.start);
+        let formatted = format_statement(statement, depth, comments);
+        leading.extend(comments.before(span.end));
+
+        for comment in leading {
+            output.push_str(&format!("{}{}\n", indent, comment));
+        }
+        output.push_str(&formatted);
+        if let Some(comment) = comments.trailing(span.end) {
+            output.push(' ');
+            output.push_str(comment);
+        }
+        output.push('\n');
+    }
+    if let Some(end) = end {
+        for comment in comments.before(end) {
+            output.push_str(&format!("{}{}\n", indent, comment));
+        }
+    }
+
+    output
+}
+
+fn format_statement(statement: &Statement, depth: usize, comments: &mut Comments) -> String {
+    let statement = match statement {
+        Statement::Let { name, value, .. } => format!("let {} = {};", name, format_expr(value, depth, comments)),
+        Statement::Declare { name, .. } => format!("let {};", name),
+        Statement::Assign { name, value, .. } => format!("{} = {};", name, format_expr(value, depth, comments)),
+        Statement::Return { value, .. } => format!("return {};", format_expr(value, depth, comments)),
+        Statement::Expression(expr, _) => format!("{};", format_expr(expr, depth, comments)),
+    };
+
+    format!("{}{}", INDENT.repeat(depth), statement)
+}
+
+fn format_block(statements: &[Statement], depth: usize, comments: &mut Comments) -> String {
+    let last = match statements.last() {
+        Some(last) => last,
+        None => return String::from("{}"),
+    };
+
+    let end = comments.block_end(last.span().end);
+    format!("{{\n{}{}}}", format_statements(statements, depth + 1, comments, end), INDENT.repeat(depth))
+}
+
+fn format_expr(expr: &Expr, depth: usize, comments: &mut Comments) -> String {
+    match expr {
+        Expr::Const(num) => num.to_string(),
+        // debug formatting keeps the decimal point on whole numbers, so they lex as floats again
+        Expr::Float(num) => format_float(*num),
+        Expr::String(string) => format!("\"{}\"", escape_string(string)),
+        Expr::Boolean(val) => val.to_string(),
+        Expr::Null => String::from("null"),
+        Expr::Ident(name, _) => name.clone(),
+        Expr::Prefix { prefix, value } => {
+            match **value {
+                Expr::Infix { .. } | Expr::Ternary { .. } => format!("{}({})", prefix, format_expr(value, depth, comments)),
+                _ => format!("{}{}", prefix, format_expr(value, depth, comments)),
+            }
+        },
+        Expr::Infix { left, operator, right } => {
+            // infix operators are left associative, so a right operand of the same
+            //    precedence needs parentheses to keep its grouping
+            let left = format_operand(left, depth, comments, |precedence| precedence < operator_precedence(operator));
+            let right = format_operand(right, depth, comments, |precedence| precedence <= operator_precedence(operator));
+            format!("{} {} {}", left, operator, right)
+        },
+        Expr::If { condition, consequence, alternative } => {
+            let condition = format_expr(condition, depth, comments);
+            let mut output = format!("if ({}) {}", condition, format_block(consequence, depth, comments));
+            if !alternative.is_empty() {
+                output.push_str(" else ");
+                output.push_str(&format_block(alternative, depth, comments));
+            }
+            output
+        },
+        Expr::Function { parameters, body } => {
+            format!("fn({}) {}", parameters.join(", "), format_block(body, depth, comments))
+        },
+        Expr::Call { function, arguments, .. } => {
+            let function = format_postfix_operand(function, depth, comments);
+            format!("{}({})", function, format_list(arguments, depth, comments))
+        },
+        Expr::Array(elements) => format!("[{}]", format_list(elements, depth, comments)),
+        Expr::Hash(pairs) => {
+            let pairs = pairs.iter()
+                .map(|(key, value)| format!("{}: {}", format_expr(key, depth, comments), format_expr(value, depth, comments)))
+                .collect::<Vec<String>>();
+            format!("{{{}}}", pairs.join(", "))
+        },
+        Expr::Index { left, index } => {
+            let left = format_postfix_operand(left, depth, comments);
+            format!("{}[{}]", left, format_expr(index, depth, comments))
+        },
+        Expr::Interpolation(parts) => {
+            let parts = parts.iter()
+                .map(|part| match part {
+                    Expr::String(text) => escape_string(text),
+                    part => format!("${{{}}}", format_expr(part, depth, comments)),
+                })
+                .collect::<String>();
+            format!("\"{}\"", parts)
+        },
+        Expr::Ternary { condition, consequence, alternative } => {
+            // the operator is right associative, so only a condition which is itself a ternary
+            //    needs parentheses
+            let condition = match **condition {
+                Expr::Ternary { .. } => format!("({})", format_expr(condition, depth, comments)),
+                _ => format_expr(condition, depth, comments),
+            };
+            let consequence = format_expr(consequence, depth, comments);
+            format!("{} ? {} : {}", condition, consequence, format_expr(alternative, depth, comments))
+        },
+    }
+}
+
+fn format_list(exprs: &[Expr], depth: usize, comments: &mut Comments) -> String {
+    exprs.iter()
+        .map(|expr| format_expr(expr, depth, comments))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// calls and indexing bind tighter than any operator, so an operator being called or indexed
+//    is wrapped
+fn format_postfix_operand(expr: &Expr, depth: usize, comments: &mut Comments) -> String {
+    match expr {
+        Expr::Infix { .. } | Expr::Prefix { .. } | Expr::Ternary { .. } => format!("({})", format_expr(expr, depth, comments)),
+        _ => format_expr(expr, depth, comments),
+    }
+}
+
+fn format_operand(expr: &Expr, depth: usize, comments: &mut Comments, needs_parens: impl Fn(u8) -> bool) -> String {
+    match expr {
+        Expr::Infix { operator, .. } if needs_parens(operator_precedence(operator)) => {
+            format!("({})", format_expr(expr, depth, comments))
+        },
+        // which binds more loosely than any infix operator
+        Expr::Ternary { .. } => format!("({})", format_expr(expr, depth, comments)),
+        _ => format_expr(expr, depth, comments),
+    }
+}
+
+fn operator_precedence(operator: &Operator) -> u8 {
+    match operator {
+        Operator::Or => 1,
+        Operator::And => 2,
+        Operator::Equals | Operator::NotEquals => 3,
+        Operator::GreaterThan | Operator::LessThan
+            | Operator::GreaterThanOrEqual | Operator::LessThanOrEqual => 4,
+        Operator::Plus | Operator::Minus => 5,
+        Operator::Multiply | Operator::Divide => 6,
+    }
+}
+
+/// writes a string value back out in the escaped form the lexer accepts
+fn escape_string(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    let mut chars = string.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            // which would otherwise start an interpolation
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+
+    fn format_source(input: &str) -> String {
+        let mut tokens = lex(input).unwrap();
+        format_program(&parse(&mut tokens).unwrap())
+    }
+
+    #[test]
+    fn format_let() {
+        assert_eq!("let x = 5;\n", format_source("let   x=5 ;"));
+        assert_eq!("let x;\nx = 5;\n", format_source("let x ;x=5;"));
+    }
+
+    #[test]
+    fn format_line_endings() {
+        let input = "let f = fn(x) {\n\tx;\n};\nlet s = \"a\nb\";\n";
+        let formatted = "let f = fn(x) {\n    x;\n};\nlet s = \"a\\nb\";\n";
+
+        assert_eq!(formatted, format_source(input));
+        assert_eq!(formatted, format_source(&input.replace('\n', "\r\n")));
+    }
+
+    #[test]
+    fn format_ternary() {
+        assert_eq!("a || b ? c + 1 : d ? e : f;\n", format_source("a||b?c+1:d?e:f;"));
+        assert_eq!("(a ? b : c) ? d : e;\n", format_source("(a ? b : c) ? d : e;"));
+        assert_eq!("(a ? b : c) + -(d ? e : f) * (g ? h : i)(1);\n", format_source("(a ? b : c) + -(d ? e : f) * (g ? h : i)(1);"));
+    }
+
+    #[test]
+    fn format_hash() {
+        assert_eq!("{\"a\": 1, x + 1: [2], true: {}};\n", format_source("{ \"a\":1,x+1 : [2] , true:{}, };"));
+    }
+
+    #[test]
+    fn format_interpolation() {
+        assert_eq!("\"total: ${x + 1}\\n\";\n", format_source("\"total: ${ x+1 }\\n\";"));
+        // a literal `${` is escaped, whether it was written escaped or came from interpolated text
+        assert_eq!("\"\\${x} ${f(\"\\${y}\")}\";\n", format_source("\"\\${x} ${f(\"\\${y}\")}\";"));
+    }
+
+    #[test]
+    fn format_infix_parens() {
+        assert_eq!("1 + 2 * 3;\n", format_source("1 + (2 * 3);"));
+        assert_eq!("(1 + 2) * 3;\n", format_source("(1 + 2) * 3;"));
+        assert_eq!("1 - (2 - 3);\n", format_source("1 - (2 - 3);"));
+        assert_eq!("1 - 2 - 3;\n", format_source("(1 - 2) - 3;"));
+        assert_eq!("-(1 + 2);\n", format_source("-(1 + 2);"));
+        assert_eq!("(f + g)(1);\n", format_source("(f + g)(1);"));
+        assert_eq!("(-f)(1);\n", format_source("(-f)(1);"));
+        assert_eq!("f(1)(2);\n", format_source("f(1)(2);"));
+        assert_eq!("(a + b)[0];\n", format_source("(a + b)[0];"));
+        assert_eq!("-xs[0];\n", format_source("-(xs[0]);"));
+        assert_eq!("xs[i + 1][0](2);\n", format_source("xs[i+1][0](2);"));
+    }
+
+    #[test]
+    fn format_blocks() {
+        let expected = r#"let max = fn(a, b) {
+    if (a > b) {
+        return a;
+    } else {
+        return b;
+    };
+};
+max(1, 2.0);
+"#;
+        assert_eq!(
+            expected,
+            format_source("let max = fn(a, b) { if (a > b) { return a; } else { return b; }; }; max(1, 2.0);")
+        );
+    }
+
+    #[test]
+    fn format_keeps_comments() {
+        let format = |input: &str| {
+            let tokens = lex(input).unwrap();
+            format_with_comments(input, &tokens, &parse(&mut tokens.clone()).unwrap())
+        };
+        let input = r#"// adds things
+let add = fn(a, b) {   // trailing
+  /* before */ a + /* inside */ b;
+  // at the end of the block
+};
+add(1,2); /* after */
+// the end"#;
+        let expected = r#"// adds things
+let add = fn(a, b) {
+    // trailing
+    /* before */
+    /* inside */
+    a + b;
+    // at the end of the block
+};
+add(1, 2); /* after */
+// the end
+"#;
+
+        assert_eq!(expected, format(input));
+        // formatting again changes nothing
+        assert_eq!(expected, format(expected));
+        assert_eq!(format_program(&parse(&mut lex(input).unwrap()).unwrap()), format("let add = fn(a, b) { a + b; }; add(1, 2);"));
+    }
+
+    #[test]
+    fn minify_source() {
+        let input = "// doubles\nlet double = fn(x) {\n    x * 2; /* twice */\n};\nif (double(1) == 2) { puts(\"a b\"); } else { return -1.5; };\n";
+        let minified = minify(input, &lex(input).unwrap());
+
+        assert_eq!(r#"let double=fn(a){a*2;};if(double(1)==2){puts("a b");}else{return-1.5;};"#, minified);
+        assert_eq!("a--b;1/ /c", minify("a - -b;1/ /c", &lex("a - -b;1/ /c").unwrap()));
+    }
+
+    #[test]
+    fn minify_renames_locals() {
+        let input = r#"let total = 10;
+let scale = fn(value, factor) {
+    let result = value * factor;
+    let label = "${value}x${factor}=${result}";
+    if (result > total) {
+        let over = result - total;
+        return [label, over];
+    };
+    [label, result];
+};
+// each function has its own bindings, so inner can reuse the names outer was given
+let twice = fn(x) {
+    let inner = fn(x) { x * 2; };
+    inner(x) + len(scale(x, 3));
+};
+let describe = fn() { let total = "shadowed"; "${total}"; };
+[scale(2, 3), scale(4, 5), twice(7), describe()];
+"#;
+        let minified = minify(input, &lex(input).unwrap());
+
+        assert_eq!(
+            concat!(
+                r#"let total=10;let scale=fn(a,b){let c=a*b;let d="${a}x${b}=${c}";if(c>total){let e=c-total;return[d,e];};[d,c];};"#,
+                r#"let twice=fn(a){let b=fn(a){a*2;};b(a)+len(scale(a,3));};"#,
+                r#"let describe=fn(){let total="shadowed";"${total}";};[scale(2,3),scale(4,5),twice(7),describe()];"#,
+            ),
+            minified
+        );
+        // the renamed program gives the same result
+        let eval = |source: &str| {
+            let ast = crate::desugar::desugar(parse(&mut lex(source).unwrap()).unwrap());
+            crate::eval::eval_return_scope(&ast, &mut crate::eval::Env::with_output(Box::new(std::io::sink())))
+        };
+        assert_eq!("[[2x3=6, 6], [4x5=20, 10], 16, shadowed]", eval(input).unwrap().to_string());
+        assert_eq!(eval(input), eval(&minified));
+    }
+
+    #[test]
+    fn format_round_trips() {
+        let input = r#"let f = fn(x) { if (!x && 1 <= 2 || false) { "yes\t\"no\"\\\n"; }; }; f(true != false, [1, [2], []]); -f(1) / 0.5; "\${a} $${x + "${y}"}"; a ? b : (c ? d : e) + 1; {"k": {1: x}}[f({})];"#;
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        let mut tokens = lex(&format_program(&ast)).unwrap();
+        assert_eq!(ast, parse(&mut tokens).unwrap());
+    }
+}
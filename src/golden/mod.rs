@@ -0,0 +1,77 @@
+// Snapshot tests over the `.mky` fixtures in tests/golden. Each fixture has its expected
+//   outputs stored alongside it, one file per extension. Run the tests with
+//   MONKEY_UPDATE_GOLDEN=1 to regenerate the expected outputs after an intended change,
+//   then review the diff of the golden files.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::lexer::lex;
+use crate::parser::parse;
+use crate::compiler::Compiler;
+use crate::formatter::format_with_comments;
+
+const UPDATE_ENV_VAR: &str = "MONKEY_UPDATE_GOLDEN";
+
+fn fixtures() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let mut fixtures = fs::read_dir(&dir)
+        .expect("failed to read golden fixture directory")
+        .map(|entry| entry.expect("failed to read golden fixture").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mky"))
+        .collect::<Vec<PathBuf>>();
+    fixtures.sort();
+
+    fixtures
+}
+
+fn assert_golden(fixture: &Path, extension: &str, actual: &str) {
+    let expected_path = fixture.with_extension(extension);
+
+    if env::var_os(UPDATE_ENV_VAR).is_some() {
+        fs::write(&expected_path, actual).expect("failed to write golden file");
+        return;
+    }
+
+    let expected = fs::read_to_string(&expected_path)
+        .unwrap_or_else(|_| panic!(
+            "missing golden file {}, run with {}=1 to create it", expected_path.display(), UPDATE_ENV_VAR
+        ));
+    assert_eq!(
+        expected, actual,
+        "golden file {} is out of date, run with {}=1 to regenerate it", expected_path.display(), UPDATE_ENV_VAR
+    );
+}
+
+fn disassemble(source: &str) -> String {
+    let compiler = Compiler::compile_for_test(source);
+
+    let mut output = String::new();
+    for (position, op) in compiler.instructions().iter().enumerate() {
+        output.push_str(&format!("{:04} {:?}\n", position, op));
+    }
+    output.push_str("constants:\n");
+    for (index, constant) in compiler.constants().iter().enumerate() {
+        output.push_str(&format!("{:04} {:?}\n", index, constant));
+    }
+
+    output
+}
+
+#[test]
+fn golden_disassembly() {
+    for fixture in fixtures() {
+        let source = fs::read_to_string(&fixture).expect("failed to read golden fixture");
+        assert_golden(&fixture, "disasm", &disassemble(&source));
+    }
+}
+
+#[test]
+fn golden_format() {
+    for fixture in fixtures() {
+        let source = fs::read_to_string(&fixture).expect("failed to read golden fixture");
+        let tokens = lex(&source).unwrap();
+        let program = parse(&mut tokens.clone()).unwrap();
+        assert_golden(&fixture, "fmt", &format_with_comments(&source, &tokens, &program));
+    }
+}
@@ -0,0 +1,159 @@
+// The grammar of the language as data, for documentation and tooling. The parser is a
+// hand written Pratt parser rather than being generated from this, so the tests below parse
+// programs to check the two agree, in particular on the precedence of the infix operators.
+
+/// a named rule of the grammar, written in EBNF
+pub struct Production {
+    pub name: &'static str,
+    pub rule: &'static str,
+}
+
+/// the infix operators from the loosest binding to the tightest, all of them left associative
+pub const INFIX_LEVELS: &[(&str, &[&str])] = &[
+    ("or", &["||"]),
+    ("and", &["&&"]),
+    ("equality", &["==", "!="]),
+    ("comparison", &["<", ">", "<=", ">="]),
+    ("sum", &["+", "-"]),
+    ("product", &["*", "/"]),
+];
+
+/// the operand of the tightest binding infix operators
+const INFIX_OPERAND: &str = "prefix";
+
+pub const PRODUCTIONS: &[Production] = &[
+    Production { name: "program", rule: "{ statement }" },
+    Production { name: "statement", rule: "( let | assign | return | expression ) \";\"" },
+    Production { name: "let", rule: "\"let\" identifier [ \"=\" expression ]" },
+    Production { name: "assign", rule: "identifier \"=\" expression" },
+    Production { name: "return", rule: "\"return\" expression" },
+    Production { name: "block", rule: "\"{\" { statement } \"}\"" },
+    Production { name: "expression", rule: "conditional" },
+    // right associative, the alternative may be another conditional
+    Production { name: "conditional", rule: "or [ \"?\" expression \":\" conditional ]" },
+    // the infix levels go here, see INFIX_LEVELS
+    Production { name: "prefix", rule: "( \"!\" | \"-\" ) prefix | postfix" },
+    // calls and indexes, which apply to the result of whatever comes before them
+    Production { name: "postfix", rule: "primary { \"(\" [ list ] \")\" | \"[\" expression \"]\" }" },
+    Production {
+        name: "primary",
+        rule: "integer | float | string | \"true\" | \"false\" | \"null\" | identifier | \"(\" expression \")\" | if | function | array | hash",
+    },
+    Production { name: "if", rule: "\"if\" \"(\" expression \")\" block [ \"else\" block ]" },
+    Production { name: "function", rule: "\"fn\" \"(\" [ identifier { \",\" identifier } [ \",\" ] ] \")\" block" },
+    Production { name: "array", rule: "\"[\" [ list ] \"]\"" },
+    // a brace at the start of an expression is always a hash, since blocks only follow `if`,
+    //    `else`, and `fn`
+    Production { name: "hash", rule: "\"{\" [ pair { \",\" pair } [ \",\" ] ] \"}\"" },
+    Production { name: "pair", rule: "expression \":\" expression" },
+    // a trailing comma is allowed
+    Production { name: "list", rule: "expression { \",\" expression } [ \",\" ]" },
+    Production { name: "identifier", rule: "( letter | \"_\" ) { letter | digit | \"_\" }" },
+    Production { name: "integer", rule: "digit { digit }" },
+    Production { name: "float", rule: "digit { digit } \".\" digit { digit }" },
+    // text and the expressions interpolated into it
+    Production {
+        name: "string",
+        rule: "'\"' { character | \"\\\\\" ( '\"' | \"\\\\\" | \"n\" | \"t\" | \"$\" ) | \"${\" expression \"}\" } '\"'",
+    },
+];
+
+/// the grammar in EBNF, one production per line
+pub fn ebnf() -> String {
+    let mut output = String::new();
+    for production in PRODUCTIONS {
+        output.push_str(&format!("{} = {} ;\n", production.name, production.rule));
+        if production.name == "conditional" {
+            output.push_str(&infix_productions());
+        }
+    }
+
+    output
+}
+
+// each level's operands are the next tighter level
+fn infix_productions() -> String {
+    let mut output = String::new();
+    for (index, (name, operators)) in INFIX_LEVELS.iter().enumerate() {
+        let operand = INFIX_LEVELS.get(index + 1).map_or(INFIX_OPERAND, |(name, _)| *name);
+        let operators = operators.iter()
+            .map(|operator| format!("\"{}\"", operator))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        output.push_str(&format!("{} = {} {{ ( {} ) {} }} ;\n", name, operand, operators, operand));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::lexer::lex;
+    use crate::parser::{parse, Expr, Operator, Statement};
+
+    fn parse_expr(input: &str) -> Expr {
+        match parse(&mut lex(input).unwrap()).unwrap().remove(0) {
+            Statement::Expression(expr, _) => expr,
+            statement => panic!("expected an expression, got {:?}", statement),
+        }
+    }
+
+    fn operator(text: &str) -> Operator {
+        match parse_expr(&format!("a {} b;", text)) {
+            Expr::Infix { operator, .. } => operator,
+            expr => panic!("expected an infix expression, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn infix_levels_match_parser() {
+        let (a, b, c) = (ast::ident("a"), ast::ident("b"), ast::ident("c"));
+        for (index, (_, operators)) in INFIX_LEVELS.iter().enumerate() {
+            for looser in *operators {
+                assert_eq!(*looser, operator(looser).to_string());
+                // operators on the same level are left associative
+                for same in *operators {
+                    assert_eq!(
+                        ast::infix(ast::infix(a.clone(), operator(looser), b.clone()), operator(same), c.clone()),
+                        parse_expr(&format!("a {} b {} c;", looser, same)),
+                    );
+                }
+                // and those on tighter levels bind first, on either side
+                for (_, tighter) in &INFIX_LEVELS[index + 1..] {
+                    for tighter in *tighter {
+                        assert_eq!(
+                            ast::infix(a.clone(), operator(looser), ast::infix(b.clone(), operator(tighter), c.clone())),
+                            parse_expr(&format!("a {} b {} c;", looser, tighter)),
+                        );
+                        assert_eq!(
+                            ast::infix(ast::infix(a.clone(), operator(tighter), b.clone()), operator(looser), c.clone()),
+                            parse_expr(&format!("a {} b {} c;", tighter, looser)),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ebnf_output() {
+        let ebnf = ebnf();
+
+        assert!(ebnf.starts_with("program = { statement } ;\n"));
+        assert!(ebnf.contains("conditional = or [ \"?\" expression \":\" conditional ] ;\nor = and { ( \"||\" ) and } ;\n"));
+        assert!(ebnf.contains("product = prefix { ( \"*\" | \"/\" ) prefix } ;\nprefix = "));
+        // every rule referred to is defined
+        let defined: Vec<&str> = ebnf.lines().map(|line| line.split(" = ").next().unwrap()).collect();
+        for line in ebnf.lines() {
+            let rule = line.split_once(" = ").unwrap().1;
+            for word in rule.split(|c: char| !c.is_ascii_alphabetic() && c != '"' && c != '\'') {
+                let is_name = !word.is_empty() && !word.contains('"') && !word.contains('\'');
+                if is_name && !["letter", "digit", "character"].contains(&word) {
+                    assert!(defined.contains(&word), "`{}` is not defined", word);
+                }
+            }
+        }
+    }
+}
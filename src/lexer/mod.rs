@@ -1,18 +1,29 @@
-use logos::Logos;
+use logos::{Filter, Lexer, Logos};
+use std::fmt;
+use std::ops::Range;
+
+mod trivia;
+pub use self::trivia::{trivia, Trivia, TriviaKind};
 
 #[derive(Logos, Debug, PartialEq, Clone)]
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[logos(trivia = r"\p{Whitespace}")]
 pub enum Token {
     #[error]
+    #[regex(r"//[^\n]*", logos::skip)] // single-line comment
+    #[token("/*", block_comment)]
     ERROR,
     EOF,
-    #[regex("[a-zA-Z]+", |lexer| lexer.slice().to_owned())]
+    #[regex("[a-zA-Z_][a-zA-Z0-9_]*", |lexer| lexer.slice().to_owned())]
     IDENT(String),
     #[regex("[0-9]+", |lexer| lexer.slice().parse())]
     INT(i32),
-    #[regex(r#""[^"]*""#, |lexer| lexer.slice()[1..(lexer.slice().len()-1)].to_owned())]
+    #[regex(r"[0-9]+\.[0-9]+", |lexer| lexer.slice().parse())]
+    FLOAT(f64),
+    #[token("\"", string_literal)]
     STRING(String), // string literal, let x = "my string";
+    INVALID_STRING(StringError), // produced by lex in place of the error for a malformed string literal
+    INTERPOLATED(Vec<StringPart>), // produced by lex in place of a string literal containing `${...}`
     #[token = "="]
     ASSIGN,
     #[token = "+"]
@@ -41,6 +52,10 @@ pub enum Token {
     LBRACE,
     #[token = "}"]
     RBRACE,
+    #[token = "["]
+    LBRACKET,
+    #[token = "]"]
+    RBRACKET,
     #[token = "fn"]
     FUNCTION,
     #[token = "let"]
@@ -55,28 +70,517 @@ pub enum Token {
     TRUE,
     #[token = "false"]
     FALSE,
+    #[token = "null"]
+    NULL,
     #[token = "=="]
     EQ,
     #[token = "!="]
     NOT_EQ,
+    #[token = "<="]
+    LT_EQ,
+    #[token = ">="]
+    GT_EQ,
+    #[token = "&&"]
+    AND,
+    #[token = "||"]
+    OR,
+    #[token = "?"]
+    QUESTION,
+    #[token = ":"]
+    COLON,
+}
+
+// tokens are displayed the way they appear in source, for use in error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::ERROR => "invalid token",
+            Token::EOF => "end of input",
+            Token::IDENT(name) => return write!(f, "identifier `{}`", name),
+            Token::INT(value) => return write!(f, "`{}`", value),
+            Token::FLOAT(value) => return write!(f, "`{}`", format_float(*value)),
+            Token::STRING(string) => return write!(f, "`{:?}`", string),
+            Token::INVALID_STRING(_) => "invalid string literal",
+            Token::INTERPOLATED(_) => "interpolated string literal",
+            Token::ASSIGN => "`=`",
+            Token::PLUS => "`+`",
+            Token::MINUS => "`-`",
+            Token::SLASH => "`/`",
+            Token::ASTERISK => "`*`",
+            Token::LT => "`<`",
+            Token::GT => "`>`",
+            Token::BANG => "`!`",
+            Token::COMMA => "`,`",
+            Token::SEMICOLON => "`;`",
+            Token::LPAREN => "`(`",
+            Token::RPAREN => "`)`",
+            Token::LBRACE => "`{`",
+            Token::RBRACE => "`}`",
+            Token::LBRACKET => "`[`",
+            Token::RBRACKET => "`]`",
+            Token::FUNCTION => "`fn`",
+            Token::LET => "`let`",
+            Token::IF => "`if`",
+            Token::ELSE => "`else`",
+            Token::RETURN => "`return`",
+            Token::TRUE => "`true`",
+            Token::FALSE => "`false`",
+            Token::NULL => "`null`",
+            Token::EQ => "`==`",
+            Token::NOT_EQ => "`!=`",
+            Token::LT_EQ => "`<=`",
+            Token::GT_EQ => "`>=`",
+            Token::AND => "`&&`",
+            Token::OR => "`||`",
+            Token::QUESTION => "`?`",
+            Token::COLON => "`:`",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// skips past the end of a block comment, or produces an error if the comment is never closed
+fn block_comment(lexer: &mut Lexer<Token>) -> Filter<()> {
+    match lexer.remainder().find("*/") {
+        Some(end) => {
+            lexer.bump(end + 2);
+            Filter::Skip
+        },
+        None => {
+            lexer.bump(lexer.remainder().len());
+            Filter::Emit(())
+        },
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StringError {
+    // the offset is in bytes from the opening quote to the backslash
+    InvalidEscape { escape: char, offset: usize },
+    // the input ended before the closing quote
+    Unterminated,
+}
+
+impl fmt::Display for StringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StringError::InvalidEscape { escape, .. } => write!(f, "invalid escape sequence `\\{}` in string literal", escape),
+            StringError::Unterminated => write!(f, "string literal is not closed before the end of input"),
+        }
+    }
+}
+
+/// a piece of an interpolated string literal, the code between `${` and `}` is lexed as part
+///    of the input, so the tokens have spans pointing into it
+#[derive(Debug, PartialEq, Clone)]
+pub enum StringPart {
+    Text(String),
+    // ends with the end of input, at the closing brace, so the tokens can be parsed on their own
+    Code(Vec<SpannedToken>),
+}
+
+// a piece of a string literal as it is scanned, with interpolated code as a byte range from
+//    just after the opening quote
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Code(Range<usize>),
+}
+
+fn string_literal(lexer: &mut Lexer<Token>) -> Result<String, StringError> {
+    let (segments, length) = scan_string(lexer.remainder());
+    lexer.bump(length);
+
+    // the literal is scanned again by lex if it contains code, which is lexed from there
+    segments.map(|segments| segments.into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Text(text) => Some(text),
+            Segment::Code(_) => None,
+        })
+        .collect())
+}
+
+/// reads a string literal from just after its opening quote, returning its pieces along with
+///    the number of bytes it covers, including the closing quote
+fn scan_string(source: &str) -> (Result<Vec<Segment>, StringError>, usize) {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut error = None;
+    let mut position = 0;
+
+    while let Some(c) = source[position..].chars().next() {
+        let index = position;
+        position += c.len_utf8();
+        match c {
+            '"' => {
+                segments.push(Segment::Text(text));
+                return (error.map_or(Ok(segments), Err), position);
+            },
+            '\\' => {
+                let escape = match source[position..].chars().next() {
+                    Some(escape) => escape,
+                    None => break,
+                };
+                position += escape.len_utf8();
+                match escape {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    '"' => text.push('"'),
+                    '\\' => text.push('\\'),
+                    '$' => text.push('$'),
+                    // scanning continues to the closing quote so lexing can resume after the literal
+                    escape => {
+                        error.get_or_insert(StringError::InvalidEscape { escape, offset: index + 1 });
+                    },
+                }
+            },
+            '$' if source[position..].starts_with('{') => {
+                let start = position + 1;
+                let length = match code_length(&source[start..]) {
+                    Some(length) => length,
+                    None => break,
+                };
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+                segments.push(Segment::Code(start..start + length));
+                position = start + length + 1;
+            },
+            // a literal spanning lines has the same value whichever line endings the file uses
+            '\r' if source[position..].starts_with('\n') => {},
+            c => text.push(c),
+        }
+    }
+
+    (Err(StringError::Unterminated), source.len())
+}
+
+// the length of the code interpolated into a string literal, up to the brace which closes it,
+//    or None if the input ends first
+fn code_length(source: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut position = 0;
+    while let Some(c) = source[position..].chars().next() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(position),
+            '}' => depth -= 1,
+            // a brace in a string literal nested in the code doesn't count
+            '"' => match scan_string(&source[position + 1..]) {
+                (Err(StringError::Unterminated), _) => return None,
+                (_, length) => position += length,
+            },
+            _ => {},
+        }
+        position += c.len_utf8();
+    }
+
+    None
 }
 
-pub fn lex(input: &str) -> Vec<Token> {
-    let mut tokens = Token::lexer(input)
-        .collect::<Vec<Token>>();
-    tokens.push(Token::EOF);
+/// the location of a piece of source code, lines and columns start from one
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize, // byte offset
+    pub end: usize, // byte offset, exclusive
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// creates a span covering from the start of this span to the end of another
+    pub fn to(&self, end: &Span) -> Span {
+        Span {
+            end: end.end,
+            ..*self
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// reads through lexed tokens in order without removing them, for the parser and anything
+///    else which walks tokens with lookahead
+pub struct TokenStream<'a> {
+    tokens: &'a [SpannedToken],
+    position: usize,
+}
+
+impl<'a> TokenStream<'a> {
+    /// the tokens must end with the end of input, as those from lex do
+    pub fn new(tokens: &'a [SpannedToken]) -> Self {
+        assert!(tokens.last().is_some_and(|token| token.token == Token::EOF), "tokens must end with EOF");
+
+        TokenStream { tokens, position: 0 }
+    }
+
+    pub fn peek(&self) -> &SpannedToken {
+        self.peek_nth(0)
+    }
+
+    /// the token n places after the next one, or the end of input if there are fewer left
+    pub fn peek_nth(&self, n: usize) -> &SpannedToken {
+        let last = self.tokens.len() - 1;
+        &self.tokens[(self.position + n).min(last)]
+    }
+
+    /// consumes the next token, the end of input is never consumed so it can be read again,
+    ///    which is why this isn't an iterator
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> SpannedToken {
+        let token = self.peek().clone();
+        if self.position < self.tokens.len() - 1 {
+            self.position += 1;
+        }
+
+        token
+    }
+
+    /// consumes the next token if it is the expected one, returning its span, or otherwise
+    ///    leaves it in place and returns it
+    pub fn expect(&mut self, expected: &Token) -> Result<Span, SpannedToken> {
+        if self.peek().token == *expected {
+            Ok(self.next().span)
+        } else {
+            Err(self.peek().clone())
+        }
+    }
+
+    /// the number of tokens consumed so far
+    pub fn consumed(&self) -> usize {
+        self.position
+    }
+}
+
+/// formats a float as the shortest literal which lexes back to the same value, always with a
+///    decimal point so it doesn't read back as an integer, and never with an exponent or
+///    locale specific separators which the lexer doesn't accept
+pub fn format_float(num: f64) -> String {
+    // infinity and nan have no literal, so are left as rust writes them
+    if !num.is_finite() {
+        return num.to_string();
+    }
 
-    tokens
+    // rust's display for floats is the shortest representation which round trips, without
+    //    an exponent
+    let mut formatted = num.to_string();
+    if !formatted.contains('.') {
+        formatted.push_str(".0");
+    }
+
+    formatted
+}
+
+/// the words the lexer turns into keyword tokens rather than identifiers
+pub const KEYWORDS: &[&str] = &["fn", "let", "if", "else", "return", "true", "false", "null"];
+
+/// the number of columns between tab stops when lex computes the column of a token
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// a character the lexer doesn't recognise, with the line it is on so it can be pointed out
+#[derive(Debug, PartialEq, Clone)]
+pub struct LexError {
+    pub slice: String,
+    pub span: Span,
+    pub source_line: String,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "lex error at {}:{}: unexpected `{}`", self.span.line, self.span.column, self.slice)?;
+        writeln!(f, "{}", self.source_line)?;
+        write!(f, "{}^", " ".repeat(self.span.column - 1))
+    }
+}
+
+pub fn lex(input: &str) -> Result<Vec<SpannedToken>, LexError> {
+    lex_with_tab_width(input, DEFAULT_TAB_WIDTH)
+}
+
+/// lexes the input, counting a tab as advancing the column to the next multiple of tab_width
+///    so columns match what an editor with that tab width shows
+pub fn lex_with_tab_width(input: &str, tab_width: usize) -> Result<Vec<SpannedToken>, LexError> {
+    lex_range(input, 0..input.len(), tab_width)
+}
+
+// lexes part of the input, such as the code interpolated into a string literal, with spans
+//    in the whole input
+fn lex_range(input: &str, range: Range<usize>, tab_width: usize) -> Result<Vec<SpannedToken>, LexError> {
+    let mut tokens = vec![];
+
+    // the lexer only reports byte offsets, so lines and columns are computed by
+    //    walking the input alongside it
+    let mut line = 1;
+    // counted from zero, in columns rather than characters since a tab can span several
+    let mut column = 0;
+    let mut position = 0;
+    let mut span_at = |offset: usize| {
+        for c in input[position..offset].chars() {
+            match c {
+                '\n' => {
+                    line += 1;
+                    column = 0;
+                },
+                '\t' => column += tab_width - column % tab_width,
+                _ => column += 1,
+            }
+        }
+        position = offset;
+
+        (line, column + 1)
+    };
+
+    let offset = range.start;
+    for (token, range) in Token::lexer(&input[range.clone()]).spanned() {
+        let range = range.start + offset..range.end + offset;
+        // logos discards the reason a string literal was rejected, so the literal is scanned
+        //    again to find it, pointing invalid escapes out precisely
+        let (token, start, end) = match token {
+            Token::ERROR if input[range.start..].starts_with('"') => {
+                match scan_string(&input[range.start + 1..]).0 {
+                    Err(StringError::InvalidEscape { escape, offset }) => {
+                        let start = range.start + offset;
+                        (Token::INVALID_STRING(StringError::InvalidEscape { escape, offset }), start, start + 1 + escape.len_utf8())
+                    },
+                    Err(error) => (Token::INVALID_STRING(error), range.start, range.end),
+                    Ok(_) => unreachable!("string literal rejected by lexer but scanned successfully"),
+                }
+            },
+            Token::STRING(value) if input[range.clone()].contains("${") => {
+                let segments = match scan_string(&input[range.start + 1..]).0 {
+                    Ok(segments) => segments,
+                    Err(_) => unreachable!("string literal accepted by lexer but not scanned successfully"),
+                };
+                // such as when the only `${` is escaped
+                if segments.iter().all(|segment| matches!(segment, Segment::Text(_))) {
+                    (Token::STRING(value), range.start, range.end)
+                } else {
+                    let code_start = range.start + 1;
+                    let parts = segments.into_iter()
+                        .filter(|segment| *segment != Segment::Text(String::new()))
+                        .map(|segment| match segment {
+                            Segment::Text(text) => Ok(StringPart::Text(text)),
+                            Segment::Code(code) =>
+                                lex_range(input, code.start + code_start..code.end + code_start, tab_width).map(StringPart::Code),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    (Token::INTERPOLATED(parts), range.start, range.end)
+                }
+            },
+            token => (token, range.start, range.end),
+        };
+
+        let (line, column) = span_at(start);
+        if token == Token::ERROR {
+            let line_start = input[..start].rfind('\n').map_or(0, |index| index + 1);
+            let line_end = input[start..].find('\n').map_or(input.len(), |index| start + index);
+            return Err(LexError {
+                slice: input[start..end].to_string(),
+                span: Span { start, end, line, column },
+                source_line: input[line_start..line_end].trim_end_matches('\r').to_string(),
+            });
+        }
+        tokens.push(SpannedToken {
+            token,
+            span: Span { start, end, line, column },
+        });
+    }
+
+    let (line, column) = span_at(range.end);
+    tokens.push(SpannedToken {
+        token: Token::EOF,
+        span: Span { start: range.end, end: range.end, line, column },
+    });
+
+    Ok(tokens)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn tokens_only(input: &str) -> Vec<Token> {
+        lex(input).unwrap().into_iter().map(|spanned| spanned.token).collect()
+    }
+
+    #[test]
+    fn lex_tabs_and_crlf() {
+        let spans = |input: &str, tab_width: usize| {
+            lex_with_tab_width(input, tab_width).unwrap().into_iter()
+                .map(|token| (token.span.line, token.span.column))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec![(1, 5), (1, 9), (1, 10)], spans("\tx\t;", 4));
+        assert_eq!(vec![(1, 3), (1, 5), (1, 6)], spans("\tx\t;", 2));
+        assert_eq!(vec![(1, 3), (1, 5), (1, 6)], spans("x\tx\t;", 2)[1..].to_vec());
+        assert_eq!(spans("let x = 1;\n  x;\n", 4), spans("let x = 1;\r\n  x;\r\n", 4));
+
+        assert_eq!(tokens_only("\"a\nb\";"), tokens_only("\"a\r\nb\";"));
+    }
+
+    #[test]
+    fn lex_float_round_trip() {
+        for num in &[0.1, 2.0, 123.456, 1e21, 1e-7, 0.30000000000000004, f64::MAX] {
+            let formatted = format_float(*num);
+            assert_eq!(vec![Token::FLOAT(*num), Token::EOF], tokens_only(&formatted), "{}", formatted);
+        }
+        assert_eq!("2.0", format_float(2.0));
+        assert_eq!("0.0000001", format_float(1e-7));
+        assert_eq!("-1.5", format_float(-1.5));
+        assert_eq!("inf", format_float(f64::INFINITY));
+    }
+
+    #[test]
+    fn lex_identifiers() {
+        assert_eq!(vec![Token::IDENT(String::from("my_var2")), Token::EOF], tokens_only("my_var2"));
+        assert_eq!(vec![Token::IDENT(String::from("_")), Token::EOF], tokens_only("_"));
+        // an identifier can't start with a digit
+        assert_eq!(vec![Token::INT(2), Token::IDENT(String::from("x")), Token::EOF], tokens_only("2x"));
+        // keywords are only matched whole
+        assert_eq!(vec![Token::IDENT(String::from("let_")), Token::EOF], tokens_only("let_"));
+    }
+
+    #[test]
+    fn lex_float_separators() {
+        // a comma is never a decimal separator, and digits are never grouped
+        assert_eq!(vec![Token::INT(1), Token::COMMA, Token::INT(5), Token::EOF], tokens_only("1,5"));
+        assert!(!tokens_only("1.000,5").contains(&Token::FLOAT(1000.5)));
+        assert!(!lex("1_000.5").is_ok_and(|tokens| tokens.iter().any(|token| token.token == Token::FLOAT(1000.5))));
+    }
+
+    #[test]
+    fn token_stream() {
+        let tokens = lex("let x;").unwrap();
+        let mut stream = TokenStream::new(&tokens);
+
+        assert_eq!(Token::LET, stream.peek().token);
+        assert_eq!(Token::SEMICOLON, stream.peek_nth(2).token);
+        assert_eq!(Token::EOF, stream.peek_nth(10).token);
+
+        assert_eq!(Ok(Span { start: 0, end: 3, line: 1, column: 1 }), stream.expect(&Token::LET));
+        assert_eq!(Err(Token::IDENT(String::from("x"))), stream.expect(&Token::SEMICOLON).map_err(|found| found.token));
+        assert_eq!(Token::IDENT(String::from("x")), stream.next().token);
+        assert_eq!(Token::SEMICOLON, stream.next().token);
+        // the end of input is never consumed
+        assert_eq!(Token::EOF, stream.next().token);
+        assert_eq!(Token::EOF, stream.next().token);
+        assert_eq!(3, stream.consumed());
+    }
+
+    #[test]
+    fn lex_keywords() {
+        for keyword in KEYWORDS {
+            assert!(!matches!(tokens_only(keyword)[0], Token::IDENT(_)), "{}", keyword);
+        }
+    }
+
     #[test]
     fn lex_tokens() {
         let input = "=+(){},;";
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -97,7 +601,7 @@ mod tests {
     #[test]
     fn lex_let() {
         let input = "let five = 5;";
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -115,7 +619,7 @@ mod tests {
     #[test]
     fn lex_let_ident_contains_keyword() {
         let input = "let letter = 5;";
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -133,7 +637,7 @@ mod tests {
     #[test]
     fn lex_ident_ending_with_semicolon() {
         let input = "let ten = 5 + five;";
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -157,7 +661,7 @@ mod tests {
               x + y;
             };
         "#;
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -186,7 +690,7 @@ mod tests {
     #[test]
     fn lex_function_call() {
         let input = "let result = add(five, ten);";
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -209,7 +713,7 @@ mod tests {
     #[test]
     fn lex_additional_opeations() {
         let input = "- / * < > !";
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -234,7 +738,7 @@ mod tests {
                 return false;
             }
         "#;
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -265,7 +769,7 @@ mod tests {
             10 == 10;
             10 != 9;
         "#;
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -283,10 +787,140 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lex_less_greater_equal() {
+        let input = "1 <= 2; 2 >= 1; 1 < 2;";
+        let tokens = tokens_only(input);
+
+        assert_eq!(
+            vec![
+                Token::INT(1),
+                Token::LT_EQ,
+                Token::INT(2),
+                Token::SEMICOLON,
+                Token::INT(2),
+                Token::GT_EQ,
+                Token::INT(1),
+                Token::SEMICOLON,
+                Token::INT(1),
+                Token::LT,
+                Token::INT(2),
+                Token::SEMICOLON,
+                Token::EOF,
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn lex_and_or() {
+        let input = "true && false || true;";
+        let tokens = tokens_only(input);
+
+        assert_eq!(
+            vec![
+                Token::TRUE,
+                Token::AND,
+                Token::FALSE,
+                Token::OR,
+                Token::TRUE,
+                Token::SEMICOLON,
+                Token::EOF,
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn lex_float() {
+        let input = "1.5 + 10;";
+        let tokens = tokens_only(input);
+
+        assert_eq!(
+            vec![
+                Token::FLOAT(1.5),
+                Token::PLUS,
+                Token::INT(10),
+                Token::SEMICOLON,
+                Token::EOF,
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn lex_comments() {
+        let input = r#"
+            // a single-line comment
+            let x = 5; // trailing comment
+            /* a block
+               comment * with / stars */
+            x /* inline */ + 1;
+            /**/ /***/ /* ** */
+        "#;
+        let tokens = tokens_only(input);
+
+        assert_eq!(
+            vec![
+                Token::LET,
+                Token::IDENT(String::from("x")),
+                Token::ASSIGN,
+                Token::INT(5),
+                Token::SEMICOLON,
+                Token::IDENT(String::from("x")),
+                Token::PLUS,
+                Token::INT(1),
+                Token::SEMICOLON,
+                Token::EOF,
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn lex_unterminated_block_comment() {
+        let error = lex("1; /* never closed").unwrap_err();
+
+        assert_eq!("/* never closed", error.slice);
+        assert_eq!(Span { start: 3, end: 18, line: 1, column: 4 }, error.span);
+    }
+
+    #[test]
+    fn lex_error() {
+        let error = lex("let x = 1;\nlet y = x @ 2;\nlet z = 3;").unwrap_err();
+
+        assert_eq!(
+            LexError {
+                slice: String::from("@"),
+                span: Span { start: 21, end: 22, line: 2, column: 11 },
+                source_line: String::from("let y = x @ 2;"),
+            },
+            error
+        );
+        assert_eq!("lex error at 2:11: unexpected `@`\nlet y = x @ 2;\n          ^", error.to_string());
+    }
+
+    #[test]
+    fn lex_slash_is_not_comment() {
+        let input = "4 / 2;";
+        let tokens = tokens_only(input);
+
+        assert_eq!(
+            vec![
+                Token::INT(4),
+                Token::SLASH,
+                Token::INT(2),
+                Token::SEMICOLON,
+                Token::EOF,
+            ],
+            tokens
+        );
+    }
+
     #[test]
     fn lex_string() {
         let input = r#"let words = "foo bar";"#;
-        let tokens = lex(input);
+        let tokens = tokens_only(input);
 
         assert_eq!(
             vec![
@@ -301,4 +935,111 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lex_string_escapes() {
+        let input = r#""say \"hi\"\n\tand \\ leave" "";"#;
+        let tokens = tokens_only(input);
+
+        assert_eq!(
+            vec![
+                Token::STRING(String::from("say \"hi\"\n\tand \\ leave")),
+                Token::STRING(String::new()),
+                Token::SEMICOLON,
+                Token::EOF,
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn lex_invalid_string() {
+        // the error points at the bad escape, and lexing resumes after the literal
+        let tokens = lex("x;\nlet s = \"ab\\qc\\z\";").unwrap();
+        assert_eq!(
+            SpannedToken {
+                token: Token::INVALID_STRING(StringError::InvalidEscape { escape: 'q', offset: 3 }),
+                span: Span { start: 14, end: 16, line: 2, column: 12 },
+            },
+            tokens[5]
+        );
+        assert_eq!(Token::SEMICOLON, tokens[6].token);
+
+        let tokens = lex("let s = \"abc;").unwrap();
+        assert_eq!(
+            SpannedToken {
+                token: Token::INVALID_STRING(StringError::Unterminated),
+                span: Span { start: 8, end: 13, line: 1, column: 9 },
+            },
+            tokens[3]
+        );
+        assert_eq!(Token::EOF, tokens[4].token);
+    }
+
+    #[test]
+    fn lex_interpolation() {
+        let tokens = lex("let s = \"a ${x + 1}\tb ${ f(\"}\") }${y}\";\n\"\\${x} $5\";").unwrap();
+        let parts = match &tokens[3].token {
+            Token::INTERPOLATED(parts) => parts,
+            token => panic!("unexpected token {:?}", token),
+        };
+        let code = |part: &StringPart| match part {
+            StringPart::Code(tokens) => tokens.iter().map(|token| token.token.clone()).collect::<Vec<_>>(),
+            part => panic!("unexpected part {:?}", part),
+        };
+        assert_eq!(StringPart::Text(String::from("a ")), parts[0]);
+        assert_eq!(vec![Token::IDENT(String::from("x")), Token::PLUS, Token::INT(1), Token::EOF], code(&parts[1]));
+        assert_eq!(StringPart::Text(String::from("\tb ")), parts[2]);
+        assert_eq!(
+            vec![Token::IDENT(String::from("f")), Token::LPAREN, Token::STRING(String::from("}")), Token::RPAREN, Token::EOF],
+            code(&parts[3])
+        );
+        assert_eq!(vec![Token::IDENT(String::from("y")), Token::EOF], code(&parts[4]));
+        assert_eq!(5, parts.len());
+        assert_eq!(Token::SEMICOLON, tokens[4].token);
+
+        // the tokens of the code have spans in the whole input
+        match &parts[1] {
+            StringPart::Code(tokens) => assert_eq!(Span { start: 15, end: 16, line: 1, column: 16 }, tokens[1].span),
+            part => panic!("unexpected part {:?}", part),
+        }
+
+        // an escaped `$` and one not followed by a brace are text
+        assert_eq!(Token::STRING(String::from("${x} $5")), tokens[5].token);
+
+        assert_eq!(Token::INVALID_STRING(StringError::Unterminated), lex("\"a ${x\";").unwrap()[0].token);
+        assert_eq!(Token::INVALID_STRING(StringError::Unterminated), lex("\"a ${\"}\";").unwrap()[0].token);
+        assert!(lex("\"${x @ y}\";").is_err());
+    }
+
+    #[test]
+    fn lex_spans() {
+        let input = "let x = 5;\n  x + \"é\" + y;";
+        let spans : Vec<Span> = lex(input).unwrap().into_iter().map(|spanned| spanned.span).collect();
+
+        assert_eq!(
+            vec![
+                Span { start: 0, end: 3, line: 1, column: 1 }, // let
+                Span { start: 4, end: 5, line: 1, column: 5 }, // x
+                Span { start: 6, end: 7, line: 1, column: 7 }, // =
+                Span { start: 8, end: 9, line: 1, column: 9 }, // 5
+                Span { start: 9, end: 10, line: 1, column: 10 }, // ;
+                Span { start: 13, end: 14, line: 2, column: 3 }, // x
+                Span { start: 15, end: 16, line: 2, column: 5 }, // +
+                Span { start: 17, end: 21, line: 2, column: 7 }, // "é", two bytes but one column
+                Span { start: 22, end: 23, line: 2, column: 11 }, // +
+                Span { start: 24, end: 25, line: 2, column: 13 }, // y
+                Span { start: 25, end: 26, line: 2, column: 14 }, // ;
+                Span { start: 26, end: 26, line: 2, column: 15 }, // EOF
+            ],
+            spans
+        );
+    }
+
+    #[test]
+    fn span_to() {
+        let start = Span { start: 4, end: 5, line: 2, column: 1 };
+        let end = Span { start: 9, end: 10, line: 2, column: 6 };
+
+        assert_eq!(Span { start: 4, end: 10, line: 2, column: 1 }, start.to(&end));
+    }
 }
@@ -0,0 +1,101 @@
+// The whitespace and comments the lexer skips, recovered from the gaps between token spans so
+// tools which rewrite source, such as the formatter, can put them back. They are kept in a
+// side table alongside the tokens rather than in the token list, so the parser never sees them.
+
+use super::SpannedToken;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,
+    BlockComment,
+}
+
+/// a run of whitespace or a comment, as byte offsets into the source
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Trivia {
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+
+    pub fn is_comment(&self) -> bool {
+        self.kind != TriviaKind::Whitespace
+    }
+}
+
+/// the trivia before each of the tokens lexed from the source, entry i holding what comes
+///    between tokens i - 1 and i, so the entry for the end of input holds what ends the source
+pub fn trivia(source: &str, tokens: &[SpannedToken]) -> Vec<Vec<Trivia>> {
+    let mut position = 0;
+    tokens.iter()
+        .map(|token| {
+            let gap = split_gap(source, position, token.span.start);
+            position = token.span.end;
+            gap
+        })
+        .collect()
+}
+
+// splits the source between two tokens, which the lexer only allows to be whitespace and comments
+fn split_gap(source: &str, start: usize, end: usize) -> Vec<Trivia> {
+    let mut trivia = vec![];
+    let mut position = start;
+    while position < end {
+        let rest = &source[position..end];
+        let (kind, len) = if rest.starts_with("//") {
+            (TriviaKind::LineComment, rest.find('\n').unwrap_or(rest.len()))
+        } else if rest.starts_with("/*") {
+            (TriviaKind::BlockComment, rest.find("*/").map_or(rest.len(), |index| index + 2))
+        } else {
+            let len = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+            // anything else can't come from the lexer, but is kept rather than looping on it
+            (TriviaKind::Whitespace, len.max(rest.chars().next().map_or(0, char::len_utf8)))
+        };
+        trivia.push(Trivia { kind, start: position, end: position + len });
+        position += len;
+    }
+
+    trivia
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+
+    #[test]
+    fn trivia_round_trip() {
+        let source = "// header\nlet x = 5; /* a\n block */ x;\r\n\t// end";
+        let tokens = lex(source).unwrap();
+        let trivia = trivia(source, &tokens);
+        assert_eq!(tokens.len(), trivia.len());
+
+        // the trivia and tokens in order are exactly the source
+        let mut rebuilt = String::new();
+        for (token, trivia) in tokens.iter().zip(&trivia) {
+            trivia.iter().for_each(|trivia| rebuilt.push_str(trivia.text(source)));
+            rebuilt.push_str(&source[token.span.start..token.span.end]);
+        }
+        assert_eq!(source, rebuilt);
+
+        assert_eq!(
+            vec![
+                Trivia { kind: TriviaKind::LineComment, start: 0, end: 9 },
+                Trivia { kind: TriviaKind::Whitespace, start: 9, end: 10 },
+            ],
+            trivia[0]
+        );
+        let comments = |trivia: &[Trivia]| trivia.iter()
+            .filter(|trivia| trivia.is_comment())
+            .map(|trivia| (trivia.kind, trivia.text(source)))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(TriviaKind::BlockComment, "/* a\n block */")], comments(&trivia[5]));
+        assert_eq!(vec![(TriviaKind::LineComment, "// end")], comments(trivia.last().unwrap()));
+    }
+}
@@ -0,0 +1,38 @@
+//! An interpreter for the monkey programming language, with a tree walking evaluator and a
+//! bytecode compiler and vm. Embedders lex and parse source, then either evaluate it in an
+//! [`eval::Env`] or compile it for a [`vm::VM`].
+//!
+//! ```
+//! use monkey::{desugar::desugar, eval::{eval_return_scope, Env, Object}, lexer::lex, parser::parse};
+//!
+//! let ast = parse(&mut lex("let double = fn(x) { x * 2; }; double(21);").unwrap()).unwrap();
+//! let result = eval_return_scope(&desugar(ast), &mut Env::new());
+//!
+//! assert_eq!(Ok(Object::Integer(42)), result);
+//! ```
+
+pub mod parser;
+pub mod lexer;
+pub mod visit;
+pub mod desugar;
+pub mod eval;
+pub mod builtins;
+pub mod stats;
+pub mod standalone;
+pub mod cache;
+pub mod source;
+pub mod repl;
+pub mod term;
+pub mod profile;
+pub mod trace;
+pub mod code;
+pub mod compiler;
+pub mod vm;
+pub mod formatter;
+pub mod ast;
+pub mod diff;
+pub mod grammar;
+pub mod refactor;
+
+#[cfg(test)]
+mod golden;
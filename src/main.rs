@@ -1,29 +1,485 @@
-mod parser;
-use crate::parser::parse;
+use monkey::parser::{parse, check_reserved_words};
+use monkey::lexer::{lex, SpannedToken, Token};
+use monkey::desugar::desugar;
+use monkey::eval::{eval_return_scope, run_main, Env, Object};
+use monkey::builtins::{replay, Context, Host, Limits, BUILTINS, DETERMINISTIC_SEED};
+use monkey::stats::program_stats;
+use monkey::formatter::{format_with_comments, minify};
+use monkey::grammar;
+use monkey::refactor::{self, Position, RefactorError};
+use monkey::standalone;
+use monkey::source;
+use monkey::repl::{self, Command, ReplHelper, Theme};
+use monkey::trace::StderrTracer;
+use monkey::compiler::{compile_from_source_optimized, Compiler, SymbolTable};
+use monkey::cache::Cache;
+use monkey::code::disassemble;
+use monkey::vm::{self, VM};
 
-mod lexer;
-use crate::lexer::lex;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
 
-mod eval;
-use crate::eval::{eval_return_scope, Object, Env};
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
 
-mod code;
-mod compiler;
-mod vm;
+enum ExecutionMode {
+    // the value of every line entered is printed
+    Repl,
+    // only output written by the program itself is shown, unless print_result is set
+    //    in which case the final value of the program is printed as well
+    Script { print_result: bool, args: Vec<String> },
+}
 
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
+// how the repl runs each line, and how scripts are run
+#[derive(Clone, Copy)]
+enum Engine {
+    // the tree walking evaluator
+    Eval,
+    // the bytecode compiler and vm
+    Vm,
+}
+
+#[derive(Default)]
+struct Options {
+    // identifiers using words reserved for future keywords are errors rather than warnings
+    strict: bool,
+    // where the folded stacks of a profile of the program are written, if anywhere
+    profile: Option<String>,
+    // a report of the objects the program allocated is printed when it finishes
+    heap_profile: bool,
+    // where the clock, random numbers, and answers to prompts the program reads are logged,
+    //    if anywhere
+    record: Option<String>,
+    // a log recorded by an earlier run, which the program reads from in place of the clock,
+    //    random numbers, and user
+    replay: Option<String>,
+    // each step the evaluator takes is written to stderr
+    trace: bool,
+    limits: Limits,
+    // invalid UTF-8 in a source file is replaced rather than being an error
+    lossy_utf8: bool,
+    // where scripts run in the vm are cached once compiled, if anywhere
+    cache_dir: Option<String>,
+    // random numbers are seeded the same on every run, so output is stable across runs and
+    //    platforms
+    deterministic: bool,
+}
+
+// the flags read by the interpreter, those ending in `=` take a value
+const FLAGS: &[&str] = &[
+    "--print-result", "--strict", "--profile=", "--heap-profile", "--record=", "--replay=", "--trace",
+    "--lossy-utf8", "--deterministic", "--cache-dir=", "--max-string-length=", "--max-array-length=",
+    "--max-hash-length=", "--engine=", "--prompt=", "--continuation-prompt=", "--color=", "--plain",
+];
+
+// the first arguments which name a command rather than a script to run
+const COMMANDS: &[&str] = &["stats", "fmt", "minify", "refactor", "grammar", "build"];
 
 fn main() {
-    let mut rl = Editor::<()>::new();
-    let mut env = Env::new();
+    // a standalone executable built from a script runs only that script
+    if let Some(source) = standalone::embedded_script() {
+        let options = Options::default();
+        let mode = ExecutionMode::Script { print_result: false, args: env::args().skip(1).collect() };
+        let succeeded = run_source(&source, &mut Env::with_host(host(&options)), &mode, &options).is_some();
+        process::exit(if succeeded { 0 } else { 1 });
+    }
+
+    let mut args : Vec<String> = env::args().skip(1).collect();
+    // the interpreter's flags stop at the script's path, and the arguments after it are passed to
+    //    the script as they are, even those which look like flags, while commands take flags
+    //    anywhere
+    let script_args = match args.iter().position(|arg| !arg.starts_with("--")) {
+        Some(index) if !COMMANDS.contains(&args[index].as_str()) => args.split_off(index + 1),
+        _ => vec![],
+    };
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with("--") && !is_flag(arg)) {
+        eprintln!("unknown flag `{}`", flag);
+        process::exit(2);
+    }
+    let print_result = args.iter().any(|arg| arg == "--print-result");
+    let options = Options {
+        strict: args.iter().any(|arg| arg == "--strict"),
+        profile: args.iter()
+            .find_map(|arg| arg.strip_prefix("--profile="))
+            .map(String::from),
+        heap_profile: args.iter().any(|arg| arg == "--heap-profile"),
+        record: args.iter()
+            .find_map(|arg| arg.strip_prefix("--record="))
+            .map(String::from),
+        replay: args.iter()
+            .find_map(|arg| arg.strip_prefix("--replay="))
+            .map(String::from),
+        trace: args.iter().any(|arg| arg == "--trace"),
+        lossy_utf8: args.iter().any(|arg| arg == "--lossy-utf8"),
+        deterministic: args.iter().any(|arg| arg == "--deterministic"),
+        // the flag takes precedence over the environment variable
+        cache_dir: args.iter()
+            .find_map(|arg| arg.strip_prefix("--cache-dir="))
+            .map(String::from)
+            .or_else(|| env::var("MONKEY_CACHE_DIR").ok()),
+        limits: Limits {
+            max_string_length: size_flag(&args, "--max-string-length="),
+            max_array_length: size_flag(&args, "--max-array-length="),
+            max_hash_length: size_flag(&args, "--max-hash-length="),
+        },
+    };
+
+    // the flag takes precedence over the environment variable
+    let engine = args.iter()
+        .find_map(|arg| arg.strip_prefix("--engine="))
+        .map(String::from)
+        .or_else(|| env::var("MONKEY_ENGINE").ok());
+    let engine = match engine.as_deref() {
+        None | Some("eval") => Engine::Eval,
+        Some("vm") => Engine::Vm,
+        Some(engine) => {
+            eprintln!("unknown engine `{}`, expected `eval` or `vm`", engine);
+            process::exit(2);
+        },
+    };
+
+    let positional : Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    match positional.as_slice() {
+        [command, path] if *command == "stats" => run_stats(path),
+        [command, path] if *command == "fmt" => run_fmt(path),
+        [command, path] if *command == "minify" => run_minify(path),
+        [command, action, target, name] if *command == "refactor" && *action == "extract-fn" => {
+            run_refactor(target, |source, start, end| match end {
+                Some(end) => refactor::extract_function(source, start, end, name),
+                None => Err(RefactorError::InvalidSelection),
+            })
+        },
+        [command, action, target] if *command == "refactor" && *action == "inline-var" => {
+            run_refactor(target, |source, position, _| refactor::inline_variable(source, position))
+        },
+        [command] if *command == "grammar" => print!("{}", grammar::ebnf()),
+        [command, script, flag, output] if *command == "build" && *flag == "-o" => run_build(script, output),
+        [command, script] if *command == "build" => {
+            // by default the executable is named after the script, without its extension
+            let output = Path::new(script.as_str()).with_extension("");
+            run_build(script, &output.to_string_lossy())
+        },
+        // the arguments after the path are passed to the script's main function
+        [path] => match engine {
+            Engine::Eval => run_file(path, ExecutionMode::Script { print_result, args: script_args }, &options),
+            Engine::Vm => run_vm_file(path, print_result, &script_args, &options),
+        },
+        [] => run_repl(engine, &options, repl_theme(&args)),
+        [command, ..] => {
+            eprintln!("wrong arguments for `{}`", command);
+            process::exit(2);
+        },
+    }
+}
+
+fn is_flag(arg: &str) -> bool {
+    FLAGS.iter().any(|flag| if flag.ends_with('=') { arg.starts_with(flag) } else { arg == *flag })
+}
+
+// the value of a flag such as --max-string-length=1024, exiting if it isn't a number
+fn size_flag(args: &[String], prefix: &str) -> Option<usize> {
+    let value = args.iter().find_map(|arg| arg.strip_prefix(prefix))?;
+    match value.parse() {
+        Ok(size) => Some(size),
+        Err(_) => {
+            eprintln!("{} expects a number, got `{}`", prefix.trim_end_matches('='), value);
+            process::exit(2);
+        },
+    }
+}
+
+fn run_stats(path: &str) {
+    let source = match source::read(path, false) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(2);
+        },
+    };
+    let mut tokens = match lex(&source) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    };
+    match parse(&mut tokens) {
+        Ok(ast) => println!("{}", program_stats(&ast)),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    }
+}
+
+// prints the script formatted, keeping its comments
+fn run_fmt(path: &str) {
+    let source = match source::read(path, false) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(2);
+        },
+    };
+    let tokens = match lex(&source) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    };
+    match parse(&mut tokens.clone()) {
+        Ok(program) => print!("{}", format_with_comments(&source, &tokens, &program)),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    }
+}
+
+// rewrites the script named by a target such as `file.mky:10:4-12:20` in place, leaving it as
+//    it was when the refactoring can't be done safely
+fn run_refactor(target: &str, refactoring: impl Fn(&str, Position, Option<Position>) -> Result<String, RefactorError>) {
+    let (path, start, end) = match refactor::parse_target(target) {
+        Some(target) => target,
+        None => {
+            eprintln!("expected a path followed by a position such as file.mky:3:5, or a range such as file.mky:3:5-4:10");
+            process::exit(2);
+        },
+    };
+    let source = match source::read(path, false) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(2);
+        },
+    };
+    match refactoring(&source, start, end) {
+        Ok(output) => if let Err(err) = fs::write(path, output) {
+            eprintln!("failed to write {}: {}", path, err);
+            process::exit(2);
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    }
+}
+
+// prints the script without comments or whitespace and with short names for the bindings of
+//    functions, for embedding where space is tight
+fn run_minify(path: &str) {
+    let source = match source::read(path, false) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(2);
+        },
+    };
+    let tokens = match lex(&source) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    };
+    // a script which doesn't parse is reported rather than minified
+    if let Err(err) = parse(&mut tokens.clone()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+
+    println!("{}", minify(&source, &tokens));
+}
+
+// exits with status 1 if the executable can't be built
+fn run_build(script: &str, output: &str) {
+    match standalone::build(Path::new(script), Path::new(output)) {
+        Ok(()) => println!("built {}", output),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    }
+}
+
+// exits with status 1 if the program fails, or 2 if the source can't be read
+fn run_file(path: &str, mode: ExecutionMode, options: &Options) {
+    let source = match source::read(path, options.lossy_utf8) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(2);
+        },
+    };
+    let mut env = Env::with_host(script_host(options));
+    if options.profile.is_some() {
+        env.enable_profiling();
+    }
+    if options.trace {
+        env.set_tracer(Box::new(StderrTracer));
+    }
+    let succeeded = run_source(&source, &mut env, &mode, options).is_some();
+
+    write_recording(env.host(), options);
+
+    // a program which fails part way through is still profiled up to the failure
+    if let (Some(path), Some(profiler)) = (&options.profile, env.profiler()) {
+        if let Err(err) = fs::write(path, profiler.folded()) {
+            eprintln!("failed to write profile to {}: {}", path, err);
+        }
+    }
+    if options.heap_profile {
+        eprint!("{}", env.heap().report());
+    }
+    if !succeeded {
+        process::exit(1);
+    }
+}
+
+// exits with status 1 if the program fails, or 2 if the source can't be read or the flags
+//    need the evaluator
+fn run_vm_file(path: &str, print_result: bool, args: &[String], options: &Options) {
+    // the vm runs bytecode rather than statements, so it has nothing to attribute time or
+    //    steps to
+    for (flag, set) in [("--profile", options.profile.is_some()), ("--trace", options.trace)] {
+        if set {
+            eprintln!("{} is only supported by the eval engine, not with --engine=vm", flag);
+            process::exit(2);
+        }
+    }
+    let source = match source::read(path, options.lossy_utf8) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", path, err);
+            process::exit(2);
+        },
+    };
+
+    let cache = options.cache_dir.as_ref().map(Cache::new);
+    let byte_code = match cache.as_ref().and_then(|cache| cache.get(&source)) {
+        Some(byte_code) => byte_code,
+        // reserved words are reported by the run which compiles the script, rather than by
+        //    every run
+        None => {
+            if let Ok(tokens) = lex(&source) {
+                if !report_reserved_words(&tokens, options) {
+                    process::exit(1);
+                }
+            }
+            let byte_code = match compile_from_source_optimized(&source) {
+                Ok(byte_code) => byte_code,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                },
+            };
+            if let (Some(cache), Some(dir)) = (&cache, &options.cache_dir) {
+                if let Err(err) = cache.put(&source, &byte_code) {
+                    eprintln!("failed to write to the cache in {}: {}", dir, err);
+                }
+            }
+            byte_code
+        },
+    };
+
+    let mut vm = VM::with_host(byte_code, script_host(options));
+    // a script is run through its main function when it defines one, as with the evaluator
+    let succeeded = match vm.run_main(args) {
+        Ok(result) => {
+            if print_result {
+                println!("{}", result);
+            }
+            true
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        },
+    };
+
+    write_recording(vm.host(), options);
+    // the vm doesn't know which line allocated each object, so only the kinds are reported
+    if options.heap_profile {
+        eprint!("{}", vm.heap().report());
+    }
+    if !succeeded {
+        process::exit(1);
+    }
+}
+
+fn run_repl(engine: Engine, options: &Options, theme: Theme) {
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(ReplHelper::with_color(theme.color)));
+    let history = history_path();
+    if let Some(history) = &history {
+        // there is no history the first time the repl is run
+        let _ = rl.load_history(history);
+    }
+    let mut env = repl_env(options);
+    let mut vm_state = VmState::default();
+    // the result of the last line which ran, for :show
+    let mut last_result = None;
+    // errors in the program are reported by run_source, but a bug in the interpreter should
+    //    not end the session either, so panics are reported the same way and the line abandoned
+    panic::set_hook(Box::new(|info| eprintln!("internal error: {}", info)));
+    // the lines of a statement which continues onto the next line
+    let mut pending = String::new();
     loop {
-        let readline = rl.readline(">> ");
+        let prompt = if pending.is_empty() { &theme.prompt } else { &theme.continuation };
+        let readline = rl.readline(prompt);
         match readline {
             Ok(line) => {
-                let mut tokens = lex(&line);
-                let ast = parse(&mut tokens);
-                display_object(eval_return_scope(ast, &mut env));
+                if !line.trim().is_empty() {
+                    rl.add_history_entry(line.trim_end_matches('\n'));
+                }
+                pending.push_str(line.trim_end_matches('\n'));
+                if repl::is_incomplete(&pending) {
+                    pending.push('\n');
+                    continue;
+                }
+                let input = mem::take(&mut pending);
+
+                if let Some(command) = repl::parse_command(&input) {
+                    match command {
+                        Ok(command) => run_command(command, engine, &mut env, &mut vm_state, last_result.as_ref(), options),
+                        Err(err) => eprintln!("{}", err),
+                    }
+                    continue;
+                }
+
+                let result = panic::catch_unwind(AssertUnwindSafe(|| match engine {
+                    Engine::Eval => run_source(&input, &mut env, &ExecutionMode::Repl, options),
+                    Engine::Vm => run_vm_line(&input, &mut vm_state, options),
+                }));
+                match result {
+                    Ok(Some(result)) => last_result = Some(result),
+                    Ok(None) => {},
+                    Err(_) => {
+                        env.reset_scopes();
+                        // the vm's state may have been taken part way through the line, so rather
+                        //    than continue with globals which don't match the symbol table, start over
+                        vm_state = VmState::default();
+                    },
+                }
+
+                let globals = match engine {
+                    Engine::Eval => env.globals().into_iter().map(|(name, _)| name.to_string()).collect(),
+                    Engine::Vm => vm_state.symbol_table.globals().into_iter().map(|(name, _)| name.to_string()).collect(),
+                };
+                if let Some(helper) = rl.helper_mut() {
+                    helper.set_globals(globals);
+                }
+            },
+            // interrupting a statement which continues over several lines abandons it
+            //    rather than ending the session
+            Err(ReadlineError::Interrupted) if !pending.is_empty() => {
+                pending.clear();
             },
             Err(ReadlineError::Interrupted) => {
                 break
@@ -37,15 +493,267 @@ fn main() {
             }
         }
     }
+
+    if let Some(history) = &history {
+        if let Err(err) = rl.save_history(history) {
+            eprintln!("failed to save history to {}: {}", history.display(), err);
+        }
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")).map(PathBuf::from)
+}
+
+// the history is kept in the home directory, so it is shared by every session
+fn history_path() -> Option<PathBuf> {
+    Some(home_dir()?.join(".monkey_history"))
+}
+
+// the theme from ~/.monkeyrc, then from flags, with colors left out when asked for plain
+//    output or when the terminal can't draw them
+fn repl_theme(args: &[String]) -> Theme {
+    let mut theme = Theme::default();
+    if let Some(path) = home_dir().map(|home| home.join(".monkeyrc")) {
+        // most people won't have an rc file
+        if let Ok(contents) = fs::read_to_string(&path) {
+            if let Err(err) = theme.apply_rc(&contents) {
+                eprintln!("ignoring the rest of {} after {}", path.display(), err);
+            }
+        }
+    }
+
+    let flags = [("--prompt=", "prompt"), ("--continuation-prompt=", "continuation"), ("--color=", "color")];
+    for (prefix, setting) in &flags {
+        if let Some(value) = args.iter().find_map(|arg| arg.strip_prefix(prefix)) {
+            if let Err(err) = theme.set(setting, value) {
+                eprintln!("{}", err);
+                process::exit(2);
+            }
+        }
+    }
+
+    // the default prompts are ascii, so plain output only needs to leave out the colors
+    if args.iter().any(|arg| arg == "--plain") || colors_disabled() {
+        theme.color = None;
+    }
+
+    theme
+}
+
+// when asked for no colors, or when the terminal can't draw them
+fn colors_disabled() -> bool {
+    let dumb_terminal = env::var("TERM").is_ok_and(|term| term == "dumb");
+    env::var_os("NO_COLOR").is_some() || dumb_terminal
+}
+
+fn host(options: &Options) -> Host {
+    // scripts only style their output when it goes to a terminal, rather than a file or pipe
+    let color = io::stdout().is_terminal() && !colors_disabled();
+    let host = Host::new(Box::new(io::stdout()))
+        .with_limits(options.limits)
+        .with_color(color);
+    if options.deterministic {
+        host.with_seed(DETERMINISTIC_SEED)
+    } else {
+        host
+    }
+}
+
+// the host for running a script, recording or replaying what it reads when asked, exits with
+//    status 2 if the replay log can't be read
+fn script_host(options: &Options) -> Host {
+    let mut host = host(options);
+    if options.record.is_some() {
+        host = host.with_recording();
+    }
+    if let Some(path) = &options.replay {
+        let log = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|log| replay::parse_log(&log).map_err(|err| err.to_string()));
+        match log {
+            Ok(log) => host = host.with_replay(log),
+            Err(err) => {
+                eprintln!("failed to read replay log {}: {}", path, err);
+                process::exit(2);
+            },
+        }
+    }
+
+    host
+}
+
+// the log is written even when the program fails, since that is the run worth reproducing
+fn write_recording(host: &Host, options: &Options) {
+    if let (Some(path), Some(log)) = (&options.record, host.recorded()) {
+        if let Err(err) = fs::write(path, replay::format_log(log)) {
+            eprintln!("failed to write replay log to {}: {}", path, err);
+        }
+    }
+}
+
+fn repl_env(options: &Options) -> Env {
+    Env::with_host(host(options))
+}
+
+fn run_command(
+    command: Command,
+    engine: Engine,
+    env: &mut Env,
+    vm_state: &mut VmState,
+    last_result: Option<&Object>,
+    options: &Options,
+) {
+    match command {
+        Command::Tokens(source) => match lex(source) {
+            Ok(tokens) => {
+                for token in tokens.into_iter().filter(|token| token.token != Token::EOF) {
+                    println!("{}:{} {:?}", token.span.line, token.span.column, token.token);
+                }
+            },
+            Err(err) => eprintln!("{}", err),
+        },
+        Command::Ast(source) => match lex(source).map(|mut tokens| parse(&mut tokens)) {
+            Ok(Ok(ast)) => ast.iter().for_each(|statement| println!("{:#?}", statement)),
+            Ok(Err(err)) => eprintln!("{}", err),
+            Err(err) => eprintln!("{}", err),
+        },
+        Command::Bytecode(source) => {
+            // compiled with a copy of the vm engine's state, so the source can refer to its
+            //    globals without defining anything itself
+            let mut compiler = Compiler::new_with_state(vm_state.symbol_table.clone(), vm_state.constants.clone());
+            match compiler.compile_line(source) {
+                Ok(byte_code) => print!("{}", disassemble(&byte_code.instructions, &byte_code.constants)),
+                Err(err) => eprintln!("{}", err),
+            }
+        },
+        Command::Env => {
+            let globals : Vec<(&str, Option<&Object>)> = match engine {
+                Engine::Eval => env.globals(),
+                Engine::Vm => vm_state.symbol_table.globals().into_iter()
+                    .map(|(name, _)| (name, vm::get_global(&vm_state.symbol_table, &vm_state.globals, name)))
+                    .collect(),
+            };
+            for (name, value) in globals {
+                match value {
+                    Some(value) => println!("{} = {}", name, value),
+                    None => println!("{} (unassigned)", name),
+                }
+            }
+        },
+        Command::Reset => {
+            *env = repl_env(options);
+            *vm_state = VmState::default();
+        },
+        Command::Show => match last_result {
+            Some(result) => println!("{}", result),
+            None => eprintln!("there is no result to show"),
+        },
+        Command::Doc(index) => println!("{}", BUILTINS[index]),
+    }
+}
+
+// the state the vm engine carries from one line of the repl to the next
+#[derive(Default)]
+struct VmState {
+    symbol_table: SymbolTable,
+    constants: Vec<Object>,
+    globals: Vec<Option<Object>>,
+}
+
+// returns the result of the line, or None if it failed to compile or run, after reporting the error
+fn run_vm_line(input: &str, state: &mut VmState, options: &Options) -> Option<Object> {
+    // a line which doesn't lex is reported by the compiler below
+    if let Ok(tokens) = lex(input) {
+        if !report_reserved_words(&tokens, options) {
+            return None;
+        }
+    }
+
+    let mut compiler = Compiler::new_with_state(mem::take(&mut state.symbol_table), mem::take(&mut state.constants));
+    // a line which fails to compile leaves the state as it was before the line
+    let compiled = compiler.compile_line(input);
+    let (symbol_table, constants) = compiler.into_state();
+    state.symbol_table = symbol_table;
+    state.constants = constants;
+    let byte_code = match compiled {
+        Ok(byte_code) => byte_code,
+        Err(err) => {
+            eprintln!("{}", err);
+            return None;
+        },
+    };
+
+    let mut vm = VM::new_with_global_store(byte_code, mem::take(&mut state.globals));
+    let result = match vm.run() {
+        Ok(()) => {
+            let result = vm.last_popped().clone();
+            println!("{}", repl::preview(&result, repl::PREVIEW_ELEMENTS));
+            Some(result)
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            None
+        },
+    };
+    state.globals = vm.into_globals();
+
+    result
+}
+
+// returns false if the program uses a reserved word in strict mode, after reporting the error
+fn report_reserved_words(tokens: &[SpannedToken], options: &Options) -> bool {
+    match check_reserved_words(tokens, options.strict) {
+        Ok(warnings) => {
+            warnings.iter().for_each(|warning| eprintln!("{}", warning));
+            true
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        },
+    }
 }
 
-fn display_object(obj: Object) {
-    match obj {
-        Object::Integer(num) => println!("{}", num),
-        Object::String(string) => println!("{}", string),
-        Object::Boolean(val) => println!("{}", val),
-        Object::Function{parameters: _, body: _} => println!("function"),
-        Object::Null => println!("null"),
-        Object::Return(obj) => display_object(*obj),
+// returns the result of the program, or None if it failed to parse or run, after reporting
+//    the error
+fn run_source(input: &str, env: &mut Env, mode: &ExecutionMode, options: &Options) -> Option<Object> {
+    let mut tokens = match lex(input) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            eprintln!("{}", err);
+            return None;
+        },
+    };
+    if !report_reserved_words(&tokens, options) {
+        return None;
     }
+    let ast = match parse(&mut tokens) {
+        Ok(ast) => desugar(ast),
+        Err(err) => {
+            eprintln!("{}", err);
+            return None;
+        },
+    };
+    // a script is run through its main function when it defines one, the repl never is
+    let result = match mode {
+        ExecutionMode::Repl => eval_return_scope(&ast, env),
+        ExecutionMode::Script { args, .. } => run_main(&ast, args, env),
+    };
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("{}", err);
+            return None;
+        },
+    };
+
+    match mode {
+        // large values would bury the session, so the repl prints them cut short
+        ExecutionMode::Repl => println!("{}", repl::preview(&result, repl::PREVIEW_ELEMENTS)),
+        ExecutionMode::Script { print_result: true, .. } => println!("{}", result),
+        ExecutionMode::Script { print_result: false, .. } => {},
+    }
+
+    Some(result)
 }
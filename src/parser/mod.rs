@@ -1,23 +1,114 @@
-use crate::lexer::Token;
+use crate::lexer::{Token, SpannedToken, Span, StringError, StringPart, TokenStream};
+use std::fmt;
 
-#[derive(Debug, PartialEq, Clone)]
+mod reserved;
+pub use self::reserved::{check_reserved_words, ReservedWordWarning, RESERVED_WORDS};
+
+#[derive(Debug, Clone)]
 pub enum Statement {
-    Let{ name: String, value: Expr},
-    Return{ value: Expr },
-    Expression(Expr),
+    Let{ name: String, value: Expr, span: Span },
+    Declare{ name: String, span: Span },
+    Assign{ name: String, value: Expr, span: Span },
+    Return{ value: Expr, span: Span },
+    Expression(Expr, Span),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+// statements are compared by structure alone, ignoring where they appear in the source,
+//    so that the same program formatted differently produces an equal ast
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Statement::Let { name, value, .. }, Statement::Let { name: other_name, value: other_value, .. }) =>
+                name == other_name && value == other_value,
+            (Statement::Declare { name, .. }, Statement::Declare { name: other_name, .. }) =>
+                name == other_name,
+            (Statement::Assign { name, value, .. }, Statement::Assign { name: other_name, value: other_value, .. }) =>
+                name == other_name && value == other_value,
+            (Statement::Return { value, .. }, Statement::Return { value: other_value, .. }) =>
+                value == other_value,
+            (Statement::Expression(expr, _), Statement::Expression(other_expr, _)) =>
+                expr == other_expr,
+            _ => false,
+        }
+    }
+}
+
+impl Statement {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Let { span, .. }
+                | Statement::Declare { span, .. }
+                | Statement::Assign { span, .. }
+                | Statement::Return { span, .. }
+                | Statement::Expression(_, span) => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Expr {
     Const(i32),
+    Float(f64),
     String(String),
     Boolean(bool),
-    Ident(String),
+    Null,
+    // the span is where the name appears, for reporting it when it isn't defined
+    Ident(String, Span),
     Prefix{prefix: Prefix, value: Box<Expr>},
     Infix{left: Box<Expr>, operator: Operator, right: Box<Expr>},
     If{condition: Box<Expr>, consequence: Vec<Statement>, alternative: Vec<Statement>},
     Function{parameters: Vec<String>, body: Vec<Statement>},
-    Call{function: Box<Expr>, arguments: Vec<Expr>},
+    Array(Vec<Expr>),
+    // the key and value of each pair, in the order they are written
+    Hash(Vec<(Expr, Expr)>),
+    // the span runs from the start of the function expression to the closing parenthesis
+    Call{function: Box<Expr>, arguments: Vec<Expr>, span: Span},
+    Index{left: Box<Expr>, index: Box<Expr>},
+    // `condition ? consequence : alternative`, removed by the desugar pass
+    Ternary{condition: Box<Expr>, consequence: Box<Expr>, alternative: Box<Expr>},
+    // the pieces of a string literal such as `"total: ${x + 1}"`, the text as strings along with
+    //    the interpolated expressions, whose values are joined as they are displayed
+    Interpolation(Vec<Expr>),
+}
+
+// like statements, expressions are compared by structure alone, ignoring their spans
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Expr::Const(num), Expr::Const(other_num)) => num == other_num,
+            (Expr::Float(num), Expr::Float(other_num)) => num == other_num,
+            (Expr::String(string), Expr::String(other_string)) => string == other_string,
+            (Expr::Boolean(val), Expr::Boolean(other_val)) => val == other_val,
+            (Expr::Null, Expr::Null) => true,
+            (Expr::Ident(name, _), Expr::Ident(other_name, _)) => name == other_name,
+            (Expr::Prefix { prefix, value }, Expr::Prefix { prefix: other_prefix, value: other_value }) =>
+                prefix == other_prefix && value == other_value,
+            (
+                Expr::Infix { left, operator, right },
+                Expr::Infix { left: other_left, operator: other_operator, right: other_right },
+            ) => left == other_left && operator == other_operator && right == other_right,
+            (
+                Expr::If { condition, consequence, alternative },
+                Expr::If { condition: other_condition, consequence: other_consequence, alternative: other_alternative },
+            ) => condition == other_condition && consequence == other_consequence && alternative == other_alternative,
+            (Expr::Function { parameters, body }, Expr::Function { parameters: other_parameters, body: other_body }) =>
+                parameters == other_parameters && body == other_body,
+            (Expr::Array(elements), Expr::Array(other_elements)) => elements == other_elements,
+            (Expr::Hash(pairs), Expr::Hash(other_pairs)) => pairs == other_pairs,
+            (
+                Expr::Call { function, arguments, .. },
+                Expr::Call { function: other_function, arguments: other_arguments, .. },
+            ) => function == other_function && arguments == other_arguments,
+            (Expr::Index { left, index }, Expr::Index { left: other_left, index: other_index }) =>
+                left == other_left && index == other_index,
+            (
+                Expr::Ternary { condition, consequence, alternative },
+                Expr::Ternary { condition: other_condition, consequence: other_consequence, alternative: other_alternative },
+            ) => condition == other_condition && consequence == other_consequence && alternative == other_alternative,
+            (Expr::Interpolation(parts), Expr::Interpolation(other_parts)) => parts == other_parts,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -34,130 +125,257 @@ pub enum Operator {
     Divide,
     GreaterThan,
     LessThan,
+    GreaterThanOrEqual,
+    LessThanOrEqual,
     Equals,
     NotEquals,
+    And,
+    Or,
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Prefix::Bang => write!(f, "!"),
+            Prefix::Minus => write!(f, "-"),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = match self {
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::GreaterThan => ">",
+            Operator::LessThan => "<",
+            Operator::GreaterThanOrEqual => ">=",
+            Operator::LessThanOrEqual => "<=",
+            Operator::Equals => "==",
+            Operator::NotEquals => "!=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+        };
+        write!(f, "{}", operator)
+    }
 }
 
 #[derive(PartialOrd, PartialEq)]
 enum Precedence {
     Lowest,
+    Ternary,     // X ? Y : Z
+    Or,          // ||
+    And,         // &&
     Equals ,     // ==
-    LessGreater, // > or <
+    LessGreater, // > or < or >= or <=
     Sum,         // +
     Product,     // *
     Prefix,      // -X or !X
-    // Call,        // myFunction(X) - not used
+    Call,        // myFunction(X)
+    Index,       // array[X]
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    UnexpectedToken { found: Token, span: Span },
+    ExpectedToken { expected: Token, found: Token, span: Span },
+    ExpectedIdentifier { found: Token, span: Span },
+    KeywordAsIdentifier { keyword: &'static str, span: Span },
+    // only produced in strict mode
+    ReservedWord { word: String, span: Span },
+    // the span points at the offending escape sequence, or the start of an unterminated literal
+    InvalidString { error: StringError, span: Span },
+    // the span is that of the opening brace
+    UnterminatedBlock { span: Span },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken { found, span } =>
+                write!(f, "parse error at {}:{}: unexpected {}", span.line, span.column, found),
+            ParseError::ExpectedToken { expected, found, span } =>
+                write!(f, "parse error at {}:{}: expected {} but found {}", span.line, span.column, expected, found),
+            ParseError::ExpectedIdentifier { found, span } =>
+                write!(f, "parse error at {}:{}: expected identifier but found {}", span.line, span.column, found),
+            ParseError::KeywordAsIdentifier { keyword, span } =>
+                write!(f, "parse error at {}:{}: `{}` is a keyword and cannot be used as a name, try something like `{}Value` instead",
+                    span.line, span.column, keyword, keyword),
+            ParseError::ReservedWord { word, span } =>
+                write!(f, "parse error at {}:{}: `{}` is reserved for future use and cannot be used as a name in strict mode",
+                    span.line, span.column, word),
+            ParseError::InvalidString { error, span } =>
+                write!(f, "parse error at {}:{}: {}", span.line, span.column, error),
+            ParseError::UnterminatedBlock { span } =>
+                write!(f, "parse error at {}:{}: block is never closed", span.line, span.column),
+        }
+    }
+}
+
+/// parses a program, removing the tokens it consumed from the input
+pub fn parse(input: &mut Vec<SpannedToken>) -> Result<Vec<Statement>, ParseError> {
+    let mut stream = TokenStream::new(input);
+    let program = parse_program(&mut stream);
+    let consumed = stream.consumed();
+    input.drain(..consumed);
+
+    program
 }
 
-pub fn parse(input: &mut Vec<Token>) -> Vec<Statement> {
+fn parse_program(input: &mut TokenStream) -> Result<Vec<Statement>, ParseError> {
+    let program = parse_statements(input)?;
+    // statements only stop early at a closing brace, which has no block to close here
+    if input.peek().token != Token::EOF {
+        let SpannedToken { token: found, span } = input.peek().clone();
+        return Err(ParseError::UnexpectedToken { found, span });
+    }
+
+    Ok(program)
+}
+
+/// parses statements until the end of the enclosing block or the end of input, leaving the
+///    closing token in place for the caller
+fn parse_statements(input: &mut TokenStream) -> Result<Vec<Statement>, ParseError> {
     let mut program = vec![];
 
     loop {
-        let token = &input[0];
+        let token = &input.peek().token;
+        let start = input.peek().span;
 
         match token {
-            Token::EOF => break,
-            Token::LET => parse_let(input, &mut program),
-            Token::RETURN => parse_return(input, &mut program),
-            Token::RBRACE => {
-                break;
-            },
-            _ => program.push(
-                Statement::Expression(
-                    parse_expression(input, Precedence::Lowest)
+            Token::EOF | Token::RBRACE => break,
+            Token::LET => parse_let(input, &mut program, start)?,
+            Token::RETURN => parse_return(input, &mut program, start)?,
+            Token::IDENT(_) if input.peek_nth(1).token == Token::ASSIGN => parse_assign(input, &mut program, start)?,
+            _ if input.peek_nth(1).token == Token::ASSIGN && token.keyword().is_some() =>
+                return Err(ParseError::KeywordAsIdentifier { keyword: token.keyword().unwrap(), span: start }),
+            _ => {
+                let expr = parse_expression(input, Precedence::Lowest)?;
+                program.push(
+                    Statement::Expression(expr, statement_span(start, input))
                 )
-            )
+            }
         }
-        assert_eq!(Token::SEMICOLON, input.remove(0));
+        expect(input, Token::SEMICOLON)?;
 
     }
 
-    program
+    Ok(program)
 }
 
-fn parse_let(input: &mut Vec<Token>, program: &mut Vec<Statement>) {
-    assert_eq!(Token::LET, input.remove(0));
-    let name = match input.remove(0) {
-        Token::IDENT(name) => name,
-        _ => panic!("parse error at let statement"),
-    };
-    assert_eq!(Token::ASSIGN, input.remove(0));
-    let value = parse_expression(input, Precedence::Lowest);
-    program.push(Statement::Let {name, value});
+/// parses a brace delimited block of statements
+fn parse_block(input: &mut TokenStream) -> Result<Vec<Statement>, ParseError> {
+    let open = expect(input, Token::LBRACE)?;
+    let block = parse_statements(input)?;
+    if input.peek().token == Token::EOF {
+        return Err(ParseError::UnterminatedBlock { span: open });
+    }
+    expect(input, Token::RBRACE)?;
+
+    Ok(block)
 }
 
-fn parse_return(input: &mut Vec<Token>, program: &mut Vec<Statement>) {
-    assert_eq!(Token::RETURN, input.remove(0));
-    let value = parse_expression(input, Precedence::Lowest);
-    program.push(Statement::Return {value});
+/// consumes the next token, which must be the expected one, returning its span
+fn expect(input: &mut TokenStream, expected: Token) -> Result<Span, ParseError> {
+    input.expect(&expected)
+        .map_err(|SpannedToken { token: found, span }| ParseError::ExpectedToken { expected, found, span })
+}
+
+/// the error for finding the next token when a different one was expected
+fn expected_error(input: &TokenStream, expected: Token) -> ParseError {
+    let SpannedToken { token: found, span } = input.peek().clone();
+    ParseError::ExpectedToken { expected, found, span }
+}
+
+/// consumes the next token, which must be an identifier, returning its name
+fn expect_ident(input: &mut TokenStream) -> Result<String, ParseError> {
+    match input.next() {
+        SpannedToken { token: Token::IDENT(name), .. } => Ok(name),
+        SpannedToken { token, span } if token.keyword().is_some() =>
+            Err(ParseError::KeywordAsIdentifier { keyword: token.keyword().unwrap(), span }),
+        SpannedToken { token: found, span } =>
+            Err(ParseError::ExpectedIdentifier { found, span }),
+    }
 }
 
-fn parse_expression(input: &mut Vec<Token>, precedence: Precedence) -> Expr {
-    let mut left_expr = match input.remove(0) {
+/// a statement spans from its first token up to and including the semicolon which ends it
+fn statement_span(start: Span, input: &TokenStream) -> Span {
+    start.to(&input.peek().span)
+}
+
+fn parse_let(input: &mut TokenStream, program: &mut Vec<Statement>, start: Span) -> Result<(), ParseError> {
+    expect(input, Token::LET)?;
+    let name = expect_ident(input)?;
+    if input.peek().token == Token::SEMICOLON {
+        program.push(Statement::Declare {name, span: statement_span(start, input)});
+        return Ok(());
+    }
+    expect(input, Token::ASSIGN)?;
+    let value = parse_expression(input, Precedence::Lowest)?;
+    program.push(Statement::Let {name, value, span: statement_span(start, input)});
+
+    Ok(())
+}
+
+fn parse_assign(input: &mut TokenStream, program: &mut Vec<Statement>, start: Span) -> Result<(), ParseError> {
+    let name = expect_ident(input)?;
+    expect(input, Token::ASSIGN)?;
+    let value = parse_expression(input, Precedence::Lowest)?;
+    program.push(Statement::Assign {name, value, span: statement_span(start, input)});
+
+    Ok(())
+}
+
+fn parse_return(input: &mut TokenStream, program: &mut Vec<Statement>, start: Span) -> Result<(), ParseError> {
+    expect(input, Token::RETURN)?;
+    let value = parse_expression(input, Precedence::Lowest)?;
+    program.push(Statement::Return {value, span: statement_span(start, input)});
+
+    Ok(())
+}
+
+fn parse_expression(input: &mut TokenStream, precedence: Precedence) -> Result<Expr, ParseError> {
+    // the end of input is left in place so later lookahead never runs off the token list
+    if input.peek().token == Token::EOF {
+        let SpannedToken { token: found, span } = input.peek().clone();
+        return Err(ParseError::UnexpectedToken { found, span });
+    }
+
+    let SpannedToken { token, span } = input.next();
+    let mut left_expr = match token {
         Token::INT(value) => Expr::Const(value),
+        Token::FLOAT(value) => Expr::Float(value),
         Token::TRUE => Expr::Boolean(true),
         Token::FALSE => Expr::Boolean(false),
-        Token::IDENT(value) => {
-            if &input[0] == &Token::LPAREN {
-                input.remove(0);
-                let mut args = vec![];
-                // must be expressions separated by comma, or RPAREN
-                loop {
-                    match &input[0] {
-                        Token::RPAREN => {
-                            input.remove(0);
-                            break
-                        },
-                        _ => {
-                            args.push(parse_expression(input, Precedence::Lowest));
-                        },
-                    }
-
-                    match input.remove(0) {
-                        Token::RPAREN => break,
-                        Token::COMMA => continue,
-                        _ => panic!("unexpected parameter found while parsing function args"),
-                    }
-                }
-                Expr::Call {
-                    function: Box::new(Expr::Ident(value)),
-                    arguments: args
-                }
-            } else {
-                Expr::Ident(value)
-            }
-        },
+        Token::NULL => Expr::Null,
+        Token::IDENT(value) => Expr::Ident(value, span),
         Token::BANG => Expr::Prefix{
             prefix: Prefix::Bang,
-            value: Box::new(parse_expression(input, Precedence::Prefix))
+            value: Box::new(parse_expression(input, Precedence::Prefix)?)
         },
         Token::MINUS => Expr::Prefix{
             prefix: Prefix::Minus,
-            value: Box::new(parse_expression(input, Precedence::Prefix))
+            value: Box::new(parse_expression(input, Precedence::Prefix)?)
         },
         Token::LPAREN => {
-            let expr = parse_expression(input, Precedence::Lowest);
-            assert_eq!(Token::RPAREN, input.remove(0));
+            let expr = parse_expression(input, Precedence::Lowest)?;
+            expect(input, Token::RPAREN)?;
 
             expr
         },
         Token::IF => {
-            assert_eq!(Token::LPAREN, input.remove(0));
-            let condition = parse_expression(input, Precedence::Lowest);
-            assert_eq!(Token::RPAREN, input.remove(0));
-
-            assert_eq!(Token::LBRACE, input.remove(0));
-            let consequence = parse(input);
-            assert_eq!(Token::RBRACE, input.remove(0));
+            expect(input, Token::LPAREN)?;
+            let condition = parse_expression(input, Precedence::Lowest)?;
+            expect(input, Token::RPAREN)?;
 
-            let alternative = if &input[0] == &Token::ELSE {
-                input.remove(0);
+            let consequence = parse_block(input)?;
 
-                assert_eq!(Token::LBRACE, input.remove(0));
-                let alternative = parse(input);
-                assert_eq!(Token::RBRACE, input.remove(0));
+            let alternative = if input.peek().token == Token::ELSE {
+                input.next();
 
-                alternative
+                parse_block(input)?
             } else {
                 Vec::new()
             };
@@ -170,66 +388,168 @@ fn parse_expression(input: &mut Vec<Token>, precedence: Precedence) -> Expr {
         },
         Token::FUNCTION => {
             let mut parameters = vec![];
-            assert_eq!(Token::LPAREN, input.remove(0));
+            expect(input, Token::LPAREN)?;
             // must be idents seperated by comma, or RPAREN
             loop {
-                match input.remove(0) {
-                    Token::RPAREN => break,
-                    Token::IDENT(ident) => {
-                        parameters.push(ident);
-                        match input.remove(0) {
-                            Token::RPAREN => break,
-                            Token::COMMA => continue,
-                            _ => panic!("unexpected parameter found while parsing function parameters"),
-                        }
-                    },
-                    _ => panic!("unexpected parameter found while parsing function parameters"),
+                if input.peek().token == Token::RPAREN {
+                    input.next();
+                    break;
+                }
+                parameters.push(expect_ident(input)?);
+                match input.peek().token {
+                    Token::RPAREN => { input.next(); break },
+                    Token::COMMA => { input.next(); continue },
+                    _ => return Err(expected_error(input, Token::RPAREN)),
                 }
             }
 
-            assert_eq!(Token::LBRACE, input.remove(0));
-            let body = parse(input);
-            assert_eq!(Token::RBRACE, input.remove(0));
+            let body = parse_block(input)?;
 
             Expr::Function {
                 parameters,
                 body,
             }
         },
+        Token::LBRACKET => Expr::Array(parse_expression_list(input, Token::RBRACKET)?.0),
+        Token::LBRACE => Expr::Hash(parse_hash_pairs(input)?),
         Token::STRING(string) => Expr::String(string),
-        _ => panic!("parse error at expression"),
+        Token::INVALID_STRING(error) => return Err(ParseError::InvalidString { error, span }),
+        Token::INTERPOLATED(parts) => Expr::Interpolation(
+            parts.into_iter()
+                .map(|part| match part {
+                    StringPart::Text(text) => Ok(Expr::String(text)),
+                    StringPart::Code(tokens) => parse_interpolated(&tokens),
+                })
+                .collect::<Result<_, _>>()?
+        ),
+        found => return Err(ParseError::UnexpectedToken { found, span }),
     };
 
-    let mut next_token = &input[0];
+    let mut next_token = &input.peek().token;
     while precedence < next_token.precedence() {
-        left_expr = parse_infix(left_expr, input);
-        next_token = &input[0];
+        left_expr = parse_infix(left_expr, span, input)?;
+        next_token = &input.peek().token;
+    }
+
+    Ok(left_expr)
+}
+
+/// parses the code interpolated into a string literal, which must be a single expression
+fn parse_interpolated(tokens: &[SpannedToken]) -> Result<Expr, ParseError> {
+    let mut input = TokenStream::new(tokens);
+    let expr = parse_expression(&mut input, Precedence::Lowest)?;
+    if input.peek().token != Token::EOF {
+        let SpannedToken { token: found, span } = input.peek().clone();
+        return Err(ParseError::UnexpectedToken { found, span });
     }
 
-    left_expr
+    Ok(expr)
 }
 
-fn parse_infix(left: Expr, input: &mut Vec<Token>) -> Expr {
-    let next_token = input.remove(0);
+/// parses expressions separated by commas, up to and including the closing token, returning
+///    them along with the span of the closing token
+fn parse_expression_list(input: &mut TokenStream, end: Token) -> Result<(Vec<Expr>, Span), ParseError> {
+    let mut list = vec![];
+    // must be expressions separated by comma, or the closing token
+    loop {
+        if input.peek().token == end {
+            return Ok((list, input.next().span));
+        }
+        list.push(parse_expression(input, Precedence::Lowest)?);
+
+        match &input.peek().token {
+            token if *token == end => return Ok((list, input.next().span)),
+            Token::COMMA => { input.next(); continue },
+            _ => return Err(expected_error(input, end)),
+        }
+    }
+}
+
+/// parses the `key: value` pairs of a hash literal separated by commas, up to and including the
+///    closing brace
+fn parse_hash_pairs(input: &mut TokenStream) -> Result<Vec<(Expr, Expr)>, ParseError> {
+    let mut pairs = vec![];
+    loop {
+        if input.peek().token == Token::RBRACE {
+            input.next();
+            return Ok(pairs);
+        }
+        let key = parse_expression(input, Precedence::Lowest)?;
+        expect(input, Token::COLON)?;
+        pairs.push((key, parse_expression(input, Precedence::Lowest)?));
+
+        match input.peek().token {
+            Token::RBRACE => { input.next(); return Ok(pairs) },
+            Token::COMMA => { input.next(); continue },
+            _ => return Err(expected_error(input, Token::RBRACE)),
+        }
+    }
+}
+
+/// parses the operator after the left operand, which began at the start span
+fn parse_infix(left: Expr, start: Span, input: &mut TokenStream) -> Result<Expr, ParseError> {
+    let next_token = input.next().token;
     let operator = match &next_token {
+        // a call is an infix operation on whatever expression comes before the parenthesis, so
+        //    the result of a call or a function literal can be called directly
+        Token::LPAREN => {
+            let (arguments, end) = parse_expression_list(input, Token::RPAREN)?;
+            return Ok(Expr::Call { function: Box::new(left), arguments, span: start.to(&end) });
+        },
+        Token::LBRACKET => {
+            let index = parse_expression(input, Precedence::Lowest)?;
+            expect(input, Token::RBRACKET)?;
+            return Ok(Expr::Index { left: Box::new(left), index: Box::new(index) });
+        },
+        // parsing the alternative at the lowest precedence makes the operator right associative,
+        //    so `a ? b : c ? d : e` is `a ? b : (c ? d : e)`
+        Token::QUESTION => {
+            let consequence = parse_expression(input, Precedence::Lowest)?;
+            expect(input, Token::COLON)?;
+            let alternative = parse_expression(input, Precedence::Lowest)?;
+            return Ok(Expr::Ternary {
+                condition: Box::new(left),
+                consequence: Box::new(consequence),
+                alternative: Box::new(alternative),
+            });
+        },
         Token::PLUS => Operator::Plus,
         Token::MINUS => Operator::Minus,
         Token::SLASH => Operator::Divide,
         Token::ASTERISK => Operator::Multiply,
         Token::LT => Operator::LessThan,
         Token::GT => Operator::GreaterThan,
+        Token::LT_EQ => Operator::LessThanOrEqual,
+        Token::GT_EQ => Operator::GreaterThanOrEqual,
         Token::EQ => Operator::Equals,
         Token::NOT_EQ => Operator::NotEquals,
-        _ => panic!("parse infix called on invalid operator"),
+        Token::AND => Operator::And,
+        Token::OR => Operator::Or,
+        // only called when the next token has an infix precedence
+        _ => unreachable!("parse infix called on invalid operator"),
     };
-    Expr::Infix {
+    Ok(Expr::Infix {
         left: Box::new(left),
         operator,
-        right: Box::new(parse_expression(input, next_token.precedence())),
-    }
+        right: Box::new(parse_expression(input, next_token.precedence())?),
+    })
 }
 
 impl Token {
+    fn keyword(&self) -> Option<&'static str> {
+        match self {
+            Token::FUNCTION => Some("fn"),
+            Token::LET => Some("let"),
+            Token::IF => Some("if"),
+            Token::ELSE => Some("else"),
+            Token::RETURN => Some("return"),
+            Token::TRUE => Some("true"),
+            Token::FALSE => Some("false"),
+            Token::NULL => Some("null"),
+            _ => None,
+        }
+    }
+
     fn precedence(&self) -> Precedence {
         match self {
             Token::PLUS => Precedence::Sum,
@@ -238,8 +558,15 @@ impl Token {
             Token::ASTERISK => Precedence::Product,
             Token::LT => Precedence::LessGreater,
             Token::GT => Precedence::LessGreater,
+            Token::LT_EQ => Precedence::LessGreater,
+            Token::GT_EQ => Precedence::LessGreater,
             Token::EQ => Precedence::Equals,
             Token::NOT_EQ => Precedence::Equals,
+            Token::AND => Precedence::And,
+            Token::OR => Precedence::Or,
+            Token::QUESTION => Precedence::Ternary,
+            Token::LPAREN => Precedence::Call,
+            Token::LBRACKET => Precedence::Index,
             _ => Precedence::Lowest
         }
     }
@@ -248,31 +575,110 @@ impl Token {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ast;
     use crate::lexer::lex;
 
     #[test]
     fn parse_let() {
         let input = "let x = 5;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
-                Statement::Let { name: String::from("x"), value: Expr::Const(5) },
+                Statement::Let { name: String::from("x"), value: Expr::Const(5), span: Span::default() },
             ],
             ast
         );
     }
 
+    #[test]
+    fn parse_declare() {
+        let input = "let x;";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                Statement::Declare { name: String::from("x"), span: Span::default() },
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_assign() {
+        let input = "x = x + 1;";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                Statement::Assign {
+                    name: String::from("x"),
+                    value: Expr::Infix {
+                        left: Box::new(Expr::Ident(String::from("x"), Span::default())),
+                        operator: Operator::Plus,
+                        right: Box::new(Expr::Const(1)),
+                    },
+                    span: Span::default(),
+                },
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_statement_spans() {
+        let input = "let x = 5;\nx;\nif (x) {\n  return x;\n};";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        match &ast[..] {
+            [Statement::Let { span: let_span, .. }, Statement::Expression(_, expression_span), Statement::Expression(Expr::If { consequence, .. }, if_span)] => {
+                assert_eq!(Span { start: 0, end: 10, line: 1, column: 1 }, *let_span);
+                assert_eq!(Span { start: 11, end: 13, line: 2, column: 1 }, *expression_span);
+                assert_eq!(Span { start: 14, end: 37, line: 3, column: 1 }, *if_span);
+                match &consequence[..] {
+                    [Statement::Return { span, .. }] => assert_eq!(Span { start: 25, end: 34, line: 4, column: 3 }, *span),
+                    _ => panic!("unexpected if consequence"),
+                }
+            },
+            _ => panic!("unexpected ast"),
+        }
+    }
+
+    #[test]
+    fn parse_expression_spans() {
+        let input = "x;\n  add(x, f(1));";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        match &ast[..] {
+            [Statement::Expression(Expr::Ident(_, ident_span), _), Statement::Expression(Expr::Call { function, arguments, span }, _)] => {
+                assert_eq!(Span { start: 0, end: 1, line: 1, column: 1 }, *ident_span);
+                assert_eq!(Span { start: 5, end: 17, line: 2, column: 3 }, *span);
+                match (function.as_ref(), &arguments[..]) {
+                    (Expr::Ident(_, add_span), [_, Expr::Call { span: inner_span, .. }]) => {
+                        assert_eq!(Span { start: 5, end: 8, line: 2, column: 3 }, *add_span);
+                        assert_eq!(Span { start: 12, end: 16, line: 2, column: 10 }, *inner_span);
+                    },
+                    _ => panic!("unexpected call"),
+                }
+            },
+            _ => panic!("unexpected ast"),
+        }
+    }
+
     #[test]
     fn parse_return() {
         let input = "return 5;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
-                Statement::Return { value: Expr::Const(5) },
+                Statement::Return { value: Expr::Const(5), span: Span::default() },
             ],
             ast
         );
@@ -281,12 +687,12 @@ mod tests {
     #[test]
     fn parse_let_ident() {
         let input = "let myVar = anotherV;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
-                Statement::Let { name: String::from("myVar"), value: Expr::Ident(String::from("anotherV")) },
+                Statement::Let { name: String::from("myVar"), value: Expr::Ident(String::from("anotherV"), Span::default()), span: Span::default() },
             ],
             ast
         );
@@ -295,12 +701,12 @@ mod tests {
     #[test]
     fn parse_expression_statement() {
         let input = "foo;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
-                Statement::Expression(Expr::Ident(String::from("foo"))),
+                Statement::Expression(Expr::Ident(String::from("foo"), Span::default()), Span::default()),
             ],
             ast
         );
@@ -309,12 +715,26 @@ mod tests {
     #[test]
     fn parse_expression_statement_const() {
         let input = "5;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                Statement::Expression(Expr::Const(5), Span::default()),
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_expression_statement_float() {
+        let input = "5.25;";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
-                Statement::Expression(Expr::Const(5)),
+                Statement::Expression(Expr::Float(5.25), Span::default()),
             ],
             ast
         );
@@ -323,12 +743,12 @@ mod tests {
     #[test]
     fn parse_expression_statement_string() {
         let input = r#" "foo bar";"#;
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
-                Statement::Expression(Expr::String(String::from("foo bar"))),
+                Statement::Expression(Expr::String(String::from("foo bar")), Span::default()),
             ],
             ast
         );
@@ -337,8 +757,8 @@ mod tests {
     #[test]
     fn parse_prefix_expression() {
         let input = "!5; -15;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
@@ -346,13 +766,15 @@ mod tests {
                     Expr::Prefix{
                         prefix: Prefix::Bang,
                         value: Box::new(Expr::Const(5))
-                    }
+                    },
+                    Span::default()
                 ),
                 Statement::Expression(
                     Expr::Prefix{
                         prefix: Prefix::Minus,
                         value: Box::new(Expr::Const(15))
-                    }
+                    },
+                    Span::default()
                 ),
             ],
             ast
@@ -373,8 +795,12 @@ mod tests {
             ("5 / 6;", Operator::Divide),
             ("5 > 6;", Operator::GreaterThan),
             ("5 < 6;", Operator::LessThan),
+            ("5 >= 6;", Operator::GreaterThanOrEqual),
+            ("5 <= 6;", Operator::LessThanOrEqual),
             ("5 == 6;", Operator::Equals),
             ("5 != 6;", Operator::NotEquals),
+            ("5 && 6;", Operator::And),
+            ("5 || 6;", Operator::Or),
         ];
 
         for (input, operator) in test_cases {
@@ -383,8 +809,8 @@ mod tests {
     }
 
     fn parse_infix_expression(input: &str, operator: Operator) {
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
@@ -392,7 +818,7 @@ mod tests {
                     left: Box::new(Expr::Const(5)),
                     operator,
                     right: Box::new(Expr::Const(6)),
-                }),
+                }, Span::default()),
             ],
             ast
         );
@@ -401,16 +827,16 @@ mod tests {
     #[test]
     fn parse_infix_expression_order_of_operations() {
         let input = "-a * 6;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
                 Statement::Expression(Expr::Infix{
-                    left: Box::new(Expr::Prefix{ prefix: Prefix::Minus, value: Box::new(Expr::Ident(String::from("a")))}),
+                    left: Box::new(Expr::Prefix{ prefix: Prefix::Minus, value: Box::new(Expr::Ident(String::from("a"), Span::default()))}),
                     operator: Operator::Multiply,
                     right: Box::new(Expr::Const(6)),
-                }),
+                }, Span::default()),
             ],
             ast
         );
@@ -419,8 +845,8 @@ mod tests {
     #[test]
     fn parse_bool() {
         let input = "!true == false;";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
@@ -428,7 +854,116 @@ mod tests {
                     left: Box::new(Expr::Prefix{ prefix: Prefix::Bang, value: Box::new(Expr::Boolean(true))}),
                     operator: Operator::Equals,
                     right: Box::new(Expr::Boolean(false)),
-                }),
+                }, Span::default()),
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_null() {
+        let mut tokens = lex("let x = null;").unwrap();
+
+        assert_eq!(Ok(vec![ast::let_("x", ast::null())]), parse(&mut tokens));
+        assert_eq!(
+            Err(ParseError::KeywordAsIdentifier { keyword: "null", span: Span { start: 4, end: 8, line: 1, column: 5 } }),
+            parse(&mut lex("let null = 1;").unwrap())
+        );
+    }
+
+    #[test]
+    fn parse_and_or_precedence() {
+        let input = "a || b && c == d;";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                Statement::Expression(Expr::Infix{
+                    left: Box::new(Expr::Ident(String::from("a"), Span::default())),
+                    operator: Operator::Or,
+                    right: Box::new(Expr::Infix {
+                        left: Box::new(Expr::Ident(String::from("b"), Span::default())),
+                        operator: Operator::And,
+                        right: Box::new(Expr::Infix {
+                            left: Box::new(Expr::Ident(String::from("c"), Span::default())),
+                            operator: Operator::Equals,
+                            right: Box::new(Expr::Ident(String::from("d"), Span::default())),
+                        }),
+                    }),
+                }, Span::default()),
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_large_program() {
+        // each token is read in place, so this stays fast however long the program is
+        let input = "let x = [1, 2, 3];\n".repeat(50_000);
+        let mut tokens = lex(&input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(50_000, ast.len());
+        // the consumed tokens are removed, leaving the end of input
+        assert_eq!(vec![Token::EOF], tokens.into_iter().map(|token| token.token).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn parse_with_comments() {
+        let input = r#"
+            // add the numbers
+            let sum = fn(a, /* first */ b) {
+                a + // the left side
+                    b;
+            };
+        "#;
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                Statement::Let {
+                    name: String::from("sum"),
+                    value: Expr::Function {
+                        parameters: vec![String::from("a"), String::from("b")],
+                        body: vec![
+                            Statement::Expression(Expr::Infix {
+                                left: Box::new(Expr::Ident(String::from("a"), Span::default())),
+                                operator: Operator::Plus,
+                                right: Box::new(Expr::Ident(String::from("b"), Span::default())),
+                            }, Span::default()),
+                        ],
+                    },
+                    span: Span::default(),
+                },
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_identifiers_with_digits_and_underscores() {
+        let input = "let add_2 = fn(_x, y1) { _x + y1; };";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                Statement::Let {
+                    name: String::from("add_2"),
+                    value: Expr::Function {
+                        parameters: vec![String::from("_x"), String::from("y1")],
+                        body: vec![
+                            Statement::Expression(Expr::Infix {
+                                left: Box::new(Expr::Ident(String::from("_x"), Span::default())),
+                                operator: Operator::Plus,
+                                right: Box::new(Expr::Ident(String::from("y1"), Span::default())),
+                            }, Span::default()),
+                        ],
+                    },
+                    span: Span::default(),
+                },
             ],
             ast
         );
@@ -437,8 +972,8 @@ mod tests {
     #[test]
     fn parse_paren() {
         let input = "1 + (2 + 3);";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
@@ -450,7 +985,7 @@ mod tests {
                         operator: Operator::Plus,
                         right: Box::new(Expr::Const(3))
                     }),
-                }),
+                }, Span::default()),
             ],
             ast
         );
@@ -459,16 +994,16 @@ mod tests {
     #[test]
     fn parse_if() {
         let input = "if (5) { 6; };";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
                 Statement::Expression(Expr::If{
                     condition: Box::new(Expr::Const(5)),
-                    consequence: vec![Statement::Expression(Expr::Const(6))],
+                    consequence: vec![Statement::Expression(Expr::Const(6), Span::default())],
                     alternative: Vec::new()
-                }),
+                }, Span::default()),
             ],
             ast
         );
@@ -477,16 +1012,16 @@ mod tests {
     #[test]
     fn parse_if_else() {
         let input = "if (5) { 6; } else { 7; };";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
                 Statement::Expression(Expr::If{
                     condition: Box::new(Expr::Const(5)),
-                    consequence: vec![Statement::Expression(Expr::Const(6))],
-                    alternative: vec![Statement::Expression(Expr::Const(7))],
-                }),
+                    consequence: vec![Statement::Expression(Expr::Const(6), Span::default())],
+                    alternative: vec![Statement::Expression(Expr::Const(7), Span::default())],
+                }, Span::default()),
             ],
             ast
         );
@@ -495,8 +1030,8 @@ mod tests {
     #[test]
     fn parse_function_literal() {
         let input = "let myFunc = fn(x, y) {x + y;};";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
@@ -507,13 +1042,15 @@ mod tests {
                         body: vec![
                             Statement::Expression(
                                 Expr::Infix {
-                                    left: Box::new(Expr::Ident(String::from("x"))),
+                                    left: Box::new(Expr::Ident(String::from("x"), Span::default())),
                                     operator: Operator::Plus,
-                                    right: Box::new(Expr::Ident(String::from("y")))
-                                }
+                                    right: Box::new(Expr::Ident(String::from("y"), Span::default()))
+                                },
+                                Span::default()
                             )
                         ]
-                    }
+                    },
+                    span: Span::default(),
                 },
             ],
             ast
@@ -523,50 +1060,339 @@ mod tests {
     #[test]
     fn parse_function_call() {
         let input = "add(1, 2);";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
                 Statement::Expression(
                     Expr::Call {
-                        function: Box::new(Expr::Ident(String::from("add"))),
-                        arguments: vec![Expr::Const(1), Expr::Const(2)]
-                    }
+                        function: Box::new(Expr::Ident(String::from("add"), Span::default())),
+                        arguments: vec![Expr::Const(1), Expr::Const(2)],
+                        span: Span::default(),
+                    },
+                    Span::default()
                 )
             ],
             ast
         );
     }
 
+    #[test]
+    fn parse_higher_order_calls() {
+        let input = "fn(x) { x; }(5); makeAdder(1)(2); -f(1); (a + b)(c);";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                ast::expr(ast::call_expr(ast::function(["x"], [ast::expr(ast::ident("x"))]), [ast::int(5)])),
+                ast::expr(ast::call_expr(ast::call("makeAdder", [ast::int(1)]), [ast::int(2)])),
+                // a call binds tighter than a prefix operator
+                ast::expr(ast::prefix(Prefix::Minus, ast::call("f", [ast::int(1)]))),
+                ast::expr(ast::call_expr(ast::infix(ast::ident("a"), Operator::Plus, ast::ident("b")), [ast::ident("c")])),
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_array() {
+        let input = "[1, x + 1, []];";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                ast::expr(ast::array([
+                    ast::int(1),
+                    ast::infix(ast::ident("x"), Operator::Plus, ast::int(1)),
+                    ast::array([]),
+                ])),
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_hash() {
+        let input = r#"{"a": 1, x + 1: [], true: {},}; {}; f({1: 2})[0];"#;
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                ast::expr(ast::hash([
+                    (ast::string("a"), ast::int(1)),
+                    (ast::infix(ast::ident("x"), Operator::Plus, ast::int(1)), ast::array([])),
+                    (ast::boolean(true), ast::hash([])),
+                ])),
+                ast::expr(ast::hash([])),
+                ast::expr(ast::index(ast::call("f", [ast::hash([(ast::int(1), ast::int(2))])]), ast::int(0))),
+            ],
+            ast
+        );
+    }
+
+    #[test]
+    fn parse_index() {
+        let input = "xs[1]; xs[i + 1][0]; [1, 2][0]; -xs[0]; f(1)[2]; xs[0](1); a * xs[1];";
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                ast::expr(ast::index(ast::ident("xs"), ast::int(1))),
+                ast::expr(ast::index(
+                    ast::index(ast::ident("xs"), ast::infix(ast::ident("i"), Operator::Plus, ast::int(1))),
+                    ast::int(0),
+                )),
+                ast::expr(ast::index(ast::array([ast::int(1), ast::int(2)]), ast::int(0))),
+                // indexing binds tighter than prefix and infix operators
+                ast::expr(ast::prefix(Prefix::Minus, ast::index(ast::ident("xs"), ast::int(0)))),
+                ast::expr(ast::index(ast::call("f", [ast::int(1)]), ast::int(2))),
+                ast::expr(ast::call_expr(ast::index(ast::ident("xs"), ast::int(0)), [ast::int(1)])),
+                ast::expr(ast::infix(ast::ident("a"), Operator::Multiply, ast::index(ast::ident("xs"), ast::int(1)))),
+            ],
+            ast
+        );
+        assert!(matches!(
+            parse(&mut lex("xs[1;").unwrap()),
+            Err(ParseError::ExpectedToken { expected: Token::RBRACKET, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_ternary() {
+        let input = "a ? b : c; a || b ? c + 1 : d; a ? b : c ? d : e; a ? b ? c : d : e; (a ? b : c) + 1; let x = a ? f(b) : [c];";
+        let mut tokens = lex(input).unwrap();
+        let (a, b, c, d, e) = (ast::ident("a"), ast::ident("b"), ast::ident("c"), ast::ident("d"), ast::ident("e"));
+
+        assert_eq!(
+            vec![
+                ast::expr(ast::ternary(a.clone(), b.clone(), c.clone())),
+                // binding more loosely than any infix operator
+                ast::expr(ast::ternary(
+                    ast::infix(a.clone(), Operator::Or, b.clone()),
+                    ast::infix(c.clone(), Operator::Plus, ast::int(1)),
+                    d.clone(),
+                )),
+                // and right associative
+                ast::expr(ast::ternary(a.clone(), b.clone(), ast::ternary(c.clone(), d.clone(), e.clone()))),
+                ast::expr(ast::ternary(a.clone(), ast::ternary(b.clone(), c.clone(), d.clone()), e.clone())),
+                ast::expr(ast::infix(ast::ternary(a.clone(), b.clone(), c.clone()), Operator::Plus, ast::int(1))),
+                ast::let_("x", ast::ternary(a, ast::call("f", [b]), ast::array([c]))),
+            ],
+            parse(&mut tokens).unwrap()
+        );
+        assert!(matches!(
+            parse(&mut lex("a ? b;").unwrap()),
+            Err(ParseError::ExpectedToken { expected: Token::COLON, found: Token::SEMICOLON, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_interpolation() {
+        let mut tokens = lex(r#""total: ${x + 1}!"; "${xs[0]}${"s"}";"#).unwrap();
+
+        assert_eq!(
+            vec![
+                ast::expr(ast::interpolation([
+                    ast::string("total: "),
+                    ast::infix(ast::ident("x"), Operator::Plus, ast::int(1)),
+                    ast::string("!"),
+                ])),
+                ast::expr(ast::interpolation([ast::index(ast::ident("xs"), ast::int(0)), ast::string("s")])),
+            ],
+            parse(&mut tokens).unwrap()
+        );
+
+        let error = |input: &str| parse(&mut lex(input).unwrap()).unwrap_err().to_string();
+        assert_eq!("parse error at 1:6: unexpected end of input", error(r#""a ${}";"#));
+        assert_eq!("parse error at 1:8: unexpected `2`", error(r#""a ${1 2}";"#));
+    }
+
+    #[test]
+    fn parse_program() {
+        let input = r#"
+            let fib = fn(n) {
+                if (n < 2) { return n; };
+                fib(n - 1) + fib(n - 2);
+            };
+            puts(fib(10), "done");
+        "#;
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
+
+        assert_eq!(
+            vec![
+                ast::let_("fib", ast::function(["n"], [
+                    ast::expr(ast::if_(
+                        ast::infix(ast::ident("n"), Operator::LessThan, ast::int(2)),
+                        [ast::return_(ast::ident("n"))],
+                    )),
+                    ast::expr(ast::infix(
+                        ast::call("fib", [ast::infix(ast::ident("n"), Operator::Minus, ast::int(1))]),
+                        Operator::Plus,
+                        ast::call("fib", [ast::infix(ast::ident("n"), Operator::Minus, ast::int(2))]),
+                    )),
+                ])),
+                ast::expr(ast::call("puts", [ast::call("fib", [ast::int(10)]), ast::string("done")])),
+            ],
+            ast
+        );
+    }
+
     #[test]
     fn parse_function_expression() {
         let input = "myFunc(x + y, a + b);";
-        let mut tokens = lex(input);
-        let ast = parse(&mut tokens);
+        let mut tokens = lex(input).unwrap();
+        let ast = parse(&mut tokens).unwrap();
 
         assert_eq!(
             vec![
                 Statement::Expression(
                     Expr::Call {
-                        function: Box::new(Expr::Ident(String::from("myFunc"))),
+                        function: Box::new(Expr::Ident(String::from("myFunc"), Span::default())),
                         arguments: vec![
                             Expr::Infix {
-                                left: Box::new(Expr::Ident(String::from("x"))),
+                                left: Box::new(Expr::Ident(String::from("x"), Span::default())),
                                 operator: Operator::Plus,
-                                right: Box::new(Expr::Ident(String::from("y")))
+                                right: Box::new(Expr::Ident(String::from("y"), Span::default()))
                             },
                             Expr::Infix {
-                                left: Box::new(Expr::Ident(String::from("a"))),
+                                left: Box::new(Expr::Ident(String::from("a"), Span::default())),
                                 operator: Operator::Plus,
-                                right: Box::new(Expr::Ident(String::from("b")))
+                                right: Box::new(Expr::Ident(String::from("b"), Span::default()))
                             },
-                        ]
-                    }
+                        ],
+                        span: Span::default(),
+                    },
+                    Span::default()
                 )
             ],
             ast
         );
     }
 
+    fn parse_error(input: &str) -> ParseError {
+        let mut tokens = lex(input).unwrap();
+        parse(&mut tokens).unwrap_err()
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            ParseError::ExpectedToken {
+                expected: Token::SEMICOLON,
+                found: Token::EOF,
+                span: Span { start: 9, end: 9, line: 1, column: 10 },
+            },
+            parse_error("let x = 5")
+        );
+        assert_eq!(
+            ParseError::ExpectedIdentifier {
+                found: Token::INT(5),
+                span: Span { start: 4, end: 5, line: 1, column: 5 },
+            },
+            parse_error("let 5 = x;")
+        );
+        assert_eq!(
+            ParseError::UnexpectedToken {
+                found: Token::RPAREN,
+                span: Span { start: 7, end: 8, line: 2, column: 5 },
+            },
+            parse_error("1;\n1 + );")
+        );
+        assert_eq!(
+            ParseError::UnexpectedToken {
+                found: Token::RBRACE,
+                span: Span { start: 3, end: 4, line: 1, column: 4 },
+            },
+            parse_error("1; }")
+        );
+        assert_eq!(
+            ParseError::UnterminatedBlock {
+                span: Span { start: 10, end: 11, line: 1, column: 11 },
+            },
+            parse_error("if (true) { 1;")
+        );
+    }
+
+    #[test]
+    fn parse_invalid_string() {
+        assert_eq!(
+            "parse error at 1:12: invalid escape sequence `\\q` in string literal",
+            parse_error(r#"let s = "ab\q";"#).to_string()
+        );
+        assert_eq!(
+            "parse error at 1:9: string literal is not closed before the end of input",
+            parse_error(r#"let s = "ab;"#).to_string()
+        );
+    }
+
+    #[test]
+    fn parse_keyword_as_identifier() {
+        assert_eq!(
+            ParseError::KeywordAsIdentifier {
+                keyword: "let",
+                span: Span { start: 4, end: 7, line: 1, column: 5 },
+            },
+            parse_error("let let = 5;")
+        );
+        assert_eq!(
+            ParseError::KeywordAsIdentifier {
+                keyword: "fn",
+                span: Span { start: 0, end: 2, line: 1, column: 1 },
+            },
+            parse_error("fn = 5;")
+        );
+        assert_eq!(
+            ParseError::KeywordAsIdentifier {
+                keyword: "if",
+                span: Span { start: 6, end: 8, line: 1, column: 7 },
+            },
+            parse_error("fn(a, if) { a; };")
+        );
+        assert_eq!(
+            "parse error at 1:5: `return` is a keyword and cannot be used as a name, try something like `returnValue` instead",
+            parse_error("let return;").to_string()
+        );
+    }
+
+    #[test]
+    fn parse_unclosed_array() {
+        assert_eq!(
+            ParseError::ExpectedToken {
+                expected: Token::RBRACKET,
+                found: Token::SEMICOLON,
+                span: Span { start: 5, end: 6, line: 1, column: 6 },
+            },
+            parse_error("[1, 2;")
+        );
+    }
+
+    #[test]
+    fn parse_hash_without_colon() {
+        assert_eq!(
+            ParseError::ExpectedToken {
+                expected: Token::COLON,
+                found: Token::RBRACE,
+                span: Span { start: 4, end: 5, line: 1, column: 5 },
+            },
+            parse_error("{\"a\"};")
+        );
+    }
+
+    #[test]
+    fn parse_error_display() {
+        assert_eq!(
+            "parse error at 1:10: expected `;` but found end of input",
+            parse_error("let x = 5").to_string()
+        );
+        assert_eq!(
+            "parse error at 1:5: expected identifier but found `5`",
+            parse_error("let 5 = x;").to_string()
+        );
+    }
 }
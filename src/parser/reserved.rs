@@ -0,0 +1,100 @@
+use crate::lexer::{Token, SpannedToken, Span, StringPart};
+use super::ParseError;
+use std::fmt;
+
+/// words which are likely to become keywords as the language grows, using them as
+///    identifiers today means a script may stop working in a later version
+pub const RESERVED_WORDS: &[&str] = &[
+    "while", "for", "loop", "break", "continue", "match", "in",
+    "import", "export", "const", "class", "struct",
+];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ReservedWordWarning {
+    pub word: String,
+    pub span: Span,
+}
+
+impl fmt::Display for ReservedWordWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "warning at {}:{}: `{}` is reserved for future use and may become a keyword",
+            self.span.line, self.span.column, self.word)
+    }
+}
+
+/// finds identifiers which use a reserved word, in strict mode the first one is an error
+///    rather than a warning
+pub fn check_reserved_words(tokens: &[SpannedToken], strict: bool) -> Result<Vec<ReservedWordWarning>, ParseError> {
+    let mut warnings = vec![];
+    find_reserved_words(tokens, strict, &mut warnings)?;
+
+    Ok(warnings)
+}
+
+fn find_reserved_words(tokens: &[SpannedToken], strict: bool, warnings: &mut Vec<ReservedWordWarning>) -> Result<(), ParseError> {
+    for SpannedToken { token, span } in tokens {
+        match token {
+            Token::IDENT(word) if RESERVED_WORDS.contains(&word.as_str()) => {
+                if strict {
+                    return Err(ParseError::ReservedWord { word: word.clone(), span: *span });
+                }
+                warnings.push(ReservedWordWarning { word: word.clone(), span: *span });
+            },
+            // including those in the code interpolated into string literals
+            Token::INTERPOLATED(parts) => for part in parts {
+                if let StringPart::Code(tokens) = part {
+                    find_reserved_words(tokens, strict, warnings)?;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+
+    #[test]
+    fn reserved_words_warn() {
+        let tokens = lex("let while = 1;\nwhile + whilst;").unwrap();
+
+        assert_eq!(
+            Ok(vec![
+                ReservedWordWarning {
+                    word: String::from("while"),
+                    span: Span { start: 4, end: 9, line: 1, column: 5 },
+                },
+                ReservedWordWarning {
+                    word: String::from("while"),
+                    span: Span { start: 15, end: 20, line: 2, column: 1 },
+                },
+            ]),
+            check_reserved_words(&tokens, false)
+        );
+    }
+
+    #[test]
+    fn reserved_words_strict() {
+        let tokens = lex("let x = 1; let match = x;").unwrap();
+
+        assert_eq!(
+            Err(ParseError::ReservedWord {
+                word: String::from("match"),
+                span: Span { start: 15, end: 20, line: 1, column: 16 },
+            }),
+            check_reserved_words(&tokens, true)
+        );
+        assert_eq!(Ok(vec![]), check_reserved_words(&lex("let x = 1;").unwrap(), true));
+        assert_eq!(
+            Err(ParseError::ReservedWord {
+                word: String::from("in"),
+                span: Span { start: 5, end: 7, line: 1, column: 6 },
+            }),
+            check_reserved_words(&lex("\"a ${in}\";").unwrap(), true)
+        );
+    }
+}
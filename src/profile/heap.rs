@@ -0,0 +1,115 @@
+// Tracks the objects allocated by a program, by the kind of object and by the line of the
+// statement which allocated it. Sizes are an estimate of the memory behind the object, such as
+// the bytes of a string, rather than an exact accounting of the interpreter's own allocations.
+
+use crate::eval::{HashKey, Object};
+use std::collections::HashMap;
+use std::mem;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Allocations {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl Allocations {
+    fn add(&mut self, bytes: usize) {
+        self.count += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Default)]
+pub struct HeapProfile {
+    by_kind: HashMap<&'static str, Allocations>,
+    by_line: HashMap<usize, Allocations>,
+}
+
+/// the estimated size of an object which lives on the heap, or None for objects which don't
+pub fn allocation_size(object: &Object) -> Option<usize> {
+    match object {
+        Object::String(string) => Some(string.len()),
+        Object::Array(elements) => Some(elements.len() * mem::size_of::<Object>()),
+        Object::Hash(pairs) => Some(pairs.len() * (mem::size_of::<HashKey>() + mem::size_of::<Object>())),
+        Object::Function { .. } | Object::CompiledFunction { .. } => Some(mem::size_of::<Object>()),
+        _ => None,
+    }
+}
+
+impl HeapProfile {
+    pub fn new() -> Self {
+        HeapProfile::default()
+    }
+
+    /// records the object as allocated by the given line, if it lives on the heap
+    pub fn record(&mut self, object: &Object, line: usize) {
+        if let Some(bytes) = allocation_size(object) {
+            self.by_kind.entry(object.type_name()).or_default().add(bytes);
+            self.by_line.entry(line).or_default().add(bytes);
+        }
+    }
+
+    /// records the object by its kind alone, for the vm which doesn't know the lines its
+    ///    instructions were compiled from
+    pub fn record_without_line(&mut self, object: &Object) {
+        if let Some(bytes) = allocation_size(object) {
+            self.by_kind.entry(object.type_name()).or_default().add(bytes);
+        }
+    }
+
+    /// allocations by kind, sorted by kind
+    pub fn by_kind(&self) -> Vec<(&'static str, Allocations)> {
+        let mut kinds = self.by_kind.iter().map(|(kind, allocations)| (*kind, *allocations)).collect::<Vec<_>>();
+        kinds.sort_by_key(|(kind, _)| *kind);
+
+        kinds
+    }
+
+    /// allocations by line, with the lines which allocated the most bytes first
+    pub fn by_line(&self) -> Vec<(usize, Allocations)> {
+        let mut lines = self.by_line.iter().map(|(line, allocations)| (*line, *allocations)).collect::<Vec<_>>();
+        lines.sort_by_key(|(line, allocations)| (std::cmp::Reverse(allocations.bytes), *line));
+
+        lines
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = String::from("allocations by kind:\n");
+        for (kind, allocations) in self.by_kind() {
+            report.push_str(&format!("  {}: {} ({} bytes)\n", kind, allocations.count, allocations.bytes));
+        }
+        if self.by_line.is_empty() {
+            return report;
+        }
+        report.push_str("allocations by line:\n");
+        for (line, allocations) in self.by_line() {
+            report.push_str(&format!("  line {}: {} ({} bytes)\n", line, allocations.count, allocations.bytes));
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_profile_report() {
+        let mut profile = HeapProfile::new();
+        profile.record(&Object::String("ab".into()), 1);
+        profile.record(&Object::Integer(1), 1);
+        profile.record(&Object::String("abcd".into()), 2);
+        profile.record(&Object::String("abcd".into()), 2);
+
+        assert_eq!(
+            "allocations by kind:\n  string: 3 (10 bytes)\n\
+             allocations by line:\n  line 2: 2 (8 bytes)\n  line 1: 1 (2 bytes)\n",
+            profile.report()
+        );
+
+        let mut profile = HeapProfile::new();
+        profile.record_without_line(&Object::String("ab".into()));
+        assert_eq!("allocations by kind:\n  string: 1 (2 bytes)\n", profile.report());
+    }
+}
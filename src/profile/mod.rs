@@ -0,0 +1,81 @@
+// Counts the work done by a program against the stack of monkey functions which were active at
+// the time, and writes the counts as folded stacks, one line per distinct stack such as
+// `main;fib;fib 42`, which tools such as inferno and flamegraph render directly.
+
+use std::collections::HashMap;
+
+pub mod heap;
+
+// the name given to the outermost frame, which holds the top level of the program
+const ROOT: &str = "main";
+// the name used for calls to a function which isn't bound to a name, such as `fn(x) { x }(1)`
+pub const ANONYMOUS: &str = "<anonymous>";
+
+pub struct Profiler {
+    stack: Vec<String>,
+    // keyed by the active stack joined with `;`
+    counts: HashMap<String, u64>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            stack: vec![String::from(ROOT)],
+            counts: HashMap::new(),
+        }
+    }
+
+    pub fn enter(&mut self, name: &str) {
+        self.stack.push(name.to_string());
+    }
+
+    pub fn exit(&mut self) {
+        // the root frame is never popped, even by an unbalanced exit
+        if self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    /// attributes one unit of work to the current stack
+    pub fn count(&mut self) {
+        *self.counts.entry(self.stack.join(";")).or_insert(0) += 1;
+    }
+
+    /// the counts in folded stacks format, sorted by stack so the output is stable between runs
+    pub fn folded(&self) -> String {
+        let mut stacks = self.counts.iter().collect::<Vec<_>>();
+        stacks.sort();
+
+        stacks.into_iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folded_stacks() {
+        let mut profiler = Profiler::new();
+        profiler.count();
+        profiler.enter("fib");
+        profiler.count();
+        profiler.enter("fib");
+        profiler.count();
+        profiler.count();
+        profiler.exit();
+        profiler.exit();
+        profiler.exit();
+        profiler.count();
+
+        assert_eq!("main 2\nmain;fib 1\nmain;fib;fib 2\n", profiler.folded());
+    }
+}
@@ -0,0 +1,589 @@
+// Source to source refactorings for the `monkey refactor` commands. Each decides what to change
+// from the parsed program, then edits the source text directly so everything it doesn't touch,
+// comments included, stays as written. The result is parsed and compiled again before it is
+// returned, so a refactoring which would break the program is an error rather than a silent
+// change in its meaning.
+
+use crate::builtins;
+use crate::compiler::compile_from_source;
+use crate::lexer::{lex, LexError, Span, SpannedToken, Token};
+use crate::parser::{parse, Expr, ParseError, Statement};
+use crate::visit::{walk_expr, walk_statement, Visit};
+use std::fmt;
+
+const INDENT: &str = "    ";
+
+/// a position in the source as an editor shows it, lines and columns start from one
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// reads a position written as `line:column`
+    pub fn parse(text: &str) -> Option<Position> {
+        let (line, column) = text.split_once(':')?;
+        Some(Position { line: line.parse().ok()?, column: column.parse().ok()? })
+    }
+
+    fn of(span: &Span) -> Position {
+        Position { line: span.line, column: span.column }
+    }
+}
+
+/// splits a target such as `file.mky:10:4-12:20` or `file.mky:3:5` into the path and the
+///    position or range after it
+pub fn parse_target(target: &str) -> Option<(&str, Position, Option<Position>)> {
+    let (target, end) = match target.rsplit_once('-').and_then(|(rest, end)| Some((rest, Position::parse(end)?))) {
+        Some((rest, end)) => (rest, Some(end)),
+        None => (target, None),
+    };
+    let (rest, column) = target.rsplit_once(':')?;
+    let (path, line) = rest.rsplit_once(':')?;
+    let start = Position { line: line.parse().ok()?, column: column.parse().ok()? };
+
+    Some((path, start, end))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RefactorError {
+    Lex(LexError),
+    Parse(ParseError),
+    // the selection doesn't cover whole statements of a single block
+    InvalidSelection,
+    // there is no let statement at the position
+    NoVariable,
+    InvalidName(String),
+    NameTaken(String),
+    ContainsReturn,
+    AssignsOuterVariable(String),
+    UsedAfterSelection(String),
+    Redefined(String),
+    Reassigned(String),
+    UsedOutsideScope(String),
+    SideEffects(String),
+    // the rewritten program no longer parses or compiles, which is a bug in the refactoring
+    Broken(String),
+}
+
+impl fmt::Display for RefactorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefactorError::Lex(err) => write!(f, "{}", err),
+            RefactorError::Parse(err) => write!(f, "{}", err),
+            RefactorError::InvalidSelection => write!(f, "the selection must cover whole statements of a single block"),
+            RefactorError::NoVariable => write!(f, "there is no let statement at the position"),
+            RefactorError::InvalidName(name) => write!(f, "`{}` is not a valid name", name),
+            RefactorError::NameTaken(name) => write!(f, "`{}` is already used in the program", name),
+            RefactorError::ContainsReturn =>
+                write!(f, "the selection returns from the enclosing function, which it can't do from a new one"),
+            RefactorError::AssignsOuterVariable(name) =>
+                write!(f, "the selection assigns to `{}`, which would only change the new function's copy", name),
+            RefactorError::UsedAfterSelection(name) =>
+                write!(f, "`{}` is defined in the selection and used after it", name),
+            RefactorError::Redefined(name) =>
+                write!(f, "`{}` is defined more than once, so it may not mean the same thing everywhere", name),
+            RefactorError::Reassigned(name) => write!(f, "`{}` is assigned to, so its value may change", name),
+            RefactorError::UsedOutsideScope(name) => write!(f, "`{}` is used outside of the statements after it", name),
+            RefactorError::SideEffects(name) =>
+                write!(f, "the value of `{}` calls a function, which would run at a different time once inlined", name),
+            RefactorError::Broken(err) => write!(f, "the refactoring would break the program: {}", err),
+        }
+    }
+}
+
+/// moves the statements between the positions into a new function with the given name,
+///    defined before the top level statement holding them and called in their place. Variables
+///    of the enclosing function which the statements use become the function's parameters.
+pub fn extract_function(source: &str, start: Position, end: Position, name: &str) -> Result<String, RefactorError> {
+    let tokens = lex(source).map_err(RefactorError::Lex)?;
+    let program = parse(&mut tokens.clone()).map_err(RefactorError::Parse)?;
+    check_new_name(&program, name)?;
+
+    let (start, end) = selection_offsets(&tokens, start, end).ok_or(RefactorError::InvalidSelection)?;
+    let selection = find_selection(&program, start, end, &[])?;
+    let selected = &selection.block[selection.first..=selection.last];
+
+    let mut names = Names::default();
+    selected.iter().for_each(|statement| names.visit_statement(statement));
+    if names.returns {
+        return Err(RefactorError::ContainsReturn);
+    }
+    if let Some(name) = names.assigned.iter().find(|name| !names.defined.contains(name) && selection.locals.contains(name)) {
+        return Err(RefactorError::AssignsOuterVariable(name.clone()));
+    }
+    let mut after = Names::default();
+    selection.block[selection.last + 1..].iter().for_each(|statement| after.visit_statement(statement));
+    let mut defined_here = selected.iter().filter_map(defined_name);
+    if let Some(name) = defined_here.find(|name| after.used.contains(name) || after.assigned.contains(name)) {
+        return Err(RefactorError::UsedAfterSelection(name.clone()));
+    }
+    // globals are visible from the new function, only the enclosing function's variables need
+    //    passing in
+    let parameters = names.used.iter()
+        .filter(|name| !names.defined.contains(name) && selection.locals.contains(name))
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let first = selected[0].span();
+    let last = selected[selected.len() - 1].span();
+    let top = program.iter()
+        .find(|statement| statement.span().start <= first.start && first.end <= statement.span().end)
+        .expect("the selection starts within a top level statement")
+        .span();
+    let insert_at = top.start;
+    let body = reindent(&source[first.start..last.end], first.column);
+
+    let output = format!(
+        "{}let {} = fn({}) {{\n{}\n}};\n\n{}{}({});{}",
+        &source[..insert_at],
+        name,
+        parameters,
+        body,
+        &source[insert_at..first.start],
+        name,
+        parameters,
+        &source[last.end..],
+    );
+
+    validate(source, output)
+}
+
+/// replaces each use of the variable defined by the let statement at the position with its
+///    value, and removes the let statement
+pub fn inline_variable(source: &str, position: Position) -> Result<String, RefactorError> {
+    let tokens = lex(source).map_err(RefactorError::Lex)?;
+    let program = parse(&mut tokens.clone()).map_err(RefactorError::Parse)?;
+
+    let offset = tokens.iter()
+        .find(|token| Position::of(&token.span) <= position && position.line == token.span.line
+            && position.column < token.span.column + (token.span.end - token.span.start))
+        .map(|token| token.span.start)
+        .ok_or(RefactorError::NoVariable)?;
+    let (block, index) = find_let(&program, offset).ok_or(RefactorError::NoVariable)?;
+    let (name, value, span) = match &block[index] {
+        Statement::Let { name, value, span } => (name, value, *span),
+        _ => unreachable!("find_let only finds let statements"),
+    };
+
+    let mut names = Names::default();
+    program.iter().for_each(|statement| names.visit_statement(statement));
+    // the uses are all after the let statement in its block, so only a definition there can
+    //    give the value or the variable a different meaning where it is used
+    let mut later = Names::default();
+    block[index + 1..].iter().for_each(|statement| later.visit_statement(statement));
+    let mut value_names = Names::default();
+    value_names.visit_expr(value);
+    for used in value_names.used.iter().chain(Some(name)) {
+        if later.defined.contains(used) {
+            return Err(RefactorError::Redefined(used.clone()));
+        }
+        if names.assigned.contains(used) {
+            return Err(RefactorError::Reassigned(used.clone()));
+        }
+    }
+
+    // the uses are the identifiers with the name other than the one the let statement defines,
+    //    which is the first after the let keyword
+    let is_name = |token: &&SpannedToken| token.token == Token::IDENT(name.clone());
+    let definition = tokens.iter().filter(is_name).find(|token| token.span.start > span.start);
+    let uses = tokens.iter()
+        .filter(is_name)
+        .filter(|token| Some(*token) != definition)
+        .map(|token| token.span)
+        .collect::<Vec<_>>();
+    let scope_end = block[block.len() - 1].span().end;
+    if uses.iter().any(|use_span| use_span.start < span.end || use_span.end > scope_end) {
+        return Err(RefactorError::UsedOutsideScope(name.clone()));
+    }
+    // a call is only moved when it still runs once, right where it did
+    let next_statement = block.get(index + 1).map(Statement::span);
+    let runs_in_place = match (uses.as_slice(), next_statement) {
+        ([], _) => true,
+        ([use_span], Some(next)) => next.start <= use_span.start && use_span.end <= next.end,
+        _ => false,
+    };
+    if value_names.calls && !runs_in_place {
+        return Err(RefactorError::SideEffects(name.clone()));
+    }
+
+    let assign = tokens.iter()
+        .find(|token| token.span.start > span.start && token.token == Token::ASSIGN)
+        .expect("a let statement with a value has an equals sign");
+    let value_text = source[assign.span.end..span.end - 1].trim();
+    let needs_parens = !matches!(
+        value,
+        Expr::Const(_) | Expr::Float(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Null | Expr::Ident(..)
+            | Expr::Call { .. } | Expr::Array(_) | Expr::Hash(_) | Expr::Index { .. }
+    );
+
+    let mut output = source.to_string();
+    for use_span in uses.iter().rev() {
+        let replacement = match needs_parens && !stands_alone(&tokens, use_span) {
+            true => format!("({})", value_text),
+            false => value_text.to_string(),
+        };
+        output.replace_range(use_span.start..use_span.end, &replacement);
+    }
+    output.replace_range(statement_line(source, &span), "");
+
+    validate(source, output)
+}
+
+// the parts of a block a selection covers, along with the variables local to the function
+//    the block is in
+struct Selection<'a> {
+    block: &'a [Statement],
+    first: usize,
+    last: usize,
+    locals: Vec<String>,
+}
+
+fn find_selection<'a>(block: &'a [Statement], start: usize, end: usize, locals: &[String]) -> Result<Selection<'a>, RefactorError> {
+    let contains = |span: Span| start <= span.start && span.end <= end;
+    let selected = block.iter().enumerate()
+        .filter(|(_, statement)| contains(statement.span()))
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if let (Some(&first), Some(&last)) = (selected.first(), selected.last()) {
+        // a statement partly selected can't be moved
+        let overlaps = |span: Span| span.start < end && start < span.end;
+        if block.iter().any(|statement| !contains(statement.span()) && overlaps(statement.span())) {
+            return Err(RefactorError::InvalidSelection);
+        }
+        return Ok(Selection { block, first, last, locals: locals.to_vec() });
+    }
+
+    // otherwise the selection is within one of the blocks of the statement holding it
+    let holder = block.iter()
+        .find(|statement| statement.span().start <= start && end <= statement.span().end)
+        .ok_or(RefactorError::InvalidSelection)?;
+    let mut blocks = ChildBlocks::default();
+    blocks.visit_statement(holder);
+    for (child, parameters) in blocks.0 {
+        let locals = match parameters {
+            // a function body starts a new scope, whose variables are its parameters and lets
+            Some(parameters) => {
+                let mut names = Names::default();
+                child.iter().for_each(|statement| names.visit_statement(statement));
+                parameters.iter().cloned().chain(names.defined).collect()
+            },
+            None => locals.to_vec(),
+        };
+        if let Ok(selection) = find_selection(child, start, end, &locals) {
+            return Ok(selection);
+        }
+    }
+
+    Err(RefactorError::InvalidSelection)
+}
+
+// the block and index of the innermost let statement holding the offset
+fn find_let(block: &[Statement], offset: usize) -> Option<(&[Statement], usize)> {
+    let index = block.iter().position(|statement| statement.span().start <= offset && offset < statement.span().end)?;
+    let mut blocks = ChildBlocks::default();
+    blocks.visit_statement(&block[index]);
+    for (child, _) in blocks.0 {
+        if let Some(found) = find_let(child, offset) {
+            return Some(found);
+        }
+    }
+
+    match block[index] {
+        Statement::Let { .. } => Some((block, index)),
+        _ => None,
+    }
+}
+
+// the byte offsets from the first token at or after the start up to the end of the last token
+//    starting at or before the end
+fn selection_offsets(tokens: &[SpannedToken], start: Position, end: Position) -> Option<(usize, usize)> {
+    let tokens = tokens.iter().filter(|token| token.token != Token::EOF);
+    let first = tokens.clone().find(|token| Position::of(&token.span) >= start)?;
+    let last = tokens.rev().find(|token| Position::of(&token.span) <= end)?;
+
+    Some((first.span.start, last.span.end))
+}
+
+// whether the token is a whole expression, so any expression can take its place without
+//    parentheses, as the value of a let statement or an argument is
+fn stands_alone(tokens: &[SpannedToken], span: &Span) -> bool {
+    let index = tokens.iter().position(|token| token.span == *span).expect("the span is of a token");
+    let before = index.checked_sub(1).map(|before| &tokens[before].token);
+    let after = &tokens[index + 1].token;
+
+    matches!(before, None | Some(Token::ASSIGN) | Some(Token::RETURN) | Some(Token::LPAREN) | Some(Token::LBRACKET)
+        | Some(Token::COMMA) | Some(Token::LBRACE) | Some(Token::SEMICOLON))
+        && matches!(after, Token::SEMICOLON | Token::RPAREN | Token::RBRACKET | Token::COMMA)
+}
+
+fn check_new_name(program: &[Statement], name: &str) -> Result<(), RefactorError> {
+    let is_identifier = matches!(
+        lex(name).as_deref(),
+        Ok([SpannedToken { token: Token::IDENT(_), .. }, SpannedToken { token: Token::EOF, .. }])
+    );
+    if !is_identifier {
+        return Err(RefactorError::InvalidName(name.to_string()));
+    }
+
+    let mut names = Names::default();
+    program.iter().for_each(|statement| names.visit_statement(statement));
+    let name = name.to_string();
+    if builtins::lookup(&name).is_some() || names.defined.contains(&name) || names.used.contains(&name) {
+        return Err(RefactorError::NameTaken(name));
+    }
+
+    Ok(())
+}
+
+fn defined_name(statement: &Statement) -> Option<&String> {
+    match statement {
+        Statement::Let { name, .. } | Statement::Declare { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |index| index + 1)
+}
+
+// the range to remove to take out a statement, the whole line when nothing else is on it
+fn statement_line(source: &str, span: &Span) -> std::ops::Range<usize> {
+    let start = line_start(source, span.start);
+    let line_end = source[span.end..].find('\n').map_or(source.len(), |index| span.end + index + 1);
+    if source[start..span.start].trim().is_empty() && source[span.end..line_end].trim().is_empty() {
+        return start..line_end;
+    }
+    let spaces = source[span.end..].len() - source[span.end..].trim_start_matches([' ', '\t']).len();
+
+    span.start..span.end + spaces
+}
+
+// indents statements by one level for a function body, after removing the indentation they had
+//    in the block they came from, which started at the column of the first
+fn reindent(text: &str, column: usize) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let line = match index {
+                0 => line,
+                _ => {
+                    let indentation = line.len() - line.trim_start().len();
+                    &line[indentation.min(column - 1)..]
+                },
+            };
+            if line.is_empty() { String::new() } else { format!("{}{}", INDENT, line) }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn validate(source: &str, output: String) -> Result<String, RefactorError> {
+    let mut tokens = lex(&output).map_err(|err| RefactorError::Broken(err.to_string()))?;
+    parse(&mut tokens).map_err(|err| RefactorError::Broken(err.to_string()))?;
+    // compiling resolves every name, so a rewrite which leaves one undefined is caught here, as
+    //    long as the program compiled before it
+    if compile_from_source(source).is_ok() {
+        compile_from_source(&output).map_err(|err| RefactorError::Broken(err.to_string()))?;
+    }
+
+    Ok(output)
+}
+
+// the names a piece of program uses and defines, each name used listed once in order of use
+#[derive(Default)]
+struct Names {
+    used: Vec<String>,
+    defined: Vec<String>,
+    assigned: Vec<String>,
+    // whether a return leaves the code visited, rather than a function within it
+    returns: bool,
+    calls: bool,
+    function_depth: usize,
+}
+
+impl Visit for Names {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let { name, .. } | Statement::Declare { name, .. } => self.defined.push(name.clone()),
+            Statement::Assign { name, .. } => self.assigned.push(name.clone()),
+            Statement::Return { .. } if self.function_depth == 0 => self.returns = true,
+            _ => {},
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Ident(name, _) if !self.used.contains(name) => self.used.push(name.clone()),
+            Expr::Call { .. } => self.calls = true,
+            Expr::Function { parameters, .. } => {
+                self.defined.extend(parameters.iter().cloned());
+                self.function_depth += 1;
+                walk_expr(self, expr);
+                self.function_depth -= 1;
+                return;
+            },
+            _ => {},
+        }
+        walk_expr(self, expr);
+    }
+}
+
+// the blocks directly within a statement, with the parameters of those which are function bodies
+#[derive(Default)]
+struct ChildBlocks<'a>(Vec<(&'a [Statement], Option<&'a [String]>)>);
+
+impl<'a> ChildBlocks<'a> {
+    fn visit_statement(&mut self, statement: &'a Statement) {
+        match statement {
+            Statement::Let { value, .. }
+                | Statement::Assign { value, .. }
+                | Statement::Return { value, .. }
+                | Statement::Expression(value, _) => self.visit_expr(value),
+            Statement::Declare { .. } => {},
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'a Expr) {
+        match expr {
+            Expr::If { condition, consequence, alternative } => {
+                self.visit_expr(condition);
+                self.0.push((consequence, None));
+                self.0.push((alternative, None));
+            },
+            Expr::Function { parameters, body } => self.0.push((body, Some(parameters))),
+            Expr::Prefix { value, .. } => self.visit_expr(value),
+            Expr::Infix { left, right, .. } | Expr::Index { left, index: right } => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+            },
+            Expr::Call { function, arguments, .. } => {
+                self.visit_expr(function);
+                arguments.iter().for_each(|argument| self.visit_expr(argument));
+            },
+            Expr::Array(elements) | Expr::Interpolation(elements) =>
+                elements.iter().for_each(|element| self.visit_expr(element)),
+            Expr::Hash(pairs) => pairs.iter().for_each(|(key, value)| {
+                self.visit_expr(key);
+                self.visit_expr(value);
+            }),
+            Expr::Ternary { condition, consequence, alternative } => {
+                self.visit_expr(condition);
+                self.visit_expr(consequence);
+                self.visit_expr(alternative);
+            },
+            Expr::Const(_) | Expr::Float(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Null | Expr::Ident(..) => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn targets() {
+        assert_eq!(Some(("file.mky", at(10, 4), Some(at(12, 20)))), parse_target("file.mky:10:4-12:20"));
+        assert_eq!(Some(("my-file.mky", at(3, 5), None)), parse_target("my-file.mky:3:5"));
+        assert_eq!(None, parse_target("file.mky:3"));
+        assert_eq!(None, parse_target("file.mky"));
+    }
+
+    #[test]
+    fn extract_top_level() {
+        let source = "let a = 1;\n// the sum\nputs(a + 1);\nputs(a + 2);\nputs(3);\n";
+
+        assert_eq!(
+            Ok(String::from("let a = 1;\n// the sum\nlet show = fn() {\n    puts(a + 1);\n    puts(a + 2);\n};\n\nshow();\nputs(3);\n")),
+            extract_function(source, at(3, 1), at(4, 12), "show")
+        );
+    }
+
+    #[test]
+    fn extract_from_function() {
+        let source = "let f = fn(x, y) {\n    let z = x * 2;\n    let w = z + y;\n    w + 1;\n};\nf(1, 2);\n";
+        let expected = "let double_plus = fn(x, y) {\n    let z = x * 2;\n    let w = z + y;\n    w + 1;\n};\n\n\
+            let f = fn(x, y) {\n    double_plus(x, y);\n};\nf(1, 2);\n";
+
+        assert_eq!(Ok(String::from(expected)), extract_function(source, at(2, 5), at(4, 10), "double_plus"));
+        // the selection needs only cover the statements' tokens, and may be within an if
+        let source = "let f = fn(x) {\n    if (x > 0) {\n        puts(x);\n    };\n};\n";
+        assert_eq!(
+            Ok(String::from("let show = fn(x) {\n    puts(x);\n};\n\nlet f = fn(x) {\n    if (x > 0) {\n        show(x);\n    };\n};\n")),
+            extract_function(source, at(3, 1), at(3, 20), "show")
+        );
+    }
+
+    #[test]
+    fn extract_errors() {
+        let source = "let f = fn(x) {\n    let y = x;\n    y = y + 1;\n    return y;\n};\nlet g = 1;\nputs(g);\n";
+
+        assert_eq!(Err(RefactorError::InvalidName(String::from("1x"))), extract_function(source, at(7, 1), at(7, 8), "1x"));
+        assert_eq!(Err(RefactorError::InvalidName(String::from("let"))), extract_function(source, at(7, 1), at(7, 8), "let"));
+        assert_eq!(Err(RefactorError::NameTaken(String::from("g"))), extract_function(source, at(7, 1), at(7, 8), "g"));
+        assert_eq!(Err(RefactorError::NameTaken(String::from("len"))), extract_function(source, at(7, 1), at(7, 8), "len"));
+        // part of a statement
+        assert_eq!(Err(RefactorError::InvalidSelection), extract_function(source, at(7, 1), at(7, 4), "h"));
+        assert_eq!(Err(RefactorError::InvalidSelection), extract_function(source, at(6, 5), at(7, 8), "h"));
+        assert_eq!(Err(RefactorError::ContainsReturn), extract_function(source, at(3, 5), at(4, 14), "h"));
+        assert_eq!(Err(RefactorError::AssignsOuterVariable(String::from("y"))), extract_function(source, at(3, 5), at(3, 14), "h"));
+        assert_eq!(Err(RefactorError::UsedAfterSelection(String::from("y"))), extract_function(source, at(2, 5), at(2, 14), "h"));
+        assert_eq!(Err(RefactorError::UsedAfterSelection(String::from("g"))), extract_function(source, at(6, 1), at(6, 10), "h"));
+    }
+
+    #[test]
+    fn inline() {
+        let source = "let a = 2;\nlet b = a + 1; // three\nputs(b * 2, [b]);\n";
+
+        // the comment after the statement is kept
+        assert_eq!(Ok(String::from("let a = 2;\n// three\nputs((a + 1) * 2, [a + 1]);\n")), inline_variable(source, at(2, 5)));
+        assert_eq!(
+            Ok(String::from("let b = 2 + 1; // three\nputs(b * 2, [b]);\n")),
+            inline_variable(source, at(1, 1))
+        );
+        // a call runs once in the same place
+        assert_eq!(
+            Ok(String::from("let f = fn() { 1; };\nputs(f());\n")),
+            inline_variable("let f = fn() { 1; };\nlet x = f();\nputs(x);\n", at(2, 5))
+        );
+        // within a function, whose parameter may share a name with others elsewhere
+        assert_eq!(
+            Ok(String::from("let f = fn(x) { x * 2; };\nlet g = fn(x) { x; };\n")),
+            inline_variable("let f = fn(x) { let y = x * 2; y; };\nlet g = fn(x) { x; };\n", at(1, 21))
+        );
+    }
+
+    #[test]
+    fn inline_errors() {
+        assert_eq!(Err(RefactorError::NoVariable), inline_variable("let a = 1;\nputs(a);\n", at(2, 1)));
+        assert_eq!(Err(RefactorError::Reassigned(String::from("a"))), inline_variable("let a = 1; a = 2; a;", at(1, 5)));
+        assert_eq!(Err(RefactorError::Reassigned(String::from("a"))), inline_variable("let a = 1; let b = a; a = 2; b;", at(1, 16)));
+        assert_eq!(
+            Err(RefactorError::Redefined(String::from("a"))),
+            inline_variable("let a = 1; let f = fn(a) { a; }; a;", at(1, 5))
+        );
+        assert_eq!(
+            Err(RefactorError::Redefined(String::from("x"))),
+            inline_variable("let x = 1; let y = x + 1; let f = fn(x) { y; };", at(1, 16))
+        );
+        assert_eq!(
+            Err(RefactorError::UsedOutsideScope(String::from("f"))),
+            inline_variable("let f = fn(n) { f(n); };", at(1, 5))
+        );
+        assert_eq!(
+            Err(RefactorError::SideEffects(String::from("x"))),
+            inline_variable("let x = puts(1);\nputs(2);\nx;\n", at(1, 5))
+        );
+        assert_eq!(
+            Err(RefactorError::SideEffects(String::from("x"))),
+            inline_variable("let x = len([1]);\nx + x;\n", at(1, 5))
+        );
+    }
+}
@@ -0,0 +1,299 @@
+// Helpers for the interactive repl, the loop itself lives in main.
+
+use crate::lexer::{lex, Token, TokenStream, KEYWORDS};
+use crate::builtins::{self, BUILTINS};
+use crate::eval::Object;
+use crate::term::Color;
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::Helper;
+use std::borrow::Cow;
+use std::fmt;
+
+mod theme;
+pub use self::theme::{RcError, Theme, ThemeError};
+
+/// a line starting with `:` which asks the repl about the session, rather than being run
+#[derive(Debug, PartialEq)]
+pub enum Command<'a> {
+    // the tokens the lexer produces for the source
+    Tokens(&'a str),
+    // the tree the parser produces for the source
+    Ast(&'a str),
+    // the disassembled instructions the compiler produces for the source
+    Bytecode(&'a str),
+    // the globals defined so far
+    Env,
+    // forget every global, as if the repl had been restarted
+    Reset,
+    // the last result in full, when it was too large to print
+    Show,
+    // the arity and description of the builtin at this index in BUILTINS
+    Doc(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CommandError<'a> {
+    Unknown(&'a str),
+    MissingSource(&'a str),
+    MissingBuiltin,
+    UnknownBuiltin(&'a str),
+}
+
+impl fmt::Display for CommandError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Unknown(name) =>
+                write!(f, "unknown command `:{}`, expected one of :tokens, :ast, :bytecode, :env, :reset, :show, or :doc", name),
+            CommandError::MissingSource(name) => write!(f, "`:{}` expects source code, such as `:{} 1 + 2;`", name, name),
+            CommandError::MissingBuiltin => write!(f, "`:doc` expects the name of a builtin, such as `:doc len`"),
+            CommandError::UnknownBuiltin(name) => write!(f, "there is no builtin named `{}`", name),
+        }
+    }
+}
+
+/// parses a line of the repl as a command, or returns None when it is source to be run
+pub fn parse_command(line: &str) -> Option<Result<Command<'_>, CommandError<'_>>> {
+    let line = line.trim().strip_prefix(':')?;
+    let (name, source) = match line.find(char::is_whitespace) {
+        Some(index) => (&line[..index], line[index..].trim()),
+        None => (line, ""),
+    };
+
+    let command = match name {
+        "tokens" | "ast" | "bytecode" if source.is_empty() => return Some(Err(CommandError::MissingSource(name))),
+        "tokens" => Command::Tokens(source),
+        "ast" => Command::Ast(source),
+        "bytecode" => Command::Bytecode(source),
+        "env" => Command::Env,
+        "reset" => Command::Reset,
+        "show" => Command::Show,
+        "doc" if source.is_empty() => return Some(Err(CommandError::MissingBuiltin)),
+        "doc" => match builtins::lookup(source) {
+            Some(index) => Command::Doc(index),
+            None => return Some(Err(CommandError::UnknownBuiltin(source))),
+        },
+        name => return Some(Err(CommandError::Unknown(name))),
+    };
+
+    Some(Ok(command))
+}
+
+/// the most elements of an array or pairs of a hash the repl prints, the rest are left for `:show`
+pub const PREVIEW_ELEMENTS: usize = 100;
+
+/// renders a value as Display does, but with arrays and hashes cut short after the given number of
+///    elements
+pub fn preview(value: &Object, max_elements: usize) -> String {
+    match value {
+        Object::Array(elements) => {
+            let mut rendered = elements.iter()
+                .take(max_elements)
+                .map(|element| preview(element, max_elements))
+                .collect::<Vec<String>>();
+            if elements.len() > max_elements {
+                rendered.push(format!(
+                    "... ({} of {} elements, use :show to expand)", max_elements, elements.len()
+                ));
+            }
+            format!("[{}]", rendered.join(", "))
+        },
+        Object::Hash(pairs) => {
+            let mut rendered = pairs.iter()
+                .take(max_elements)
+                .map(|(key, value)| format!("{}: {}", key.to_object(), preview(value, max_elements)))
+                .collect::<Vec<String>>();
+            if pairs.len() > max_elements {
+                rendered.push(format!(
+                    "... ({} of {} pairs, use :show to expand)", max_elements, pairs.len()
+                ));
+            }
+            format!("{{{}}}", rendered.join(", "))
+        },
+        Object::Return(value) => preview(value, max_elements),
+        value => value.to_string(),
+    }
+}
+
+/// whether the input stops part way through a statement, with a bracket left open or ending
+///    on an operator, in which case the repl reads another line rather than reporting an error
+pub fn is_incomplete(input: &str) -> bool {
+    // a line which doesn't lex can't be completed by another, so it is reported straight away
+    let tokens = match lex(input) {
+        Ok(tokens) => tokens,
+        Err(_) => return false,
+    };
+    let mut stream = TokenStream::new(&tokens);
+    let mut depth = 0;
+    let mut last = None;
+    while stream.peek().token != Token::EOF {
+        let token = stream.next().token;
+        match token {
+            Token::LPAREN | Token::LBRACE | Token::LBRACKET => depth += 1,
+            Token::RPAREN | Token::RBRACE | Token::RBRACKET => depth -= 1,
+            _ => {},
+        }
+        last = Some(token);
+    }
+    if depth > 0 {
+        return true;
+    }
+
+    matches!(
+        last.as_ref(),
+        Some(Token::ASSIGN)
+            | Some(Token::PLUS)
+            | Some(Token::MINUS)
+            | Some(Token::SLASH)
+            | Some(Token::ASTERISK)
+            | Some(Token::LT)
+            | Some(Token::GT)
+            | Some(Token::BANG)
+            | Some(Token::COMMA)
+            | Some(Token::EQ)
+            | Some(Token::NOT_EQ)
+            | Some(Token::LT_EQ)
+            | Some(Token::GT_EQ)
+            | Some(Token::AND)
+            | Some(Token::OR)
+            | Some(Token::QUESTION)
+            | Some(Token::COLON)
+    )
+}
+
+/// completes keywords, builtins, and the globals of the session, and colors the prompt
+#[derive(Default)]
+pub struct ReplHelper {
+    // the globals, which the repl updates after each line
+    globals: Vec<String>,
+    color: Option<Color>,
+}
+
+impl ReplHelper {
+    pub fn with_color(color: Option<Color>) -> Self {
+        ReplHelper { color, ..ReplHelper::default() }
+    }
+
+
+    pub fn set_globals(&mut self, globals: Vec<String>) {
+        self.globals = globals;
+    }
+
+    /// the names starting with the given prefix, sorted and without duplicates
+    pub fn completions(&self, prefix: &str) -> Vec<String> {
+        let mut names = KEYWORDS.iter().map(|keyword| keyword.to_string())
+            .chain(BUILTINS.iter().map(|builtin| builtin.name.to_string()))
+            .chain(self.globals.iter().cloned())
+            .filter(|name| name.starts_with(prefix))
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+
+        names
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &rustyline::Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        // the identifier being typed, which ends at the cursor
+        let start = line[..pos].rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).map_or(0, |index| index + 1);
+        if start == pos {
+            return Ok((pos, Vec::new()));
+        }
+
+        Ok((start, self.completions(&line[start..pos])))
+    }
+}
+
+impl Hinter for ReplHelper {}
+impl Highlighter for ReplHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        match self.color {
+            Some(color) => Cow::Owned(color.paint(prompt)),
+            None => Cow::Borrowed(prompt),
+        }
+    }
+}
+impl Validator for ReplHelper {}
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::HashKey;
+    use std::rc::Rc;
+
+    #[test]
+    fn commands() {
+        assert_eq!(None, parse_command("1 + 2;"));
+        assert_eq!(Some(Ok(Command::Tokens("1 + 2;"))), parse_command(":tokens 1 + 2;"));
+        assert_eq!(Some(Ok(Command::Ast("let x = 1;"))), parse_command("  :ast   let x = 1;"));
+        assert_eq!(Some(Ok(Command::Bytecode("1;"))), parse_command(":bytecode 1;"));
+        assert_eq!(Some(Ok(Command::Env)), parse_command(":env"));
+        assert_eq!(Some(Ok(Command::Reset)), parse_command(":reset"));
+        assert_eq!(Some(Ok(Command::Show)), parse_command(":show"));
+        assert_eq!(Some(Ok(Command::Doc(builtins::lookup("len").unwrap()))), parse_command(":doc len"));
+
+        assert_eq!(Some(Err(CommandError::MissingSource("ast"))), parse_command(":ast"));
+        assert_eq!(Some(Err(CommandError::Unknown("quit"))), parse_command(":quit"));
+        assert_eq!(Some(Err(CommandError::MissingBuiltin)), parse_command(":doc"));
+        assert_eq!(Some(Err(CommandError::UnknownBuiltin("nope"))), parse_command(":doc nope"));
+        assert_eq!(
+            "there is no builtin named `nope`",
+            parse_command(":doc nope").unwrap().unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn previews() {
+        let small = Object::Array(Rc::new(vec![Object::Integer(1), Object::String("a".into())]));
+        assert_eq!(small.to_string(), preview(&small, 2));
+        assert_eq!("5", preview(&Object::Integer(5), 2));
+
+        let large = Object::Array(Rc::new((0..5).map(Object::Integer).collect()));
+        assert_eq!("[0, 1, ... (2 of 5 elements, use :show to expand)]", preview(&large, 2));
+
+        let nested = Object::Array(Rc::new(vec![large.clone()]));
+        assert_eq!("[[0, 1, ... (2 of 5 elements, use :show to expand)]]", preview(&nested, 2));
+
+        let hash = Object::Hash(Rc::new((0..3).map(|key| (HashKey::Integer(key), large.clone())).collect()));
+        assert_eq!(
+            "{0: [0, 1, ... (2 of 5 elements, use :show to expand)], 1: [0, 1, ... (2 of 5 elements, use :show to expand)], \
+             ... (2 of 3 pairs, use :show to expand)}",
+            preview(&hash, 2)
+        );
+    }
+
+    #[test]
+    fn completions() {
+        let mut helper = ReplHelper::default();
+        helper.set_globals(vec![String::from("first"), String::from("fib")]);
+
+        assert_eq!(vec!["false", "fib", "first", "fn"], helper.completions("f"));
+        assert_eq!(vec!["len", "let"], helper.completions("le"));
+        assert!(helper.completions("z").is_empty());
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(is_incomplete("let add = fn(a, b) {"));
+        assert!(is_incomplete("let add = fn(a, b) {\n  a + b;"));
+        assert!(is_incomplete("puts(1,"));
+        assert!(is_incomplete("[1, 2"));
+        assert!(is_incomplete("let x = 1 +"));
+        assert!(is_incomplete("let x ="));
+        assert!(is_incomplete("let x = a ?"));
+        assert!(is_incomplete("let x = a ? b :"));
+
+        assert!(!is_incomplete(""));
+        assert!(!is_incomplete("let add = fn(a, b) {\n  a + b;\n};"));
+        assert!(!is_incomplete("1 + 2;"));
+        // a stray closing bracket is left for the parser to report
+        assert!(!is_incomplete("1 + 2);"));
+        assert!(!is_incomplete("let x = 1"));
+    }
+}
@@ -0,0 +1,125 @@
+// How the repl looks, set from the rc file and then from flags.
+
+use crate::term::Color;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Theme {
+    pub prompt: String,
+    // the prompt for the lines of a statement which continues over several lines
+    pub continuation: String,
+    // the color the prompts are drawn in, None for plain text
+    pub color: Option<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            prompt: String::from(">> "),
+            continuation: String::from(".. "),
+            color: Some(Color::Green),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ThemeError {
+    UnknownSetting(String),
+    UnknownColor(String),
+    // a line of the rc file which isn't a `setting = value` pair
+    Malformed(String),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThemeError::UnknownSetting(name) =>
+                write!(f, "unknown setting `{}`, expected one of prompt, continuation, or color", name),
+            ThemeError::UnknownColor(name) =>
+                write!(f, "unknown color `{}`, expected red, green, yellow, blue, magenta, cyan, or none", name),
+            ThemeError::Malformed(line) => write!(f, "expected `setting = value`, got `{}`", line),
+        }
+    }
+}
+
+/// an error in the rc file, with the line it is on
+#[derive(Debug, PartialEq)]
+pub struct RcError {
+    pub line: usize,
+    pub error: ThemeError,
+}
+
+impl fmt::Display for RcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl Theme {
+    /// changes one setting, values may be quoted to keep leading or trailing spaces
+    pub fn set(&mut self, setting: &str, value: &str) -> Result<(), ThemeError> {
+        let value = value.strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        match setting {
+            "prompt" => self.prompt = value.to_string(),
+            "continuation" => self.continuation = value.to_string(),
+            "color" if value == "none" => self.color = None,
+            "color" => self.color = Some(Color::parse(value).ok_or_else(|| ThemeError::UnknownColor(value.to_string()))?),
+            setting => return Err(ThemeError::UnknownSetting(setting.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// applies the settings of an rc file, one `setting = value` per line, skipping blank
+    ///    lines and comments starting with `#`
+    pub fn apply_rc(&mut self, contents: &str) -> Result<(), RcError> {
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |error| RcError { line: index + 1, error };
+            let (setting, value) = line.split_once('=')
+                .ok_or_else(|| error(ThemeError::Malformed(line.to_string())))?;
+            self.set(setting.trim(), value.trim()).map_err(error)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rc_file() {
+        let mut theme = Theme::default();
+        theme.apply_rc("# monkey\n\nprompt = \"monkey> \"\ncontinuation=...\ncolor = none\n").unwrap();
+        assert_eq!(Theme { prompt: String::from("monkey> "), continuation: String::from("..."), color: None }, theme);
+
+        let mut theme = Theme::default();
+        theme.set("color", "cyan").unwrap();
+        assert_eq!(Some(Color::Cyan), theme.color);
+    }
+
+    #[test]
+    fn rc_file_errors() {
+        let mut theme = Theme::default();
+        assert_eq!(
+            Err(RcError { line: 2, error: ThemeError::UnknownColor(String::from("pink")) }),
+            theme.apply_rc("prompt = >\ncolor = pink")
+        );
+        assert_eq!(
+            Err(RcError { line: 1, error: ThemeError::UnknownSetting(String::from("font")) }),
+            theme.apply_rc("font = mono")
+        );
+        assert_eq!(
+            Err(RcError { line: 1, error: ThemeError::Malformed(String::from("prompt")) }),
+            theme.apply_rc("prompt")
+        );
+    }
+}
@@ -0,0 +1,80 @@
+// Reading source files, which are expected to be UTF-8. A leading byte order mark, as some
+// editors on Windows write, is dropped rather than being lexed as an invalid token.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+
+const BOM: &str = "\u{feff}";
+
+#[derive(Debug)]
+pub enum SourceError {
+    Io(io::Error),
+    // the byte offset of the first byte which isn't part of a valid UTF-8 character
+    InvalidUtf8 { offset: usize },
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceError::Io(err) => write!(f, "{}", err),
+            SourceError::InvalidUtf8 { offset } =>
+                write!(f, "source is not valid UTF-8, starting at byte {}", offset),
+        }
+    }
+}
+
+impl From<io::Error> for SourceError {
+    fn from(err: io::Error) -> Self {
+        SourceError::Io(err)
+    }
+}
+
+/// reads the source at the path, or from stdin when the path is `-`
+pub fn read(path: &str, lossy: bool) -> Result<String, SourceError> {
+    let mut bytes = Vec::new();
+    if path == "-" {
+        io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        bytes = fs::read(path)?;
+    }
+
+    decode(bytes, lossy)
+}
+
+/// decodes source as UTF-8 without its byte order mark, when lossy invalid bytes are replaced
+///    with U+FFFD rather than being an error
+pub fn decode(bytes: Vec<u8>, lossy: bool) -> Result<String, SourceError> {
+    let source = match String::from_utf8(bytes) {
+        Ok(source) => source,
+        Err(err) if lossy => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+        Err(err) => return Err(SourceError::InvalidUtf8 { offset: err.utf8_error().valid_up_to() }),
+    };
+
+    Ok(match source.strip_prefix(BOM) {
+        Some(source) => source.to_string(),
+        None => source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_source() {
+        assert_eq!("let x = 1;", decode(b"let x = 1;".to_vec(), false).unwrap());
+        assert_eq!("let x = 1;", decode(b"\xef\xbb\xbflet x = 1;".to_vec(), false).unwrap());
+        // only a leading byte order mark is dropped
+        assert_eq!("1;\u{feff}", decode("1;\u{feff}".as_bytes().to_vec(), false).unwrap());
+    }
+
+    #[test]
+    fn decode_invalid_utf8() {
+        assert!(matches!(
+            decode(b"let x = \"\xff\";".to_vec(), false),
+            Err(SourceError::InvalidUtf8 { offset: 9 })
+        ));
+        assert_eq!("let x = \"\u{fffd}\";", decode(b"let x = \"\xff\";".to_vec(), true).unwrap());
+    }
+}
@@ -0,0 +1,144 @@
+// Builds a standalone executable from a script by appending the script's source to a copy of
+// the running monkey binary, followed by a trailer holding the length of the source and a
+// marker. On startup the binary checks itself for the trailer, and runs the embedded script
+// rather than the repl when it finds one.
+
+use crate::lexer::{lex, LexError};
+use crate::parser::{parse, ParseError};
+use crate::source::{self, SourceError};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const MARKER: &[u8; 8] = b"MKYEMBED";
+// the length of the source followed by the marker
+const TRAILER_LEN: usize = 8 + MARKER.len();
+
+#[derive(Debug)]
+pub enum BuildError {
+    Io(io::Error),
+    Source(SourceError),
+    Lex(LexError),
+    Parse(ParseError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Io(err) => write!(f, "build error: {}", err),
+            BuildError::Source(err) => write!(f, "build error: {}", err),
+            BuildError::Lex(err) => write!(f, "{}", err),
+            BuildError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(err: io::Error) -> Self {
+        BuildError::Io(err)
+    }
+}
+
+/// writes an executable to the output path which runs the script when started
+pub fn build(script: &Path, output: &Path) -> Result<(), BuildError> {
+    if output == script {
+        return Err(BuildError::Io(io::Error::new(io::ErrorKind::InvalidInput, "the output would overwrite the script")));
+    }
+    let source = source::decode(fs::read(script)?, false).map_err(BuildError::Source)?;
+    // a script which doesn't parse is reported now, rather than by the executable when it is run
+    parse(&mut lex(&source).map_err(BuildError::Lex)?).map_err(BuildError::Parse)?;
+
+    let runner = fs::read(std::env::current_exe()?)?;
+    fs::write(output, embed(runner, &source))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(output, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+fn embed(mut executable: Vec<u8>, source: &str) -> Vec<u8> {
+    // an executable built from another standalone executable replaces its script
+    if let Ok(Some(embedded)) = read_embedded(&mut io::Cursor::new(&executable)) {
+        executable.truncate(executable.len() - embedded.len() - TRAILER_LEN);
+    }
+
+    executable.extend(source.as_bytes());
+    executable.extend(&(source.len() as u64).to_le_bytes());
+    executable.extend(MARKER);
+
+    executable
+}
+
+/// the script embedded in the running executable, if it is a standalone executable
+pub fn embedded_script() -> Option<String> {
+    let mut executable = File::open(std::env::current_exe().ok()?).ok()?;
+
+    read_embedded(&mut executable).ok()?
+}
+
+fn read_embedded(file: &mut (impl Read + Seek)) -> io::Result<Option<String>> {
+    let file_len = file.seek(SeekFrom::End(0))?;
+    if file_len < TRAILER_LEN as u64 {
+        return Ok(None);
+    }
+
+    let mut trailer = [0; TRAILER_LEN];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64)))?;
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != MARKER {
+        return Ok(None);
+    }
+
+    let mut source_len = [0; 8];
+    source_len.copy_from_slice(&trailer[..8]);
+    let source_len = u64::from_le_bytes(source_len);
+    if source_len > file_len - TRAILER_LEN as u64 {
+        return Ok(None);
+    }
+
+    let mut source = vec![0; source_len as usize];
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64) - source_len as i64))?;
+    file.read_exact(&mut source)?;
+
+    Ok(String::from_utf8(source).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Option<String> {
+        read_embedded(&mut io::Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn embed_round_trip() {
+        let executable = embed(b"runner".to_vec(), "puts(1);");
+
+        assert_eq!(Some(String::from("puts(1);")), read(&executable));
+        assert!(executable.starts_with(b"runner"));
+    }
+
+    #[test]
+    fn embed_replaces_existing_script() {
+        let executable = embed(embed(b"runner".to_vec(), "1;"), "2;");
+
+        assert_eq!(Some(String::from("2;")), read(&executable));
+        assert_eq!(embed(b"runner".to_vec(), "2;"), executable);
+    }
+
+    #[test]
+    fn read_without_script() {
+        assert_eq!(None, read(b""));
+        assert_eq!(None, read(b"an ordinary executable"));
+        // a marker with a length longer than the file is not an embedded script
+        let mut bytes = 1000_u64.to_le_bytes().to_vec();
+        bytes.extend(MARKER);
+        assert_eq!(None, read(&bytes));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::parser::{Statement, Expr};
+use crate::visit::{Visit, walk_block, walk_statement, walk_expr};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// a summary of the size and shape of a program
+#[derive(Debug, PartialEq, Default)]
+pub struct Stats {
+    pub functions: usize,
+    pub statements: usize,
+    // blocks nest inside function bodies and if expressions, top level statements are at depth zero
+    pub max_depth: usize,
+    // distinct names which are bound or referenced
+    pub identifiers: BTreeSet<String>,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "functions: {}", self.functions)?;
+        writeln!(f, "statements: {}", self.statements)?;
+        writeln!(f, "max nesting depth: {}", self.max_depth)?;
+        write!(f, "identifiers: {}", self.identifiers.len())
+    }
+}
+
+pub fn program_stats(program: &[Statement]) -> Stats {
+    let mut collector = StatsCollector { stats: Stats::default(), depth: 0 };
+    collector.visit_block(program);
+
+    collector.stats
+}
+
+struct StatsCollector {
+    stats: Stats,
+    // the depth of the block currently being visited
+    depth: usize,
+}
+
+impl Visit for StatsCollector {
+    fn visit_block(&mut self, statements: &[Statement]) {
+        self.stats.max_depth = self.stats.max_depth.max(self.depth);
+        walk_block(self, statements);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        self.stats.statements += 1;
+        match statement {
+            Statement::Let { name, .. } | Statement::Assign { name, .. } | Statement::Declare { name, .. } => {
+                self.stats.identifiers.insert(name.clone());
+            },
+            Statement::Return { .. } | Statement::Expression(..) => {},
+        }
+        walk_statement(self, statement);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Ident(name, _) => {
+                self.stats.identifiers.insert(name.clone());
+            },
+            Expr::Function { parameters, .. } => {
+                self.stats.functions += 1;
+                self.stats.identifiers.extend(parameters.iter().cloned());
+            },
+            _ => {},
+        }
+
+        // the blocks of if expressions and function bodies are nested one level deeper
+        let nested = matches!(expr, Expr::If { .. } | Expr::Function { .. });
+        if nested {
+            self.depth += 1;
+        }
+        walk_expr(self, expr);
+        if nested {
+            self.depth -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+
+    fn source_stats(input: &str) -> Stats {
+        let mut tokens = lex(input).unwrap();
+        program_stats(&parse(&mut tokens).unwrap())
+    }
+
+    #[test]
+    fn stats_counts() {
+        let stats = source_stats(r#"
+            let max = fn(a, b) {
+                if (a > b) { return a; } else { return b; };
+            };
+            let apply = fn(f) { fn(x) { f(x); }; };
+            max(1, 2);
+        "#);
+
+        assert_eq!(3, stats.functions);
+        // 3 at the top level, 1 in max, 2 in its if blocks, 1 in apply, 1 in the inner function
+        assert_eq!(8, stats.statements);
+        assert_eq!(2, stats.max_depth);
+        assert_eq!(
+            vec!["a", "apply", "b", "f", "max", "x"],
+            stats.identifiers.iter().map(String::as_str).collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn stats_display() {
+        assert_eq!(
+            "functions: 0\nstatements: 2\nmax nesting depth: 0\nidentifiers: 1",
+            source_stats("let x = 1; x + 1;").to_string()
+        );
+    }
+}
@@ -0,0 +1,56 @@
+// Drawing text in the terminal with ansi escape codes, for the repl's prompts and the builtins
+// which style a program's output.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+}
+
+impl Color {
+    pub fn parse(name: &str) -> Option<Color> {
+        match name {
+            "red" => Some(Color::Red),
+            "green" => Some(Color::Green),
+            "yellow" => Some(Color::Yellow),
+            "blue" => Some(Color::Blue),
+            "magenta" => Some(Color::Magenta),
+            "cyan" => Some(Color::Cyan),
+            _ => None,
+        }
+    }
+
+    /// the text wrapped in the ansi escape codes which draw it in this color
+    pub fn paint(self, text: &str) -> String {
+        let code = match self {
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+            Color::Blue => 34,
+            Color::Magenta => 35,
+            Color::Cyan => 36,
+        };
+
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+/// the text wrapped in the ansi escape codes which draw it in bold
+pub fn bold(text: &str) -> String {
+    format!("\x1b[1m{}\x1b[0m", text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paint() {
+        assert_eq!("\x1b[32m>> \x1b[0m", Color::Green.paint(">> "));
+        assert_eq!("\x1b[1mhi\x1b[0m", bold("hi"));
+    }
+}
@@ -0,0 +1,46 @@
+// Events the evaluator reports as it runs a program, for embedders who want to follow its
+// progress in their own tooling. Objects are reference counted rather than garbage collected,
+// so there are no collection events.
+
+/// something the evaluator did, lines are those of the statement being evaluated
+#[derive(Debug, PartialEq, Clone)]
+pub enum TraceEvent<'a> {
+    StatementEnter { line: usize },
+    StatementExit { line: usize },
+    // the function is named by the identifier it was called through
+    Call { function: &'a str, line: usize },
+    Return { function: &'a str, line: usize },
+    Allocation { kind: &'static str, bytes: usize, line: usize },
+}
+
+pub trait Tracer {
+    fn event(&mut self, event: TraceEvent);
+}
+
+/// writes each event on its own line to stderr
+pub struct StderrTracer;
+
+impl Tracer for StderrTracer {
+    fn event(&mut self, event: TraceEvent) {
+        eprintln!("trace: {:?}", event);
+    }
+}
+
+/// forwards events to the `tracing` crate, so they reach whichever subscriber the embedder
+///    has installed
+#[cfg(feature = "tracing")]
+pub struct TracingTracer;
+
+#[cfg(feature = "tracing")]
+impl Tracer for TracingTracer {
+    fn event(&mut self, event: TraceEvent) {
+        match event {
+            TraceEvent::StatementEnter { line } => tracing::trace!(target: "monkey", line, "statement enter"),
+            TraceEvent::StatementExit { line } => tracing::trace!(target: "monkey", line, "statement exit"),
+            TraceEvent::Call { function, line } => tracing::debug!(target: "monkey", function, line, "call"),
+            TraceEvent::Return { function, line } => tracing::debug!(target: "monkey", function, line, "return"),
+            TraceEvent::Allocation { kind, bytes, line } =>
+                tracing::trace!(target: "monkey", kind, bytes, line, "allocation"),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// Traversals over the AST. A `Visit` reads the tree and a `Fold` rebuilds it, both walk
+// every child by default so an implementation only overrides the nodes it cares about,
+// calling the matching `walk_*` or `map_*` function to continue into the children.
+
+use crate::parser::{Statement, Expr};
+
+pub trait Visit {
+    fn visit_block(&mut self, statements: &[Statement]) {
+        walk_block(self, statements);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+pub fn walk_block<V: Visit + ?Sized>(visitor: &mut V, statements: &[Statement]) {
+    for statement in statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<V: Visit + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match statement {
+        Statement::Let { value, .. }
+            | Statement::Assign { value, .. }
+            | Statement::Return { value, .. }
+            | Statement::Expression(value, _) => visitor.visit_expr(value),
+        Statement::Declare { .. } => {},
+    }
+}
+
+pub fn walk_expr<V: Visit + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Prefix { value, .. } => visitor.visit_expr(value),
+        Expr::Infix { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        },
+        Expr::If { condition, consequence, alternative } => {
+            visitor.visit_expr(condition);
+            visitor.visit_block(consequence);
+            visitor.visit_block(alternative);
+        },
+        Expr::Function { body, .. } => visitor.visit_block(body),
+        Expr::Call { function, arguments, .. } => {
+            visitor.visit_expr(function);
+            arguments.iter().for_each(|argument| visitor.visit_expr(argument));
+        },
+        Expr::Array(elements) | Expr::Interpolation(elements) =>
+            elements.iter().for_each(|element| visitor.visit_expr(element)),
+        Expr::Hash(pairs) => pairs.iter().for_each(|(key, value)| {
+            visitor.visit_expr(key);
+            visitor.visit_expr(value);
+        }),
+        Expr::Ternary { condition, consequence, alternative } => {
+            visitor.visit_expr(condition);
+            visitor.visit_expr(consequence);
+            visitor.visit_expr(alternative);
+        },
+        Expr::Index { left, index } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(index);
+        },
+        Expr::Const(_) | Expr::Float(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Null | Expr::Ident(..) => {},
+    }
+}
+
+pub trait Fold {
+    fn fold_block(&mut self, statements: Vec<Statement>) -> Vec<Statement> {
+        map_block(self, statements)
+    }
+
+    fn fold_statement(&mut self, statement: Statement) -> Statement {
+        map_statement(self, statement)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        map_expr(self, expr)
+    }
+}
+
+pub fn map_block<F: Fold + ?Sized>(folder: &mut F, statements: Vec<Statement>) -> Vec<Statement> {
+    statements.into_iter().map(|statement| folder.fold_statement(statement)).collect()
+}
+
+pub fn map_statement<F: Fold + ?Sized>(folder: &mut F, statement: Statement) -> Statement {
+    match statement {
+        Statement::Let { name, value, span } => Statement::Let { name, value: folder.fold_expr(value), span },
+        Statement::Declare { name, span } => Statement::Declare { name, span },
+        Statement::Assign { name, value, span } => Statement::Assign { name, value: folder.fold_expr(value), span },
+        Statement::Return { value, span } => Statement::Return { value: folder.fold_expr(value), span },
+        Statement::Expression(expr, span) => Statement::Expression(folder.fold_expr(expr), span),
+    }
+}
+
+pub fn map_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Prefix { prefix, value } => Expr::Prefix {
+            prefix,
+            value: Box::new(folder.fold_expr(*value)),
+        },
+        Expr::Infix { left, operator, right } => Expr::Infix {
+            left: Box::new(folder.fold_expr(*left)),
+            operator,
+            right: Box::new(folder.fold_expr(*right)),
+        },
+        Expr::If { condition, consequence, alternative } => Expr::If {
+            condition: Box::new(folder.fold_expr(*condition)),
+            consequence: folder.fold_block(consequence),
+            alternative: folder.fold_block(alternative),
+        },
+        Expr::Function { parameters, body } => Expr::Function {
+            parameters,
+            body: folder.fold_block(body),
+        },
+        Expr::Call { function, arguments, span } => Expr::Call {
+            function: Box::new(folder.fold_expr(*function)),
+            arguments: arguments.into_iter().map(|argument| folder.fold_expr(argument)).collect(),
+            span,
+        },
+        Expr::Array(elements) => Expr::Array(elements.into_iter().map(|element| folder.fold_expr(element)).collect()),
+        Expr::Hash(pairs) => Expr::Hash(
+            pairs.into_iter().map(|(key, value)| (folder.fold_expr(key), folder.fold_expr(value))).collect()
+        ),
+        Expr::Interpolation(parts) => Expr::Interpolation(parts.into_iter().map(|part| folder.fold_expr(part)).collect()),
+        Expr::Ternary { condition, consequence, alternative } => Expr::Ternary {
+            condition: Box::new(folder.fold_expr(*condition)),
+            consequence: Box::new(folder.fold_expr(*consequence)),
+            alternative: Box::new(folder.fold_expr(*alternative)),
+        },
+        Expr::Index { left, index } => Expr::Index {
+            left: Box::new(folder.fold_expr(*left)),
+            index: Box::new(folder.fold_expr(*index)),
+        },
+        Expr::Const(_) | Expr::Float(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Null | Expr::Ident(..) => expr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::*;
+    use crate::parser::{Prefix, Operator};
+
+    #[test]
+    fn visit_reaches_nested_expressions() {
+        struct Idents(Vec<String>);
+
+        impl Visit for Idents {
+            fn visit_expr(&mut self, expr: &Expr) {
+                if let Expr::Ident(name, _) = expr {
+                    self.0.push(name.clone());
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        let program = vec![
+            let_("f", function(["a"], [return_(call("g", [ident("a"), array([ident("b")])]))])),
+            expr(if_else(ident("c"), [expr(ident("d"))], [assign("e", prefix(Prefix::Minus, ident("h")))])),
+        ];
+        let mut idents = Idents(vec![]);
+        idents.visit_block(&program);
+
+        assert_eq!(vec!["g", "a", "b", "c", "d", "h"], idents.0);
+    }
+
+    #[test]
+    fn fold_rebuilds_the_tree() {
+        struct Increment;
+
+        impl Fold for Increment {
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                match expr {
+                    Expr::Const(num) => Expr::Const(num + 1),
+                    expr => map_expr(self, expr),
+                }
+            }
+        }
+
+        assert_eq!(
+            vec![let_("f", function(["a"], [expr(if_(infix(ident("a"), Operator::Plus, int(2)), [return_(array([int(3)]))]))]))],
+            Increment.fold_block(vec![
+                let_("f", function(["a"], [expr(if_(infix(ident("a"), Operator::Plus, int(1)), [return_(array([int(2)]))]))])),
+            ])
+        );
+    }
+}
@@ -1,190 +1,594 @@
-use crate::eval::Object;
-use crate::compiler::ByteCode;
+use crate::eval::{Object, NativeFunction, RuntimeError, boolean_operand, build_hash, float_operands, index_object, integer_arithmetic, integer_negate, interpolate};
+use crate::parser::{Operator, Prefix};
+use crate::compiler::{ByteCode, Symbol, SymbolScope, SymbolTable};
 use crate::code::convert_two_u8s_be_to_usize;
+use crate::builtins::{self, Context, Host};
+use crate::profile::heap::HeapProfile;
+use std::io::{self, Write};
+use std::rc::Rc;
 
 const STACK_SIZE : usize = 2048;
 
-// the compiler can output any index up to the max u16 value
-//  but keeping an array of that size on the stack of our Rust VM causes trouble
-const GLOBAL_SIZE : usize = 2048;
+// the state of a function call which is restored when the called function returns
+struct Frame {
+    instructions: Rc<Vec<u8>>,
+    ip: usize,
+    base_pointer: usize,
+}
 
-struct VM {
-    instructions: Vec<u8>,
+pub struct VM {
+    instructions: Rc<Vec<u8>>, // the instructions currently being run, either the program or a function body
     constants: Vec<Object>,
-    stack: [Object; STACK_SIZE],
-    globals: [Object; GLOBAL_SIZE],
+    // allocated up front at its full size, on the heap since it is too large to move around
+    //    inline in the vm
+    stack: Vec<Object>,
+    // grows as globals are set, the compiler can output any index up to the max u16 value
+    //  but most programs only use a handful, a global whose `let` hasn't run yet has no value
+    globals: Vec<Option<Object>>,
     sp: usize, // stores the next FREE space on the stack
+    ip: usize, // instruction pointer
+    base_pointer: usize, // the stack position of the first local in the current function
+    frames: Vec<Frame>,
+    host: Host, // where builtins such as puts write their output, along with anything else they can reach
+    global_names: Vec<String>, // by index, to name a global which is read before it is set
 }
 
 impl VM {
-    fn new(byte_code: ByteCode) -> Self {
+    pub fn new(byte_code: ByteCode) -> Self {
+        VM::with_output(byte_code, Box::new(io::stdout()))
+    }
+
+    pub fn with_output(byte_code: ByteCode, output: Box<dyn Write>) -> Self {
+        VM::with_host(byte_code, Host::new(output))
+    }
+
+    pub fn with_host(byte_code: ByteCode, host: Host) -> Self {
         VM {
-            instructions: byte_code.instructions,
+            instructions: Rc::new(byte_code.instructions),
             constants: byte_code.constants,
-            // we rely on the stack pointer to ensure we don't read zeroed memory
-            // this should have the same result as [Object::Null, STACK_SIZE] which is not allow because Object is not copy
-            stack: unsafe { std::mem::zeroed() },
-            // we rely on compiler generating valid code to ensure we don't read zeroed memory
-            globals: unsafe { std::mem::zeroed() },
-            sp: 0
+            // filled with null, since last_popped reads the slot above the stack pointer even
+            //    when nothing was popped
+            stack: vec![Object::Null; STACK_SIZE],
+            globals: Vec::new(),
+            sp: 0,
+            ip: 0,
+            base_pointer: 0,
+            frames: Vec::new(),
+            host,
+            global_names: byte_code.globals,
+        }
+    }
+
+    /// a vm which continues from the globals of an earlier one, so each line of the repl can use
+    ///    the globals set by the lines before it
+    pub fn new_with_global_store(byte_code: ByteCode, globals: Vec<Option<Object>>) -> Self {
+        VM {
+            globals,
+            ..VM::new(byte_code)
         }
     }
 
-    fn run(&mut self) {
-        let mut ip = 0; // instruction pointer
+    /// the globals to continue from when running the next line
+    pub fn into_globals(self) -> Vec<Option<Object>> {
+        self.globals
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        self.ip = 0;
+        self.execute(None)
+    }
 
-        while ip < self.instructions.len() {
-            let instruction_address = ip;
-            ip += 1;
+    /// runs instructions until the end of the program, or when given a call depth until a
+    ///    function returns to that depth
+    fn execute(&mut self, return_depth: Option<usize>) -> Result<(), RuntimeError> {
+        while self.ip < self.instructions.len() {
+            let instruction_address = self.ip;
+            self.ip += 1;
 
             match self.instructions[instruction_address] {
                 0x01 => {
                     // OpConstant
-                    let const_index = convert_two_u8s_be_to_usize(self.instructions[ip], self.instructions[ip + 1]);
-                    ip += 2;
-                    self.push(self.constants[const_index].clone());
+                    let const_index = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                    self.ip += 2;
+                    let constant = self.constants[const_index].clone();
+                    // a string, array, or hash made by the compiler is still held to the host's
+                    //    limits, as it is in the evaluator
+                    if let Object::String(_) | Object::Array(_) | Object::Hash(_) = constant {
+                        self.check_size(&constant)?;
+                    }
+                    self.push(constant)?;
                 },
                 0x02 => {
                     // OpPop
-                    self.pop();
+                    self.pop()?;
                 },
                 0x03 => {
                     // OpAdd
-                    match (self.pop(), self.pop()) {
-                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(left + right)),
-                        _ => panic!("unhandled argument types to OpAdd"),
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(integer_arithmetic(Operator::Plus, left, right)?))?,
+                        (Object::String(right), Object::String(left)) => {
+                            let result = Object::String((left.to_string() + &right).into());
+                            self.allocate(&result)?;
+                            self.push(result)?;
+                        },
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Float(left + right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::Plus, &left, &right)),
+                        },
                     }
                 },
                 0x04 => {
                     // OpSub
-                    match (self.pop(), self.pop()) {
-                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(left - right)),
-                        _ => panic!("unhandled argument types to OpSub"),
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(integer_arithmetic(Operator::Minus, left, right)?))?,
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Float(left - right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::Minus, &left, &right)),
+                        },
                     }
                 },
                 0x05 => {
                     // OpMul
-                    match (self.pop(), self.pop()) {
-                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(left * right)),
-                        _ => panic!("unhandled argument types to OpMul"),
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(integer_arithmetic(Operator::Multiply, left, right)?))?,
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Float(left * right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::Multiply, &left, &right)),
+                        },
                     }
                 },
                 0x06 => {
                     // OpDiv
-                    match (self.pop(), self.pop()) {
-                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(left / right)),
-                        _ => panic!("unhandled argument types to OpDiv"),
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Integer(integer_arithmetic(Operator::Divide, left, right)?))?,
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Float(left / right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::Divide, &left, &right)),
+                        },
                     }
                 },
                 0x07 => {
                     // OpTrue
-                    self.push(Object::Boolean(true));
+                    self.push(Object::Boolean(true))?;
                 },
                 0x08 => {
                     // OpFalse
-                    self.push(Object::Boolean(false));
+                    self.push(Object::Boolean(false))?;
                 },
                 0x09 => {
                     // OpEquals
-                    match (self.pop(), self.pop()) {
-                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Boolean(left == right)),
-                        (Object::Boolean(right), Object::Boolean(left)) => self.push(Object::Boolean(left == right)),
-                        _ => panic!("unhandled argument types to OpEquals"),
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Boolean(left == right))?,
+                        (Object::Boolean(right), Object::Boolean(left)) => self.push(Object::Boolean(left == right))?,
+                        // any value can be compared with null, which is only equal to itself
+                        (right, left) if left == Object::Null || right == Object::Null => self.push(Object::Boolean(left == right))?,
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Boolean(left == right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::Equals, &left, &right)),
+                        },
                     }
                 },
                 0x0A => {
                     // OpNotEquals
-                    match (self.pop(), self.pop()) {
-                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Boolean(left != right)),
-                        (Object::Boolean(right), Object::Boolean(left)) => self.push(Object::Boolean(left != right)),
-                        _ => panic!("unhandled argument types to OpNotEquals"),
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Boolean(left != right))?,
+                        (Object::Boolean(right), Object::Boolean(left)) => self.push(Object::Boolean(left != right))?,
+                        (right, left) if left == Object::Null || right == Object::Null => self.push(Object::Boolean(left != right))?,
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Boolean(left != right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::NotEquals, &left, &right)),
+                        },
                     }
                 },
                 0x0B => {
                     // OpGreaterThan
-                    match (self.pop(), self.pop()) {
-                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Boolean(left > right)),
-                        _ => panic!("unhandled argument types to OpGreaterThan"),
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Boolean(left > right))?,
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Boolean(left > right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::GreaterThan, &left, &right)),
+                        },
                     }
                 },
                 0x0C => {
                     // OpMinus
-                    match self.pop() {
-                        Object::Integer(num) => self.push(Object::Integer(-num)),
-                        _ => panic!("unhandled arg type to OpMinus"),
+                    match self.pop()? {
+                        Object::Integer(num) => self.push(Object::Integer(integer_negate(num)?))?,
+                        Object::Float(num) => self.push(Object::Float(-num))?,
+                        operand => return Err(RuntimeError::InvalidPrefixOperand { prefix: Prefix::Minus, operand: operand.type_name() }),
                     }
                 },
                 0x0D => {
                     // OpBang
-                    match self.pop() {
-                        Object::Boolean(bool) => self.push(Object::Boolean(!bool)),
-                        _ => panic!("unhandled arg type to OpBang"),
+                    match self.pop()? {
+                        Object::Boolean(bool) => self.push(Object::Boolean(!bool))?,
+                        operand => return Err(RuntimeError::InvalidPrefixOperand { prefix: Prefix::Bang, operand: operand.type_name() }),
                     }
                 },
                 0x0E => {
                     // OpJumpNotTrue
-                    match self.pop() {
+                    match self.pop()? {
                         Object::Boolean(true) => {
-                            ip += 2; // don't jump, but skip the jump address
+                            self.ip += 2; // don't jump, but skip the jump address
                         },
-                        Object::Boolean(false) => {
-                            let jump_address = convert_two_u8s_be_to_usize(self.instructions[ip], self.instructions[ip + 1]);
-                            ip = jump_address;
+                        // like the evaluator, any condition other than true takes the else branch
+                        _ => {
+                            let jump_address = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                            self.ip = jump_address;
                         },
-                        _ => panic!("unhandled arg type to OpJumpNotTrue"),
                     }
 
                 },
                 0x0F => {
                     // OpJump
-                    let jump_address = convert_two_u8s_be_to_usize(self.instructions[ip], self.instructions[ip + 1]);
-                    ip = jump_address;
+                    let jump_address = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                    self.ip = jump_address;
                 },
                 0x10 => {
                     // OpSetGlobal
-                    let global_index = convert_two_u8s_be_to_usize(self.instructions[ip], self.instructions[ip + 1]);
-                    ip += 2;
+                    let global_index = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                    self.ip += 2;
 
-                    let value = self.pop();
+                    let value = self.pop()?;
 
-                    self.globals[global_index] = value;
+                    if global_index >= self.globals.len() {
+                        self.globals.resize(global_index + 1, None);
+                    }
+                    self.globals[global_index] = Some(value);
                 },
                 0x11 => {
                     // OpGetGlobal
-                    let global_index = convert_two_u8s_be_to_usize(self.instructions[ip], self.instructions[ip + 1]);
-                    ip += 2;
+                    let global_index = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                    self.ip += 2;
+
+                    // a global whose `let` was skipped, such as one inside an `if` which wasn't
+                    //    taken, is not defined as in the evaluator
+                    let value = match self.globals.get(global_index) {
+                        Some(Some(value)) => value.clone(),
+                        _ => return Err(RuntimeError::UnknownIdentifier(self.global_name(global_index))),
+                    };
+                    self.push(initialized(value)?)?;
+                },
+                0x12 => {
+                    // OpGetBuiltin
+                    let builtin_index = self.instructions[self.ip] as usize;
+                    self.ip += 1;
 
-                    self.push(self.globals[global_index].clone());
+                    self.push(Object::Builtin(builtin_index))?;
                 },
+                0x13 => {
+                    // OpCall
+                    let argument_count = self.instructions[self.ip] as usize;
+                    self.ip += 1;
+
+                    let function = self.stack[self.sp - argument_count - 1].clone();
+
+                    match function {
+                        Object::CompiledFunction { instructions, num_locals, num_parameters } => {
+                            check_argument_count(num_parameters, argument_count)?;
+                            self.enter_function(instructions, num_locals, argument_count)?;
+                        },
+                        Object::Builtin(builtin_index) => {
+                            let arguments = self.stack[self.sp - argument_count..self.sp].to_vec();
+                            self.sp -= argument_count + 1;
+
+                            let result = builtins::call(builtin_index, &arguments, self)?;
+                            self.host.heap_mut().record_without_line(&result);
+                            self.push(result)?;
+                        },
+                        Object::Native(native) => {
+                            let arguments = self.stack[self.sp - argument_count..self.sp].to_vec();
+                            self.sp -= argument_count + 1;
+
+                            let result = native.call(&arguments)?;
+                            self.allocate(&result)?;
+                            self.push(result)?;
+                        },
+                        obj => return Err(RuntimeError::NotCallable(obj.type_name())),
+                    }
+                },
+                0x14 | 0x15 => {
+                    // OpReturnValue, OpReturn
+                    let return_value = match self.instructions[instruction_address] {
+                        0x14 => self.pop()?,
+                        _ => Object::Null,
+                    };
+
+                    let frame = match self.frames.pop() {
+                        Some(frame) => frame,
+                        None => {
+                            // like in the evaluator, a return outside of a function ends the program
+                            //    with the returned value
+                            self.push(return_value)?;
+                            self.pop()?;
+                            return Ok(());
+                        },
+                    };
+                    // drop the locals and the function itself from the stack
+                    self.sp = self.base_pointer - 1;
+                    self.instructions = frame.instructions;
+                    self.ip = frame.ip;
+                    self.base_pointer = frame.base_pointer;
+
+                    self.push(return_value)?;
+
+                    if return_depth == Some(self.frames.len()) {
+                        return Ok(());
+                    }
+                },
+                0x16 => {
+                    // OpGetLocal
+                    let local_index = self.instructions[self.ip] as usize;
+                    self.ip += 1;
+
+                    let value = self.stack[self.base_pointer + local_index].clone();
+                    self.push(initialized(value)?)?;
+                },
+                0x17 => {
+                    // OpSetLocal
+                    let local_index = self.instructions[self.ip] as usize;
+                    self.ip += 1;
+
+                    let value = self.pop()?;
+
+                    self.stack[self.base_pointer + local_index] = value;
+                },
+                0x18 => {
+                    // OpNull
+                    self.push(Object::Null)?;
+                },
+                0x19 => {
+                    // OpArray
+                    let element_count = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                    self.ip += 2;
+
+                    if element_count > self.sp {
+                        return Err(RuntimeError::StackUnderflow);
+                    }
+                    let elements = self.stack[self.sp - element_count..self.sp].to_vec();
+                    self.sp -= element_count;
+
+                    let array = Object::Array(Rc::new(elements));
+                    self.allocate(&array)?;
+                    self.push(array)?;
+                },
+                0x1A => {
+                    // OpIndex
+                    let index = self.pop()?;
+                    let left = self.pop()?;
+
+                    self.push(index_object(&left, &index)?)?;
+                },
+                0x1B => {
+                    // OpInterpolate
+                    let part_count = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                    self.ip += 2;
+
+                    if part_count > self.sp {
+                        return Err(RuntimeError::StackUnderflow);
+                    }
+                    let string = interpolate(&self.stack[self.sp - part_count..self.sp]);
+                    self.sp -= part_count;
+
+                    self.allocate(&string)?;
+                    self.push(string)?;
+                },
+                0x1C => {
+                    // OpGreaterEqual
+                    match (self.pop()?, self.pop()?) {
+                        (Object::Integer(right), Object::Integer(left)) => self.push(Object::Boolean(left >= right))?,
+                        (right, left) => match float_operands(&left, &right) {
+                            Some((left, right)) => self.push(Object::Boolean(left >= right))?,
+                            None => return Err(RuntimeError::invalid_infix_operands(Operator::GreaterThanOrEqual, &left, &right)),
+                        },
+                    }
+                },
+                0x1D => {
+                    // OpAndOperand, the operand is left on the stack for the jump which follows
+                    boolean_operand(Operator::And, &self.stack[self.sp - 1])?;
+                },
+                0x1E => {
+                    // OpOrOperand
+                    boolean_operand(Operator::Or, &self.stack[self.sp - 1])?;
+                },
+                0x1F => {
+                    // OpHash
+                    let value_count = convert_two_u8s_be_to_usize(self.instructions[self.ip], self.instructions[self.ip + 1]);
+                    self.ip += 2;
+
+                    if value_count > self.sp {
+                        return Err(RuntimeError::StackUnderflow);
+                    }
+                    let pairs = self.stack[self.sp - value_count..self.sp]
+                        .chunks(2)
+                        .map(|pair| (pair[0].clone(), pair[1].clone()))
+                        .collect();
+                    self.sp -= value_count;
+
+                    let hash = build_hash(pairs)?;
+                    self.allocate(&hash)?;
+                    self.push(hash)?;
+                },
+                // the compiler only emits valid instructions
                 _ => panic!("unhandled instruction"),
             }
         }
+
+        Ok(())
     }
 
-    fn push(&mut self, obj: Object) {
+    fn enter_function(&mut self, instructions: Rc<Vec<u8>>, num_locals: usize, argument_count: usize) -> Result<(), RuntimeError> {
+        // deep recursion runs out of room for the locals before anything else
+        if self.sp - argument_count + num_locals > STACK_SIZE {
+            return Err(RuntimeError::StackOverflow);
+        }
+
+        self.frames.push(Frame {
+            instructions: std::mem::replace(&mut self.instructions, instructions),
+            ip: self.ip,
+            base_pointer: self.base_pointer,
+        });
+        // the arguments are already on the stack as the first locals, the rest
+        //    of the locals are reserved above them
+        self.base_pointer = self.sp - argument_count;
+        self.sp = self.base_pointer + num_locals;
+        self.ip = 0;
+
+        Ok(())
+    }
+
+    fn push(&mut self, obj: Object) -> Result<(), RuntimeError> {
+        if self.sp >= STACK_SIZE {
+            return Err(RuntimeError::StackOverflow);
+        }
+
         self.stack[self.sp] = obj;
-        self.sp += 1; // ignoring the potential stack overflow
+        self.sp += 1;
+
+        Ok(())
     }
 
-    fn pop(&mut self) -> Object {
-        // ignoring the potential of stack underflow
-        // cloning rather than mem::replace to support the last_popped method for testing
+    fn pop(&mut self) -> Result<Object, RuntimeError> {
+        if self.sp == 0 {
+            return Err(RuntimeError::StackUnderflow);
+        }
+
+        // cloning rather than mem::replace to support the last_popped method
         let obj = self.stack[self.sp - 1].clone();
         self.sp -= 1;
 
-        obj
+        Ok(obj)
+    }
+
+    /// runs a script, then calls its `main` function if it defines one, returning the value of
+    ///    `main` or of the script when there is none, as eval::run_main does for the evaluator
+    pub fn run_main(&mut self, args: &[String]) -> Result<Object, RuntimeError> {
+        self.run()?;
+        let main = match self.global_names.iter().position(|name| name == "main").and_then(|index| self.globals.get(index)) {
+            Some(Some(Object::Uninitialized(_))) | Some(None) | None => return Ok(self.last_popped().clone()),
+            Some(Some(main)) => main.clone(),
+        };
+
+        let arguments = match &main {
+            Object::CompiledFunction { num_parameters: 0, .. } => vec![],
+            _ => vec![Object::Array(Rc::new(args.iter().map(|arg| Object::from(arg.as_str())).collect()))],
+        };
+        self.call(main, arguments)
+    }
+
+    fn global_name(&self, index: usize) -> String {
+        self.global_names.get(index).cloned().unwrap_or_else(|| format!("global {}", index))
     }
 
-    fn last_popped(&self) -> &Object {
+    /// the value of the last statement run, which the repl prints
+    pub fn last_popped(&self) -> &Object {
         // the stack pointer points to the next "free" space, which also holds the most recently popped element
         &self.stack[self.sp]
     }
+
+    /// the objects allocated by the program so far, constants such as string literals are
+    ///    shared rather than allocated each time they are pushed so aren't counted
+    pub fn heap(&self) -> &HeapProfile {
+        self.host.heap()
+    }
+
+    // checks the object the program built is within the limits, and records it in the heap profile
+    fn allocate(&mut self, object: &Object) -> Result<(), RuntimeError> {
+        self.check_size(object)?;
+        self.host.heap_mut().record_without_line(object);
+
+        Ok(())
+    }
+}
+
+/// defines a global which calls the rust function, for the vm's equivalent of
+///    Env::register_native, the symbol table and globals are then used to compile and run
+///    programs as with Compiler::new_with_state and VM::new_with_global_store
+pub fn register_native(
+    symbol_table: &mut SymbolTable,
+    globals: &mut Vec<Option<Object>>,
+    name: &str,
+    function: impl Fn(&[Object]) -> Result<Object, RuntimeError> + 'static,
+) {
+    set_global(symbol_table, globals, name, Object::Native(NativeFunction::new(name, function)));
+}
+
+/// defines or replaces a global, for the vm's equivalent of Env::set_global
+pub fn set_global(symbol_table: &mut SymbolTable, globals: &mut Vec<Option<Object>>, name: &str, value: Object) {
+    let index = match symbol_table.resolve(&name.to_string()) {
+        Some(Symbol { scope: SymbolScope::Global, index }) => index,
+        _ => symbol_table.define(name.to_string()).index,
+    } as usize;
+    if index >= globals.len() {
+        globals.resize(index + 1, None);
+    }
+    globals[index] = Some(value);
+}
+
+/// the value of a global after a program has run, for the vm's equivalent of Env::get_global,
+///    None for a global which was declared but never assigned
+pub fn get_global<'a>(symbol_table: &SymbolTable, globals: &'a [Option<Object>], name: &str) -> Option<&'a Object> {
+    match symbol_table.resolve(&name.to_string()) {
+        Some(Symbol { scope: SymbolScope::Global, index }) => match globals.get(index as usize) {
+            Some(Some(Object::Uninitialized(_))) => None,
+            value => value.and_then(Option::as_ref),
+        },
+        _ => None,
+    }
+}
+
+// the value of a variable which is read, an error if it was declared and never assigned
+fn initialized(value: Object) -> Result<Object, RuntimeError> {
+    match value {
+        Object::Uninitialized(name) => Err(RuntimeError::UninitializedVariable(name.to_string())),
+        value => Ok(value),
+    }
+}
+
+fn check_argument_count(num_parameters: usize, argument_count: usize) -> Result<(), RuntimeError> {
+    if argument_count == num_parameters {
+        Ok(())
+    } else {
+        Err(RuntimeError::WrongArgumentCount { expected: num_parameters, found: argument_count })
+    }
+}
+
+impl Context for VM {
+    fn host(&mut self) -> &mut Host {
+        &mut self.host
+    }
+
+    fn call(&mut self, function: Object, arguments: Vec<Object>) -> Result<Object, RuntimeError> {
+        match function {
+            Object::Builtin(index) => builtins::call(index, &arguments, self),
+            Object::Native(native) => native.call(&arguments),
+            Object::CompiledFunction { instructions, num_locals, num_parameters } => {
+                check_argument_count(num_parameters, arguments.len())?;
+
+                // set up the stack as OpCall would, then run until the function returns
+                //    back to the builtin which called it
+                let return_depth = self.frames.len();
+                self.push(Object::Null)?; // in place of the function, which is not read again
+                let argument_count = arguments.len();
+                for argument in arguments {
+                    self.push(argument)?;
+                }
+                self.enter_function(instructions, num_locals, argument_count)?;
+                self.execute(Some(return_depth))?;
+
+                Ok(self.pop()?)
+            },
+            obj => Err(RuntimeError::NotCallable(obj.type_name())),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::compiler::compile_from_source;
+    use crate::compiler::{compile_from_source, compile_from_source_optimized, Compiler};
+    use crate::eval::HashKey;
+    use crate::builtins::{CapturedOutput, Limits};
 
     #[test]
     fn run_infix() {
@@ -194,6 +598,28 @@ mod tests {
         assert_last_popped("6 / 2;", Object::Integer(3));
     }
 
+    #[test]
+    fn run_float() {
+        assert_last_popped("1.5;", Object::Float(1.5));
+        assert_last_popped("-1.5;", Object::Float(-1.5));
+        assert_last_popped("1 + 0.5;", Object::Float(1.5));
+        assert_last_popped("0.5 - 1;", Object::Float(-0.5));
+        assert_last_popped("0.5 * 4;", Object::Float(2.0));
+        assert_last_popped("3 / 2.0;", Object::Float(1.5));
+        assert_last_popped("1.5 > 1;", Object::Boolean(true));
+        assert_last_popped("1 < 0.5;", Object::Boolean(false));
+        assert_last_popped("2.0 == 2;", Object::Boolean(true));
+        assert_last_popped("2.0 != 2;", Object::Boolean(false));
+    }
+
+    #[test]
+    fn run_string() {
+        assert_last_popped(r#""monkey";"#, Object::String("monkey".into()));
+        assert_last_popped(r#""mon" + "key";"#, Object::String("monkey".into()));
+        assert_last_popped(r#""mon" + "key" + "banana";"#, Object::String("monkeybanana".into()));
+        assert_last_popped(r#"let s = "a"; len(s + "bc");"#, Object::Integer(3));
+    }
+
     #[test]
     fn run_bool() {
         assert_last_popped("true;", Object::Boolean(true));
@@ -228,6 +654,48 @@ mod tests {
         assert_last_popped("1 < 2;", Object::Boolean(true));
     }
 
+    #[test]
+    fn run_less_greater_equal() {
+        assert_last_popped("1 <= 1;", Object::Boolean(true));
+        assert_last_popped("1 <= 2;", Object::Boolean(true));
+        assert_last_popped("2 <= 1;", Object::Boolean(false));
+        assert_last_popped("1 >= 1;", Object::Boolean(true));
+        assert_last_popped("2 >= 1;", Object::Boolean(true));
+        assert_last_popped("1 >= 2;", Object::Boolean(false));
+        assert_last_popped("1.5 <= 2;", Object::Boolean(true));
+        assert_last_popped("2 >= 2.5;", Object::Boolean(false));
+    }
+
+    #[test]
+    fn run_nan_comparisons_match_eval() {
+        use crate::{desugar::desugar, eval::{eval_return_scope, Env}, lexer::lex, parser::parse};
+
+        // every comparison with NaN is false, including the ones which hold for all other numbers
+        let nan = "let nan = 0.0 / 0.0;";
+        for comparison in ["nan <= 1;", "nan >= 1;", "1 <= nan;", "1 >= nan;", "nan < nan;", "nan > nan;", "nan == nan;"] {
+            let input = format!("{} {}", nan, comparison);
+            let evaluated = eval_return_scope(&desugar(parse(&mut lex(&input).unwrap()).unwrap()), &mut Env::new());
+            assert_eq!(Ok(Object::Boolean(false)), evaluated, "{}", comparison);
+            assert_last_popped(&input, Object::Boolean(false));
+        }
+    }
+
+    #[test]
+    fn run_and_or() {
+        assert_last_popped("true && true;", Object::Boolean(true));
+        assert_last_popped("true && false;", Object::Boolean(false));
+        assert_last_popped("false && true;", Object::Boolean(false));
+        assert_last_popped("false || true;", Object::Boolean(true));
+        assert_last_popped("true || false;", Object::Boolean(true));
+        assert_last_popped("false || false;", Object::Boolean(false));
+        assert_last_popped("1 < 2 && 2 < 3;", Object::Boolean(true));
+        assert_last_popped("false && 5;", Object::Boolean(false));
+        assert_last_popped("true || 5;", Object::Boolean(true));
+        assert_run_error("5 && true;", RuntimeError::InvalidLogicalOperand { operator: Operator::And, operand: "integer" });
+        assert_run_error("true && 5;", RuntimeError::InvalidLogicalOperand { operator: Operator::And, operand: "integer" });
+        assert_run_error("false || null;", RuntimeError::InvalidLogicalOperand { operator: Operator::Or, operand: "null" });
+    }
+
     #[test]
     fn run_prefix() {
         assert_last_popped("-1;", Object::Integer(-1));
@@ -243,18 +711,441 @@ mod tests {
         assert_last_popped("if (false) { 10; } else { 20; };", Object::Integer(20));
     }
 
+    #[test]
+    fn run_ternary() {
+        assert_last_popped("1 < 2 ? 10 : 11;", Object::Integer(10));
+        assert_last_popped("let x = 3; x > 5 ? \"big\" : x > 1 ? \"medium\" : \"small\";", Object::String("medium".into()));
+        assert_last_popped("let f = fn(n) { n == 0 ? 1 : n * f(n - 1); }; f(5);", Object::Integer(120));
+    }
+
     #[test]
     fn run_variable_declaration() {
         assert_last_popped("let one = 1; one;", Object::Integer(1));
         assert_last_popped("let one = 1; let two = one + one; one + two;", Object::Integer(3));
     }
 
+    #[test]
+    fn run_many_globals() {
+        // more globals than the vm used to have room for, named ga, gb, ..., gaa, gab, ...
+        let name = |mut i: usize| {
+            let mut name = String::from("g");
+            loop {
+                name.insert(1, (b'a' + (i % 26) as u8) as char);
+                if i < 26 {
+                    return name;
+                }
+                i = i / 26 - 1;
+            }
+        };
+        let input = (0..3000).map(|i| format!("let {} = {};", name(i), i)).collect::<String>()
+            + &format!("{} + {};", name(0), name(2999));
+
+        assert_last_popped(&input, Object::Integer(2999));
+    }
+
+    #[test]
+    fn run_lines() {
+        let mut compiler = Compiler::new_with_state(SymbolTable::new(), Vec::new());
+        let mut vm = VM::new_with_global_store(compiler.compile_line("let one = 1;").unwrap(), Vec::new());
+        vm.run().unwrap();
+
+        let mut vm = VM::new_with_global_store(compiler.compile_line("one + 1;").unwrap(), vm.into_globals());
+        vm.run().unwrap();
+        assert_eq!(&Object::Integer(2), vm.last_popped());
+
+        // a line which pops nothing has no value
+        let mut vm = VM::new_with_global_store(compiler.compile_line("").unwrap(), vm.into_globals());
+        vm.run().unwrap();
+        assert_eq!(&Object::Null, vm.last_popped());
+    }
+
+    #[test]
+    fn run_globals_from_host() {
+        let mut symbol_table = SymbolTable::new();
+        let mut globals = Vec::new();
+        set_global(&mut symbol_table, &mut globals, "limit", Object::Integer(1));
+        // setting a global again replaces it rather than defining another
+        set_global(&mut symbol_table, &mut globals, "limit", Object::Integer(10));
+        assert_eq!(1, globals.len());
+
+        let mut compiler = Compiler::new_with_state(symbol_table, Vec::new());
+        let mut vm = VM::new_with_global_store(compiler.compile_line("let doubled = limit * 2;").unwrap(), globals);
+        vm.run().unwrap();
+        let (symbol_table, _) = compiler.into_state();
+        let globals = vm.into_globals();
+
+        assert_eq!(Some(&Object::Integer(20)), get_global(&symbol_table, &globals, "doubled"));
+        assert_eq!(None, get_global(&symbol_table, &globals, "missing"));
+    }
+
+    #[test]
+    fn run_native() {
+        let mut symbol_table = SymbolTable::new();
+        let mut globals = Vec::new();
+        register_native(&mut symbol_table, &mut globals, "double", |arguments| match arguments {
+            [Object::Integer(num)] => Ok(Object::Integer(num * 2)),
+            _ => Err(RuntimeError::WrongArgumentCount { expected: 1, found: arguments.len() }),
+        });
+
+        let mut compiler = Compiler::new_with_state(symbol_table, Vec::new());
+        let mut vm = VM::new_with_global_store(compiler.compile_line("double(21);").unwrap(), globals);
+        vm.run().unwrap();
+        assert_eq!(&Object::Integer(42), vm.last_popped());
+
+        let mut vm = VM::new_with_global_store(compiler.compile_line("double();").unwrap(), vm.into_globals());
+        assert_eq!(Err(RuntimeError::WrongArgumentCount { expected: 1, found: 0 }), vm.run());
+    }
+
+    #[test]
+    fn run_assign() {
+        assert_last_popped("let one = 1; one = one + 1; one;", Object::Integer(2));
+    }
+
+    #[test]
+    fn run_declare() {
+        assert_last_popped("let one; one = 1; one;", Object::Integer(1));
+        assert_last_popped("let f = fn() { let x; x = 2; x; }; f();", Object::Integer(2));
+        // like the evaluator, reading a variable before it is assigned is an error
+        assert_run_error("let one; one;", RuntimeError::UninitializedVariable(String::from("one")));
+        assert_run_error("let f = fn() { let x; x + 1; }; f();", RuntimeError::UninitializedVariable(String::from("x")));
+    }
+
+    #[test]
+    fn run_builtin() {
+        assert_last_popped("str(5);", Object::String("5".into()));
+        assert_last_popped("puts(1, true);", Object::Null);
+        assert_last_popped("let x = str(1 + 2); x;", Object::String("3".into()));
+    }
+
+    #[test]
+    fn run_arrays() {
+        let array = |elements: Vec<Object>| Object::Array(Rc::new(elements));
+
+        assert_last_popped("[];", array(vec![]));
+        assert_last_popped(
+            "let two = 2; [1, two, two * 2];",
+            array(vec![Object::Integer(1), Object::Integer(2), Object::Integer(4)])
+        );
+        assert_last_popped(
+            "[[1], [], [true, \"a\"]];",
+            array(vec![
+                array(vec![Object::Integer(1)]),
+                array(vec![]),
+                array(vec![Object::Boolean(true), Object::String("a".into())]),
+            ])
+        );
+        // elements are evaluated in order, inside functions as well as at the top level
+        assert_last_popped(
+            "let pair = fn(a, b) { [b, a]; }; pair(1, if (true) { 2; });",
+            array(vec![Object::Integer(2), Object::Integer(1)])
+        );
+        assert_last_popped("let f = fn() { [fn() { 3; }]; }; first(f())();", Object::Integer(3));
+        assert_last_popped("let xs = [1, 2]; let ys = xs; xs = push(xs, 3); len(ys);", Object::Integer(2));
+    }
+
+    #[test]
+    fn run_index() {
+        assert_last_popped("[1, 2, 3][0];", Object::Integer(1));
+        assert_last_popped("let xs = [1, 2, 3]; xs[1 + 1];", Object::Integer(3));
+        assert_last_popped("let f = fn(i) { [[1, 2], [3]][i + 1][i]; }; f(0);", Object::Integer(3));
+        assert_last_popped("let f = fn() { [fn(x) { x * 2; }]; }; f()[0](4);", Object::Integer(8));
+        assert_last_popped("[1, 2, 3][3];", Object::Null);
+        assert_last_popped("[1, 2, 3][-1];", Object::Null);
+        assert_last_popped("[][0];", Object::Null);
+
+        assert_run_error("1[0];", RuntimeError::InvalidIndex { left: "integer", index: "integer" });
+        assert_run_error("[1][true];", RuntimeError::InvalidIndex { left: "array", index: "boolean" });
+
+        assert_last_popped(r#"{"a": 1, 2: true}["a"];"#, Object::Integer(1));
+        assert_last_popped(r#"let f = fn(key) { {"a": 1, 2: true}[key + 1]; }; f(1);"#, Object::Boolean(true));
+        assert_last_popped(r#"{"a": {"b": [3]}}["a"]["b"][0];"#, Object::Integer(3));
+        assert_last_popped(r#"{"a": 1}["b"];"#, Object::Null);
+        assert_run_error("{}[[]];", RuntimeError::UnusableHashKey("array"));
+    }
+
+    #[test]
+    fn run_interpolation() {
+        assert_last_popped(r#"let x = 2; "total: ${x + 1}";"#, Object::String("total: 3".into()));
+        assert_last_popped(r#""${1.5} ${true} ${null} ${[1, "a"]} ${"s"}";"#, Object::String("1.5 true null [1, a] s".into()));
+        assert_last_popped(r#"let f = fn(name) { "hi ${name}"; }; "${f("${1}")}!";"#, Object::String("hi 1!".into()));
+        assert_run_error(r#""${1 + true}";"#, RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "integer", right: "boolean" });
+
+        let host = Host::new(Box::new(std::io::sink()))
+            .with_limits(Limits { max_string_length: Some(3), ..Limits::default() });
+        let mut vm = VM::with_host(compile_from_source(r#""${1}${2}${3}"; "${1}${2}${3}${4}";"#).unwrap(), host);
+        assert_eq!(Err(RuntimeError::ValueTooLarge { kind: "string", limit: 3 }), vm.run());
+    }
+
+    #[test]
+    fn run_main() {
+        let run = |input: &str| {
+            let mut vm = VM::with_output(compile_from_source(input).unwrap(), Box::new(std::io::sink()));
+            vm.run_main(&[String::from("a"), String::from("b")])
+        };
+
+        assert_eq!(Ok(Object::Integer(2)), run("let main = fn(args) { len(args); };"));
+        assert_eq!(Ok(Object::String("b".into())), run("let main = fn(args) { last(args); };"));
+        assert_eq!(Ok(Object::Integer(7)), run("let main = fn() { 7; };"));
+        assert_eq!(Ok(Object::Integer(3)), run("let helper = fn(x) { x + 1; }; let main = fn() { helper(2); };"));
+        // without a main function the value of the script is returned
+        assert_eq!(Ok(Object::Integer(3)), run("1 + 2;"));
+        assert_eq!(Ok(Object::Integer(3)), run("let f = fn() { let main = 1; main; }; f() + 2;"));
+        assert_eq!(Err(RuntimeError::NotCallable("integer")), run("let main = 5;"));
+    }
+
+    #[test]
+    fn run_heap_profile() {
+        let input = r#"let s = "a"; let t = s + s + s; let a = push([], t); memory_stats()[1];"#;
+        let mut vm = VM::with_output(compile_from_source(input).unwrap(), Box::new(std::io::sink()));
+        vm.run().unwrap();
+
+        // "aa" and then "aaa", the literal is a constant which isn't allocated when it is pushed
+        assert_eq!("[string, 2, 5]", vm.last_popped().to_string());
+        // the one push returns and the one memory_stats returns, the empty array is a constant too
+        assert_eq!(
+            vec![("array", 2), ("string", 2)],
+            vm.heap().by_kind().into_iter().map(|(kind, allocations)| (kind, allocations.count)).collect::<Vec<_>>()
+        );
+        assert!(vm.heap().by_line().is_empty());
+    }
+
+    #[test]
+    fn run_hashes() {
+        let hash = |pairs: Vec<(HashKey, Object)>| Object::Hash(Rc::new(pairs.into_iter().collect()));
+
+        assert_last_popped("{};", hash(vec![]));
+        assert_last_popped(
+            r#"let two = 2; {"one": 1, two: two * 2, true: {}, "one": 3};"#,
+            hash(vec![
+                (HashKey::String("one".into()), Object::Integer(3)),
+                (HashKey::Integer(2), Object::Integer(4)),
+                (HashKey::Boolean(true), hash(vec![])),
+            ])
+        );
+        assert_last_popped(
+            "let f = fn(k) { {k: [k]}; }; f(1);",
+            hash(vec![(HashKey::Integer(1), Object::Array(Rc::new(vec![Object::Integer(1)])))])
+        );
+        assert_run_error("{[1]: 2};", RuntimeError::UnusableHashKey("array"));
+    }
+
+    #[test]
+    fn run_array_limits() {
+        let host = Host::new(Box::new(std::io::sink()))
+            .with_limits(Limits { max_array_length: Some(2), ..Limits::default() });
+        let mut vm = VM::with_host(compile_from_source("[1, 2]; [1, 2, 3];").unwrap(), host);
+
+        assert_eq!(Err(RuntimeError::ValueTooLarge { kind: "array", limit: 2 }), vm.run());
+    }
+
+    #[test]
+    fn run_hash_limits() {
+        let host = Host::new(Box::new(std::io::sink()))
+            .with_limits(Limits { max_hash_length: Some(1), ..Limits::default() });
+        let mut vm = VM::with_host(compile_from_source("{1: 2}; {1: 2, 3: 4};").unwrap(), host);
+
+        assert_eq!(Err(RuntimeError::ValueTooLarge { kind: "hash", limit: 1 }), vm.run());
+    }
+
+    #[test]
+    fn run_limits_on_folded_constants() {
+        // the optimizer builds the string at compile time, which the limits still apply to
+        let host = Host::new(Box::new(std::io::sink()))
+            .with_limits(Limits { max_string_length: Some(3), ..Limits::default() });
+        let mut vm = VM::with_host(compile_from_source_optimized(r#""ab" + "cd";"#).unwrap(), host);
+
+        assert_eq!(Err(RuntimeError::ValueTooLarge { kind: "string", limit: 3 }), vm.run());
+    }
+
+    #[test]
+    fn run_array_builtins() {
+        let array = |elements: &[i32]| Object::Array(Rc::new(elements.iter().map(|num| Object::Integer(*num)).collect()));
+
+        assert_last_popped("len([1, 2, 3]);", Object::Integer(3));
+        assert_last_popped("first([1, 2, 3]);", Object::Integer(1));
+        assert_last_popped("last([1, 2, 3]);", Object::Integer(3));
+        assert_last_popped("rest([1, 2, 3]);", array(&[2, 3]));
+        assert_last_popped("push([1, 2, 3], 4);", array(&[1, 2, 3, 4]));
+        // builtins copy rather than change the array they are given
+        assert_last_popped("let xs = [1, 2, 3]; push(xs, 4); xs;", array(&[1, 2, 3]));
+
+        // arrays from the host work the same as those built by the program
+        let mut symbol_table = SymbolTable::new();
+        let mut globals = Vec::new();
+        set_global(&mut symbol_table, &mut globals, "xs", array(&[1, 2, 3]));
+        let mut compiler = Compiler::new_with_state(symbol_table, Vec::new());
+        let mut vm = VM::new_with_global_store(compiler.compile_line("push(xs, len(xs) + 1);").unwrap(), globals);
+        vm.run().unwrap();
+        assert_eq!(&array(&[1, 2, 3, 4]), vm.last_popped());
+    }
+
+    #[test]
+    fn run_puts_output() {
+        let output = CapturedOutput::default();
+        let mut vm = VM::with_output(compile_from_source("puts(1, 2 > 1); puts();").unwrap(), Box::new(output.clone()));
+        vm.run().unwrap();
+
+        assert_eq!("1\ntrue\n", output.contents());
+    }
+
+    #[test]
+    fn run_errors() {
+        assert_run_error("1 + true;", RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "integer", right: "boolean" });
+        assert_run_error(r#""a" - "b";"#, RuntimeError::InvalidInfixOperands { operator: Operator::Minus, left: "string", right: "string" });
+        assert_run_error(r#""a" + 1;"#, RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "string", right: "integer" });
+        assert_run_error("-true;", RuntimeError::InvalidPrefixOperand { prefix: Prefix::Minus, operand: "boolean" });
+        assert_run_error("first(1);", RuntimeError::BuiltinArgumentType { name: "first", expected: "array", found: "integer" });
+        assert_run_error("str(1, 2);", RuntimeError::BuiltinArgumentCount { name: "str", min: 1, max: Some(1), found: 2 });
+        assert_run_error("let x = 1; x();", RuntimeError::NotCallable("integer"));
+        assert_run_error("let f = fn(a, b) { a; }; f(1);", RuntimeError::WrongArgumentCount { expected: 2, found: 1 });
+        assert_run_error("5 / 0;", RuntimeError::DivisionByZero);
+        assert_run_error("2147483647 + 1;", RuntimeError::IntegerOverflow(String::from("2147483647 + 1")));
+        assert_run_error("65536 * 65536;", RuntimeError::IntegerOverflow(String::from("65536 * 65536")));
+        assert_run_error("let min = -2147483647 - 1; -min;", RuntimeError::IntegerOverflow(String::from("-(-2147483648)")));
+    }
+
+    #[test]
+    fn run_functions() {
+        assert_last_popped("let add = fn(a, b) { a + b; }; add(1, 2);", Object::Integer(3));
+        assert_last_popped("let f = fn() { let x = 2; let y; y = x * 3; y; }; f();", Object::Integer(6));
+        assert_last_popped("let g = 10; let f = fn(a) { a + g; }; f(1) + f(2);", Object::Integer(23));
+        assert_last_popped("let f = fn() { }; f();", Object::Null);
+        assert_last_popped("let f = fn() { let x = 1; }; f();", Object::Null);
+        assert_last_popped("let one = fn() { 1; }; let two = fn() { one() + one(); }; two();", Object::Integer(2));
+        assert_last_popped("fn(x) { x * 2; }(5);", Object::Integer(10));
+        assert_last_popped("let one = fn() { 1; }; let get = fn() { one; }; get()();", Object::Integer(1));
+    }
+
+    #[test]
+    fn run_recursive_function() {
+        assert_last_popped(
+            "let fact = fn(n) { if (n < 2) { 1; } else { n * fact(n - 1); }; }; fact(5);",
+            Object::Integer(120)
+        );
+        assert_last_popped(
+            "let fib = fn(n) { if (n < 2) { n; } else { fib(n - 1) + fib(n - 2); }; }; fib(10);",
+            Object::Integer(55)
+        );
+    }
+
+    #[test]
+    fn run_return() {
+        assert_last_popped("let f = fn() { return 1; 2; }; f();", Object::Integer(1));
+        assert_last_popped("let f = fn(n) { if (n > 0) { return 1; }; 0; }; f(1) * 10 + f(0);", Object::Integer(10));
+        assert_last_popped(
+            "let f = fn(n) { if (n > 0) { if (n > 1) { return 2; } else { return 1; }; }; let x = 0; x; }; f(2) * 100 + f(1) * 10 + f(0);",
+            Object::Integer(210)
+        );
+        // a return outside of a function ends the program
+        assert_last_popped("1; return 2; 3;", Object::Integer(2));
+    }
+
+    #[test]
+    fn run_null() {
+        assert_last_popped("null;", Object::Null);
+        assert_last_popped("null == null;", Object::Boolean(true));
+        assert_last_popped("let a; a = null; a != null;", Object::Boolean(false));
+        assert_last_popped("1 == null;", Object::Boolean(false));
+        assert_last_popped("null != true;", Object::Boolean(true));
+        assert_last_popped("if (false) { 1; } == null;", Object::Boolean(true));
+    }
+
+    #[test]
+    fn run_if_without_value() {
+        assert_last_popped("if (false) { 10; };", Object::Null);
+        assert_last_popped("let f = fn(a) { if (a) { let b = 1; }; a; }; f(true);", Object::Boolean(true));
+    }
+
+    #[test]
+    fn run_global_never_set() {
+        // the global's `let` is skipped, as in the evaluator it is not defined
+        assert_run_error("if (false) { let x = 1; }; x;", RuntimeError::UnknownIdentifier(String::from("x")));
+        assert_run_error("if (false) { let x = 1; }; let y = 2; x;", RuntimeError::UnknownIdentifier(String::from("x")));
+        assert_last_popped("if (false) { let x = 1; }; let y = 2; y;", Object::Integer(2));
+    }
+
+    #[test]
+    fn call_from_builtin_context() {
+        let mut vm = VM::new(compile_from_source(r#"
+            let double = fn(x) { x * 2; };
+            let quadruple = fn(x) { double(double(x)); };
+            let fail = fn() { 1 + true; };
+            1000;
+        "#).unwrap());
+        vm.run().unwrap();
+        let global = |index: usize| vm.globals[index].clone().unwrap();
+        let (double, quadruple, fail) = (global(0), global(1), global(2));
+
+        // a builtin re-enters the vm to call the function, and gets control back once it returns
+        assert_eq!(Ok(Object::Integer(6)), vm.call(double.clone(), vec![Object::Integer(3)]));
+        assert_eq!(Ok(Object::Integer(12)), vm.call(quadruple, vec![Object::Integer(3)]));
+        assert_eq!(Ok(Object::Integer(3)), vm.call(Object::Builtin(0), vec![Object::String("abc".into())]));
+        assert_eq!(Err(RuntimeError::WrongArgumentCount { expected: 1, found: 0 }), vm.call(double, vec![]));
+        assert_eq!(
+            Err(RuntimeError::InvalidInfixOperands { operator: Operator::Plus, left: "integer", right: "boolean" }),
+            vm.call(fail, vec![])
+        );
+    }
+
+    #[test]
+    fn run_stack_limits() {
+        assert_run_error("let f = fn(n) { f(n + 1); }; f(0);", RuntimeError::StackOverflow);
+        // a function with many locals runs out of room in fewer calls
+        assert_run_error(
+            "let f = fn(a, b, c) { let d = a; let e = b; f(d, e, c); }; f(1, 2, 3);",
+            RuntimeError::StackOverflow
+        );
+
+        let mut vm = VM::new(ByteCode { instructions: Vec::new(), constants: Vec::new(), globals: vec![] });
+        for _ in 0..STACK_SIZE {
+            vm.push(Object::Null).unwrap();
+        }
+        assert_eq!(Err(RuntimeError::StackOverflow), vm.push(Object::Null));
+
+        let pop = crate::code::make_op(crate::code::OpCode::OpPop);
+        let mut vm = VM::new(ByteCode { instructions: pop, constants: Vec::new(), globals: vec![] });
+        assert_eq!(Err(RuntimeError::StackUnderflow), vm.run());
+    }
+
+    #[test]
+    fn run_optimized_matches_unoptimized() {
+        let inputs = vec![
+            "1 + 2 * 3;",
+            "-(4 - 6) / 2;",
+            "if (1 > 2) { 10; } else { 20; };",
+            "if (2 >= 2) { 10; }; 3333;",
+            "let a = 5; if (a > 2 && true) { a * 2; } else { 0; };",
+            "!(1 == 2) != false;",
+            r#"let s = "a" + "b"; s + "c";"#,
+            "null == null; 1 != null;",
+            "let f = fn(n) { if (n > 0) { n + f(n - 1); } else { 0 - 1 + 1; }; }; f(4);",
+            "[1 + 2, [if (true) { 3; }], !true];",
+            "let xs = [10, 20]; xs[2 - 1] + [1][5 * 0];",
+            r#"let g = fn() { {"a": 2 * 3}; }; g()["a"] + len("abc") + len([1, 2]);"#,
+        ];
+
+        for input in inputs {
+            let mut vm = VM::new(compile_from_source(input).unwrap());
+            vm.run().unwrap();
+            let mut optimized_vm = VM::new(compile_from_source_optimized(input).unwrap());
+            optimized_vm.run().unwrap();
+
+            assert_eq!(vm.last_popped(), optimized_vm.last_popped(), "{}", input);
+        }
+    }
+
     fn assert_last_popped(input: &str, obj: Object) {
-        let byte_code = compile_from_source(input);
+        let byte_code = compile_from_source(input).unwrap();
 
         let mut vm = VM::new(byte_code);
-        vm.run();
+        vm.run().unwrap();
 
         assert_eq!(&obj, vm.last_popped());
     }
+
+    fn assert_run_error(input: &str, expected: RuntimeError) {
+        let mut vm = VM::new(compile_from_source(input).unwrap());
+
+        assert_eq!(Err(expected), vm.run());
+    }
 }
@@ -0,0 +1,172 @@
+// Runs the monkey executable on scripts, for the flags which are handled by main rather than
+// by the library.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+// a path in the temp directory unique to this test process
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("monkey-cli-{}-{}", std::process::id(), name))
+}
+
+fn run(name: &str, script: &str, args: &[&str]) -> Output {
+    run_with_script_args(name, script, args, &[])
+}
+
+// tests run in parallel, so each names its script differently
+fn run_with_script_args(name: &str, script: &str, args: &[&str], script_args: &[&str]) -> Output {
+    let path = temp_path(&format!("{}.mky", name));
+    fs::write(&path, script).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .args(args)
+        .arg(&path)
+        .args(script_args)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+
+    output
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn record_and_replay() {
+    let script = "puts(clock()); puts(random()); puts(random(1000));";
+    let log = temp_path("replay.log");
+    let record = format!("--record={}", log.display());
+    let replay = format!("--replay={}", log.display());
+
+    let recorded = stdout(&run("record", script, &[&record]));
+    assert_eq!(3, fs::read_to_string(&log).unwrap().lines().count());
+    assert_eq!(recorded, stdout(&run("replay", script, &[&replay])));
+
+    // a program which reads more than was recorded can't be replayed
+    let diverged = run("diverged", "puts(clock()); puts(random()); puts(random(1000)); random();", &[&replay]);
+    assert_eq!(Some(1), diverged.status.code());
+    assert!(String::from_utf8_lossy(&diverged.stderr).contains("random"));
+
+    fs::remove_file(&log).unwrap();
+}
+
+#[test]
+fn deterministic_runs_match() {
+    let script = "puts(random()); puts(random(1000000));";
+    let first = stdout(&run("deterministic-first", script, &["--deterministic"]));
+    let second = stdout(&run("deterministic-second", script, &["--deterministic"]));
+
+    assert_eq!(first, second);
+    // the vm reads random numbers through the same host
+    assert_eq!(first, stdout(&run("deterministic-vm", script, &["--deterministic", "--engine=vm"])));
+}
+
+#[test]
+fn main_receives_script_args() {
+    let script = "let main = fn(args) { puts(len(args)); last(args); };";
+    let cache = temp_path("cache");
+    let cache_dir = format!("--cache-dir={}", cache.display());
+
+    let run = |args: &[&str]| stdout(&run_with_script_args("main", script, args, &["a", "b"]));
+    assert_eq!("2\nb\n", run(&["--print-result"]));
+    assert_eq!("2\nb\n", run(&["--print-result", "--engine=vm"]));
+    // the second run reads the compiled script from the cache
+    assert_eq!("2\nb\n", run(&["--print-result", "--engine=vm", &cache_dir]));
+    assert_eq!("2\nb\n", run(&["--print-result", "--engine=vm", &cache_dir]));
+
+    fs::remove_dir_all(&cache).unwrap();
+}
+
+#[test]
+fn script_args_after_the_path() {
+    let script = "let main = fn(args) { puts(args); };";
+
+    // flags after the path are the script's, not the interpreter's
+    let output = run_with_script_args("script-flags", script, &[], &["--print-result", "--bogus", "-x"]);
+    assert_eq!("[--print-result, --bogus, -x]\n", stdout(&output));
+
+    let output = run("unknown-flag", script, &["--bogus"]);
+    assert_eq!(Some(2), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown flag `--bogus`"));
+}
+
+#[test]
+fn vm_record_and_replay() {
+    let script = "puts(clock()); puts(random());";
+    let log = temp_path("vm-replay.log");
+    let record = format!("--record={}", log.display());
+    let replay = format!("--replay={}", log.display());
+
+    let recorded = stdout(&run("vm-record", script, &["--engine=vm", &record]));
+    assert_eq!(2, fs::read_to_string(&log).unwrap().lines().count());
+    assert_eq!(recorded, stdout(&run("vm-replay", script, &["--engine=vm", &replay])));
+    // a log recorded by one engine replays on the other
+    assert_eq!(recorded, stdout(&run("vm-replay-eval", script, &[&replay])));
+
+    fs::remove_file(&log).unwrap();
+}
+
+#[test]
+fn vm_rejects_eval_only_flags() {
+    let profile = temp_path("profile.folded");
+    let profile = format!("--profile={}", profile.display());
+    for flag in &[profile.as_str(), "--trace"] {
+        let output = run("vm-flags", "1;", &["--engine=vm", flag]);
+        assert_eq!(Some(2), output.status.code(), "{}", flag);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("only supported by the eval engine"));
+    }
+}
+
+#[test]
+fn replay_answers_to_prompts() {
+    let script = r#"let name = prompt("name: "); puts("hi " + name); puts(confirm("ok?")); puts(prompt("more: "));"#;
+    let log = temp_path("input-replay.log");
+    let record = format!("--record={}", log.display());
+    let replay = format!("--replay={}", log.display());
+
+    let path = temp_path("input-record.mky");
+    fs::write(&path, script).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .arg(&record)
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"monkey\nmaybe\ny\n").unwrap();
+    let recorded = stdout(&child.wait_with_output().unwrap());
+    fs::remove_file(&path).unwrap();
+    assert_eq!("hi monkey\ntrue\nnull\n", recorded);
+    assert_eq!("input \"monkey\"\ninput \"maybe\"\ninput \"y\"\ninput end\n", fs::read_to_string(&log).unwrap());
+
+    // the answers come from the log, with nothing on stdin
+    assert_eq!(recorded, stdout(&run("input-replay", script, &[&replay])));
+    assert_eq!(recorded, stdout(&run("input-replay-vm", script, &["--engine=vm", &replay])));
+
+    fs::remove_file(&log).unwrap();
+}
+
+#[test]
+fn stats_fails_on_invalid_scripts() {
+    let path = temp_path("stats.mky");
+    let stats = |script: &str| {
+        fs::write(&path, script).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_monkey")).arg("stats").arg(&path).output().unwrap()
+    };
+
+    assert!(stats("let a = 1;").status.success());
+    // a script which doesn't lex, and one which doesn't parse
+    for script in &["let a = 1 @ 2;", "let = 1;"] {
+        let output = stats(script);
+        assert_eq!(Some(1), output.status.code(), "{}", script);
+        assert!(output.stdout.is_empty());
+        assert!(!output.stderr.is_empty());
+    }
+
+    fs::remove_file(&path).unwrap();
+}